@@ -2,8 +2,8 @@ use std::future::Future;
 
 use sd_prisma::{
 	prisma::{
-		crdt_operation, file_path, label, label_on_object, location, media_data, object, tag,
-		tag_on_object, PrismaClient, SortOrder,
+		album, crdt_operation, file_path, label, label_on_object, location, media_data, object,
+		object_in_album, object_in_space, space, tag, tag_on_object, PrismaClient, SortOrder,
 	},
 	prisma_sync,
 };
@@ -106,6 +106,8 @@ pub async fn backfill_operations(db: &PrismaClient, sync: &crate::Manager, insta
 												),
 												option_sync_entry!(l.hidden, hidden),
 												option_sync_entry!(l.date_created, date_created),
+												option_sync_entry!(l.scan_schedule, scan_schedule),
+												option_sync_entry!(l.watcher_mode, watcher_mode),
 											],
 										),
 									)
@@ -334,6 +336,181 @@ pub async fn backfill_operations(db: &PrismaClient, sync: &crate::Manager, insta
 			)
 			.await?;
 
+			paginate(
+				|cursor| {
+					db.album()
+						.find_many(vec![album::id::gt(cursor)])
+						.order_by(album::id::order(SortOrder::Asc))
+						.exec()
+				},
+				|album| album.id,
+				|albums| {
+					db.crdt_operation()
+						.create_many(
+							albums
+								.into_iter()
+								.flat_map(|a| {
+									sync.shared_create(
+										prisma_sync::album::SyncId { pub_id: a.pub_id },
+										chain_optional_iter(
+											[],
+											[
+												option_sync_entry!(a.name, album::name),
+												option_sync_entry!(a.is_hidden, album::is_hidden),
+												option_sync_entry!(
+													a.date_created,
+													album::date_created
+												),
+												option_sync_entry!(
+													a.date_modified,
+													album::date_modified
+												),
+											],
+										),
+									)
+								})
+								.map(|o| crdt_op_unchecked_db(&o, instance_id))
+								.collect(),
+						)
+						.exec()
+				},
+			)
+			.await?;
+
+			paginate_relation(
+				|group_id, item_id| {
+					db.object_in_album()
+						.find_many(vec![
+							object_in_album::album_id::gt(group_id),
+							object_in_album::object_id::gt(item_id),
+						])
+						.order_by(object_in_album::album_id::order(SortOrder::Asc))
+						.order_by(object_in_album::object_id::order(SortOrder::Asc))
+						.include(object_in_album::include!({
+							album: select { pub_id }
+							object: select { pub_id }
+						}))
+						.exec()
+				},
+				|o_a| (o_a.album_id, o_a.object_id),
+				|object_in_albums| {
+					db.crdt_operation()
+						.create_many(
+							object_in_albums
+								.into_iter()
+								.flat_map(|o_a| {
+									sync.relation_create(
+										prisma_sync::object_in_album::SyncId {
+											album: prisma_sync::album::SyncId {
+												pub_id: o_a.album.pub_id,
+											},
+											object: prisma_sync::object::SyncId {
+												pub_id: o_a.object.pub_id,
+											},
+										},
+										chain_optional_iter(
+											[],
+											[option_sync_entry!(
+												o_a.date_created,
+												object_in_album::date_created
+											)],
+										),
+									)
+								})
+								.map(|o| crdt_op_unchecked_db(&o, instance_id))
+								.collect(),
+						)
+						.exec()
+				},
+			)
+			.await?;
+
+			paginate(
+				|cursor| {
+					db.space()
+						.find_many(vec![space::id::gt(cursor)])
+						.order_by(space::id::order(SortOrder::Asc))
+						.exec()
+				},
+				|space| space.id,
+				|spaces| {
+					db.crdt_operation()
+						.create_many(
+							spaces
+								.into_iter()
+								.flat_map(|s| {
+									sync.shared_create(
+										prisma_sync::space::SyncId { pub_id: s.pub_id },
+										chain_optional_iter(
+											[],
+											[
+												option_sync_entry!(s.name, space::name),
+												option_sync_entry!(
+													s.description,
+													space::description
+												),
+												option_sync_entry!(
+													s.date_created,
+													space::date_created
+												),
+												option_sync_entry!(
+													s.date_modified,
+													space::date_modified
+												),
+											],
+										),
+									)
+								})
+								.map(|o| crdt_op_unchecked_db(&o, instance_id))
+								.collect(),
+						)
+						.exec()
+				},
+			)
+			.await?;
+
+			paginate_relation(
+				|group_id, item_id| {
+					db.object_in_space()
+						.find_many(vec![
+							object_in_space::space_id::gt(group_id),
+							object_in_space::object_id::gt(item_id),
+						])
+						.order_by(object_in_space::space_id::order(SortOrder::Asc))
+						.order_by(object_in_space::object_id::order(SortOrder::Asc))
+						.include(object_in_space::include!({
+							space: select { pub_id }
+							object: select { pub_id }
+						}))
+						.exec()
+				},
+				|o_s| (o_s.space_id, o_s.object_id),
+				|object_in_spaces| {
+					db.crdt_operation()
+						.create_many(
+							object_in_spaces
+								.into_iter()
+								.flat_map(|o_s| {
+									sync.relation_create(
+										prisma_sync::object_in_space::SyncId {
+											space: prisma_sync::space::SyncId {
+												pub_id: o_s.space.pub_id,
+											},
+											object: prisma_sync::object::SyncId {
+												pub_id: o_s.object.pub_id,
+											},
+										},
+										[],
+									)
+								})
+								.map(|o| crdt_op_unchecked_db(&o, instance_id))
+								.collect(),
+						)
+						.exec()
+				},
+			)
+			.await?;
+
 			paginate(
 				|cursor| {
 					db.label()
@@ -0,0 +1,314 @@
+//! Compaction and garbage collection of the sync operations logs.
+//!
+//! Operations are written for every change and were kept forever, so after big reindexes they
+//! end up taking most of the library database. Nothing needed to converge is lost by compacting:
+//! - Only the latest update of a field of a record matters, older ones are discarded as old
+//!   by the ingest actor of every instance anyway.
+//! - Operations of a deleted record can be dropped altogether once every known instance went
+//!   past the deletion, as none of them can still be missing it. What a peer went past of is
+//!   only known from the clocks it reports when requesting our operations.
+//!
+//! The latest operation of each instance is always kept, as the sync clocks are derived from
+//! them when the library is loaded.
+
+use crate::SharedState;
+
+use sd_prisma::prisma::{instance, PrismaClient};
+use sd_utils::from_bytes_to_uuid;
+
+use std::collections::HashMap;
+
+use prisma_client_rust::{raw, PrismaValue, QueryError};
+use serde::{Deserialize, Serialize};
+use uhlc::NTP64;
+
+/// The tables sync operations are stored in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationLog {
+	/// `crdt_operation`: operations created by this instance or ingested from the others
+	Local,
+	/// `cloud_crdt_operation`: operations received from the cloud, ingested or waiting to be
+	Cloud,
+}
+
+impl OperationLog {
+	const fn table(self) -> &'static str {
+		match self {
+			Self::Local => "crdt_operation",
+			Self::Cloud => "cloud_crdt_operation",
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OperationLogStats {
+	pub operations: u64,
+	/// Size of the operations' columns, not counting the table and index overhead
+	pub bytes: u64,
+}
+
+pub async fn stats(db: &PrismaClient, log: OperationLog) -> Result<OperationLogStats, QueryError> {
+	#[derive(Deserialize)]
+	struct RawStats {
+		operations: i64,
+		bytes: Option<i64>,
+	}
+
+	let stats: Vec<RawStats> = db
+		._query_raw(raw!(&format!(
+			"SELECT COUNT(*) AS operations,
+				SUM(LENGTH(model) + LENGTH(record_id) + LENGTH(kind) + LENGTH(data)) AS bytes
+			FROM {}",
+			log.table()
+		)))
+		.exec()
+		.await?;
+
+	Ok(stats
+		.first()
+		.map(|stats| OperationLogStats {
+			operations: stats.operations as u64,
+			bytes: stats.bytes.unwrap_or(0) as u64,
+		})
+		.unwrap_or_default())
+}
+
+/// Size of the whole database file, which only shrinks after a [`vacuum`]
+pub async fn database_size(db: &PrismaClient) -> Result<u64, QueryError> {
+	#[derive(Deserialize)]
+	struct RawSize {
+		bytes: i64,
+	}
+
+	let size: Vec<RawSize> = db
+		._query_raw(raw!(
+			"SELECT page_count * page_size AS bytes FROM pragma_page_count(), pragma_page_size()"
+		))
+		.exec()
+		.await?;
+
+	Ok(size.first().map(|size| size.bytes as u64).unwrap_or(0))
+}
+
+/// Gives the space freed by compaction back to the file system
+pub async fn vacuum(db: &PrismaClient) -> Result<(), QueryError> {
+	db._execute_raw(raw!("VACUUM")).exec().await?;
+
+	Ok(())
+}
+
+/// Deletes the `Update` operations that were superseded by a newer one of the same field of the
+/// same record, returning how many were deleted
+pub async fn collapse_superseded_updates(
+	db: &PrismaClient,
+	log: OperationLog,
+) -> Result<u64, QueryError> {
+	let table = log.table();
+	let keep = keep_latest_operations_clause(db, log).await?;
+
+	// We have no data coming from the user, so this is sql injection safe
+	db._execute_raw(raw!(&format!(
+		"DELETE FROM {table}
+		WHERE kind LIKE 'u:%'{keep} AND EXISTS (
+			SELECT 1 FROM {table} AS newer
+			WHERE newer.model = {table}.model
+				AND newer.record_id = {table}.record_id
+				AND newer.kind = {table}.kind
+				AND (
+					newer.timestamp > {table}.timestamp
+					OR (newer.timestamp = {table}.timestamp AND newer.id > {table}.id)
+				)
+		)"
+	)))
+	.exec()
+	.await
+	.map(|count| count as u64)
+}
+
+/// Deletes every operation of the records deleted before the position all known instances are
+/// past of, including the deletion itself, returning how many were deleted
+pub async fn drop_deleted_records(
+	shared: &SharedState,
+	log: OperationLog,
+) -> Result<u64, QueryError> {
+	let Some(watermark) = watermark(shared).await? else {
+		return Ok(0);
+	};
+
+	let table = log.table();
+	let keep = keep_latest_operations_clause(&shared.db, log).await?;
+
+	shared
+		.db
+		._execute_raw(raw!(
+			&format!(
+				"DELETE FROM {table}
+				WHERE EXISTS (
+					SELECT 1 FROM {table} AS deletion
+					WHERE deletion.kind = 'd'
+						AND deletion.model = {table}.model
+						AND deletion.record_id = {table}.record_id
+						AND deletion.timestamp >= {table}.timestamp
+						AND deletion.timestamp < {{}}
+				){keep}"
+			),
+			PrismaValue::BigInt(watermark.as_u64() as i64)
+		))
+		.exec()
+		.await
+		.map(|count| count as u64)
+}
+
+/// Deletes the operations received from the cloud that were already ingested, as they're kept in
+/// the local log from then on, returning how many were deleted
+pub async fn drop_ingested_cloud_operations(shared: &SharedState) -> Result<u64, QueryError> {
+	let keep = keep_latest_operations_clause(&shared.db, OperationLog::Cloud).await?;
+
+	let instances = shared
+		.db
+		.instance()
+		.find_many(vec![])
+		.select(instance::select!({ id pub_id }))
+		.exec()
+		.await?;
+
+	let timestamps = shared.timestamps.read().await.clone();
+
+	let mut count = 0;
+
+	for instance in instances {
+		let Some(timestamp) = timestamps.get(&from_bytes_to_uuid(&instance.pub_id)) else {
+			continue;
+		};
+
+		count += shared
+			.db
+			._execute_raw(raw!(
+				&format!(
+					"DELETE FROM cloud_crdt_operation
+					WHERE instance_id = {{}} AND timestamp <= {{}}{keep}"
+				),
+				PrismaValue::Int(i64::from(instance.id)),
+				PrismaValue::BigInt(timestamp.as_u64() as i64)
+			))
+			.exec()
+			.await? as u64;
+	}
+
+	Ok(count)
+}
+
+/// Excludes the latest operation of each instance from a deletion
+async fn keep_latest_operations_clause(
+	db: &PrismaClient,
+	log: OperationLog,
+) -> Result<String, QueryError> {
+	#[derive(Deserialize)]
+	struct RawId {
+		id: i32,
+	}
+
+	// SQLite takes the bare columns of aggregate queries from the row with the maximum value
+	let ids: Vec<RawId> = db
+		._query_raw(raw!(&format!(
+			"SELECT id, MAX(timestamp) AS timestamp FROM {} GROUP BY instance_id",
+			log.table()
+		)))
+		.exec()
+		.await?;
+
+	Ok(if ids.is_empty() {
+		String::new()
+	} else {
+		format!(
+			" AND id NOT IN ({})",
+			ids.iter()
+				.map(|RawId { id }| id.to_string())
+				.collect::<Vec<_>>()
+				.join(", ")
+		)
+	})
+}
+
+/// The oldest clock position all known instances are past of, `None` if it can't be known.
+///
+/// This instance is past of the latest operation it has of each instance, ingested or created by
+/// it, and a peer of the clock it reported for each instance along its last request for our
+/// operations. Peers that never reported one hold compaction back.
+async fn watermark(shared: &SharedState) -> Result<Option<NTP64>, QueryError> {
+	#[derive(Deserialize)]
+	struct RawPosition {
+		instance_id: i32,
+		timestamp: i64,
+	}
+
+	let instances = shared
+		.db
+		.instance()
+		.find_many(vec![])
+		.select(instance::select!({ id pub_id }))
+		.exec()
+		.await?;
+
+	let positions = shared
+		.db
+		._query_raw::<RawPosition>(raw!(
+			"SELECT instance_id, MAX(timestamp) AS timestamp FROM crdt_operation GROUP BY instance_id"
+		))
+		.exec()
+		.await?
+		.into_iter()
+		.map(|position| (position.instance_id, NTP64(position.timestamp as u64)))
+		.collect::<HashMap<_, _>>();
+
+	let peer_clocks = shared
+		.db
+		.sync_peer_clock()
+		.find_many(vec![])
+		.exec()
+		.await?
+		.into_iter()
+		.map(|clock| {
+			(
+				(
+					from_bytes_to_uuid(&clock.peer_pub_id),
+					from_bytes_to_uuid(&clock.instance_pub_id),
+				),
+				NTP64(clock.timestamp as u64),
+			)
+		})
+		.collect::<HashMap<_, _>>();
+
+	let timestamps = shared.timestamps.read().await;
+
+	let instances = instances
+		.iter()
+		.map(|instance| (instance.id, from_bytes_to_uuid(&instance.pub_id)))
+		.collect::<Vec<_>>();
+
+	let own = instances.iter().map(|&(id, pub_id)| {
+		let ingested = timestamps.get(&pub_id).copied();
+
+		match (positions.get(&id).copied(), ingested) {
+			(Some(stored), Some(ingested)) => Some(NTP64::max(stored, ingested)),
+			(stored, ingested) => stored.or(ingested),
+		}
+	});
+
+	// a peer has its own operations, for the others it's as far as it reported
+	let peer_clocks = &peer_clocks;
+	let peers = instances
+		.iter()
+		.filter(|&&(_, peer)| peer != shared.instance)
+		.flat_map(|&(_, peer)| {
+			instances
+				.iter()
+				.filter(move |&&(_, instance)| instance != peer)
+				.map(move |&(_, instance)| peer_clocks.get(&(peer, instance)).copied())
+		});
+
+	Ok(own
+		.chain(peers)
+		.collect::<Option<Vec<_>>>()
+		.and_then(|positions| positions.into_iter().min()))
+}
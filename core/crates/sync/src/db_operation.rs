@@ -1,9 +1,13 @@
 use rmp_serde::to_vec;
-use sd_prisma::prisma::{cloud_crdt_operation, crdt_operation, instance, PrismaClient};
+use sd_prisma::prisma::{
+	cloud_crdt_operation, crdt_operation, instance, quarantined_crdt_operation, PrismaClient,
+};
 use sd_sync::CRDTOperation;
 use uhlc::NTP64;
 use uuid::Uuid;
 
+use crate::quarantine::MalformedOperation;
+
 crdt_operation::include!(crdt_include {
 	instance: select { pub_id }
 });
@@ -17,18 +21,32 @@ impl crdt_include::Data {
 		NTP64(self.timestamp as u64)
 	}
 
-	pub fn instance(&self) -> Uuid {
-		Uuid::from_slice(&self.instance.pub_id).unwrap()
+	fn decode(&self) -> Result<CRDTOperation, String> {
+		Ok(CRDTOperation {
+			instance: Uuid::from_slice(&self.instance.pub_id)
+				.map_err(|e| format!("malformed instance id: {e}"))?,
+			timestamp: self.timestamp(),
+			record_id: rmp_serde::from_slice(&self.record_id)
+				.map_err(|e| format!("malformed record id: {e}"))?,
+			model: self.model.clone(),
+			data: rmp_serde::from_slice(&self.data).map_err(|e| format!("malformed data: {e}"))?,
+		})
 	}
 
-	pub fn into_operation(self) -> CRDTOperation {
-		CRDTOperation {
-			instance: self.instance(),
-			timestamp: self.timestamp(),
-			record_id: rmp_serde::from_slice(&self.record_id).unwrap(),
-			model: self.model,
-			data: rmp_serde::from_slice(&self.data).unwrap(),
-		}
+	pub fn into_operation(self) -> Result<CRDTOperation, MalformedOperation> {
+		self.decode().map_err(|reason| MalformedOperation {
+			id: self.id,
+			operation: quarantined_crdt_operation::CreateUnchecked {
+				timestamp: self.timestamp,
+				model: self.model,
+				record_id: self.record_id,
+				kind: self.kind,
+				data: self.data,
+				instance_pub_id: self.instance.pub_id,
+				reason,
+				_params: vec![],
+			},
+		})
 	}
 }
 
@@ -37,18 +55,32 @@ impl cloud_crdt_include::Data {
 		NTP64(self.timestamp as u64)
 	}
 
-	pub fn instance(&self) -> Uuid {
-		Uuid::from_slice(&self.instance.pub_id).unwrap()
+	fn decode(&self) -> Result<CRDTOperation, String> {
+		Ok(CRDTOperation {
+			instance: Uuid::from_slice(&self.instance.pub_id)
+				.map_err(|e| format!("malformed instance id: {e}"))?,
+			timestamp: self.timestamp(),
+			record_id: rmp_serde::from_slice(&self.record_id)
+				.map_err(|e| format!("malformed record id: {e}"))?,
+			model: self.model.clone(),
+			data: serde_json::from_slice(&self.data).map_err(|e| format!("malformed data: {e}"))?,
+		})
 	}
 
-	pub fn into_operation(self) -> CRDTOperation {
-		CRDTOperation {
-			instance: self.instance(),
-			timestamp: self.timestamp(),
-			record_id: rmp_serde::from_slice(&self.record_id).unwrap(),
-			model: self.model,
-			data: serde_json::from_slice(&self.data).unwrap(),
-		}
+	pub fn into_operation(self) -> Result<CRDTOperation, MalformedOperation> {
+		self.decode().map_err(|reason| MalformedOperation {
+			id: self.id,
+			operation: quarantined_crdt_operation::CreateUnchecked {
+				timestamp: self.timestamp,
+				model: self.model,
+				record_id: self.record_id,
+				kind: self.kind,
+				data: self.data,
+				instance_pub_id: self.instance.pub_id,
+				reason,
+				_params: vec![],
+			},
+		})
 	}
 }
 
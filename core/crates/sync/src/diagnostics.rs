@@ -0,0 +1,164 @@
+//! What's known of how sync is going since the library was loaded, to debug it without opening
+//! the database by hand.
+
+use sd_sync::CRDTOperation;
+
+use std::{
+	collections::{HashMap, VecDeque},
+	sync::{Mutex, PoisonError},
+	time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uhlc::NTP64;
+use uuid::Uuid;
+
+/// How many rejected operations are kept, the oldest being dropped first
+const REJECTED_OPERATIONS_KEPT: usize = 500;
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+	P2P,
+	Cloud,
+	Bundle,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+	Sent,
+	Received,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange {
+	pub at: SystemTime,
+	pub operations: usize,
+}
+
+/// The last successful exchanges with a peer over a transport
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Exchanges {
+	pub sent: Option<Exchange>,
+	pub received: Option<Exchange>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RejectedOperation {
+	pub at: SystemTime,
+	pub operation: CRDTOperation,
+	pub reason: String,
+}
+
+#[derive(Default)]
+pub struct Diagnostics {
+	/// By peer instance, `None` for the cloud and for bundles not exported for a given instance
+	exchanges: Mutex<HashMap<(Option<Uuid>, Transport), Exchanges>>,
+	/// The clocks each peer instance sent along its last request for operations
+	peer_clocks: Mutex<HashMap<Uuid, Vec<(Uuid, NTP64)>>>,
+	rejected: Mutex<VecDeque<RejectedOperation>>,
+}
+
+impl Diagnostics {
+	pub fn record_exchange(
+		&self,
+		peer: Option<Uuid>,
+		transport: Transport,
+		direction: Direction,
+		operations: usize,
+	) {
+		let exchange = Some(Exchange {
+			at: SystemTime::now(),
+			operations,
+		});
+
+		let mut exchanges = self
+			.exchanges
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+		let exchanges = exchanges.entry((peer, transport)).or_default();
+
+		match direction {
+			Direction::Sent => exchanges.sent = exchange,
+			Direction::Received => exchanges.received = exchange,
+		}
+	}
+
+	pub fn record_peer_clocks(&self, peer: Uuid, clocks: Vec<(Uuid, NTP64)>) {
+		self.peer_clocks
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(peer, clocks);
+	}
+
+	pub fn record_rejected(&self, operation: CRDTOperation, reason: impl Into<String>) {
+		let mut rejected = self.rejected.lock().unwrap_or_else(PoisonError::into_inner);
+
+		if rejected.len() == REJECTED_OPERATIONS_KEPT {
+			rejected.pop_front();
+		}
+
+		rejected.push_back(RejectedOperation {
+			at: SystemTime::now(),
+			operation,
+			reason: reason.into(),
+		});
+	}
+
+	pub fn exchanges(&self) -> Vec<(Option<Uuid>, Transport, Exchanges)> {
+		self.exchanges
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.iter()
+			.map(|(&(peer, transport), &exchanges)| (peer, transport, exchanges))
+			.collect()
+	}
+
+	pub fn peer_clocks(&self) -> HashMap<Uuid, Vec<(Uuid, NTP64)>> {
+		self.peer_clocks
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+
+	/// Newest first
+	pub fn rejected(&self) -> Vec<RejectedOperation> {
+		self.rejected
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.iter()
+			.rev()
+			.cloned()
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rejected_operations_are_bounded() {
+		let diagnostics = Diagnostics::default();
+
+		for timestamp in 0..(REJECTED_OPERATIONS_KEPT as u64 + 10) {
+			diagnostics.record_rejected(
+				CRDTOperation {
+					instance: Uuid::nil(),
+					timestamp: NTP64(timestamp),
+					model: "Location".to_string(),
+					record_id: rmpv::Value::Nil,
+					data: sd_sync::CRDTOperationData::Delete,
+				},
+				"test",
+			);
+		}
+
+		let rejected = diagnostics.rejected();
+		assert_eq!(rejected.len(), REJECTED_OPERATIONS_KEPT);
+		assert_eq!(
+			rejected[0].operation.timestamp,
+			NTP64(REJECTED_OPERATIONS_KEPT as u64 + 9)
+		);
+	}
+}
@@ -1,24 +1,46 @@
 use std::{
+	collections::{HashMap, HashSet},
 	ops::Deref,
 	sync::{atomic::Ordering, Arc},
 };
 
 use sd_prisma::{
-	prisma::{crdt_operation, SortOrder},
+	prisma::{crdt_operation, instance, PrismaClient},
 	prisma_sync::ModelSyncData,
 };
-use sd_sync::CRDTOperation;
-use tokio::sync::{mpsc, oneshot, Mutex};
-use tracing::debug;
+use sd_sync::{CRDTOperation, CompressedCRDTOperation, CompressedCRDTOperations};
+use sd_utils::{from_bytes_to_uuid, uuid_to_bytes};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tracing::{debug, error, warn};
 use uhlc::{Timestamp, NTP64};
 use uuid::Uuid;
 
 use crate::{
 	actor::{create_actor_io, ActorIO, ActorTypes},
+	crdt_op_unchecked_db,
 	db_operation::write_crdt_op_to_db,
-	wait, SharedState,
+	quarantine::{self, quarantined_op_db},
+	wait, SharedState, SyncMessage,
 };
 
+/// Record ids queried at once when looking for the latest stored operations of a batch
+const RECORDS_PER_QUERY: usize = 500;
+
+/// Operations of a record are only superseded by operations of the same kind, which for
+/// updates includes the field
+type OperationKey = (String, Vec<u8>, String);
+
+type RecordOperations = (Uuid, String, rmpv::Value, Vec<CompressedCRDTOperation>);
+
+/// The timestamp of the latest operation of each kind of the records being ingested
+#[derive(Debug, Default)]
+struct LatestOperations {
+	/// Among the stored operations, an operation received again is stored with its timestamp
+	stored: HashMap<OperationKey, NTP64>,
+	/// Among the operations being ingested
+	received: HashMap<OperationKey, NTP64>,
+}
+
 #[derive(Debug)]
 #[must_use]
 /// Stuff that can be handled outside the actor
@@ -58,6 +80,10 @@ pub struct Actor {
 	state: Option<State>,
 	shared: Arc<SharedState>,
 	io: ActorIO<Self>,
+	tx: broadcast::Sender<SyncMessage>,
+	/// The latest timestamp received from each instance during the current ingestion, applied or
+	/// not, so its next request doesn't ask for the same operations again
+	received: HashMap<Uuid, NTP64>,
 }
 
 impl Actor {
@@ -67,6 +93,9 @@ impl Actor {
 				self.shared.active.store(false, Ordering::Relaxed);
 				self.shared.active_notify.notify_waiters();
 
+				// operations that weren't applied are requested again by the next ingestion
+				self.received.clear();
+
 				wait!(self.io.event_rx, Event::Notification);
 
 				self.shared.active.store(true, Ordering::Relaxed);
@@ -77,15 +106,15 @@ impl Actor {
 			State::RetrievingMessages => {
 				let (tx, mut rx) = oneshot::channel::<()>();
 
+				let mut timestamps = self.timestamps.read().await.clone();
+				for (&instance, &received) in &self.received {
+					let timestamp = timestamps.entry(instance).or_default();
+					*timestamp = NTP64::max(*timestamp, received);
+				}
+
 				self.io
 					.send(Request::Messages {
-						timestamps: self
-							.timestamps
-							.read()
-							.await
-							.iter()
-							.map(|(&k, &v)| (k, v))
-							.collect(),
+						timestamps: timestamps.into_iter().collect(),
 						tx,
 					})
 					.await
@@ -115,9 +144,7 @@ impl Actor {
 						event.messages.last().unwrap().timestamp.as_u64(),
 					);
 
-					for op in event.messages {
-						self.receive_crdt_operation(op).await;
-					}
+					self.receive_crdt_operations(event.messages).await;
 				}
 
 				match event.has_more {
@@ -137,7 +164,7 @@ impl Actor {
 		})
 	}
 
-	pub fn spawn(shared: Arc<SharedState>) -> Handler {
+	pub fn spawn(shared: Arc<SharedState>, tx: broadcast::Sender<SyncMessage>) -> Handler {
 		let (actor_io, handler_io) = create_actor_io::<Self>();
 
 		tokio::spawn(async move {
@@ -145,6 +172,8 @@ impl Actor {
 				state: Some(Default::default()),
 				io: actor_io,
 				shared,
+				tx,
+				received: HashMap::new(),
 			};
 
 			loop {
@@ -162,79 +191,375 @@ impl Actor {
 	}
 
 	// where the magic happens
-	async fn receive_crdt_operation(&mut self, op: CRDTOperation) {
-		// first, we update the HLC's timestamp with the incoming one.
-		// this involves a drift check + sets the last time of the clock
-		self.clock
-			.update_with_timestamp(&Timestamp::new(op.timestamp, op.instance.into()))
-			.expect("timestamp has too much drift!");
-
-		// read the timestamp for the operation's instance, or insert one if it doesn't exist
-		let timestamp = self.timestamps.read().await.get(&op.instance).cloned();
-
-		// copy some fields bc rust ownership
-		let op_instance = op.instance;
-		let op_timestamp = op.timestamp;
-
-		if !self.is_operation_old(&op).await {
-			// actually go and apply the operation in the db
-			self.apply_op(op).await.ok();
-
-			// update the stored timestamp for this instance - will be derived from the crdt operations table on restart
-			self.timestamps.write().await.insert(
-				op_instance,
-				NTP64::max(timestamp.unwrap_or_default(), op_timestamp),
+	async fn receive_crdt_operations(&mut self, mut ops: Vec<CRDTOperation>) {
+		// an operation can be received twice in the same batch, eg. over both the cloud and p2p
+		let mut unique = HashSet::new();
+		ops.retain(|op| unique.insert((op.instance, op.timestamp)));
+
+		// the next request of this ingestion starts after the received operations, even the ones
+		// that couldn't be applied, so they aren't requested again until the next one
+		for op in &ops {
+			let timestamp = self.received.entry(op.instance).or_default();
+			*timestamp = NTP64::max(*timestamp, op.timestamp);
+		}
+
+		// operations come in timestamp order
+		let order = ops
+			.iter()
+			.map(|op| (op.instance, op.timestamp))
+			.collect::<Vec<_>>();
+
+		// operations that may be applied later, the stored timestamps mustn't go past them
+		let mut retryable = HashSet::<(Uuid, NTP64)>::new();
+		let mut quarantined = vec![];
+
+		// first, we update the HLC's timestamp with the incoming ones.
+		// this involves a drift check + sets the last time of the clock.
+		// operations too far in the future are quarantined, applying them would have this
+		// instance's clock follow the drifted one. they're requested again by the next
+		// ingestion, until the clock caught up with them
+		let ops = ops
+			.into_iter()
+			.filter(|op| {
+				match self
+					.clock
+					.update_with_timestamp(&Timestamp::new(op.timestamp, op.instance.into()))
+				{
+					Ok(()) => true,
+					Err(e) => {
+						retryable.insert((op.instance, op.timestamp));
+						quarantined.push((op.clone(), format!("clock drift: {e}")));
+						false
+					}
+				}
+			})
+			.collect::<Vec<_>>();
+
+		// record ids are compared with the stored ones encoded, the ones that can't be encoded
+		// can't be stored either
+		let mut records = vec![];
+		for record in CompressedCRDTOperations::new(ops).into_records() {
+			match rmp_serde::to_vec(&record.2) {
+				Ok(record_id) => records.push((record, record_id)),
+				Err(e) => quarantined.extend(
+					record_operations(record).map(|op| (op, format!("malformed record id: {e}"))),
+				),
+			}
+		}
+
+		let ops = match self.latest_timestamps(&records).await {
+			Ok(latest) => records
+				.into_iter()
+				.flat_map(|(record, record_id)| {
+					record_operations(record).map(move |op| (op, record_id.clone()))
+				})
+				.filter(|(op, record_id)| !is_operation_old(&latest, op, record_id))
+				.map(|(op, _)| op)
+				.collect::<Vec<_>>(),
+			Err(e) => {
+				// without knowing which are old nothing can be applied, they'll be requested again
+				error!("Failed to fetch the latest operations of the ingested records: {e:#?}");
+				self.quarantine_ops(quarantined).await;
+				return;
+			}
+		};
+
+		let instance_ids = match self.instance_ids(&ops).await {
+			Ok(instance_ids) => instance_ids,
+			Err(e) => {
+				error!("Failed to fetch the instances of the ingested operations: {e:#?}");
+				self.quarantine_ops(quarantined).await;
+				return;
+			}
+		};
+
+		let (ops, unknown): (Vec<_>, Vec<_>) = ops
+			.into_iter()
+			.partition(|op| instance_ids.contains_key(&op.instance));
+
+		for op in unknown {
+			warn!("Skipping operation from unknown instance '{}'", op.instance);
+			// the instance may be added later on
+			retryable.insert((op.instance, op.timestamp));
+			self.diagnostics
+				.record_rejected(op, "created by an instance unknown to this one");
+		}
+
+		// operations of unknown models or with malformed record ids can't be applied
+		let (ops, malformed): (Vec<_>, Vec<_>) = ops
+			.into_iter()
+			.partition(|op| ModelSyncData::from_op(op.clone()).is_some());
+
+		quarantined.extend(
+			malformed
+				.into_iter()
+				.map(|op| (op, "unknown model or malformed record id".to_string())),
+		);
+
+		if !ops.is_empty() {
+			let applied = match self.apply_ops(&ops, &instance_ids).await {
+				Ok(()) => ops,
+				Err(e) => {
+					warn!(
+						"Failed to apply {} operations at once, applying them one by one: {e:#?}",
+						ops.len()
+					);
+
+					let mut applied = vec![];
+					for op in ops {
+						match self.apply_op(op.clone()).await {
+							Ok(()) => applied.push(op),
+							Err(e) => {
+								error!("Failed to apply operation: {e:#?}");
+								quarantined.push((op, format!("failed to apply: {e}")));
+							}
+						}
+					}
+					applied
+				}
+			};
+
+			if !applied.is_empty() {
+				// the drifted ones were quarantined the previous times they were received
+				if let Err(e) = quarantine::release(&self.db, &applied).await {
+					error!("Failed to release applied sync operations from quarantine: {e:#?}");
+				}
+
+				self.io.req_tx.send(Request::Ingested).await.ok();
+			}
+		}
+
+		self.quarantine_ops(quarantined).await;
+
+		// update the stored timestamp for each instance - will be derived from the crdt operations table on restart.
+		// it only goes up to the operations before the first one that may still be applied
+		let mut timestamps = self.timestamps.write().await;
+		let mut blocked = HashSet::new();
+		for (instance, timestamp) in order {
+			if blocked.contains(&instance) {
+				continue;
+			}
+
+			if retryable.contains(&(instance, timestamp)) {
+				blocked.insert(instance);
+				continue;
+			}
+
+			let stored = timestamps.entry(instance).or_default();
+			*stored = NTP64::max(*stored, timestamp);
+		}
+	}
+
+	/// Sets aside the operations that can't be ingested, so the others can be
+	async fn quarantine_ops(&self, ops: Vec<(CRDTOperation, String)>) {
+		if ops.is_empty() {
+			return;
+		}
+
+		let creates = ops
+			.iter()
+			.filter_map(|(op, reason)| {
+				quarantined_op_db(op, reason.as_str())
+					.map_err(|e| error!("Failed to encode quarantined sync operation: {e:#?}"))
+					.ok()
+			})
+			.collect();
+
+		for (op, reason) in ops {
+			warn!(
+				"Quarantining sync operation '{}' of instance '{}': {reason}",
+				op.timestamp.as_u64(),
+				op.instance
 			);
+			self.diagnostics.record_rejected(op, reason);
+		}
+
+		match quarantine::quarantine(&self.db, creates).await {
+			// they were already quarantined the previous times they were received
+			Ok(0) => {}
+			Ok(count) => {
+				self.tx.send(SyncMessage::Quarantined(count)).ok();
+			}
+			Err(e) => error!("Failed to quarantine sync operations: {e:#?}"),
 		}
 	}
 
-	async fn apply_op(&mut self, op: CRDTOperation) -> prisma_client_rust::Result<()> {
+	/// The database ids of the instances that created the operations
+	async fn instance_ids(
+		&self,
+		ops: &[CRDTOperation],
+	) -> prisma_client_rust::Result<HashMap<Uuid, instance::id::Type>> {
+		Ok(self
+			.db
+			.instance()
+			.find_many(vec![instance::pub_id::in_vec(
+				ops.iter()
+					.map(|op| op.instance)
+					.collect::<HashSet<_>>()
+					.into_iter()
+					.map(uuid_to_bytes)
+					.collect(),
+			)])
+			.select(instance::select!({ id pub_id }))
+			.exec()
+			.await?
+			.into_iter()
+			.map(|instance| (from_bytes_to_uuid(&instance.pub_id), instance.id))
+			.collect())
+	}
+
+	/// Applies the operations and writes them to the operations table in a single transaction
+	async fn apply_ops(
+		&self,
+		ops: &[CRDTOperation],
+		instance_ids: &HashMap<Uuid, instance::id::Type>,
+	) -> prisma_client_rust::Result<()> {
+		let creates = ops
+			.iter()
+			.map(|op| crdt_op_unchecked_db(op, instance_ids[&op.instance]))
+			.collect::<Vec<_>>();
+		let ops = ops.to_vec();
+
 		self.db
 			._transaction()
 			.with_timeout(30 * 1000)
 			.run(|db| async move {
-				// apply the operation to the actual record
-				let sync_data = ModelSyncData::from_op(op.clone());
+				for op in ops {
+					// apply the operation to the actual record, the ones without sync data were
+					// quarantined when received
+					if let Some(sync_data) = ModelSyncData::from_op(op) {
+						sync_data.exec(&db).await?;
+					}
+				}
+
+				// write the operations to the operations table
+				db.crdt_operation().create_many(creates).exec().await?;
+
+				Ok(())
+			})
+			.await
+	}
 
-				sync_data.unwrap().exec(&db).await?;
+	async fn apply_op(&mut self, op: CRDTOperation) -> prisma_client_rust::Result<()> {
+		self.db
+			._transaction()
+			.with_timeout(30 * 1000)
+			.run(|db| async move {
+				// apply the operation to the actual record, the ones without sync data were
+				// quarantined when received
+				if let Some(sync_data) = ModelSyncData::from_op(op.clone()) {
+					sync_data.exec(&db).await?;
+				}
 
 				// write the operation to the operations table
 				write_crdt_op_to_db(&op, &db).await?;
 
 				Ok(())
 			})
-			.await?;
+			.await
+	}
+
+	/// The timestamp of the latest operation of each kind of the records, among the stored
+	/// operations and the ones being ingested
+	async fn latest_timestamps(
+		&self,
+		records: &[(RecordOperations, Vec<u8>)],
+	) -> prisma_client_rust::Result<LatestOperations> {
+		let mut latest = LatestOperations::default();
+
+		let mut record_ids_by_model = HashMap::<&str, HashSet<Vec<u8>>>::new();
+
+		for ((_, model, _, ops), record_id) in records {
+			for op in ops {
+				let timestamp = latest
+					.received
+					.entry((
+						model.clone(),
+						record_id.clone(),
+						op.data.as_kind().to_string(),
+					))
+					.or_default();
+				*timestamp = NTP64::max(*timestamp, op.timestamp);
+			}
 
-		self.io.req_tx.send(Request::Ingested).await.ok();
+			record_ids_by_model
+				.entry(model.as_str())
+				.or_default()
+				.insert(record_id.clone());
+		}
 
-		Ok(())
+		for (model, stored) in stored_operations(&self.db, record_ids_by_model).await? {
+			for op in stored {
+				let timestamp = latest
+					.stored
+					.entry((model.to_string(), op.record_id, op.kind))
+					.or_default();
+				*timestamp = NTP64::max(*timestamp, NTP64(op.timestamp as u64));
+			}
+		}
+
+		Ok(latest)
 	}
+}
 
-	// determines if an operation is old and shouldn't be applied
-	async fn is_operation_old(&mut self, op: &CRDTOperation) -> bool {
-		let db = &self.db;
+crdt_operation::select!(crdt_operation_key { timestamp record_id kind });
+
+/// The operations of a record, as received
+fn record_operations(
+	(instance, model, record_id, ops): RecordOperations,
+) -> impl Iterator<Item = CRDTOperation> {
+	ops.into_iter().map(move |op| CRDTOperation {
+		instance,
+		timestamp: op.timestamp,
+		model: model.clone(),
+		record_id: record_id.clone(),
+		data: op.data,
+	})
+}
 
-		let old_timestamp = {
-			let newer_op = db
-				.crdt_operation()
-				.find_first(vec![
-					crdt_operation::timestamp::gte(op.timestamp.as_u64() as i64),
-					crdt_operation::model::equals(op.model.to_string()),
-					crdt_operation::record_id::equals(serde_json::to_vec(&op.record_id).unwrap()),
-					crdt_operation::kind::equals(op.kind().to_string()),
-				])
-				.order_by(crdt_operation::timestamp::order(SortOrder::Desc))
-				.exec()
-				.await
-				.unwrap();
+/// The stored operations of the records, by model
+async fn stored_operations<'model>(
+	db: &PrismaClient,
+	record_ids_by_model: HashMap<&'model str, HashSet<Vec<u8>>>,
+) -> prisma_client_rust::Result<Vec<(&'model str, Vec<crdt_operation_key::Data>)>> {
+	let (models, queries): (Vec<_>, Vec<_>) = record_ids_by_model
+		.into_iter()
+		.flat_map(|(model, record_ids)| {
+			record_ids
+				.into_iter()
+				.collect::<Vec<_>>()
+				.chunks(RECORDS_PER_QUERY)
+				.map(|record_ids| {
+					(
+						model,
+						db.crdt_operation()
+							.find_many(vec![
+								crdt_operation::model::equals(model.to_string()),
+								crdt_operation::record_id::in_vec(record_ids.to_vec()),
+							])
+							.select(crdt_operation_key::select()),
+					)
+				})
+				.collect::<Vec<_>>()
+		})
+		.unzip();
 
-			newer_op.map(|newer_op| newer_op.timestamp)
-		};
+	Ok(models.into_iter().zip(db._batch(queries).await?).collect())
+}
 
-		old_timestamp
-			.map(|old| old != op.timestamp.as_u64() as i64)
-			.unwrap_or_default()
-	}
+/// An operation is old, and shouldn't be applied, if there's a newer one of the same kind for
+/// its record, whose id is given encoded, or if it was already stored
+fn is_operation_old(latest: &LatestOperations, op: &CRDTOperation, record_id: &[u8]) -> bool {
+	let key = (op.model.clone(), record_id.to_vec(), op.kind().to_string());
+
+	latest
+		.stored
+		.get(&key)
+		.is_some_and(|stored| *stored >= op.timestamp)
+		|| latest
+			.received
+			.get(&key)
+			.is_some_and(|received| *received > op.timestamp)
 }
 
 impl Deref for Actor {
@@ -281,9 +606,13 @@ mod test {
 			emit_messages_flag: Arc::new(AtomicBool::new(true)),
 			active: Default::default(),
 			active_notify: Default::default(),
+			diagnostics: Default::default(),
 		});
 
-		(Actor::spawn(shared.clone()), shared)
+		(
+			Actor::spawn(shared.clone(), broadcast::channel(64).0),
+			shared,
+		)
 	}
 
 	/// If messages tx is dropped, actor should reset and assume no further messages
@@ -313,4 +642,305 @@ mod test {
 
 		Ok(())
 	}
+
+	/// Drifted operations are quarantined, so a full page of them mustn't be requested again by
+	/// the same ingestion
+	#[tokio::test]
+	async fn drifted_operations_are_paged_past() {
+		const COUNT: usize = 100;
+
+		let (ingest, _) = new_actor().await;
+
+		let drifted_instance = Uuid::new_v4();
+		let drifted_at = NTP64::from(
+			std::time::SystemTime::now()
+				.duration_since(std::time::UNIX_EPOCH)
+				.unwrap() + Duration::from_secs(60 * 60),
+		);
+		let drifted = (0..COUNT as u64 * 3 / 2)
+			.map(|i| CRDTOperation {
+				instance: drifted_instance,
+				timestamp: NTP64(drifted_at.as_u64() + i),
+				model: "Location".to_string(),
+				record_id: rmpv::Value::Nil,
+				data: sd_sync::CRDTOperationData::Create,
+			})
+			.collect::<Vec<_>>();
+
+		let mut rx = ingest.req_rx.lock().await;
+		ingest.event_tx.send(Event::Notification).await.unwrap();
+
+		let mut requests = 0;
+		tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				match rx.recv().await.unwrap() {
+					Request::Messages { timestamps, .. } => {
+						requests += 1;
+
+						let after = timestamps
+							.iter()
+							.find(|(instance, _)| *instance == drifted_instance)
+							.map(|(_, timestamp)| *timestamp);
+						let messages = drifted
+							.iter()
+							.filter(|op| after.map_or(true, |after| op.timestamp > after))
+							.take(COUNT)
+							.cloned()
+							.collect::<Vec<_>>();
+
+						ingest
+							.event_tx
+							.send(Event::Messages(MessagesEvent {
+								instance_id: drifted_instance,
+								has_more: messages.len() == COUNT,
+								messages,
+							}))
+							.await
+							.unwrap();
+					}
+					Request::Ingested => {}
+					Request::FinishedIngesting => break,
+				}
+			}
+		})
+		.await
+		.expect("ingestion kept requesting the drifted operations");
+
+		assert_eq!(requests, 2);
+	}
+
+	/// Runs a single ingestion, answering its first request with the operations picked after the
+	/// instance's timestamp, and returns that timestamp
+	async fn ingest_once(
+		ingest: &Handler,
+		instance: Uuid,
+		pick: impl Fn(Option<NTP64>) -> Vec<CRDTOperation>,
+	) -> Option<NTP64> {
+		let mut rx = ingest.req_rx.lock().await;
+		ingest.event_tx.send(Event::Notification).await.unwrap();
+
+		let mut after = None;
+		tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				match rx.recv().await.unwrap() {
+					Request::Messages { timestamps, .. } => {
+						after = timestamps
+							.iter()
+							.find(|(id, _)| *id == instance)
+							.map(|(_, timestamp)| *timestamp);
+
+						ingest
+							.event_tx
+							.send(Event::Messages(MessagesEvent {
+								instance_id: instance,
+								messages: pick(after),
+								has_more: false,
+							}))
+							.await
+							.unwrap();
+					}
+					Request::Ingested => {}
+					Request::FinishedIngesting => break,
+				}
+			}
+		})
+		.await
+		.expect("ingestion didn't finish");
+
+		after
+	}
+
+	/// A drifted operation mustn't move the instance's timestamp past operations of the instance
+	/// that are received later
+	#[tokio::test]
+	async fn drifted_operations_dont_hide_later_ones() {
+		use prisma_client_rust::chrono::Utc;
+		use sd_prisma::{prisma::location, prisma_sync};
+
+		let (ingest, shared) = new_actor().await;
+		shared.db._db_push().await.unwrap();
+
+		let remote = Uuid::new_v4();
+		shared
+			.db
+			.instance()
+			.create(
+				uuid_to_bytes(remote),
+				vec![],
+				vec![],
+				Utc::now().into(),
+				Utc::now().into(),
+				vec![],
+			)
+			.exec()
+			.await
+			.unwrap();
+
+		let now = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap();
+		let create_location = |timestamp| CRDTOperation {
+			instance: remote,
+			timestamp,
+			model: location::NAME.to_string(),
+			record_id: sd_utils::msgpack!(prisma_sync::location::SyncId {
+				pub_id: uuid_to_bytes(Uuid::new_v4()),
+			}),
+			data: sd_sync::CRDTOperationData::Create,
+		};
+
+		let drifted = create_location(NTP64::from(now + Duration::from_secs(60 * 60)));
+		let later = create_location(NTP64::from(now));
+
+		ingest_once(&ingest, remote, |_| vec![drifted.clone()]).await;
+
+		// the peer only got the in-tolerance operation after sending the drifted one
+		let after = ingest_once(&ingest, remote, |after| {
+			[later.clone(), drifted.clone()]
+				.into_iter()
+				.filter(|op| after.map_or(true, |after| op.timestamp > after))
+				.collect()
+		})
+		.await;
+
+		assert!(after.map_or(true, |after| after < later.timestamp));
+
+		let applied = shared
+			.db
+			.crdt_operation()
+			.find_many(vec![crdt_operation::timestamp::equals(
+				later.timestamp.as_u64() as i64,
+			)])
+			.exec()
+			.await
+			.unwrap();
+		assert_eq!(applied.len(), 1);
+
+		// the drifted one is still quarantined, to be requested again
+		assert_eq!(
+			shared
+				.db
+				.quarantined_crdt_operation()
+				.count(vec![])
+				.exec()
+				.await
+				.unwrap(),
+			1
+		);
+		assert!(shared
+			.timestamps
+			.read()
+			.await
+			.get(&remote)
+			.is_some_and(|timestamp| *timestamp < drifted.timestamp));
+	}
+
+	/// An operation received twice, eg. over both the cloud and p2p, is only applied and stored once
+	#[tokio::test]
+	async fn operations_received_again_are_stored_once() {
+		use prisma_client_rust::chrono::Utc;
+		use sd_prisma::{prisma::location, prisma_sync};
+
+		let (ingest, shared) = new_actor().await;
+		shared.db._db_push().await.unwrap();
+
+		let remote = Uuid::new_v4();
+		shared
+			.db
+			.instance()
+			.create(
+				uuid_to_bytes(remote),
+				vec![],
+				vec![],
+				Utc::now().into(),
+				Utc::now().into(),
+				vec![],
+			)
+			.exec()
+			.await
+			.unwrap();
+
+		let op = CRDTOperation {
+			instance: remote,
+			timestamp: NTP64::from(
+				std::time::SystemTime::now()
+					.duration_since(std::time::UNIX_EPOCH)
+					.unwrap(),
+			),
+			model: location::NAME.to_string(),
+			record_id: sd_utils::msgpack!(prisma_sync::location::SyncId {
+				pub_id: uuid_to_bytes(Uuid::new_v4()),
+			}),
+			data: sd_sync::CRDTOperationData::Create,
+		};
+
+		ingest_once(&ingest, remote, |_| vec![op.clone(), op.clone()]).await;
+		ingest_once(&ingest, remote, |_| vec![op.clone()]).await;
+
+		assert_eq!(
+			shared
+				.db
+				.crdt_operation()
+				.count(vec![crdt_operation::timestamp::equals(
+					op.timestamp.as_u64() as i64
+				)])
+				.exec()
+				.await
+				.unwrap(),
+			1
+		);
+		assert_eq!(shared.db.location().count(vec![]).exec().await.unwrap(), 1);
+		assert_eq!(
+			shared
+				.db
+				.quarantined_crdt_operation()
+				.count(vec![])
+				.exec()
+				.await
+				.unwrap(),
+			0
+		);
+	}
+
+	#[test]
+	fn superseded_operations_are_old() {
+		let op = |timestamp: u64, field: &str| CRDTOperation {
+			instance: Uuid::new_v4(),
+			timestamp: NTP64(timestamp),
+			model: "Location".to_string(),
+			record_id: rmpv::Value::Nil,
+			data: sd_sync::CRDTOperationData::Update {
+				field: field.to_string(),
+				value: rmpv::Value::Nil,
+			},
+		};
+
+		let record_id = rmp_serde::to_vec(&rmpv::Value::Nil).unwrap();
+		let key = (
+			"Location".to_string(),
+			record_id.clone(),
+			"u:name".to_string(),
+		);
+
+		let received = LatestOperations {
+			received: HashMap::from([(key.clone(), NTP64(2))]),
+			..Default::default()
+		};
+
+		assert!(is_operation_old(&received, &op(1, "name"), &record_id));
+		assert!(!is_operation_old(&received, &op(2, "name"), &record_id));
+		assert!(!is_operation_old(&received, &op(3, "name"), &record_id));
+		// updates of other fields don't supersede each other
+		assert!(!is_operation_old(&received, &op(1, "path"), &record_id));
+
+		// an operation received again was stored with its timestamp
+		let stored = LatestOperations {
+			stored: HashMap::from([(key, NTP64(2))]),
+			..Default::default()
+		};
+
+		assert!(is_operation_old(&stored, &op(1, "name"), &record_id));
+		assert!(is_operation_old(&stored, &op(2, "name"), &record_id));
+		assert!(!is_operation_old(&stored, &op(3, "name"), &record_id));
+	}
 }
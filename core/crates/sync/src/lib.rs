@@ -2,9 +2,12 @@
 
 mod actor;
 pub mod backfill;
+pub mod compaction;
 mod db_operation;
+pub mod diagnostics;
 pub mod ingest;
 mod manager;
+pub mod quarantine;
 
 use sd_prisma::prisma::{crdt_operation, instance, PrismaClient};
 use sd_sync::CRDTOperation;
@@ -22,6 +25,8 @@ pub use uhlc::NTP64;
 pub enum SyncMessage {
 	Ingested,
 	Created,
+	/// How many operations were newly quarantined, see [`quarantine`]
+	Quarantined(usize),
 }
 
 pub type Timestamps = Arc<tokio::sync::RwLock<HashMap<uuid::Uuid, NTP64>>>;
@@ -34,6 +39,7 @@ pub struct SharedState {
 	pub clock: uhlc::HLC,
 	pub active: AtomicBool,
 	pub active_notify: tokio::sync::Notify,
+	pub diagnostics: diagnostics::Diagnostics,
 }
 
 #[must_use]
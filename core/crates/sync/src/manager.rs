@@ -1,6 +1,15 @@
-use crate::{crdt_op_db, db_operation::*, ingest, SharedState, SyncMessage, NTP64};
+use crate::{
+	compaction::OperationLog,
+	crdt_op_db,
+	db_operation::*,
+	ingest,
+	quarantine::{self, MalformedOperation},
+	SharedState, SyncMessage, NTP64,
+};
 
-use sd_prisma::prisma::{cloud_crdt_operation, crdt_operation, instance, PrismaClient, SortOrder};
+use sd_prisma::prisma::{
+	cloud_crdt_operation, crdt_operation, instance, sync_peer_clock, PrismaClient, SortOrder,
+};
 use sd_sync::{CRDTOperation, OperationFactory};
 use sd_utils::uuid_to_bytes;
 
@@ -15,9 +24,36 @@ use std::{
 };
 
 use tokio::sync::{broadcast, RwLock};
+use tracing::warn;
 use uhlc::{HLCBuilder, HLC};
 use uuid::Uuid;
 
+/// Matches the operations newer than the given clocks, and all of those of the instances
+/// without a clock
+macro_rules! db_args {
+	($clocks:expr, $op:ident) => {
+		vec![prisma_client_rust::operator::or(
+			$clocks
+				.iter()
+				.map(|(instance_id, timestamp)| {
+					prisma_client_rust::and![
+						$op::instance::is(vec![instance::pub_id::equals(uuid_to_bytes(
+							*instance_id
+						))]),
+						$op::timestamp::gt(timestamp.as_u64() as i64)
+					]
+				})
+				.chain([$op::instance::is_not(vec![instance::pub_id::in_vec(
+					$clocks
+						.iter()
+						.map(|(instance_id, _)| uuid_to_bytes(*instance_id))
+						.collect(),
+				)])])
+				.collect(),
+		)]
+	};
+}
+
 /// Wrapper that spawns the ingest actor and provides utilities for reading and writing sync operations.
 pub struct Manager {
 	pub tx: broadcast::Sender<SyncMessage>,
@@ -56,9 +92,10 @@ impl Manager {
 			emit_messages_flag: emit_messages_flag.clone(),
 			active: Default::default(),
 			active_notify: Default::default(),
+			diagnostics: Default::default(),
 		});
 
-		let ingest = ingest::Actor::spawn(shared.clone());
+		let ingest = ingest::Actor::spawn(shared.clone(), tx.clone());
 
 		New {
 			manager: Self { tx, ingest, shared },
@@ -123,22 +160,31 @@ impl Manager {
 	) -> prisma_client_rust::Result<Vec<CRDTOperation>> {
 		let db = &self.db;
 
-		Ok(db
-			.crdt_operation()
-			.find_many(vec![
-				crdt_operation::instance::is(vec![instance::pub_id::equals(uuid_to_bytes(
-					instance_uuid,
-				))]),
-				crdt_operation::timestamp::gt(timestamp.as_u64() as i64),
-			])
-			.take(i64::from(count))
-			.order_by(crdt_operation::timestamp::order(SortOrder::Asc))
-			.include(crdt_include::include())
-			.exec()
-			.await?
-			.into_iter()
-			.map(|o| o.into_operation())
-			.collect())
+		loop {
+			let ops = db
+				.crdt_operation()
+				.find_many(vec![
+					crdt_operation::instance::is(vec![instance::pub_id::equals(uuid_to_bytes(
+						instance_uuid,
+					))]),
+					crdt_operation::timestamp::gt(timestamp.as_u64() as i64),
+				])
+				.take(i64::from(count))
+				.order_by(crdt_operation::timestamp::order(SortOrder::Asc))
+				.include(crdt_include::include())
+				.exec()
+				.await?;
+
+			if let Some(ops) = self
+				.decode_ops(
+					OperationLog::Local,
+					ops.into_iter().map(|o| o.into_operation()),
+				)
+				.await?
+			{
+				return Ok(ops);
+			}
+		}
 	}
 
 	pub async fn get_ops(
@@ -147,57 +193,108 @@ impl Manager {
 	) -> prisma_client_rust::Result<Vec<CRDTOperation>> {
 		let db = &self.db;
 
-		macro_rules! db_args {
-			($args:ident, $op:ident) => {
-				vec![prisma_client_rust::operator::or(
-					$args
-						.clocks
+		loop {
+			let mut ops = db
+				.crdt_operation()
+				.find_many(db_args!(args.clocks, crdt_operation))
+				.take(i64::from(args.count))
+				.order_by(crdt_operation::timestamp::order(SortOrder::Asc))
+				.include(crdt_include::include())
+				.exec()
+				.await?;
+
+			ops.sort_by(|a, b| match a.timestamp().cmp(&b.timestamp()) {
+				Ordering::Equal => a.instance.pub_id.cmp(&b.instance.pub_id),
+				o => o,
+			});
+
+			if let Some(ops) = self
+				.decode_ops(
+					OperationLog::Local,
+					ops.into_iter()
+						.take(args.count as usize)
+						.map(|o| o.into_operation()),
+				)
+				.await?
+			{
+				return Ok(ops);
+			}
+		}
+	}
+
+	/// Keeps the clocks a peer instance sent along its request for our operations, which tell
+	/// what it already received for compaction to go by
+	pub async fn record_peer_clocks(
+		&self,
+		peer: Uuid,
+		clocks: &[(Uuid, NTP64)],
+	) -> prisma_client_rust::Result<()> {
+		self.diagnostics.record_peer_clocks(peer, clocks.to_vec());
+
+		self.db
+			._batch((
+				self.db
+					.sync_peer_clock()
+					.delete_many(vec![sync_peer_clock::peer_pub_id::equals(uuid_to_bytes(
+						peer,
+					))]),
+				self.db.sync_peer_clock().create_many(
+					clocks
 						.iter()
-						.map(|(instance_id, timestamp)| {
-							prisma_client_rust::and![
-								$op::instance::is(vec![instance::pub_id::equals(uuid_to_bytes(
-									*instance_id
-								))]),
-								$op::timestamp::gt(timestamp.as_u64() as i64)
-							]
+						.map(|(instance, timestamp)| {
+							sync_peer_clock::create_unchecked(
+								uuid_to_bytes(peer),
+								uuid_to_bytes(*instance),
+								timestamp.as_u64() as i64,
+								vec![],
+							)
 						})
-						.chain([
-							$op::instance::is_not(vec![
-								instance::pub_id::in_vec(
-									$args
-										.clocks
-										.iter()
-										.map(|(instance_id, _)| {
-											uuid_to_bytes(*instance_id)
-										})
-										.collect()
-								)
-							])
-						])
 						.collect(),
-				)]
-			};
-		}
+				),
+			))
+			.await?;
 
-		let mut ops = db
+		Ok(())
+	}
+
+	/// How many operations [`Self::get_ops`] would return for the clocks, without a limit
+	pub async fn count_ops(&self, clocks: &[(Uuid, NTP64)]) -> prisma_client_rust::Result<i64> {
+		self.db
 			.crdt_operation()
-			.find_many(db_args!(args, crdt_operation))
-			.take(i64::from(args.count))
-			.order_by(crdt_operation::timestamp::order(SortOrder::Asc))
-			.include(crdt_include::include())
+			.count(db_args!(clocks, crdt_operation))
 			.exec()
-			.await?;
+			.await
+	}
 
-		ops.sort_by(|a, b| match a.timestamp().cmp(&b.timestamp()) {
-			Ordering::Equal => a.instance().cmp(&b.instance()),
-			o => o,
-		});
+	/// Every stored operation of a record, oldest first
+	pub async fn get_record_ops(
+		&self,
+		model: &str,
+		record_id: &rmpv::Value,
+	) -> prisma_client_rust::Result<Vec<CRDTOperation>> {
+		loop {
+			let ops = self
+				.db
+				.crdt_operation()
+				.find_many(vec![
+					crdt_operation::model::equals(model.to_string()),
+					crdt_operation::record_id::equals(rmp_serde::to_vec(record_id).unwrap()),
+				])
+				.order_by(crdt_operation::timestamp::order(SortOrder::Asc))
+				.include(crdt_include::include())
+				.exec()
+				.await?;
 
-		Ok(ops
-			.into_iter()
-			.take(args.count as usize)
-			.map(|o| o.into_operation())
-			.collect())
+			if let Some(ops) = self
+				.decode_ops(
+					OperationLog::Local,
+					ops.into_iter().map(|o| o.into_operation()),
+				)
+				.await?
+			{
+				return Ok(ops);
+			}
+		}
 	}
 
 	pub async fn get_cloud_ops(
@@ -206,57 +303,80 @@ impl Manager {
 	) -> prisma_client_rust::Result<Vec<CRDTOperation>> {
 		let db = &self.db;
 
-		macro_rules! db_args {
-			($args:ident, $op:ident) => {
-				vec![prisma_client_rust::operator::or(
-					$args
-						.clocks
-						.iter()
-						.map(|(instance_id, timestamp)| {
-							prisma_client_rust::and![
-								$op::instance::is(vec![instance::pub_id::equals(uuid_to_bytes(
-									*instance_id
-								))]),
-								$op::timestamp::gt(timestamp.as_u64() as i64)
-							]
-						})
-						.chain([
-							$op::instance::is_not(vec![
-								instance::pub_id::in_vec(
-									$args
-										.clocks
-										.iter()
-										.map(|(instance_id, _)| {
-											uuid_to_bytes(*instance_id)
-										})
-										.collect()
-								)
-							])
-						])
-						.collect(),
-				)]
-			};
+		loop {
+			let mut ops = db
+				.cloud_crdt_operation()
+				.find_many(db_args!(args.clocks, cloud_crdt_operation))
+				.take(i64::from(args.count))
+				.order_by(cloud_crdt_operation::timestamp::order(SortOrder::Asc))
+				.include(cloud_crdt_include::include())
+				.exec()
+				.await?;
+
+			ops.sort_by(|a, b| match a.timestamp().cmp(&b.timestamp()) {
+				Ordering::Equal => a.instance.pub_id.cmp(&b.instance.pub_id),
+				o => o,
+			});
+
+			if let Some(ops) = self
+				.decode_ops(
+					OperationLog::Cloud,
+					ops.into_iter()
+						.take(args.count as usize)
+						.map(|o| o.into_operation()),
+				)
+				.await?
+			{
+				return Ok(ops);
+			}
 		}
+	}
 
-		let mut ops = db
+	/// How many operations [`Self::get_cloud_ops`] would return for the clocks, without a limit
+	pub async fn count_cloud_ops(
+		&self,
+		clocks: &[(Uuid, NTP64)],
+	) -> prisma_client_rust::Result<i64> {
+		self.db
 			.cloud_crdt_operation()
-			.find_many(db_args!(args, cloud_crdt_operation))
-			.take(i64::from(args.count))
-			.order_by(cloud_crdt_operation::timestamp::order(SortOrder::Asc))
-			.include(cloud_crdt_include::include())
+			.count(db_args!(clocks, cloud_crdt_operation))
 			.exec()
-			.await?;
+			.await
+	}
 
-		ops.sort_by(|a, b| match a.timestamp().cmp(&b.timestamp()) {
-			Ordering::Equal => a.instance().cmp(&b.instance()),
-			o => o,
-		});
+	/// Decodes the operations read from a log, returning `None` if some of them were malformed, in
+	/// which case they were quarantined and the operations must be read again to fill the gap
+	async fn decode_ops(
+		&self,
+		log: OperationLog,
+		ops: impl IntoIterator<Item = Result<CRDTOperation, MalformedOperation>>,
+	) -> prisma_client_rust::Result<Option<Vec<CRDTOperation>>> {
+		let mut decoded = vec![];
+		let mut malformed = vec![];
+
+		for op in ops {
+			match op {
+				Ok(op) => decoded.push(op),
+				Err(op) => {
+					warn!(
+						"Quarantining malformed sync operation '{}': {}",
+						op.id, op.operation.reason
+					);
+					malformed.push(op);
+				}
+			}
+		}
+
+		if malformed.is_empty() {
+			return Ok(Some(decoded));
+		}
+
+		let count = quarantine::quarantine_malformed(&self.db, log, malformed).await?;
+		if count > 0 {
+			self.tx.send(SyncMessage::Quarantined(count)).ok();
+		}
 
-		Ok(ops
-			.into_iter()
-			.take(args.count as usize)
-			.map(|o| o.into_operation())
-			.collect())
+		Ok(None)
 	}
 }
 
@@ -0,0 +1,121 @@
+//! Operations that can't be ingested, like the ones of an instance whose clock drifted too far or
+//! the ones that can't be decoded, are set aside in the quarantine table along with the reason,
+//! so a single bad operation doesn't stop sync for the whole library. The drifted ones are requested
+//! again by the next ingestions, and released from the quarantine once they could be applied.
+
+use crate::compaction::OperationLog;
+
+use std::collections::HashMap;
+
+use sd_prisma::prisma::{
+	cloud_crdt_operation, crdt_operation, quarantined_crdt_operation, PrismaClient,
+};
+use sd_sync::CRDTOperation;
+
+use prisma_client_rust::QueryError;
+use uuid::Uuid;
+
+/// A stored operation that couldn't be decoded
+pub struct MalformedOperation {
+	/// Of the row in the operations log it was read from
+	pub id: i32,
+	pub operation: quarantined_crdt_operation::CreateUnchecked,
+}
+
+pub fn quarantined_op_db(
+	op: &CRDTOperation,
+	reason: impl Into<String>,
+) -> Result<quarantined_crdt_operation::CreateUnchecked, rmp_serde::encode::Error> {
+	Ok(quarantined_crdt_operation::CreateUnchecked {
+		timestamp: op.timestamp.0 as i64,
+		model: op.model.to_string(),
+		record_id: rmp_serde::to_vec(&op.record_id)?,
+		kind: op.kind().to_string(),
+		data: rmp_serde::to_vec(&op.data)?,
+		instance_pub_id: op.instance.as_bytes().to_vec(),
+		reason: reason.into(),
+		_params: vec![],
+	})
+}
+
+/// Returns how many of the operations weren't already quarantined
+pub async fn quarantine(
+	db: &PrismaClient,
+	ops: Vec<quarantined_crdt_operation::CreateUnchecked>,
+) -> Result<usize, QueryError> {
+	db.quarantined_crdt_operation()
+		.create_many(ops)
+		.skip_duplicates()
+		.exec()
+		.await
+		.map(|count| count as usize)
+}
+
+/// Deletes the quarantined copies of operations that were applied, like the ones set aside for a
+/// clock drift once this instance's clock caught up with them, returning how many there were
+pub async fn release(db: &PrismaClient, ops: &[CRDTOperation]) -> Result<usize, QueryError> {
+	let mut timestamps_by_instance = HashMap::<Uuid, Vec<i64>>::new();
+	for op in ops {
+		timestamps_by_instance
+			.entry(op.instance)
+			.or_default()
+			.push(op.timestamp.as_u64() as i64);
+	}
+
+	let counts = db
+		._batch(
+			timestamps_by_instance
+				.into_iter()
+				.map(|(instance, timestamps)| {
+					db.quarantined_crdt_operation().delete_many(vec![
+						quarantined_crdt_operation::instance_pub_id::equals(
+							instance.as_bytes().to_vec(),
+						),
+						quarantined_crdt_operation::timestamp::in_vec(timestamps),
+					])
+				})
+				.collect::<Vec<_>>(),
+		)
+		.await?;
+
+	Ok(counts.into_iter().sum::<i64>() as usize)
+}
+
+/// Moves the operations out of the log they were read from, so they aren't read again, returning
+/// how many weren't already quarantined
+pub async fn quarantine_malformed(
+	db: &PrismaClient,
+	log: OperationLog,
+	ops: Vec<MalformedOperation>,
+) -> Result<usize, QueryError> {
+	let (ids, ops): (Vec<_>, Vec<_>) = ops
+		.into_iter()
+		.map(|MalformedOperation { id, operation }| (id, operation))
+		.unzip();
+
+	let create = db
+		.quarantined_crdt_operation()
+		.create_many(ops)
+		.skip_duplicates();
+
+	let (count, _) = match log {
+		OperationLog::Local => {
+			db._batch((
+				create,
+				db.crdt_operation()
+					.delete_many(vec![crdt_operation::id::in_vec(ids)]),
+			))
+			.await?
+		}
+		OperationLog::Cloud => {
+			db._batch((
+				create,
+				db.cloud_crdt_operation()
+					.delete_many(vec![cloud_crdt_operation::id::in_vec(ids)]),
+			))
+			.await?
+		}
+	};
+
+	Ok(count as usize)
+}
@@ -3,17 +3,17 @@ mod mock_instance;
 use sd_core_sync::*;
 use sd_prisma::{prisma, prisma_sync};
 use sd_sync::*;
-use sd_utils::uuid_to_bytes;
+use sd_utils::{msgpack, uuid_to_bytes};
 
 use mock_instance::Instance;
 use uuid::Uuid;
 
-async fn write_test_location(instance: &Instance) -> Result<(), Box<dyn std::error::Error>> {
+async fn write_test_location(instance: &Instance) -> Result<Uuid, Box<dyn std::error::Error>> {
+	let id = Uuid::new_v4();
+
 	instance
 		.sync
 		.write_ops(&instance.db, {
-			let id = Uuid::new_v4();
-
 			let (sync_ops, db_ops): (Vec<_>, Vec<_>) = [
 				sync_db_entry!("Location 0".to_string(), prisma::location::name),
 				sync_db_entry!(
@@ -36,7 +36,7 @@ async fn write_test_location(instance: &Instance) -> Result<(), Box<dyn std::err
 		})
 		.await?;
 
-	Ok(())
+	Ok(id)
 }
 
 #[tokio::test]
@@ -95,3 +95,419 @@ async fn operations_send_and_ingest() -> Result<(), Box<dyn std::error::Error>>
 
 	Ok(())
 }
+
+async fn rename_test_location(
+	instance: &Instance,
+	id: Uuid,
+	name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+	instance
+		.sync
+		.write_op(
+			&instance.db,
+			instance.sync.shared_update(
+				prisma_sync::location::SyncId {
+					pub_id: uuid_to_bytes(id),
+				},
+				prisma::location::name::NAME,
+				msgpack!(name),
+			),
+			instance.db.location().update(
+				prisma::location::pub_id::equals(uuid_to_bytes(id)),
+				vec![prisma::location::name::set(Some(name.to_string()))],
+			),
+		)
+		.await?;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn compaction_collapses_superseded_updates() -> Result<(), Box<dyn std::error::Error>> {
+	let instance = Instance::new(Uuid::new_v4()).await;
+
+	let id = write_test_location(&instance).await?;
+	rename_test_location(&instance, id, "Location 1").await?;
+	rename_test_location(&instance, id, "Location 2").await?;
+
+	let before = compaction::stats(&instance.db, compaction::OperationLog::Local).await?;
+	assert_eq!(before.operations, 5);
+
+	let collapsed =
+		compaction::collapse_superseded_updates(&instance.db, compaction::OperationLog::Local)
+			.await?;
+	assert_eq!(collapsed, 2);
+
+	let operations = instance
+		.db
+		.crdt_operation()
+		.find_many(vec![prisma::crdt_operation::kind::equals(format!(
+			"u:{}",
+			prisma::location::name::NAME
+		))])
+		.exec()
+		.await?;
+
+	assert_eq!(operations.len(), 1);
+	assert_eq!(
+		rmp_serde::from_slice::<CRDTOperationData>(&operations[0].data)?,
+		CRDTOperationData::Update {
+			field: prisma::location::name::NAME.to_string(),
+			value: msgpack!("Location 2"),
+		}
+	);
+
+	instance.teardown().await;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn compaction_drops_deleted_records() -> Result<(), Box<dyn std::error::Error>> {
+	let instance = Instance::new(Uuid::new_v4()).await;
+
+	let id = write_test_location(&instance).await?;
+
+	instance
+		.sync
+		.write_op(
+			&instance.db,
+			instance.sync.shared_delete(prisma_sync::location::SyncId {
+				pub_id: uuid_to_bytes(id),
+			}),
+			instance
+				.db
+				.location()
+				.delete(prisma::location::pub_id::equals(uuid_to_bytes(id))),
+		)
+		.await?;
+
+	// The deletion is only dropped once the instance's clock went past it
+	assert_eq!(
+		compaction::drop_deleted_records(&instance.sync, compaction::OperationLog::Local).await?,
+		0
+	);
+
+	write_test_location(&instance).await?;
+
+	assert_eq!(
+		compaction::drop_deleted_records(&instance.sync, compaction::OperationLog::Local).await?,
+		4
+	);
+
+	let after = compaction::stats(&instance.db, compaction::OperationLog::Local).await?;
+	assert_eq!(after.operations, 3);
+
+	instance.teardown().await;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn compaction_waits_for_peers_to_receive_deletions() -> Result<(), Box<dyn std::error::Error>>
+{
+	let instance1 = Instance::new(Uuid::new_v4()).await;
+	let instance2 = Instance::new(Uuid::new_v4()).await;
+
+	Instance::pair(&instance1, &instance2).await;
+
+	let id = write_test_location(&instance1).await?;
+
+	instance1
+		.sync
+		.write_op(
+			&instance1.db,
+			instance1.sync.shared_delete(prisma_sync::location::SyncId {
+				pub_id: uuid_to_bytes(id),
+			}),
+			instance1
+				.db
+				.location()
+				.delete(prisma::location::pub_id::equals(uuid_to_bytes(id))),
+		)
+		.await?;
+
+	write_test_location(&instance1).await?;
+
+	// instance1 has to be past the deletion for instance2 too
+	let mut sync_rx = instance1.sync_rx.resubscribe();
+	write_test_location(&instance2).await?;
+	while !matches!(sync_rx.recv().await?, SyncMessage::Ingested) {}
+
+	let own_ops = instance1
+		.sync
+		.get_ops(GetOpsArgs {
+			clocks: vec![(instance2.id, NTP64(u64::MAX >> 1))],
+			count: 100,
+		})
+		.await?;
+	let deletion = own_ops
+		.iter()
+		.find(|op| matches!(op.data, CRDTOperationData::Delete))
+		.unwrap()
+		.timestamp;
+	let latest = own_ops.last().unwrap().timestamp;
+
+	// instance2 never requested our operations, so it may be missing the deletion
+	assert_eq!(
+		compaction::drop_deleted_records(&instance1.sync, compaction::OperationLog::Local).await?,
+		0
+	);
+
+	instance1
+		.sync
+		.record_peer_clocks(
+			instance2.id,
+			&[(instance1.id, NTP64(deletion.as_u64() - 1))],
+		)
+		.await?;
+
+	assert_eq!(
+		compaction::drop_deleted_records(&instance1.sync, compaction::OperationLog::Local).await?,
+		0
+	);
+
+	instance1
+		.sync
+		.record_peer_clocks(instance2.id, &[(instance1.id, latest)])
+		.await?;
+
+	assert_eq!(
+		compaction::drop_deleted_records(&instance1.sync, compaction::OperationLog::Local).await?,
+		4
+	);
+
+	instance1.teardown().await;
+	instance2.teardown().await;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn albums_and_spaces_send_and_ingest() -> Result<(), Box<dyn std::error::Error>> {
+	let instance1 = Instance::new(Uuid::new_v4()).await;
+	let instance2 = Instance::new(Uuid::new_v4()).await;
+
+	Instance::pair(&instance1, &instance2).await;
+
+	let mut sync_rx = instance2.sync_rx.resubscribe();
+
+	let object = uuid_to_bytes(Uuid::new_v4());
+	let album = uuid_to_bytes(Uuid::new_v4());
+	let space = uuid_to_bytes(Uuid::new_v4());
+
+	instance1
+		.sync
+		.write_ops(
+			&instance1.db,
+			(
+				instance1.sync.shared_create(
+					prisma_sync::object::SyncId {
+						pub_id: object.clone(),
+					},
+					[(prisma::object::kind::NAME, msgpack!(5))],
+				),
+				instance1
+					.db
+					.object()
+					.create(object.clone(), vec![prisma::object::kind::set(Some(5))]),
+			),
+		)
+		.await?;
+
+	let (album_sync, album_db): (Vec<_>, Vec<_>) =
+		[sync_db_entry!("Holidays".to_string(), prisma::album::name)]
+			.into_iter()
+			.unzip();
+
+	let album_id = instance1
+		.sync
+		.write_ops(
+			&instance1.db,
+			(
+				instance1.sync.shared_create(
+					prisma_sync::album::SyncId {
+						pub_id: album.clone(),
+					},
+					album_sync,
+				),
+				instance1.db.album().create(album.clone(), album_db),
+			),
+		)
+		.await?
+		.id;
+
+	let (space_sync, space_db): (Vec<_>, Vec<_>) =
+		[sync_db_entry!("Work".to_string(), prisma::space::name)]
+			.into_iter()
+			.unzip();
+
+	let space_id = instance1
+		.sync
+		.write_ops(
+			&instance1.db,
+			(
+				instance1.sync.shared_create(
+					prisma_sync::space::SyncId {
+						pub_id: space.clone(),
+					},
+					space_sync,
+				),
+				instance1.db.space().create(space.clone(), space_db),
+			),
+		)
+		.await?
+		.id;
+
+	let object_id = instance1
+		.db
+		.object()
+		.find_unique(prisma::object::pub_id::equals(object.clone()))
+		.exec()
+		.await?
+		.unwrap()
+		.id;
+
+	instance1
+		.sync
+		.write_ops(
+			&instance1.db,
+			(
+				instance1.sync.relation_create(
+					prisma_sync::object_in_album::SyncId {
+						album: prisma_sync::album::SyncId {
+							pub_id: album.clone(),
+						},
+						object: prisma_sync::object::SyncId {
+							pub_id: object.clone(),
+						},
+					},
+					[],
+				),
+				instance1
+					.db
+					.object_in_album()
+					.create_unchecked(album_id, object_id, vec![]),
+			),
+		)
+		.await?;
+
+	instance1
+		.sync
+		.write_ops(
+			&instance1.db,
+			(
+				instance1.sync.relation_create(
+					prisma_sync::object_in_space::SyncId {
+						space: prisma_sync::space::SyncId {
+							pub_id: space.clone(),
+						},
+						object: prisma_sync::object::SyncId {
+							pub_id: object.clone(),
+						},
+					},
+					[],
+				),
+				instance1
+					.db
+					.object_in_space()
+					.create_unchecked(space_id, object_id, vec![]),
+			),
+		)
+		.await?;
+
+	// The operations may be ingested over more than one request
+	loop {
+		assert!(matches!(sync_rx.recv().await?, SyncMessage::Ingested));
+
+		let in_album = instance2
+			.db
+			.object_in_album()
+			.count(vec![prisma::object_in_album::album::is(vec![
+				prisma::album::pub_id::equals(album.clone()),
+			])])
+			.exec()
+			.await?;
+		let in_space = instance2
+			.db
+			.object_in_space()
+			.count(vec![prisma::object_in_space::space::is(vec![
+				prisma::space::pub_id::equals(space.clone()),
+			])])
+			.exec()
+			.await?;
+
+		if in_album == 1 && in_space == 1 {
+			break;
+		}
+	}
+
+	let album = instance2
+		.db
+		.album()
+		.find_unique(prisma::album::pub_id::equals(album))
+		.exec()
+		.await?
+		.unwrap();
+	assert_eq!(album.name, Some("Holidays".to_string()));
+
+	let space = instance2
+		.db
+		.space()
+		.find_unique(prisma::space::pub_id::equals(space))
+		.exec()
+		.await?
+		.unwrap();
+	assert_eq!(space.name, Some("Work".to_string()));
+
+	instance1.teardown().await;
+	instance2.teardown().await;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn malformed_operations_are_quarantined() -> Result<(), Box<dyn std::error::Error>> {
+	let instance = Instance::new(Uuid::new_v4()).await;
+
+	write_test_location(&instance).await?;
+
+	// 0xc1 is never used by msgpack
+	instance
+		.db
+		.crdt_operation()
+		.update_many(
+			vec![prisma::crdt_operation::kind::equals("c".to_string())],
+			vec![prisma::crdt_operation::data::set(vec![0xc1])],
+		)
+		.exec()
+		.await?;
+
+	let mut sync_rx = instance.sync_rx.resubscribe();
+
+	let ops = instance
+		.sync
+		.get_ops(GetOpsArgs {
+			clocks: vec![],
+			count: 100,
+		})
+		.await?;
+
+	assert_eq!(ops.len(), 2);
+	assert!(matches!(sync_rx.recv().await?, SyncMessage::Quarantined(1)));
+
+	assert_eq!(instance.db.crdt_operation().count(vec![]).exec().await?, 2);
+	assert_eq!(
+		instance
+			.db
+			.quarantined_crdt_operation()
+			.count(vec![])
+			.exec()
+			.await?,
+		1
+	);
+
+	instance.teardown().await;
+
+	Ok(())
+}
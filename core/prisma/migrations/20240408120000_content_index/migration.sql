@@ -0,0 +1,37 @@
+-- CreateTable
+CREATE TABLE "content_index" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "cas_id" TEXT,
+    "content" TEXT NOT NULL,
+    "date_indexed" DATETIME NOT NULL,
+    "file_path_id" INTEGER NOT NULL,
+    CONSTRAINT "content_index_file_path_id_fkey" FOREIGN KEY ("file_path_id") REFERENCES "file_path" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "content_index_file_path_id_key" ON "content_index"("file_path_id");
+
+-- CreateVirtualTable
+-- External content FTS5 table, kept in sync with "content_index" by the triggers below
+CREATE VIRTUAL TABLE "content_index_fts" USING fts5(
+    "content",
+    content="content_index",
+    content_rowid="id",
+    tokenize="unicode61 remove_diacritics 2"
+);
+
+-- CreateTrigger
+CREATE TRIGGER "content_index_ai" AFTER INSERT ON "content_index" BEGIN
+    INSERT INTO "content_index_fts"("rowid", "content") VALUES (new."id", new."content");
+END;
+
+-- CreateTrigger
+CREATE TRIGGER "content_index_ad" AFTER DELETE ON "content_index" BEGIN
+    INSERT INTO "content_index_fts"("content_index_fts", "rowid", "content") VALUES ('delete', old."id", old."content");
+END;
+
+-- CreateTrigger
+CREATE TRIGGER "content_index_au" AFTER UPDATE ON "content_index" BEGIN
+    INSERT INTO "content_index_fts"("content_index_fts", "rowid", "content") VALUES ('delete', old."id", old."content");
+    INSERT INTO "content_index_fts"("rowid", "content") VALUES (new."id", new."content");
+END;
@@ -0,0 +1,71 @@
+-- AlterTable
+ALTER TABLE "file_path" ADD COLUMN "size_in_bytes_float" REAL;
+
+-- AlterTable
+ALTER TABLE "media_data" ADD COLUMN "width" INTEGER;
+ALTER TABLE "media_data" ADD COLUMN "height" INTEGER;
+ALTER TABLE "media_data" ADD COLUMN "megapixels" REAL;
+ALTER TABLE "media_data" ADD COLUMN "camera_make" TEXT;
+ALTER TABLE "media_data" ADD COLUMN "camera_model" TEXT;
+ALTER TABLE "media_data" ADD COLUMN "latitude" REAL;
+ALTER TABLE "media_data" ADD COLUMN "longitude" REAL;
+
+-- CreateIndex
+CREATE INDEX "file_path_size_in_bytes_float_idx" ON "file_path"("size_in_bytes_float");
+
+-- `size_in_bytes_bytes` is a big endian u64, which SQLite can't convert to a number by itself
+-- so we decode it from its hex representation, one nibble at a time.
+-- The conversion is only written in the update trigger, inserts and the backfill set
+-- `size_in_bytes_bytes` to itself to have it fire.
+-- CreateTrigger
+CREATE TRIGGER "file_path_size_in_bytes_float_au" AFTER UPDATE OF "size_in_bytes_bytes" ON "file_path" BEGIN
+    UPDATE "file_path" SET "size_in_bytes_float" = CASE WHEN length(new."size_in_bytes_bytes") = 8 THEN (
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 1, 1)) - 1) * 1152921504606846976 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 2, 1)) - 1) * 72057594037927936 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 3, 1)) - 1) * 4503599627370496 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 4, 1)) - 1) * 281474976710656 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 5, 1)) - 1) * 17592186044416 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 6, 1)) - 1) * 1099511627776 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 7, 1)) - 1) * 68719476736 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 8, 1)) - 1) * 4294967296 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 9, 1)) - 1) * 268435456 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 10, 1)) - 1) * 16777216 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 11, 1)) - 1) * 1048576 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 12, 1)) - 1) * 65536 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 13, 1)) - 1) * 4096 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 14, 1)) - 1) * 256 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 15, 1)) - 1) * 16 +
+        (instr('0123456789ABCDEF', substr(hex(new."size_in_bytes_bytes"), 16, 1)) - 1) * 1
+    ) END WHERE "id" = new."id";
+END;
+
+-- CreateTrigger
+CREATE TRIGGER "file_path_size_in_bytes_float_ai" AFTER INSERT ON "file_path" BEGIN
+    UPDATE "file_path" SET "size_in_bytes_bytes" = new."size_in_bytes_bytes" WHERE "id" = new."id";
+END;
+
+-- `resolution`, `camera_data` and `media_location` are JSON encoded by `sd_core::object::media`.
+-- Like the sizes, they're only extracted in the update trigger.
+-- CreateTrigger
+CREATE TRIGGER "media_data_filterable_fields_au" AFTER UPDATE OF "resolution", "camera_data", "media_location" ON "media_data" BEGIN
+    UPDATE "media_data" SET
+    "width" = CASE WHEN json_valid(CAST(new.resolution AS TEXT)) THEN json_extract(CAST(new.resolution AS TEXT), '$.width') END,
+    "height" = CASE WHEN json_valid(CAST(new.resolution AS TEXT)) THEN json_extract(CAST(new.resolution AS TEXT), '$.height') END,
+    "megapixels" = (CASE WHEN json_valid(CAST(new.resolution AS TEXT)) THEN json_extract(CAST(new.resolution AS TEXT), '$.width') END) * (CASE WHEN json_valid(CAST(new.resolution AS TEXT)) THEN json_extract(CAST(new.resolution AS TEXT), '$.height') END) / 1000000.0,
+    "camera_make" = CASE WHEN json_valid(CAST(new.camera_data AS TEXT)) THEN json_extract(CAST(new.camera_data AS TEXT), '$.device_make') END,
+    "camera_model" = CASE WHEN json_valid(CAST(new.camera_data AS TEXT)) THEN json_extract(CAST(new.camera_data AS TEXT), '$.device_model') END,
+    "latitude" = CASE WHEN json_valid(CAST(new.media_location AS TEXT)) THEN json_extract(CAST(new.media_location AS TEXT), '$.latitude') END,
+    "longitude" = CASE WHEN json_valid(CAST(new.media_location AS TEXT)) THEN json_extract(CAST(new.media_location AS TEXT), '$.longitude') END
+    WHERE "id" = new."id";
+END;
+
+-- CreateTrigger
+CREATE TRIGGER "media_data_filterable_fields_ai" AFTER INSERT ON "media_data" BEGIN
+    UPDATE "media_data" SET "resolution" = new."resolution" WHERE "id" = new."id";
+END;
+
+-- Backfill
+UPDATE "file_path" SET "size_in_bytes_bytes" = "size_in_bytes_bytes";
+
+-- Backfill
+UPDATE "media_data" SET "resolution" = "resolution";
@@ -0,0 +1,19 @@
+-- CreateTable
+CREATE TABLE "trashed_item" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "pub_id" BLOB NOT NULL,
+    "name" TEXT NOT NULL,
+    "is_dir" BOOLEAN NOT NULL,
+    "original_path" TEXT NOT NULL,
+    "trash_path" TEXT NOT NULL,
+    "location_id" INTEGER,
+    "file_path" BLOB,
+    "date_trashed" DATETIME NOT NULL,
+    CONSTRAINT "trashed_item_location_id_fkey" FOREIGN KEY ("location_id") REFERENCES "location" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "trashed_item_pub_id_key" ON "trashed_item"("pub_id");
+
+-- CreateIndex
+CREATE INDEX "trashed_item_location_id_idx" ON "trashed_item"("location_id");
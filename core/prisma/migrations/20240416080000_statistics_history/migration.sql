@@ -0,0 +1,13 @@
+-- CreateTable
+CREATE TABLE "statistics_history" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "date_captured" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "total_object_count" INTEGER NOT NULL DEFAULT 0,
+    "library_db_size" TEXT NOT NULL DEFAULT '0',
+    "total_bytes_used" TEXT NOT NULL DEFAULT '0',
+    "total_unique_bytes" TEXT NOT NULL DEFAULT '0',
+    "preview_media_bytes" TEXT NOT NULL DEFAULT '0'
+);
+
+-- CreateIndex
+CREATE INDEX "statistics_history_date_captured_idx" ON "statistics_history"("date_captured");
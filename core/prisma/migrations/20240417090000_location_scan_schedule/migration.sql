@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "location" ADD COLUMN "scan_schedule" BLOB;
+ALTER TABLE "location" ADD COLUMN "date_last_scheduled_scan" DATETIME;
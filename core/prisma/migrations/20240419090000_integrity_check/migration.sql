@@ -0,0 +1,19 @@
+-- AlterTable
+ALTER TABLE "location" ADD COLUMN "date_last_integrity_check" DATETIME;
+
+-- CreateTable
+CREATE TABLE "integrity_check" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "date_modified" DATETIME NOT NULL,
+    "date_checked" DATETIME NOT NULL,
+    "checksum" TEXT NOT NULL,
+    "date_corrupted" DATETIME,
+    "file_path_id" INTEGER NOT NULL,
+    CONSTRAINT "integrity_check_file_path_id_fkey" FOREIGN KEY ("file_path_id") REFERENCES "file_path" ("id") ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "integrity_check_file_path_id_key" ON "integrity_check"("file_path_id");
+
+-- CreateIndex
+CREATE INDEX "integrity_check_date_corrupted_idx" ON "integrity_check"("date_corrupted");
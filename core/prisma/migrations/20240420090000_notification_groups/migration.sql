@@ -0,0 +1,6 @@
+-- AlterTable
+ALTER TABLE "notification" ADD COLUMN "group_key" TEXT;
+ALTER TABLE "notification" ADD COLUMN "repeats" INTEGER NOT NULL DEFAULT 0;
+
+-- CreateIndex
+CREATE INDEX "notification_group_key_idx" ON "notification"("group_key");
@@ -0,0 +1,5 @@
+-- CreateIndex
+CREATE INDEX "crdt_operation_model_record_id_kind_timestamp_idx" ON "crdt_operation"("model", "record_id", "kind", "timestamp");
+
+-- CreateIndex
+CREATE INDEX "cloud_crdt_operation_model_record_id_kind_timestamp_idx" ON "cloud_crdt_operation"("model", "record_id", "kind", "timestamp");
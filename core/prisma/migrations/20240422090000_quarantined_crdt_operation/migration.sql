@@ -0,0 +1,15 @@
+-- CreateTable
+CREATE TABLE "quarantined_crdt_operation" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "timestamp" BIGINT NOT NULL,
+    "model" TEXT NOT NULL,
+    "record_id" BLOB NOT NULL,
+    "kind" TEXT NOT NULL,
+    "data" BLOB NOT NULL,
+    "instance_pub_id" BLOB NOT NULL,
+    "reason" TEXT NOT NULL,
+    "date_quarantined" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "quarantined_crdt_operation_instance_pub_id_timestamp_key" ON "quarantined_crdt_operation"("instance_pub_id", "timestamp");
@@ -0,0 +1,8 @@
+-- CreateTable
+CREATE TABLE "sync_peer_clock" (
+    "peer_pub_id" BLOB NOT NULL,
+    "instance_pub_id" BLOB NOT NULL,
+    "timestamp" BIGINT NOT NULL,
+
+    PRIMARY KEY ("peer_pub_id", "instance_pub_id")
+);
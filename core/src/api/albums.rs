@@ -0,0 +1,368 @@
+use crate::{invalidate_query, library::Library};
+
+use sd_cache::{Normalise, NormalisedResult, NormalisedResults};
+use sd_prisma::{
+	prisma::{album, object, object_in_album},
+	prisma_sync,
+};
+use sd_sync::OperationFactory;
+use sd_utils::{msgpack, uuid_to_bytes};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use rspc::{alpha::AlphaRouter, ErrorCode};
+use serde::Deserialize;
+use specta::Type;
+use uuid::Uuid;
+
+use super::{utils::library, Ctx, R};
+
+#[derive(Type, Deserialize)]
+#[specta(inline)]
+struct AlbumObjectsArgs {
+	album_id: album::id::Type,
+	object_ids: Vec<object::id::Type>,
+}
+
+async fn find_album_pub_id(
+	library: &Library,
+	album_id: album::id::Type,
+) -> Result<album::pub_id::Type, rspc::Error> {
+	Ok(library
+		.db
+		.album()
+		.find_unique(album::id::equals(album_id))
+		.select(album::select!({ pub_id }))
+		.exec()
+		.await?
+		.ok_or_else(|| rspc::Error::new(ErrorCode::NotFound, "Album not found".to_string()))?
+		.pub_id)
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				let albums = library.db.album().find_many(vec![]).exec().await?;
+
+				let (nodes, items) = albums.normalise(|i| i.id.to_string());
+
+				Ok(NormalisedResults { nodes, items })
+			})
+		})
+		.procedure("getForObject", {
+			R.with2(library())
+				.query(|(_, library), object_id: object::id::Type| async move {
+					let albums = library
+						.db
+						.album()
+						.find_many(vec![album::objects::some(vec![
+							object_in_album::object_id::equals(object_id),
+						])])
+						.exec()
+						.await?;
+
+					let (nodes, items) = albums.normalise(|i| i.id.to_string());
+
+					Ok(NormalisedResults { nodes, items })
+				})
+		})
+		.procedure("get", {
+			R.with2(library())
+				.query(|(_, library), album_id: album::id::Type| async move {
+					Ok(library
+						.db
+						.album()
+						.find_unique(album::id::equals(album_id))
+						.exec()
+						.await?
+						.map(|album| NormalisedResult::from(album, |i| i.id.to_string())))
+				})
+		})
+		.procedure("create", {
+			#[derive(Type, Deserialize)]
+			#[specta(inline)]
+			struct AlbumCreateArgs {
+				name: String,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: AlbumCreateArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let pub_id = uuid_to_bytes(Uuid::new_v4());
+					let date_created: DateTime<FixedOffset> = Utc::now().into();
+
+					let album = sync
+						.write_ops(
+							db,
+							(
+								sync.shared_create(
+									prisma_sync::album::SyncId {
+										pub_id: pub_id.clone(),
+									},
+									[
+										(album::name::NAME, msgpack!(&args.name)),
+										(album::is_hidden::NAME, msgpack!(false)),
+										(album::date_created::NAME, msgpack!(&date_created)),
+									],
+								),
+								db.album().create(
+									pub_id,
+									vec![
+										album::name::set(Some(args.name)),
+										album::is_hidden::set(Some(false)),
+										album::date_created::set(Some(date_created)),
+									],
+								),
+							),
+						)
+						.await?;
+
+					invalidate_query!(library, "albums.list");
+
+					Ok(NormalisedResult::from(album, |i| i.id.to_string()))
+				})
+		})
+		.procedure("update", {
+			#[derive(Type, Deserialize)]
+			#[specta(inline)]
+			struct AlbumUpdateArgs {
+				id: album::id::Type,
+				name: Option<String>,
+				is_hidden: Option<bool>,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: AlbumUpdateArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let pub_id = find_album_pub_id(&library, args.id).await?;
+					let date_modified: DateTime<FixedOffset> = Utc::now().into();
+
+					let (sync_params, db_params): (Vec<_>, Vec<_>) = [
+						args.name.map(|v| {
+							((album::name::NAME, msgpack!(&v)), album::name::set(Some(v)))
+						}),
+						args.is_hidden.map(|v| {
+							(
+								(album::is_hidden::NAME, msgpack!(v)),
+								album::is_hidden::set(Some(v)),
+							)
+						}),
+						Some((
+							(album::date_modified::NAME, msgpack!(&date_modified)),
+							album::date_modified::set(Some(date_modified)),
+						)),
+					]
+					.into_iter()
+					.flatten()
+					.unzip();
+
+					sync.write_ops(
+						db,
+						(
+							sync_params
+								.into_iter()
+								.map(|(k, v)| {
+									sync.shared_update(
+										prisma_sync::album::SyncId {
+											pub_id: pub_id.clone(),
+										},
+										k,
+										v,
+									)
+								})
+								.collect(),
+							db.album().update(album::id::equals(args.id), db_params),
+						),
+					)
+					.await?;
+
+					invalidate_query!(library, "albums.list");
+					invalidate_query!(library, "albums.get");
+					invalidate_query!(library, "albums.getForObject");
+
+					Ok(())
+				})
+		})
+		.procedure("delete", {
+			R.with2(library())
+				.mutation(|(_, library), album_id: album::id::Type| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let album = db
+						.album()
+						.find_unique(album::id::equals(album_id))
+						.select(album::select!({
+							pub_id
+							objects: select { object: select { pub_id } }
+						}))
+						.exec()
+						.await?
+						.ok_or_else(|| {
+							rspc::Error::new(ErrorCode::NotFound, "Album not found".to_string())
+						})?;
+
+					let relation_deletes = album
+						.objects
+						.into_iter()
+						.map(|o| {
+							sync.relation_delete(prisma_sync::object_in_album::SyncId {
+								album: prisma_sync::album::SyncId {
+									pub_id: album.pub_id.clone(),
+								},
+								object: prisma_sync::object::SyncId {
+									pub_id: o.object.pub_id,
+								},
+							})
+						})
+						.collect();
+
+					// Its objects are only unlinked if the album is deleted along with them
+					db._transaction()
+						.run(|tx| async move {
+							sync.write_ops(
+								&tx,
+								(
+									relation_deletes,
+									tx.object_in_album().delete_many(vec![
+										object_in_album::album_id::equals(album_id),
+									]),
+								),
+							)
+							.await?;
+
+							sync.write_op(
+								&tx,
+								sync.shared_delete(prisma_sync::album::SyncId {
+									pub_id: album.pub_id,
+								}),
+								tx.album().delete(album::id::equals(album_id)),
+							)
+							.await
+						})
+						.await?;
+
+					invalidate_query!(library, "albums.list");
+					invalidate_query!(library, "albums.get");
+					invalidate_query!(library, "albums.getForObject");
+					invalidate_query!(library, "search.objects");
+
+					Ok(())
+				})
+		})
+		.procedure("addObjects", {
+			R.with2(library())
+				.mutation(|(_, library), args: AlbumObjectsArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let album_pub_id = find_album_pub_id(&library, args.album_id).await?;
+
+					let objects = db
+						.object()
+						.find_many(vec![object::id::in_vec(args.object_ids)])
+						.select(object::select!({ id pub_id }))
+						.exec()
+						.await?;
+
+					let date_created: DateTime<FixedOffset> = Utc::now().into();
+
+					let (sync_ops, db_creates) = objects.into_iter().fold(
+						(vec![], vec![]),
+						|(mut sync_ops, mut db_creates), object| {
+							sync_ops.extend(sync.relation_create(
+								prisma_sync::object_in_album::SyncId {
+									album: prisma_sync::album::SyncId {
+										pub_id: album_pub_id.clone(),
+									},
+									object: prisma_sync::object::SyncId {
+										pub_id: object.pub_id,
+									},
+								},
+								[(object_in_album::date_created::NAME, msgpack!(&date_created))],
+							));
+
+							db_creates.push(object_in_album::CreateUnchecked {
+								album_id: args.album_id,
+								object_id: object.id,
+								_params: vec![object_in_album::date_created::set(Some(
+									date_created,
+								))],
+							});
+
+							(sync_ops, db_creates)
+						},
+					);
+
+					sync.write_ops(
+						db,
+						(
+							sync_ops,
+							db.object_in_album()
+								.create_many(db_creates)
+								.skip_duplicates(),
+						),
+					)
+					.await?;
+
+					invalidate_query!(library, "albums.getForObject");
+					invalidate_query!(library, "search.objects");
+
+					Ok(())
+				})
+		})
+		.procedure("removeObjects", {
+			R.with2(library())
+				.mutation(|(_, library), args: AlbumObjectsArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let album_pub_id = find_album_pub_id(&library, args.album_id).await?;
+
+					let objects = db
+						.object()
+						.find_many(vec![
+							object::id::in_vec(args.object_ids),
+							object::albums::some(vec![object_in_album::album_id::equals(
+								args.album_id,
+							)]),
+						])
+						.select(object::select!({ id pub_id }))
+						.exec()
+						.await?;
+
+					let (sync_ops, object_ids): (Vec<_>, Vec<_>) = objects
+						.into_iter()
+						.map(|object| {
+							(
+								sync.relation_delete(prisma_sync::object_in_album::SyncId {
+									album: prisma_sync::album::SyncId {
+										pub_id: album_pub_id.clone(),
+									},
+									object: prisma_sync::object::SyncId {
+										pub_id: object.pub_id,
+									},
+								}),
+								object.id,
+							)
+						})
+						.unzip();
+
+					sync.write_ops(
+						db,
+						(
+							sync_ops,
+							db.object_in_album().delete_many(vec![
+								object_in_album::album_id::equals(args.album_id),
+								object_in_album::object_id::in_vec(object_ids),
+							]),
+						),
+					)
+					.await?;
+
+					invalidate_query!(library, "albums.getForObject");
+					invalidate_query!(library, "search.objects");
+
+					Ok(())
+				})
+		})
+}
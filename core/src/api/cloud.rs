@@ -148,11 +148,11 @@ mod library {
 							library.id,
 							&library.db,
 							&library.sync,
-							&node.libraries,
+							&node,
 							instance.uuid,
 							instance.identity,
 							instance.node_id,
-							node.p2p.peer_metadata(),
+							instance.metadata,
 						)
 						.await?;
 					}
@@ -0,0 +1,70 @@
+use crate::{
+	invalidate_query,
+	location::{find_location, LocationError},
+	object::duplicates::{
+		get_duplicate_groups, old_duplicate_finder_job::OldDuplicateFinderJobInit,
+		resolve_duplicates, DuplicateAction, KeepStrategy,
+	},
+	old_job::Job,
+};
+
+use sd_prisma::prisma::location;
+
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+
+use super::{utils::library, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("find", {
+			R.with2(library())
+				.mutation(|(node, library), id: location::id::Type| async move {
+					let Some(location) = find_location(&library, id).exec().await? else {
+						return Err(LocationError::IdNotFound(id).into());
+					};
+
+					Job::new(OldDuplicateFinderJobInit { location })
+						.spawn(&node, &library)
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("list", {
+			R.with2(library()).query(
+				|(_, library), location_id: Option<location::id::Type>| async move {
+					get_duplicate_groups(&library.db, location_id)
+						.await
+						.map_err(Into::into)
+				},
+			)
+		})
+		.procedure("resolve", {
+			#[derive(Type, Deserialize)]
+			#[serde(rename_all = "camelCase")]
+			pub struct ResolveDuplicatesArgs {
+				pub integrity_checksum: String,
+				pub keep: KeepStrategy,
+				pub action: DuplicateAction,
+			}
+
+			R.with2(library()).mutation(
+				|(node, library),
+				 ResolveDuplicatesArgs {
+				     integrity_checksum,
+				     keep,
+				     action,
+				 }: ResolveDuplicatesArgs| async move {
+					let resolution =
+						resolve_duplicates(&node, &library, integrity_checksum, keep, action)
+							.await?;
+
+					invalidate_query!(library, "duplicates.list");
+					invalidate_query!(library, "search.paths");
+
+					Ok(resolution)
+				},
+			)
+		})
+}
@@ -3,7 +3,10 @@ use crate::{
 	invalidate_query,
 	library::Library,
 	object::{
-		fs::{error::FileSystemJobsError, find_available_filename_for_duplicate},
+		fs::{
+			error::FileSystemJobsError, find_available_filename_for_duplicate,
+			trash::trash_ephemeral_path,
+		},
 		media::media_data_extractor::{
 			can_extract_media_data_for_image, extract_media_data, MediaDataError,
 		},
@@ -110,6 +113,19 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					Ok(())
 				})
 		})
+		.procedure("trashFiles", {
+			R.with2(library())
+				.mutation(|(_, library), paths: Vec<PathBuf>| async move {
+					for path in paths {
+						trash_ephemeral_path(&library.db, path).await?;
+					}
+
+					invalidate_query!(library, "search.ephemeralPaths");
+					invalidate_query!(library, "trash.list");
+
+					Ok(())
+				})
+		})
 		.procedure("copyFiles", {
 			R.with2(library())
 				.mutation(|(_, library), args: EphemeralFileSystemOps| async move {
@@ -22,13 +22,14 @@ use sd_file_path_helper::{
 use sd_images::ConvertibleExtension;
 use sd_media_metadata::MediaMetadata;
 use sd_prisma::{
-	prisma::{file_path, location, object},
+	prisma::{file_path, label, label_on_object, location, object, tag, tag_on_object},
 	prisma_sync,
 };
 use sd_sync::OperationFactory;
 use sd_utils::{db::maybe_missing, error::FileIOError, msgpack};
 
 use std::{
+	collections::HashMap,
 	ffi::OsString,
 	path::{Path, PathBuf},
 	sync::Arc,
@@ -264,6 +265,314 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					Ok(())
 				})
 		})
+		// Applies the same changes to many objects at once, in a single transaction
+		.procedure("updateObjectsMetadata", {
+			#[derive(Type, Deserialize)]
+			pub struct UpdateObjectsMetadataArgs {
+				pub ids: Vec<object::id::Type>,
+				/// An empty note removes the current one
+				#[serde(default)]
+				pub note: Option<String>,
+				#[serde(default)]
+				pub favorite: Option<bool>,
+				#[serde(default)]
+				pub hidden: Option<bool>,
+				#[serde(default)]
+				pub important: Option<bool>,
+				#[serde(default)]
+				pub add_tags: Vec<tag::id::Type>,
+				#[serde(default)]
+				pub remove_tags: Vec<tag::id::Type>,
+				#[serde(default)]
+				pub add_labels: Vec<label::id::Type>,
+				#[serde(default)]
+				pub remove_labels: Vec<label::id::Type>,
+			}
+
+			R.with2(library()).mutation(
+				|(_, library), args: UpdateObjectsMetadataArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					if let Some(id) = added_and_removed(&args.add_tags, &args.remove_tags) {
+						return Err(rspc::Error::new(
+							ErrorCode::BadRequest,
+							format!("Tag <id='{id}'> can't be both added and removed"),
+						));
+					}
+					if let Some(id) = added_and_removed(&args.add_labels, &args.remove_labels) {
+						return Err(rspc::Error::new(
+							ErrorCode::BadRequest,
+							format!("Label <id='{id}'> can't be both added and removed"),
+						));
+					}
+
+					let (objects, tags, labels) = db
+						._batch((
+							db.object()
+								.find_many(vec![object::id::in_vec(args.ids.clone())])
+								.select(object::select!({ id pub_id })),
+							db.tag()
+								.find_many(vec![tag::id::in_vec(
+									args.add_tags
+										.iter()
+										.chain(&args.remove_tags)
+										.copied()
+										.collect(),
+								)])
+								.select(tag::select!({ id pub_id })),
+							db.label()
+								.find_many(vec![label::id::in_vec(
+									args.add_labels
+										.iter()
+										.chain(&args.remove_labels)
+										.copied()
+										.collect(),
+								)])
+								.select(label::select!({ id name })),
+						))
+						.await?;
+
+					let objects = objects
+						.into_iter()
+						.map(|o| (o.id, o.pub_id))
+						.collect::<HashMap<_, _>>();
+					let tags = tags
+						.into_iter()
+						.map(|t| (t.id, t.pub_id))
+						.collect::<HashMap<_, _>>();
+					let labels = labels
+						.into_iter()
+						.map(|l| (l.id, l.name))
+						.collect::<HashMap<_, _>>();
+
+					if let Some(id) = args.ids.iter().find(|id| !objects.contains_key(*id)) {
+						return Err(rspc::Error::new(
+							ErrorCode::NotFound,
+							format!("Object <id='{id}'> not found"),
+						));
+					}
+					if let Some(id) = args
+						.add_tags
+						.iter()
+						.chain(&args.remove_tags)
+						.find(|id| !tags.contains_key(*id))
+					{
+						return Err(rspc::Error::new(
+							ErrorCode::NotFound,
+							format!("Tag <id='{id}'> not found"),
+						));
+					}
+					if let Some(id) = args
+						.add_labels
+						.iter()
+						.chain(&args.remove_labels)
+						.find(|id| !labels.contains_key(*id))
+					{
+						return Err(rspc::Error::new(
+							ErrorCode::NotFound,
+							format!("Label <id='{id}'> not found"),
+						));
+					}
+
+					let note = args.note.map(|note| (!note.is_empty()).then_some(note));
+
+					let mut sync_ops = vec![];
+					let mut object_params = vec![];
+
+					for (field, value, param) in [
+						note.as_ref().map(|note| {
+							(
+								object::note::NAME,
+								msgpack!(note),
+								object::note::set(note.clone()),
+							)
+						}),
+						args.favorite.map(|favorite| {
+							(
+								object::favorite::NAME,
+								msgpack!(favorite),
+								object::favorite::set(Some(favorite)),
+							)
+						}),
+						args.hidden.map(|hidden| {
+							(
+								object::hidden::NAME,
+								msgpack!(hidden),
+								object::hidden::set(Some(hidden)),
+							)
+						}),
+						args.important.map(|important| {
+							(
+								object::important::NAME,
+								msgpack!(important),
+								object::important::set(Some(important)),
+							)
+						}),
+					]
+					.into_iter()
+					.flatten()
+					{
+						sync_ops.extend(objects.values().map(|pub_id| {
+							sync.shared_update(
+								prisma_sync::object::SyncId {
+									pub_id: pub_id.clone(),
+								},
+								field,
+								value.clone(),
+							)
+						}));
+						object_params.push(param);
+					}
+
+					let date_created: DateTime<FixedOffset> = Utc::now().into();
+
+					let mut tag_creates = vec![];
+					for tag_id in &args.add_tags {
+						for (object_id, pub_id) in &objects {
+							sync_ops.extend(sync.relation_create(
+								prisma_sync::tag_on_object::SyncId {
+									tag: prisma_sync::tag::SyncId {
+										pub_id: tags[tag_id].clone(),
+									},
+									object: prisma_sync::object::SyncId {
+										pub_id: pub_id.clone(),
+									},
+								},
+								[],
+							));
+							tag_creates.push(tag_on_object::CreateUnchecked {
+								tag_id: *tag_id,
+								object_id: *object_id,
+								_params: vec![tag_on_object::date_created::set(Some(date_created))],
+							});
+						}
+					}
+
+					for tag_id in &args.remove_tags {
+						sync_ops.extend(objects.values().map(|pub_id| {
+							sync.relation_delete(prisma_sync::tag_on_object::SyncId {
+								tag: prisma_sync::tag::SyncId {
+									pub_id: tags[tag_id].clone(),
+								},
+								object: prisma_sync::object::SyncId {
+									pub_id: pub_id.clone(),
+								},
+							})
+						}));
+					}
+
+					let mut label_creates = vec![];
+					for label_id in &args.add_labels {
+						for (object_id, pub_id) in &objects {
+							sync_ops.extend(sync.relation_create(
+								prisma_sync::label_on_object::SyncId {
+									label: prisma_sync::label::SyncId {
+										name: labels[label_id].clone(),
+									},
+									object: prisma_sync::object::SyncId {
+										pub_id: pub_id.clone(),
+									},
+								},
+								[],
+							));
+							label_creates.push(label_on_object::create_unchecked(
+								*label_id,
+								*object_id,
+								vec![label_on_object::date_created::set(date_created)],
+							));
+						}
+					}
+
+					for label_id in &args.remove_labels {
+						sync_ops.extend(objects.values().map(|pub_id| {
+							sync.relation_delete(prisma_sync::label_on_object::SyncId {
+								label: prisma_sync::label::SyncId {
+									name: labels[label_id].clone(),
+								},
+								object: prisma_sync::object::SyncId {
+									pub_id: pub_id.clone(),
+								},
+							})
+						}));
+					}
+
+					if sync_ops.is_empty() {
+						return Ok(());
+					}
+
+					let object_ids = objects.keys().copied().collect::<Vec<_>>();
+
+					// Empty batches are skipped so each kind of change is only queried if requested
+					sync.write_ops(
+						db,
+						(
+							sync_ops,
+							(
+								(!object_params.is_empty())
+									.then(|| {
+										db.object().update_many(
+											vec![object::id::in_vec(object_ids.clone())],
+											object_params,
+										)
+									})
+									.into_iter()
+									.collect::<Vec<_>>(),
+								(!tag_creates.is_empty())
+									.then(|| {
+										db.tag_on_object()
+											.create_many(tag_creates)
+											.skip_duplicates()
+									})
+									.into_iter()
+									.collect::<Vec<_>>(),
+								(!args.remove_tags.is_empty())
+									.then(|| {
+										db.tag_on_object().delete_many(vec![
+											tag_on_object::tag_id::in_vec(args.remove_tags.clone()),
+											tag_on_object::object_id::in_vec(object_ids.clone()),
+										])
+									})
+									.into_iter()
+									.collect::<Vec<_>>(),
+								(!label_creates.is_empty())
+									.then(|| {
+										db.label_on_object()
+											.create_many(label_creates)
+											.skip_duplicates()
+									})
+									.into_iter()
+									.collect::<Vec<_>>(),
+								(!args.remove_labels.is_empty())
+									.then(|| {
+										db.label_on_object().delete_many(vec![
+											label_on_object::label_id::in_vec(
+												args.remove_labels.clone(),
+											),
+											label_on_object::object_id::in_vec(object_ids.clone()),
+										])
+									})
+									.into_iter()
+									.collect::<Vec<_>>(),
+							),
+						),
+					)
+					.await?;
+
+					invalidate_query!(library, "search.paths");
+					invalidate_query!(library, "search.objects");
+					if !args.add_tags.is_empty() || !args.remove_tags.is_empty() {
+						invalidate_query!(library, "tags.getForObject");
+						invalidate_query!(library, "tags.getWithObjects");
+					}
+					if !args.add_labels.is_empty() || !args.remove_labels.is_empty() {
+						invalidate_query!(library, "labels.getForObject");
+						invalidate_query!(library, "labels.getWithObjects");
+					}
+
+					Ok(())
+				},
+			)
+		})
 		.procedure("createFolder", {
 			#[derive(Type, Deserialize)]
 			pub struct CreateFolderArgs {
@@ -396,9 +705,10 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 		.procedure("deleteFiles", {
 			R.with2(library())
 				.mutation(|(node, library), args: OldFileDeleterJobInit| async move {
-					match args.file_path_ids.len() {
-						0 => Ok(()),
-						1 => {
+					match (args.file_path_ids.len(), args.trash) {
+						(0, _) => Ok(()),
+						// Trashing a file has to record its `file_path`, so it's always done by the job
+						(1, false) => {
 							let (maybe_location, maybe_file_path) = library
 								.db
 								._batch((
@@ -877,3 +1187,21 @@ pub struct FromPattern {
 	pub pattern: String,
 	pub replace_all: bool,
 }
+
+/// The first id both added and removed by an update, which can't tell what the result should be
+fn added_and_removed<'a, T: PartialEq>(add: &'a [T], remove: &[T]) -> Option<&'a T> {
+	add.iter().find(|id| remove.contains(id))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ids_added_and_removed_are_found() {
+		assert_eq!(added_and_removed(&[1, 2, 3], &[4, 3, 2]), Some(&2));
+		assert_eq!(added_and_removed(&[1, 2], &[3, 4]), None);
+		assert_eq!(added_and_removed::<i32>(&[], &[1]), None);
+		assert_eq!(added_and_removed::<i32>(&[1], &[]), None);
+	}
+}
@@ -0,0 +1,68 @@
+use crate::{
+	invalidate_query,
+	location::{find_location, LocationError},
+	object::validation::{
+		accept_file_checksum, get_corrupted_files,
+		old_integrity_verifier_job::OldIntegrityVerifierJobInit,
+	},
+	old_job::Job,
+};
+
+use sd_prisma::prisma::{file_path, location};
+
+use std::path::PathBuf;
+
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+
+use super::{utils::library, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("verify", {
+			#[derive(Type, Deserialize)]
+			#[serde(rename_all = "camelCase")]
+			pub struct VerifyIntegrityArgs {
+				pub location_id: location::id::Type,
+				pub sub_path: Option<PathBuf>,
+			}
+
+			R.with2(library()).mutation(
+				|(node, library),
+				 VerifyIntegrityArgs {
+				     location_id,
+				     sub_path,
+				 }: VerifyIntegrityArgs| async move {
+					let Some(location) = find_location(&library, location_id).exec().await? else {
+						return Err(LocationError::IdNotFound(location_id).into());
+					};
+
+					Job::new(OldIntegrityVerifierJobInit { location, sub_path })
+						.spawn(&node, &library)
+						.await
+						.map_err(Into::into)
+				},
+			)
+		})
+		.procedure("report", {
+			R.with2(library()).query(
+				|(_, library), location_id: Option<location::id::Type>| async move {
+					get_corrupted_files(&library.db, location_id)
+						.await
+						.map_err(Into::into)
+				},
+			)
+		})
+		.procedure("accept", {
+			R.with2(library()).mutation(
+				|(_, library), file_path_id: file_path::id::Type| async move {
+					accept_file_checksum(&library, file_path_id).await?;
+
+					invalidate_query!(library, "integrity.report");
+
+					Ok(())
+				},
+			)
+		})
+}
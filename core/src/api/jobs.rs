@@ -2,6 +2,7 @@ use crate::{
 	invalidate_query,
 	location::{find_location, LocationError},
 	object::{
+		content::old_content_indexer_job::OldContentIndexerJobInit,
 		media::OldMediaProcessorJobInit,
 		old_file_identifier::old_file_identifier_job::OldFileIdentifierJobInit,
 		validation::old_validator_job::OldObjectValidatorJobInit,
@@ -314,6 +315,37 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					.map_err(Into::into)
 				})
 		})
+		.procedure("indexContentForLocation", {
+			#[derive(Type, Deserialize)]
+			pub struct IndexContentForLocationArgs {
+				pub id: location::id::Type,
+				pub path: PathBuf,
+				#[serde(default)]
+				pub regenerate: bool,
+			}
+
+			R.with2(library()).mutation(
+				|(node, library),
+				 IndexContentForLocationArgs {
+				     id,
+				     path,
+				     regenerate,
+				 }: IndexContentForLocationArgs| async move {
+					let Some(location) = find_location(&library, id).exec().await? else {
+						return Err(LocationError::IdNotFound(id).into());
+					};
+
+					Job::new(OldContentIndexerJobInit {
+						location,
+						sub_path: Some(path),
+						regenerate,
+					})
+					.spawn(&node, &library)
+					.await
+					.map_err(Into::into)
+				},
+			)
+		})
 		.procedure("identifyUniqueFiles", {
 			#[derive(Type, Deserialize)]
 			pub struct IdentifyUniqueFilesArgs {
@@ -1,6 +1,9 @@
 use crate::{
 	invalidate_query,
-	library::{update_library_statistics, Library, LibraryConfig, LibraryName},
+	library::{
+		get_kind_statistics, get_location_statistics, update_library_statistics, Library,
+		LibraryConfig, LibraryName,
+	},
 	location::{scan_location, LocationCreateArgs},
 	util::MaybeUndefined,
 	Node,
@@ -10,7 +13,7 @@ use futures::StreamExt;
 use sd_cache::{Model, Normalise, NormalisedResult, NormalisedResults};
 use sd_file_ext::kind::ObjectKind;
 use sd_p2p::RemoteIdentity;
-use sd_prisma::prisma::{indexer_rule, object, statistics};
+use sd_prisma::prisma::{indexer_rule, statistics, statistics_history, SortOrder};
 use tokio_stream::wrappers::IntervalStream;
 
 use std::{
@@ -22,6 +25,7 @@ use std::{
 };
 
 use async_channel as chan;
+use chrono::{DateTime, Utc};
 use directories::UserDirs;
 use futures_concurrency::{future::Join, stream::Merge};
 use once_cell::sync::Lazy;
@@ -142,26 +146,54 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 				statistics: Vec<KindStatistic>,
 			}
 			R.with2(library()).query(|(_, library), _: ()| async move {
-				let mut statistics: Vec<KindStatistic> = vec![];
-				for kind in ObjectKind::iter() {
-					let count = library
-						.db
-						.object()
-						.count(vec![object::kind::equals(Some(kind as i32))])
-						.exec()
-						.await?;
-
-					statistics.push(KindStatistic {
-						kind: kind as i32,
-						name: kind.to_string(),
-						count: count as i32,
-						total_bytes: "0".to_string(),
-					});
-				}
+				let mut by_kind = get_kind_statistics(&library.db)
+					.await?
+					.into_iter()
+					.map(|statistics| (statistics.kind, statistics))
+					.collect::<HashMap<_, _>>();
+
+				let statistics = ObjectKind::iter()
+					.map(|kind| {
+						let (count, total_bytes) = by_kind
+							.remove(&(kind as i32))
+							.map(|statistics| (statistics.count, statistics.total_bytes))
+							.unwrap_or_else(|| (0, "0".to_string()));
+
+						KindStatistic {
+							kind: kind as i32,
+							name: kind.to_string(),
+							count,
+							total_bytes,
+						}
+					})
+					.collect();
 
 				Ok(KindStatistics { statistics })
 			})
 		})
+		.procedure("locationStatistics", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(get_location_statistics(&library.db).await?)
+			})
+		})
+		.procedure("statisticsHistory", {
+			R.with2(library())
+				.query(|(_, library), since: Option<DateTime<Utc>>| async move {
+					Ok(library
+						.db
+						.statistics_history()
+						.find_many(
+							since
+								.map(|since| {
+									vec![statistics_history::date_captured::gte(since.into())]
+								})
+								.unwrap_or_default(),
+						)
+						.order_by(statistics_history::date_captured::order(SortOrder::Asc))
+						.exec()
+						.await?)
+				})
+		})
 		.procedure("create", {
 			#[derive(Deserialize, Type, Default)]
 			pub struct DefaultLocations {
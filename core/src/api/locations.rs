@@ -5,8 +5,9 @@ use crate::{
 		indexer::{rules::IndexerRuleCreateArgs, OldIndexerJobInit},
 		light_scan_location, location_with_indexer_rules,
 		non_indexed::NonIndexedPathItem,
-		relink_location, scan_location, scan_location_sub_path, LocationCreateArgs, LocationError,
-		LocationUpdateArgs,
+		relink_location, scan_location, scan_location_sub_path,
+		schedule::{get_scan_schedule, set_scan_schedule, ScanSchedule},
+		LocationCreateArgs, LocationError, LocationUpdateArgs,
 	},
 	object::old_file_identifier::old_file_identifier_job::OldFileIdentifierJobInit,
 	old_job::StatefulJob,
@@ -330,6 +331,33 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					ret
 				})
 		})
+		.procedure("getScanSchedule", {
+			R.with2(library())
+				.query(|(_, library), location_id: location::id::Type| async move {
+					get_scan_schedule(&library, location_id)
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("setScanSchedule", {
+			#[derive(Type, Deserialize)]
+			pub struct SetScanScheduleArgs {
+				pub location_id: location::id::Type,
+				pub schedule: Option<ScanSchedule>,
+			}
+
+			R.with2(library()).mutation(
+				|(_, library),
+				 SetScanScheduleArgs {
+				     location_id,
+				     schedule,
+				 }: SetScanScheduleArgs| async move {
+					set_scan_schedule(&library, location_id, schedule)
+						.await
+						.map_err(Into::into)
+				},
+			)
+		})
 		.procedure("delete", {
 			R.with2(library()).mutation(
 				|(node, library), location_id: location::id::Type| async move {
@@ -19,12 +19,15 @@ use serde::{Deserialize, Serialize};
 use specta::Type;
 use uuid::Uuid;
 
+mod albums;
 mod auth;
 mod backups;
 mod cloud;
 // mod categories;
+mod duplicates;
 mod ephemeral_files;
 mod files;
+mod integrity;
 mod jobs;
 mod keys;
 mod labels;
@@ -36,8 +39,10 @@ pub mod notifications;
 mod p2p;
 mod preferences;
 pub(crate) mod search;
+mod spaces;
 mod sync;
 mod tags;
+mod trash;
 pub mod utils;
 pub mod volumes;
 mod web_api;
@@ -202,11 +207,16 @@ pub(crate) fn mount() -> Arc<Router> {
 		.merge("volumes.", volumes::mount())
 		.merge("tags.", tags::mount())
 		.merge("labels.", labels::mount())
+		.merge("albums.", albums::mount())
+		.merge("spaces.", spaces::mount())
 		// .merge("categories.", categories::mount())
 		// .merge("keys.", keys::mount())
 		.merge("locations.", locations::mount())
 		.merge("ephemeralFiles.", ephemeral_files::mount())
 		.merge("files.", files::mount())
+		.merge("trash.", trash::mount())
+		.merge("duplicates.", duplicates::mount())
+		.merge("integrity.", integrity::mount())
 		.merge("jobs.", jobs::mount())
 		.merge("p2p.", p2p::mount())
 		.merge("models.", models::mount())
@@ -91,7 +91,9 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 
 				#[cfg(feature = "ai")]
 				{
-					use super::notifications::{NotificationData, NotificationKind};
+					use super::notifications::{
+						NotificationCategory, NotificationData, NotificationKind,
+					};
 
 					if let Some(model) = new_model {
 						let version = model.version().to_string();
@@ -99,27 +101,27 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 							let notification =
 								if let Some(image_labeller) = node.old_image_labeller.as_ref() {
 									if let Err(e) = image_labeller.change_model(model).await {
-										NotificationData {
-											title: String::from(
-												"Failed to change image detection model",
-											),
-											content: format!("Error: {e}"),
-											kind: NotificationKind::Error,
-										}
+										NotificationData::new(
+											"Failed to change image detection model",
+											format!("Error: {e}"),
+											NotificationKind::Error,
+										)
 									} else {
-										NotificationData {
-											title: String::from("Model download completed"),
-											content: format!("Sucessfuly loaded model: {version}"),
-											kind: NotificationKind::Success,
-										}
+										NotificationData::new(
+											"Model download completed",
+											format!("Sucessfuly loaded model: {version}"),
+											NotificationKind::Success,
+										)
 									}
 								} else {
-									NotificationData {
-									title: String::from("Failed to change image detection model"),
-									content: "The AI system is disabled due to a previous error. Contact support for help.".to_string(),
-									kind: NotificationKind::Success,
+									NotificationData::new(
+										"Failed to change image detection model",
+										"The AI system is disabled due to a previous error. Contact support for help.",
+										NotificationKind::Error,
+									)
 								}
-								};
+								.with_category(NotificationCategory::Ai)
+								.with_group("image-detection-model");
 
 							node.emit_notification(notification, None).await;
 						});
@@ -1,12 +1,14 @@
-use sd_prisma::prisma::notification;
+use sd_prisma::prisma::{notification, PrismaClient};
 
 use crate::api::{Ctx, R};
 use async_stream::stream;
 use chrono::{DateTime, Utc};
 use futures::future::join_all;
+use prisma_client_rust::or;
 use rspc::{alpha::AlphaRouter, ErrorCode};
 use serde::{Deserialize, Serialize};
 use specta::Type;
+use tracing::warn;
 use uuid::Uuid;
 
 /// Represents a single notification.
@@ -17,6 +19,15 @@ pub struct Notification {
 	pub data: NotificationData,
 	pub read: bool,
 	pub expires: Option<DateTime<Utc>>,
+	/// How many times the notification was emitted again while it was still around
+	#[serde(default)]
+	pub repeats: u32,
+}
+
+impl Notification {
+	pub fn is_expired(&self) -> bool {
+		self.expires.is_some_and(|expires| expires <= Utc::now())
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Type, PartialEq, Eq)]
@@ -25,6 +36,7 @@ pub enum NotificationId {
 	Library(Uuid, u32),
 	Node(u32),
 }
+/// How severe a notification is
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
 #[serde(rename_all = "camelCase")]
 pub enum NotificationKind {
@@ -34,6 +46,32 @@ pub enum NotificationKind {
 	Warning,
 }
 
+/// What a notification is about, so the frontend can filter them
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationCategory {
+	#[default]
+	General,
+	Backup,
+	Job,
+	Spacedrop,
+	Integrity,
+	Ai,
+	Sync,
+}
+
+/// A button shown on a notification, which calls an rspc mutation when clicked
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct NotificationAction {
+	pub label: String,
+	/// Key of the mutation, eg. `p2p.acceptSpacedrop`. Mutations of library notifications are
+	/// called on their library.
+	pub procedure: String,
+	pub input: serde_json::Value,
+	/// Whether the notification is dismissed once the action ran
+	pub dismiss: bool,
+}
+
 /// Represents the data of a single notification.
 /// This data is used by the frontend to properly display the notification.
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
@@ -41,6 +79,162 @@ pub struct NotificationData {
 	pub title: String,
 	pub content: String,
 	pub kind: NotificationKind,
+	#[serde(default)]
+	pub category: NotificationCategory,
+	#[serde(default)]
+	pub actions: Vec<NotificationAction>,
+	/// A notification replaces the previous one of the same group instead of piling up,
+	/// eg. the failures of a job that keeps failing
+	#[serde(default)]
+	pub group: Option<String>,
+}
+
+impl NotificationData {
+	pub fn new(
+		title: impl Into<String>,
+		content: impl Into<String>,
+		kind: NotificationKind,
+	) -> Self {
+		Self {
+			title: title.into(),
+			content: content.into(),
+			kind,
+			category: NotificationCategory::default(),
+			actions: vec![],
+			group: None,
+		}
+	}
+
+	pub fn with_category(mut self, category: NotificationCategory) -> Self {
+		self.category = category;
+		self
+	}
+
+	pub fn with_action(mut self, action: NotificationAction) -> Self {
+		self.actions.push(action);
+		self
+	}
+
+	pub fn with_group(mut self, group: impl Into<String>) -> Self {
+		self.group = Some(group.into());
+		self
+	}
+}
+
+/// Adds a node notification to the ones kept in its config, replacing the one of the same group and
+/// dropping the expired ones. Returns the notification as it was stored.
+pub(crate) fn add_node_notification(
+	notifications: &mut Vec<Notification>,
+	mut notification: Notification,
+) -> Notification {
+	notifications.retain(|n| !n.is_expired());
+
+	if let Some(previous) = notification.data.group.as_ref().and_then(|group| {
+		notifications
+			.iter_mut()
+			.find(|n| n.data.group.as_ref() == Some(group))
+	}) {
+		notification.id = previous.id.clone();
+		notification.repeats = previous.repeats + 1;
+		*previous = notification.clone();
+	} else {
+		notifications.push(notification.clone());
+	}
+
+	notification
+}
+
+/// Stores a library notification, replacing the one of the same group and pruning the expired ones
+pub(crate) async fn save_library_notification(
+	db: &PrismaClient,
+	data: &NotificationData,
+	bytes: Vec<u8>,
+	expires: Option<DateTime<Utc>>,
+) -> Result<notification::Data, prisma_client_rust::QueryError> {
+	if let Err(err) = db
+		.notification()
+		.delete_many(vec![notification::expires_at::lte(Utc::now().into())])
+		.exec()
+		.await
+	{
+		warn!("Error pruning expired notifications: {:?}", err);
+	}
+
+	let previous = match &data.group {
+		Some(group) => {
+			db.notification()
+				.find_first(vec![notification::group_key::equals(Some(group.clone()))])
+				.exec()
+				.await?
+		}
+		None => None,
+	};
+
+	match previous {
+		Some(previous) => {
+			db.notification()
+				.update(
+					notification::id::equals(previous.id),
+					vec![
+						notification::data::set(bytes),
+						notification::read::set(false),
+						notification::expires_at::set(expires.map(Into::into)),
+						notification::repeats::increment(1),
+					],
+				)
+				.exec()
+				.await
+		}
+		None => {
+			db.notification()
+				.create(
+					bytes,
+					vec![
+						notification::expires_at::set(expires.map(Into::into)),
+						notification::group_key::set(data.group.clone()),
+					],
+				)
+				.exec()
+				.await
+		}
+	}
+}
+
+/// The notifications of a library which haven't expired yet
+async fn library_notifications(
+	db: &PrismaClient,
+	library_id: Uuid,
+) -> Result<Vec<Notification>, rspc::Error> {
+	// Expired notifications are pruned when new ones are emitted
+	db.notification()
+		.find_many(vec![or![
+			notification::expires_at::gt(Utc::now().into()),
+			notification::expires_at::equals(None),
+		]])
+		.exec()
+		.await
+		.map_err(|err| {
+			rspc::Error::new(
+				ErrorCode::InternalServerError,
+				format!("Failed to get notifications for library '{library_id}': {err}"),
+			)
+		})?
+		.into_iter()
+		.map(|n| {
+			Ok(Notification {
+				id: NotificationId::Library(library_id, n.id as u32),
+				data: rmp_serde::from_slice(&n.data).map_err(|err| {
+					rspc::Error::new(
+						ErrorCode::InternalServerError,
+						format!("Failed to get notifications for library '{library_id}': {err}"),
+					)
+				})?,
+				read: n.read,
+				expires: n.expires_at.map(Into::into),
+				repeats: n.repeats as u32,
+			})
+		})
+		.collect()
 }
 
 pub(crate) fn mount() -> AlphaRouter<Ctx> {
@@ -48,42 +242,10 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 		.procedure("get", {
 			R.query(|node, _: ()| async move {
 				let mut notifications = node.config.get().await.notifications;
+				notifications.retain(|notification| !notification.is_expired());
+
 				for lib_notifications in join_all(node.libraries.get_all().await.into_iter().map(
-					|library| async move {
-						library
-							.db
-							.notification()
-							.find_many(vec![])
-							.exec()
-							.await
-							.map_err(|err| {
-								rspc::Error::new(
-									ErrorCode::InternalServerError,
-									format!(
-										"Failed to get notifications for library '{}': {}",
-										library.id, err
-									),
-								)
-							})?
-							.into_iter()
-							.map(|n| {
-								Ok(Notification {
-									id: NotificationId::Library(library.id, n.id as u32),
-									data: rmp_serde::from_slice(&n.data).map_err(|err| {
-										rspc::Error::new(
-											ErrorCode::InternalServerError,
-											format!(
-												"Failed to get notifications for library '{}': {}",
-												library.id, err
-											),
-										)
-									})?,
-									read: false,
-									expires: n.expires_at.map(Into::into),
-								})
-							})
-							.collect::<Result<Vec<Notification>, rspc::Error>>()
-					},
+					|library| async move { library_notifications(&library.db, library.id).await },
 				))
 				.await
 				{
@@ -167,3 +329,153 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			})
 		})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::Duration;
+
+	fn data(content: &str, group: Option<&str>) -> NotificationData {
+		let data = NotificationData::new("Title", content, NotificationKind::Info);
+
+		match group {
+			Some(group) => data.with_group(group),
+			None => data,
+		}
+	}
+
+	fn node_notification(
+		id: u32,
+		data: NotificationData,
+		expires: Option<DateTime<Utc>>,
+	) -> Notification {
+		Notification {
+			id: NotificationId::Node(id),
+			data,
+			read: false,
+			expires,
+			repeats: 0,
+		}
+	}
+
+	async fn save(
+		db: &PrismaClient,
+		data: NotificationData,
+		expires: Option<DateTime<Utc>>,
+	) -> notification::Data {
+		let bytes = rmp_serde::to_vec_named(&data).unwrap();
+		save_library_notification(db, &data, bytes, expires)
+			.await
+			.unwrap()
+	}
+
+	#[test]
+	fn node_notification_of_same_group_replaces_previous() {
+		let mut notifications = vec![];
+
+		add_node_notification(
+			&mut notifications,
+			node_notification(1, data("first", Some("job")), None),
+		);
+		let second = add_node_notification(
+			&mut notifications,
+			node_notification(2, data("second", Some("job")), None),
+		);
+		add_node_notification(
+			&mut notifications,
+			node_notification(3, data("other", None), None),
+		);
+
+		assert_eq!(second.id, NotificationId::Node(1));
+		assert_eq!(second.repeats, 1);
+		assert_eq!(notifications.len(), 2);
+		assert_eq!(notifications[0].data.content, "second");
+		assert_eq!(notifications[0].repeats, 1);
+	}
+
+	#[test]
+	fn expired_node_notifications_are_dropped() {
+		let mut notifications = vec![node_notification(
+			1,
+			data("expired", Some("job")),
+			Some(Utc::now() - Duration::hours(1)),
+		)];
+
+		let notification = add_node_notification(
+			&mut notifications,
+			node_notification(2, data("fresh", Some("job")), None),
+		);
+
+		// It's not a repeat of a notification the user can't see anymore
+		assert_eq!(notification.id, NotificationId::Node(2));
+		assert_eq!(notification.repeats, 0);
+		assert_eq!(notifications.len(), 1);
+		assert_eq!(notifications[0].data.content, "fresh");
+	}
+
+	#[tokio::test]
+	async fn library_notification_of_same_group_replaces_previous() {
+		let db = sd_prisma::test_db().await;
+		db._db_push().await.unwrap();
+		let library_id = Uuid::new_v4();
+
+		let first = save(&db, data("first", Some("backup")), None).await;
+		let second = save(&db, data("second", Some("backup")), None).await;
+		save(&db, data("other", None), None).await;
+
+		assert_eq!(second.id, first.id);
+		assert_eq!(second.repeats, 1);
+
+		let notifications = library_notifications(&db, library_id).await.unwrap();
+		assert_eq!(notifications.len(), 2);
+
+		let grouped = notifications
+			.iter()
+			.find(|n| n.id == NotificationId::Library(library_id, first.id as u32))
+			.unwrap();
+		assert_eq!(grouped.data.content, "second");
+		assert_eq!(grouped.repeats, 1);
+		assert!(!grouped.read);
+	}
+
+	#[tokio::test]
+	async fn expired_library_notifications_are_hidden_and_pruned() {
+		let db = sd_prisma::test_db().await;
+		db._db_push().await.unwrap();
+		let library_id = Uuid::new_v4();
+
+		save(
+			&db,
+			data("expiring", None),
+			Some(Utc::now() + Duration::hours(1)),
+		)
+		.await;
+
+		// Expired since it was emitted
+		db.notification()
+			.create(
+				rmp_serde::to_vec_named(&data("expired", None)).unwrap(),
+				vec![notification::expires_at::set(Some(
+					(Utc::now() - Duration::hours(1)).into(),
+				))],
+			)
+			.exec()
+			.await
+			.unwrap();
+
+		let notifications = library_notifications(&db, library_id).await.unwrap();
+		assert_eq!(notifications.len(), 1);
+		assert_eq!(notifications[0].data.content, "expiring");
+
+		// The expired one is only kept until the next notification
+		assert_eq!(db.notification().count(vec![]).exec().await.unwrap(), 2);
+		save(&db, data("fresh", None), None).await;
+		assert_eq!(db.notification().count(vec![]).exec().await.unwrap(), 2);
+		assert!(library_notifications(&db, library_id)
+			.await
+			.unwrap()
+			.iter()
+			.all(|n| n.data.content != "expired"));
+	}
+}
@@ -1,4 +1,7 @@
-use crate::p2p::{operations, ConnectionMethod, DiscoveryMethod, Header, P2PEvent, PeerMetadata};
+use crate::{
+	invalidate_query,
+	p2p::{operations, ConnectionMethod, DiscoveryMethod, Header, P2PEvent, PeerMetadata},
+};
 
 use sd_p2p::{PeerConnectionCandidate, RemoteIdentity};
 
@@ -7,6 +10,7 @@ use serde::Deserialize;
 use specta::Type;
 use std::path::PathBuf;
 use tokio::io::AsyncWriteExt;
+use tracing::error;
 use uuid::Uuid;
 
 use super::{Ctx, R};
@@ -117,6 +121,11 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					None => node.p2p.reject_spacedrop(id).await,
 				};
 
+				node.dismiss_notification_group(
+					&operations::spacedrop::spacedrop_notification_group(id),
+				)
+				.await;
+
 				Ok(())
 			})
 		})
@@ -124,6 +133,49 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 			R.mutation(|node, id: Uuid| async move {
 				node.p2p.cancel_spacedrop(id).await;
 
+				Ok(())
+			})
+		})
+		.procedure("startPairing", {
+			R.mutation(|node, _: ()| async move { Ok(node.p2p.start_pairing()) })
+		})
+		.procedure("stopPairing", {
+			R.mutation(|node, _: ()| async move {
+				node.p2p.stop_pairing();
+
+				Ok(())
+			})
+		})
+		.procedure("pair", {
+			#[derive(Type, Deserialize)]
+			pub struct PairArgs {
+				identity: RemoteIdentity,
+				code: String,
+			}
+
+			R.mutation(|node, PairArgs { identity, code }: PairArgs| async move {
+				let peer = node.p2p.pair(identity, code).await?;
+
+				invalidate_query!(node; node, "p2p.trustedPeers");
+
+				Ok(peer)
+			})
+		})
+		.procedure("trustedPeers", {
+			R.query(|node, _: ()| async move { Ok(node.p2p.trusted_peers().await) })
+		})
+		.procedure("untrustPeer", {
+			R.mutation(|node, identity: RemoteIdentity| async move {
+				node.p2p.untrust_peer(identity).await.map_err(|err| {
+					error!("Failed to write config: {}", err);
+					rspc::Error::new(
+						ErrorCode::InternalServerError,
+						"error updating config".into(),
+					)
+				})?;
+
+				invalidate_query!(node; node, "p2p.trustedPeers");
+
 				Ok(())
 			})
 		})
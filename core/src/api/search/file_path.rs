@@ -4,6 +4,7 @@ use sd_file_path_helper::{check_file_path_exists, IsolatedFilePathData};
 use sd_prisma::prisma::{self, file_path};
 
 use chrono::{DateTime, FixedOffset, Utc};
+use itertools::Either;
 use prisma_client_rust::{OrderByQuery, PaginatedQuery, WhereQuery};
 use rspc::ErrorCode;
 use serde::{Deserialize, Serialize};
@@ -69,6 +70,8 @@ pub enum FilePathFilterArgs {
 	ModifiedAt(Range<DateTime<Utc>>),
 	IndexedAt(Range<DateTime<Utc>>),
 	Hidden(bool),
+	/// Big endian bytes of the size, the same representation as `size_in_bytes_bytes`
+	SizeInBytes(Range<Vec<u8>>),
 }
 
 impl FilePathFilterArgs {
@@ -120,6 +123,20 @@ impl FilePathFilterArgs {
 					})
 					.unwrap_or_default()
 			}
+			Self::Name(v) if v.is_pattern() && !v.is_empty() => {
+				let (scope, exact) = match &v {
+					TextMatch::Glob(glob) => glob_params(glob),
+					_ => (vec![], false),
+				};
+
+				if exact {
+					scope
+				} else {
+					vec![id::in_vec(
+						find_file_paths_by_name_pattern(db, scope, v.into_matcher()?).await?,
+					)]
+				}
+			}
 			Self::Name(v) => v
 				.into_param(name::contains, name::starts_with, name::ends_with, |s| {
 					name::equals(Some(s))
@@ -151,11 +168,198 @@ impl FilePathFilterArgs {
 			Self::Hidden(v) => {
 				vec![hidden::equals(Some(v))]
 			}
+			Self::SizeInBytes(v) => {
+				let size_from_bytes = |bytes: Vec<u8>| {
+					size_in_bytes_from_be_bytes(&bytes).ok_or_else(|| {
+						rspc::Error::new(
+							ErrorCode::BadRequest,
+							"Size must be a big endian u64".into(),
+						)
+					})
+				};
+
+				vec![match v {
+					Range::From(v) => size_in_bytes_float::gte(size_from_bytes(v)? as f64),
+					Range::To(v) => size_in_bytes_float::lte(size_from_bytes(v)? as f64),
+				}]
+			}
 		})
 	}
 }
 
-#[derive(Deserialize, Type, Debug)]
+/// Maximum amount of file paths a pattern name filter nested in a group or in an object filter
+/// can match, above it the search is rejected instead of silently missing some of them. Top level
+/// ones are matched on the pages of `search.paths` instead, see [`find_file_paths_matching_names`]
+const MAX_PATTERN_MATCHES: usize = 1000;
+const PATTERN_SCAN_BATCH_SIZE: i64 = 10_000;
+
+/// Params matching a superset of the file names matching `glob`, from its literal name prefix and
+/// extension, and whether they match exactly the same files. Common globs like `IMG*` are then
+/// matched by SQLite like the other name filters.
+fn glob_params(glob: &str) -> (Vec<file_path::WhereParam>, bool) {
+	// LIKE is only case insensitive for ASCII, and `%` and `_` are wildcards in it
+	let is_literal = |c: char| c.is_ascii() && !"*?[]{}\\.%_".contains(c);
+
+	let prefix_len = glob.find(|c| !is_literal(c)).unwrap_or(glob.len());
+	let (prefix, rest) = glob.split_at(prefix_len);
+
+	// Extensions never contain dots, so a glob ending with a literal `.ext` requires that extension
+	let extension = glob
+		.rsplit_once('.')
+		.map(|(_, extension)| extension)
+		.filter(|extension| !extension.is_empty() && extension.chars().all(is_literal));
+
+	let mut params = vec![];
+	if !prefix.is_empty() {
+		params.push(file_path::name::starts_with(prefix.to_string()));
+	}
+	if let Some(extension) = extension {
+		// Equality is only case insensitive with the NOCASE collation of the column, so the
+		// extension is narrowed down with LIKE and matched exactly in Rust
+		params.push(file_path::extension::starts_with(extension.to_string()));
+		params.push(file_path::extension::ends_with(extension.to_string()));
+	}
+
+	// Whatever follows the prefix must be a single wildcard
+	let exact =
+		extension.is_none() && rest.starts_with('*') && rest.trim_start_matches('*').is_empty();
+
+	(params, exact)
+}
+
+/// The name of a file path along with its extension, as name patterns match it
+fn file_path_full_name(name: Option<String>, extension: Option<String>) -> Option<String> {
+	let name = name?;
+
+	Some(match extension {
+		Some(extension) if !extension.is_empty() => format!("{name}.{extension}"),
+		_ => name,
+	})
+}
+
+/// A name filter SQLite can't evaluate, like regexes, globs with our syntax or fuzzy matches:
+/// params matching a superset of its file paths and the matcher for their names
+pub struct NamePattern {
+	pub scope: Vec<file_path::WhereParam>,
+	pub matcher: TextMatcher,
+}
+
+impl FilePathFilterArgs {
+	/// Returns the name pattern of the filter if it has to be matched in Rust, so a paginated
+	/// query can match it on the file paths it pages through
+	pub fn into_name_pattern(self) -> Result<Either<Self, NamePattern>, rspc::Error> {
+		match self {
+			Self::Name(v) if v.is_pattern() && !v.is_empty() => {
+				let (scope, exact) = match &v {
+					TextMatch::Glob(glob) => glob_params(glob),
+					_ => (vec![], false),
+				};
+
+				if exact {
+					Ok(Either::Left(Self::Name(v)))
+				} else {
+					Ok(Either::Right(NamePattern {
+						scope,
+						matcher: v.into_matcher()?,
+					}))
+				}
+			}
+			v => Ok(Either::Left(v)),
+		}
+	}
+}
+
+/// Pages through the file paths of the queries built by `query`, in their order, and matches
+/// their names with `matchers` here as SQLite can't. The first `skip` matches are skipped and the
+/// scan stops after `take` more, so only as much of the scope as a page needs is scanned
+pub async fn find_file_paths_matching_names<'db>(
+	query: impl Fn() -> file_path::FindManyQuery<'db>,
+	matchers: &[TextMatcher],
+	mut skip: usize,
+	take: Option<usize>,
+) -> Result<Vec<file_path::id::Type>, rspc::Error> {
+	let mut ids = vec![];
+	let mut scanned = 0;
+
+	loop {
+		let batch = query()
+			.skip(scanned)
+			.take(PATTERN_SCAN_BATCH_SIZE)
+			.select(file_path::select!({ id name extension }))
+			.exec()
+			.await?;
+
+		scanned += batch.len() as i64;
+		let is_last_batch = (batch.len() as i64) < PATTERN_SCAN_BATCH_SIZE;
+
+		for file_path in batch {
+			let Some(full_name) = file_path_full_name(file_path.name, file_path.extension) else {
+				continue;
+			};
+
+			if !matchers.iter().all(|matcher| matcher.is_match(&full_name)) {
+				continue;
+			}
+
+			if skip > 0 {
+				skip -= 1;
+				continue;
+			}
+
+			ids.push(file_path.id);
+
+			if take.is_some_and(|take| ids.len() >= take) {
+				return Ok(ids);
+			}
+		}
+
+		if is_last_batch {
+			return Ok(ids);
+		}
+	}
+}
+
+/// For name patterns that can't be matched on the pages of a query, as they're nested in groups
+/// or object filters, we scan all of the file paths of their scope upfront
+async fn find_file_paths_by_name_pattern(
+	db: &prisma::PrismaClient,
+	scope: Vec<file_path::WhereParam>,
+	matcher: TextMatcher,
+) -> Result<Vec<file_path::id::Type>, rspc::Error> {
+	let ids = find_file_paths_matching_names(
+		|| {
+			db.file_path()
+				.find_many(scope.clone())
+				.order_by(file_path::id::order(prisma::SortOrder::Asc))
+		},
+		&[matcher],
+		0,
+		Some(MAX_PATTERN_MATCHES + 1),
+	)
+	.await?;
+
+	if ids.len() > MAX_PATTERN_MATCHES {
+		return Err(rspc::Error::new(
+			ErrorCode::BadRequest,
+			format!(
+				"The name pattern matches more than {MAX_PATTERN_MATCHES} files, \
+				make it more specific"
+			),
+		));
+	}
+
+	Ok(ids)
+}
+
+fn size_in_bytes_from_be_bytes(bytes: &[u8]) -> Option<u64> {
+	(bytes.len() <= 8).then(|| {
+		let mut buf = [0; 8];
+		buf[8 - bytes.len()..].copy_from_slice(bytes);
+		u64::from_be_bytes(buf)
+	})
+}
+
+#[derive(Deserialize, Type, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum FilePathObjectCursor {
 	DateAccessed(CursorOrderItem<DateTime<FixedOffset>>),
@@ -192,7 +396,7 @@ impl FilePathObjectCursor {
 	}
 }
 
-#[derive(Deserialize, Type, Debug)]
+#[derive(Deserialize, Type, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum FilePathCursorVariant {
 	None,
@@ -252,7 +456,7 @@ impl FilePathCursorVariant {
 	}
 }
 
-#[derive(Deserialize, Type, Debug)]
+#[derive(Deserialize, Type, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct FilePathCursor {
 	pub is_dir: bool,
@@ -293,3 +497,20 @@ impl OrderAndPagination {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn size_in_bytes_from_bytes() {
+		assert_eq!(size_in_bytes_from_be_bytes(&[]), Some(0));
+		assert_eq!(size_in_bytes_from_be_bytes(&[0x04, 0xD2]), Some(1234));
+		assert_eq!(size_in_bytes_from_be_bytes(&42_u64.to_be_bytes()), Some(42));
+		assert_eq!(
+			size_in_bytes_from_be_bytes(&u64::MAX.to_be_bytes()),
+			Some(u64::MAX)
+		);
+		assert_eq!(size_in_bytes_from_be_bytes(&[1; 9]), None);
+	}
+}
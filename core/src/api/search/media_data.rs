@@ -1,5 +1,8 @@
 use sd_prisma::prisma::{self, media_data};
 
+use chrono::{DateTime, Utc};
+use prisma_client_rust::or;
+use rspc::ErrorCode;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
@@ -27,3 +30,185 @@ impl MediaDataOrder {
 		}
 	}
 }
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GeoBoundingBox {
+	pub min_latitude: f64,
+	pub max_latitude: f64,
+	/// May be bigger than `max_longitude` for boxes crossing the antimeridian
+	pub min_longitude: f64,
+	pub max_longitude: f64,
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum MediaDataFilterArgs {
+	Width(Range<i32>),
+	Height(Range<i32>),
+	Megapixels(Range<f64>),
+	CaptureDate(Range<DateTime<Utc>>),
+	/// Pattern matches (regex, glob and fuzzy) aren't supported for camera data and are rejected
+	CameraMake(TextMatch),
+	/// Pattern matches (regex, glob and fuzzy) aren't supported for camera data and are rejected
+	CameraModel(TextMatch),
+	Location(GeoBoundingBox),
+}
+
+impl MediaDataFilterArgs {
+	pub fn into_params(self) -> Result<Vec<media_data::WhereParam>, rspc::Error> {
+		use media_data::*;
+
+		Ok(match self {
+			Self::Width(v) => vec![match v {
+				Range::From(v) => width::gte(v),
+				Range::To(v) => width::lte(v),
+			}],
+			Self::Height(v) => vec![match v {
+				Range::From(v) => height::gte(v),
+				Range::To(v) => height::lte(v),
+			}],
+			Self::Megapixels(v) => vec![match v {
+				Range::From(v) => megapixels::gte(v),
+				Range::To(v) => megapixels::lte(v),
+			}],
+			Self::CaptureDate(v) => vec![match v {
+				Range::From(v) => epoch_time::gte(v.timestamp()),
+				Range::To(v) => epoch_time::lte(v.timestamp()),
+			}],
+			Self::CameraMake(v) => camera_text_match(v)?
+				.into_param(
+					camera_make::contains,
+					camera_make::starts_with,
+					camera_make::ends_with,
+					|s| camera_make::equals(Some(s)),
+				)
+				.map(|v| vec![v])
+				.unwrap_or_default(),
+			Self::CameraModel(v) => camera_text_match(v)?
+				.into_param(
+					camera_model::contains,
+					camera_model::starts_with,
+					camera_model::ends_with,
+					|s| camera_model::equals(Some(s)),
+				)
+				.map(|v| vec![v])
+				.unwrap_or_default(),
+			Self::Location(GeoBoundingBox {
+				min_latitude,
+				max_latitude,
+				min_longitude,
+				max_longitude,
+			}) => vec![
+				latitude::gte(min_latitude),
+				latitude::lte(max_latitude),
+				if min_longitude <= max_longitude {
+					prisma_client_rust::and![
+						longitude::gte(min_longitude),
+						longitude::lte(max_longitude)
+					]
+				} else {
+					or![longitude::gte(min_longitude), longitude::lte(max_longitude)]
+				},
+			],
+		})
+	}
+}
+
+fn camera_text_match(v: TextMatch) -> Result<TextMatch, rspc::Error> {
+	if v.is_pattern() && !v.is_empty() {
+		return Err(rspc::Error::new(
+			ErrorCode::BadRequest,
+			"Camera make and model can't be filtered by a pattern".into(),
+		));
+	}
+
+	Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_prisma::prisma::{object, PrismaClient};
+
+	use uuid::Uuid;
+
+	/// Coordinates of the media data found in a bounding box, sorted by longitude
+	async fn in_bounding_box(db: &PrismaClient, bounding_box: GeoBoundingBox) -> Vec<(f64, f64)> {
+		let mut found = db
+			.media_data()
+			.find_many(
+				MediaDataFilterArgs::Location(bounding_box)
+					.into_params()
+					.unwrap(),
+			)
+			.exec()
+			.await
+			.unwrap()
+			.into_iter()
+			.filter_map(|media_data| media_data.latitude.zip(media_data.longitude))
+			.collect::<Vec<_>>();
+
+		found.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+		found
+	}
+
+	#[tokio::test]
+	async fn bounding_box_crossing_antimeridian() {
+		let db = sd_prisma::test_db().await;
+		db._db_push().await.unwrap();
+
+		for (latitude, longitude) in [(-17.7, 178.0), (-14.3, -170.7), (0.0, 0.0), (60.0, 179.5)] {
+			let object = db
+				.object()
+				.create(Uuid::new_v4().as_bytes().to_vec(), vec![])
+				.exec()
+				.await
+				.unwrap();
+
+			db.media_data()
+				.create(
+					object::id::equals(object.id),
+					vec![
+						media_data::latitude::set(Some(latitude)),
+						media_data::longitude::set(Some(longitude)),
+					],
+				)
+				.exec()
+				.await
+				.unwrap();
+		}
+
+		// From Fiji to Samoa, across the antimeridian
+		assert_eq!(
+			in_bounding_box(
+				&db,
+				GeoBoundingBox {
+					min_latitude: -20.0,
+					max_latitude: -10.0,
+					min_longitude: 175.0,
+					max_longitude: -170.0,
+				}
+			)
+			.await,
+			[(-14.3, -170.7), (-17.7, 178.0)]
+		);
+
+		// The same longitudes the other way around span the rest of the world
+		assert_eq!(
+			in_bounding_box(
+				&db,
+				GeoBoundingBox {
+					min_latitude: -20.0,
+					max_latitude: 70.0,
+					min_longitude: -170.0,
+					max_longitude: 175.0,
+				}
+			)
+			.await,
+			[(0.0, 0.0)]
+		);
+	}
+}
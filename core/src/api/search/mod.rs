@@ -5,18 +5,26 @@ use crate::{
 	},
 	library::Library,
 	location::{non_indexed, LocationError},
-	object::media::old_thumbnail::get_indexed_thumb_key,
+	object::{
+		content::{search_content, ContentSnippetPart},
+		media::{old_thumbnail::get_indexed_thumb_key, perceptual_hasher::find_similar_objects},
+	},
 	util::{unsafe_streamed_query, BatchedStream},
 };
 
 use sd_cache::{CacheNode, Model, Normalise, Reference};
 use sd_prisma::prisma::{self, PrismaClient};
 
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
+use async_recursion::async_recursion;
 use async_stream::stream;
 use futures::StreamExt;
 use itertools::Either;
+use prisma_client_rust::{
+	operator::{and, not, or},
+	Operator,
+};
 use rspc::{alpha::AlphaRouter, ErrorCode};
 use serde::{Deserialize, Serialize};
 use specta::Type;
@@ -32,6 +40,17 @@ pub use self::{file_path::*, object::*, utils::*};
 use super::{Ctx, R};
 
 const MAX_TAKE: u8 = 100;
+/// Maximum amount of file path ids queried at once when fetching a page matched in Rust
+const MAX_PAGE_IDS: usize = 1000;
+/// Maximum amount of file paths a `Content` filter can match, above it the search is rejected.
+/// `search.content` keeps the best ranked ones instead and flags its result as truncated
+const MAX_CONTENT_MATCHES: usize = 1000;
+/// Maximum nesting of `And`, `Or` and `Not` groups in a filter expression
+const MAX_FILTER_DEPTH: usize = 16;
+/// Default amount of differing bits between perceptual hashes for images to be considered similar
+const DEFAULT_SIMILARITY_DISTANCE: u8 = 10;
+/// Above this distance perceptual hashes of unrelated images start to match
+const MAX_SIMILARITY_DISTANCE: u8 = 24;
 
 #[derive(Serialize, Type, Debug)]
 struct SearchData<T: Model> {
@@ -51,18 +70,102 @@ impl<T: Model> Model for SearchData<T> {
 pub enum SearchFilterArgs {
 	FilePath(FilePathFilterArgs),
 	Object(ObjectFilterArgs),
+	/// Full-text search over the extracted contents of text, code, config and PDF files
+	Content(String),
+	/// Matches when all of the inner filters match
+	And(Vec<SearchFilterArgs>),
+	/// Matches when at least one of the inner filters matches
+	Or(Vec<SearchFilterArgs>),
+	/// Matches when the inner filter doesn't
+	Not(Box<SearchFilterArgs>),
 }
 
 impl SearchFilterArgs {
+	#[async_recursion]
 	async fn into_params<T>(
 		self,
 		db: &PrismaClient,
 		file_path: fn(Vec<prisma::file_path::WhereParam>) -> Vec<T>,
 		object: fn(Vec<prisma::object::WhereParam>) -> Vec<T>,
-	) -> Result<Vec<T>, rspc::Error> {
+		depth: usize,
+	) -> Result<Vec<T>, rspc::Error>
+	where
+		T: From<Operator<T>> + Send + 'static,
+	{
+		if depth > MAX_FILTER_DEPTH {
+			return Err(rspc::Error::new(
+				ErrorCode::BadRequest,
+				format!("filter groups can't be nested more than {MAX_FILTER_DEPTH} levels deep"),
+			));
+		}
+
 		Ok(match self {
 			Self::FilePath(v) => file_path(v.into_params(db).await?),
-			Self::Object(v) => object(v.into_params()),
+			Self::Object(v) => object(v.into_params()?),
+			Self::Content(query) => {
+				let matches = search_content(db, &query, MAX_CONTENT_MATCHES + 1).await?;
+
+				if matches.len() > MAX_CONTENT_MATCHES {
+					return Err(rspc::Error::new(
+						ErrorCode::BadRequest,
+						format!(
+							"The content query matches more than {MAX_CONTENT_MATCHES} files, \
+							make it more specific"
+						),
+					));
+				}
+
+				file_path(vec![prisma::file_path::id::in_vec(
+					matches
+						.into_iter()
+						.map(|content_match| content_match.file_path_id)
+						.collect(),
+				)])
+			}
+			Self::And(filters) => {
+				let mut params = Vec::new();
+
+				for filter in filters {
+					params.extend(filter.into_params(db, file_path, object, depth + 1).await?);
+				}
+
+				// An empty group doesn't filter anything, same as an empty filter list
+				if params.is_empty() {
+					vec![]
+				} else {
+					vec![and(params)]
+				}
+			}
+			Self::Or(filters) => {
+				let mut groups = Vec::with_capacity(filters.len());
+
+				for filter in filters {
+					let params = filter.into_params(db, file_path, object, depth + 1).await?;
+
+					// A member without params matches everything, and so does the whole group
+					if params.is_empty() {
+						return Ok(vec![]);
+					}
+
+					groups.push(and(params));
+				}
+
+				if groups.is_empty() {
+					vec![]
+				} else {
+					vec![or(groups)]
+				}
+			}
+			Self::Not(filter) => {
+				let params = filter.into_params(db, file_path, object, depth + 1).await?;
+
+				// Consistent with empty filters being ignored everywhere else
+				if params.is_empty() {
+					vec![]
+				} else {
+					vec![not(params)]
+				}
+			}
 		})
 	}
 
@@ -70,7 +173,7 @@ impl SearchFilterArgs {
 		self,
 		db: &PrismaClient,
 	) -> Result<Vec<prisma::file_path::WhereParam>, rspc::Error> {
-		self.into_params(db, |v| v, |v| vec![prisma::file_path::object::is(v)])
+		self.into_params(db, |v| v, |v| vec![prisma::file_path::object::is(v)], 0)
 			.await
 	}
 
@@ -78,7 +181,7 @@ impl SearchFilterArgs {
 		self,
 		db: &PrismaClient,
 	) -> Result<Vec<prisma::object::WhereParam>, rspc::Error> {
-		self.into_params(db, |v| vec![prisma::object::file_paths::some(v)], |v| v)
+		self.into_params(db, |v| vec![prisma::object::file_paths::some(v)], |v| v, 0)
 			.await
 	}
 }
@@ -102,6 +205,8 @@ pub fn mount() -> AlphaRouter<Ctx> {
 				with_hidden_files: bool,
 				#[specta(optional)]
 				order: Option<EphemeralPathOrder>,
+				#[specta(optional)]
+				name: Option<TextMatch>,
 			}
 			#[derive(Serialize, Type, Debug)]
 			struct EphemeralPathsResultItem {
@@ -116,9 +221,19 @@ pub fn mount() -> AlphaRouter<Ctx> {
 				     path,
 				     with_hidden_files,
 				     order,
+				     name,
 				 }| async move {
+					let name_matcher = name
+						.filter(|name| !name.is_empty())
+						.map(TextMatch::into_matcher)
+						.transpose()?;
+
 					let paths =
 						non_indexed::walk(path, with_hidden_files, node, library, |entries| {
+							if let Some(name_matcher) = name_matcher {
+								entries.retain(|entry| name_matcher.is_match(entry.name()));
+							}
+
 							macro_rules! order_match {
 								($order:ident, [$(($variant:ident, |$i:ident| $func:expr)),+]) => {{
 									match $order {
@@ -210,38 +325,77 @@ pub fn mount() -> AlphaRouter<Ctx> {
 				 }| async move {
 					let Library { db, .. } = library.as_ref();
 
-					let params = {
-						let mut params = Vec::new();
+					let (params, matchers) = split_name_patterns(db, filters).await?;
 
-						for filter in filters {
-							params.extend(filter.into_file_path_params(db).await?);
+					let query = |order_and_pagination: Option<file_path::OrderAndPagination>| {
+						let mut query = db.file_path().find_many(params.clone());
+
+						// WARN: this order_by for grouping directories MUST always come before the other order_by
+						if group_directories {
+							query = query.order_by(prisma::file_path::is_dir::order(
+								prisma::SortOrder::Desc,
+							));
 						}
 
-						params
-					};
+						// WARN: this order_by for sorting data MUST always come after the other order_by
+						if let Some(order_and_pagination) = order_and_pagination {
+							order_and_pagination.apply(&mut query, group_directories)
+						}
 
-					let mut query = db.file_path().find_many(params);
+						query
+					};
 
-					if let Some(take) = take {
-						query = query.take(take as i64);
-					}
+					let file_paths = if matchers.is_empty() {
+						let mut query = query(order_and_pagination);
 
-					// WARN: this order_by for grouping directories MUST always come before the other order_by
-					if group_directories {
-						query = query
-							.order_by(prisma::file_path::is_dir::order(prisma::SortOrder::Desc));
-					}
+						if let Some(take) = take {
+							query = query.take(take as i64);
+						}
 
-					// WARN: this order_by for sorting data MUST always come after the other order_by
-					if let Some(order_and_pagination) = order_and_pagination {
-						order_and_pagination.apply(&mut query, group_directories)
-					}
+						query
+							.include(file_path_with_object::include())
+							.exec()
+							.await?
+					} else {
+						// The offset counts matching file paths, so it's skipped while matching
+						let (order_and_pagination, skip) = match order_and_pagination {
+							Some(file_path::OrderAndPagination::Offset { offset, order }) => (
+								order.map(file_path::OrderAndPagination::OrderOnly),
+								offset.max(0) as usize,
+							),
+							order_and_pagination => (order_and_pagination, 0),
+						};
 
-					let file_paths = query
-						.include(file_path_with_object::include())
-						.exec()
+						let ids = find_file_paths_matching_names(
+							|| query(order_and_pagination.clone()),
+							&matchers,
+							skip,
+							take.map(usize::from),
+						)
 						.await?;
 
+						let mut file_paths = Vec::with_capacity(ids.len());
+						for ids in ids.chunks(MAX_PAGE_IDS) {
+							file_paths.extend(
+								db.file_path()
+									.find_many(vec![prisma::file_path::id::in_vec(ids.to_vec())])
+									.include(file_path_with_object::include())
+									.exec()
+									.await?,
+							);
+						}
+
+						// Back in the order they were matched in
+						let positions = ids
+							.iter()
+							.enumerate()
+							.map(|(position, id)| (*id, position))
+							.collect::<HashMap<_, _>>();
+						file_paths.sort_by_key(|file_path| positions[&file_path.id]);
+
+						file_paths
+					};
+
 					let mut items = Vec::with_capacity(file_paths.len());
 
 					for file_path in file_paths {
@@ -287,21 +441,146 @@ pub fn mount() -> AlphaRouter<Ctx> {
 				.query(|(_, library), Args { filters }| async move {
 					let Library { db, .. } = library.as_ref();
 
-					Ok(db
-						.file_path()
-						.count({
-							let mut params = Vec::new();
+					let (params, matchers) = split_name_patterns(db, filters).await?;
 
-							for filter in filters {
-								params.extend(filter.into_file_path_params(db).await?);
-							}
+					if matchers.is_empty() {
+						return Ok(db.file_path().count(params).exec().await? as u32);
+					}
 
-							params
-						})
-						.exec()
-						.await? as u32)
+					Ok(find_file_paths_matching_names(
+						|| {
+							db.file_path()
+								.find_many(params.clone())
+								.order_by(prisma::file_path::id::order(prisma::SortOrder::Asc))
+						},
+						&matchers,
+						0,
+						None,
+					)
+					.await?
+					.len() as u32)
 				})
 		})
+		.procedure("content", {
+			#[derive(Deserialize, Type, Debug)]
+			#[serde(rename_all = "camelCase")]
+			struct ContentSearchArgs {
+				query: String,
+				#[specta(optional)]
+				take: Option<u8>,
+				#[serde(default)]
+				filters: Vec<SearchFilterArgs>,
+			}
+
+			#[derive(Serialize, Type, Debug)]
+			#[serde(rename_all = "camelCase")]
+			struct ContentSearchHit {
+				item: Reference<ExplorerItem>,
+				/// bm25 score of the hit, lower is better
+				rank: f64,
+				snippet: Vec<ContentSnippetPart>,
+			}
+
+			#[derive(Serialize, Type, Debug)]
+			struct ContentSearchData {
+				hits: Vec<ContentSearchHit>,
+				nodes: Vec<CacheNode>,
+				/// The query matched more files than are ranked, so some matches may be missing
+				truncated: bool,
+			}
+
+			R.with2(library()).query(
+				|(node, library),
+				 ContentSearchArgs {
+				     query,
+				     take,
+				     filters,
+				 }| async move {
+					let Library { db, .. } = library.as_ref();
+
+					let take = take.unwrap_or(MAX_TAKE).min(MAX_TAKE) as usize;
+
+					let mut matches = search_content(db, &query, MAX_CONTENT_MATCHES + 1).await?;
+
+					let truncated = matches.len() > MAX_CONTENT_MATCHES;
+					matches.truncate(MAX_CONTENT_MATCHES);
+
+					let mut matches = matches
+						.into_iter()
+						.map(|content_match| (content_match.file_path_id, content_match))
+						.collect::<HashMap<_, _>>();
+
+					let params = {
+						let mut params = vec![prisma::file_path::id::in_vec(
+							matches.keys().copied().collect(),
+						)];
+
+						for filter in filters {
+							params.extend(filter.into_file_path_params(db).await?);
+						}
+
+						params
+					};
+
+					let mut file_paths = db
+						.file_path()
+						.find_many(params)
+						.include(file_path_with_object::include())
+						.exec()
+						.await?
+						.into_iter()
+						.filter_map(|file_path| {
+							matches
+								.remove(&file_path.id)
+								.map(|content_match| (content_match, file_path))
+						})
+						.collect::<Vec<_>>();
+
+					file_paths.sort_by(|(a, _), (b, _)| a.rank.total_cmp(&b.rank));
+					file_paths.truncate(take);
+
+					let mut items = Vec::with_capacity(file_paths.len());
+					let mut ranks_and_snippets = Vec::with_capacity(file_paths.len());
+
+					for (content_match, file_path) in file_paths {
+						let thumbnail_exists_locally = if let Some(cas_id) = &file_path.cas_id {
+							library
+								.thumbnail_exists(&node, cas_id)
+								.await
+								.map_err(LocationError::from)?
+						} else {
+							false
+						};
+
+						items.push(ExplorerItem::Path {
+							thumbnail: file_path
+								.cas_id
+								.as_ref()
+								.filter(|_| thumbnail_exists_locally)
+								.map(|i| get_indexed_thumb_key(i, library.id)),
+							item: file_path,
+						});
+						ranks_and_snippets.push((content_match.rank, content_match.snippet));
+					}
+
+					let (nodes, items) = items.normalise(|item| item.id());
+
+					Ok(ContentSearchData {
+						hits: items
+							.into_iter()
+							.zip(ranks_and_snippets)
+							.map(|(item, (rank, snippet))| ContentSearchHit {
+								item,
+								rank,
+								snippet,
+							})
+							.collect(),
+						nodes,
+						truncated,
+					})
+				},
+			)
+		})
 		.procedure("objects", {
 			#[derive(Deserialize, Type, Debug)]
 			#[serde(rename_all = "camelCase")]
@@ -394,6 +673,114 @@ pub fn mount() -> AlphaRouter<Ctx> {
 				},
 			)
 		})
+		.procedure("similarImages", {
+			#[derive(Deserialize, Type, Debug)]
+			#[serde(rename_all = "camelCase")]
+			struct SimilarImagesArgs {
+				object_id: prisma::object::id::Type,
+				#[specta(optional)]
+				max_distance: Option<u8>,
+				#[specta(optional)]
+				take: Option<u8>,
+			}
+
+			#[derive(Serialize, Type, Debug)]
+			struct SimilarImage {
+				item: Reference<ExplorerItem>,
+				/// Differing bits between the perceptual hashes, 0 means they look the same
+				distance: u32,
+			}
+
+			#[derive(Serialize, Type, Debug)]
+			struct SimilarImagesData {
+				hits: Vec<SimilarImage>,
+				nodes: Vec<CacheNode>,
+			}
+
+			R.with2(library()).query(
+				|(node, library),
+				 SimilarImagesArgs {
+				     object_id,
+				     max_distance,
+				     take,
+				 }| async move {
+					let Library { db, .. } = library.as_ref();
+
+					let max_distance = max_distance
+						.unwrap_or(DEFAULT_SIMILARITY_DISTANCE)
+						.min(MAX_SIMILARITY_DISTANCE);
+					let take = take.unwrap_or(MAX_TAKE).min(MAX_TAKE) as usize;
+
+					let Some(similar) =
+						find_similar_objects(db, object_id, u32::from(max_distance), take).await?
+					else {
+						return Err(rspc::Error::new(
+							ErrorCode::BadRequest,
+							"Object doesn't have a perceptual hash yet".to_string(),
+						));
+					};
+
+					let distances = similar.into_iter().collect::<HashMap<_, _>>();
+
+					let mut objects = db
+						.object()
+						.find_many(vec![prisma::object::id::in_vec(
+							distances.keys().copied().collect(),
+						)])
+						.include(object_with_file_paths::include())
+						.exec()
+						.await?
+						.into_iter()
+						.filter_map(|object| {
+							distances
+								.get(&object.id)
+								.map(|distance| (*distance, object))
+						})
+						.collect::<Vec<_>>();
+
+					objects.sort_by_key(|(distance, _)| *distance);
+
+					let mut items = Vec::with_capacity(objects.len());
+					let mut item_distances = Vec::with_capacity(objects.len());
+
+					for (distance, object) in objects {
+						let cas_id = object
+							.file_paths
+							.iter()
+							.map(|fp| fp.cas_id.as_ref())
+							.find_map(|c| c);
+
+						let thumbnail_exists_locally = if let Some(cas_id) = cas_id {
+							library
+								.thumbnail_exists(&node, cas_id)
+								.await
+								.map_err(LocationError::from)?
+						} else {
+							false
+						};
+
+						items.push(ExplorerItem::Object {
+							thumbnail: cas_id
+								.filter(|_| thumbnail_exists_locally)
+								.map(|cas_id| get_indexed_thumb_key(cas_id, library.id)),
+							item: object,
+						});
+						item_distances.push(distance);
+					}
+
+					let (nodes, items) = items.normalise(|item| item.id());
+
+					Ok(SimilarImagesData {
+						hits: items
+							.into_iter()
+							.zip(item_distances)
+							.map(|(item, distance)| SimilarImage { item, distance })
+							.collect(),
+						nodes,
+					})
+				},
+			)
+		})
 		.procedure("objectsCount", {
 			#[derive(Deserialize, Type, Debug)]
 			#[serde(rename_all = "camelCase")]
@@ -424,3 +811,274 @@ pub fn mount() -> AlphaRouter<Ctx> {
 		})
 		.merge("saved.", saved::mount())
 }
+
+/// The params of the filters, except for the name patterns among the top level ones that SQLite
+/// can't evaluate. Their matchers are returned instead, to be matched on the file paths a query
+/// pages through, see [`find_file_paths_matching_names`]
+async fn split_name_patterns(
+	db: &PrismaClient,
+	filters: Vec<SearchFilterArgs>,
+) -> Result<(Vec<prisma::file_path::WhereParam>, Vec<TextMatcher>), rspc::Error> {
+	let mut params = Vec::new();
+	let mut matchers = Vec::new();
+
+	for filter in filters {
+		match filter {
+			SearchFilterArgs::FilePath(filter) => match filter.into_name_pattern()? {
+				Either::Left(filter) => params.extend(filter.into_params(db).await?),
+				Either::Right(NamePattern { scope, matcher }) => {
+					params.extend(scope);
+					matchers.push(matcher);
+				}
+			},
+			filter => params.extend(filter.into_file_path_params(db).await?),
+		}
+	}
+
+	Ok((params, matchers))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use uuid::Uuid;
+
+	async fn db_with_files() -> std::sync::Arc<PrismaClient> {
+		let db = sd_prisma::test_db().await;
+		db._db_push().await.unwrap();
+
+		for (name, extension, hidden) in [
+			("a", "jpg", false),
+			("b", "png", false),
+			("c", "jpg", true),
+			("d", "txt", true),
+		] {
+			db.file_path()
+				.create(
+					Uuid::new_v4().as_bytes().to_vec(),
+					vec![
+						prisma::file_path::name::set(Some(name.into())),
+						prisma::file_path::extension::set(Some(extension.into())),
+						prisma::file_path::hidden::set(Some(hidden)),
+					],
+				)
+				.exec()
+				.await
+				.unwrap();
+		}
+
+		db
+	}
+
+	async fn matching_names(
+		db: &PrismaClient,
+		filter: SearchFilterArgs,
+	) -> Result<Vec<String>, rspc::Error> {
+		let mut names = db
+			.file_path()
+			.find_many(filter.into_file_path_params(db).await?)
+			.exec()
+			.await?
+			.into_iter()
+			.filter_map(|file_path| file_path.name)
+			.collect::<Vec<_>>();
+
+		names.sort();
+
+		Ok(names)
+	}
+
+	fn extension(extension: &str) -> SearchFilterArgs {
+		SearchFilterArgs::FilePath(FilePathFilterArgs::Extension(InOrNotIn::In(vec![
+			extension.into(),
+		])))
+	}
+
+	fn hidden(hidden: bool) -> SearchFilterArgs {
+		SearchFilterArgs::FilePath(FilePathFilterArgs::Hidden(hidden))
+	}
+
+	fn negate(filter: SearchFilterArgs) -> SearchFilterArgs {
+		SearchFilterArgs::Not(Box::new(filter))
+	}
+
+	#[tokio::test]
+	async fn nested_filter_groups() {
+		use SearchFilterArgs::{And, Or};
+
+		let db = db_with_files().await;
+
+		assert_eq!(
+			matching_names(
+				&db,
+				And(vec![
+					Or(vec![extension("jpg"), extension("png")]),
+					negate(hidden(true))
+				])
+			)
+			.await
+			.unwrap(),
+			["a", "b"]
+		);
+		assert_eq!(
+			matching_names(
+				&db,
+				Or(vec![
+					extension("txt"),
+					And(vec![extension("jpg"), hidden(true)])
+				])
+			)
+			.await
+			.unwrap(),
+			["c", "d"]
+		);
+		assert_eq!(
+			matching_names(&db, negate(Or(vec![extension("jpg"), hidden(true)])))
+				.await
+				.unwrap(),
+			["b"]
+		);
+	}
+
+	#[tokio::test]
+	async fn empty_filter_groups_match_everything() {
+		use SearchFilterArgs::{And, Or};
+
+		let db = db_with_files().await;
+		let everything = ["a", "b", "c", "d"];
+
+		assert_eq!(matching_names(&db, And(vec![])).await.unwrap(), everything);
+		assert_eq!(matching_names(&db, Or(vec![])).await.unwrap(), everything);
+		assert_eq!(
+			matching_names(&db, negate(And(vec![]))).await.unwrap(),
+			everything
+		);
+		assert_eq!(
+			matching_names(&db, negate(Or(vec![]))).await.unwrap(),
+			everything
+		);
+		assert_eq!(
+			matching_names(&db, Or(vec![extension("txt"), And(vec![])]))
+				.await
+				.unwrap(),
+			everything
+		);
+	}
+
+	#[tokio::test]
+	async fn name_patterns() {
+		let db = db_with_files().await;
+
+		let name = |v| SearchFilterArgs::FilePath(FilePathFilterArgs::Name(v));
+
+		// Matched in SQL
+		assert_eq!(
+			matching_names(&db, name(TextMatch::Glob("B*".into())))
+				.await
+				.unwrap(),
+			["b"]
+		);
+		// Narrowed down in SQL and matched in Rust, regardless of the collation of the column
+		assert_eq!(
+			matching_names(&db, name(TextMatch::Glob("*.JPG".into())))
+				.await
+				.unwrap(),
+			["a", "c"]
+		);
+		assert_eq!(
+			matching_names(&db, name(TextMatch::Glob("b*.png".into())))
+				.await
+				.unwrap(),
+			["b"]
+		);
+		// Matched in Rust
+		assert_eq!(
+			matching_names(&db, name(TextMatch::Glob("[ab].*".into())))
+				.await
+				.unwrap(),
+			["a", "b"]
+		);
+		assert_eq!(
+			matching_names(&db, name(TextMatch::Regex(r"^[cd]\.".into())))
+				.await
+				.unwrap(),
+			["c", "d"]
+		);
+
+		assert!(matching_names(
+			&db,
+			SearchFilterArgs::Object(ObjectFilterArgs::MediaData(
+				media_data::MediaDataFilterArgs::CameraMake(TextMatch::Glob("Can*".into()))
+			))
+		)
+		.await
+		.is_err());
+	}
+
+	/// Names of a page of the file paths matching the filters, by descending name
+	async fn page_names(
+		db: &PrismaClient,
+		filters: Vec<SearchFilterArgs>,
+		skip: usize,
+		take: Option<usize>,
+	) -> Vec<String> {
+		let (params, matchers) = split_name_patterns(db, filters).await.unwrap();
+
+		let ids = find_file_paths_matching_names(
+			|| {
+				db.file_path()
+					.find_many(params.clone())
+					.order_by(prisma::file_path::name::order(prisma::SortOrder::Desc))
+			},
+			&matchers,
+			skip,
+			take,
+		)
+		.await
+		.unwrap();
+
+		let mut file_paths = db
+			.file_path()
+			.find_many(vec![prisma::file_path::id::in_vec(ids.clone())])
+			.exec()
+			.await
+			.unwrap();
+		file_paths.sort_by_key(|file_path| ids.iter().position(|id| *id == file_path.id));
+
+		file_paths
+			.into_iter()
+			.filter_map(|file_path| file_path.name)
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn name_patterns_are_matched_on_the_requested_page() {
+		let db = db_with_files().await;
+
+		let filters = || {
+			vec![SearchFilterArgs::FilePath(FilePathFilterArgs::Name(
+				TextMatch::Regex(r"^[a-c]\.".into()),
+			))]
+		};
+
+		assert_eq!(page_names(&db, filters(), 0, Some(2)).await, ["c", "b"]);
+		assert_eq!(page_names(&db, filters(), 1, Some(2)).await, ["b", "a"]);
+		assert_eq!(page_names(&db, filters(), 0, None).await, ["c", "b", "a"]);
+	}
+
+	#[tokio::test]
+	async fn filter_groups_nested_too_deep_are_rejected() {
+		let db = db_with_files().await;
+
+		let nested = |depth| (0..depth).fold(extension("jpg"), |filter, _| negate(filter));
+
+		assert_eq!(
+			matching_names(&db, nested(MAX_FILTER_DEPTH)).await.unwrap(),
+			["a", "c"]
+		);
+		assert!(matching_names(&db, nested(MAX_FILTER_DEPTH + 1))
+			.await
+			.is_err());
+	}
+}
@@ -1,6 +1,8 @@
 // use crate::library::Category;
 
-use sd_prisma::prisma::{self, label_on_object, object, tag_on_object};
+use sd_prisma::prisma::{
+	self, label_on_object, object, object_in_album, object_in_space, tag_on_object,
+};
 
 use chrono::{DateTime, FixedOffset};
 use prisma_client_rust::{not, or, OrderByQuery, PaginatedQuery, WhereQuery};
@@ -114,14 +116,17 @@ pub enum ObjectFilterArgs {
 	Kind(InOrNotIn<i32>),
 	Tags(InOrNotIn<i32>),
 	Labels(InOrNotIn<i32>),
+	Albums(InOrNotIn<i32>),
+	Spaces(InOrNotIn<i32>),
 	DateAccessed(Range<chrono::DateTime<FixedOffset>>),
+	MediaData(MediaDataFilterArgs),
 }
 
 impl ObjectFilterArgs {
-	pub fn into_params(self) -> Vec<object::WhereParam> {
+	pub fn into_params(self) -> Result<Vec<object::WhereParam>, rspc::Error> {
 		use object::*;
 
-		match self {
+		Ok(match self {
 			Self::Favorite(v) => vec![favorite::equals(Some(v))],
 			Self::Hidden(v) => v.to_param().map(|v| vec![v]).unwrap_or_default(),
 			Self::Tags(v) => v
@@ -138,6 +143,20 @@ impl ObjectFilterArgs {
 				)
 				.map(|v| vec![v])
 				.unwrap_or_default(),
+			Self::Albums(v) => v
+				.into_param(
+					|v| albums::some(vec![object_in_album::album_id::in_vec(v)]),
+					|v| albums::none(vec![object_in_album::album_id::in_vec(v)]),
+				)
+				.map(|v| vec![v])
+				.unwrap_or_default(),
+			Self::Spaces(v) => v
+				.into_param(
+					|v| spaces::some(vec![object_in_space::space_id::in_vec(v)]),
+					|v| spaces::none(vec![object_in_space::space_id::in_vec(v)]),
+				)
+				.map(|v| vec![v])
+				.unwrap_or_default(),
 			Self::Kind(v) => v
 				.into_param(kind::in_vec, kind::not_in_vec)
 				.map(|v| vec![v])
@@ -151,7 +170,16 @@ impl ObjectFilterArgs {
 					},
 				]
 			}
-		}
+			Self::MediaData(v) => {
+				let params = v.into_params()?;
+
+				if params.is_empty() {
+					vec![]
+				} else {
+					vec![media_data::is(params)]
+				}
+			}
+		})
 	}
 }
 
@@ -1,5 +1,8 @@
 use sd_prisma::prisma;
 
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use rspc::ErrorCode;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
@@ -42,14 +45,14 @@ impl From<SortOrder> for prisma::SortOrder {
 // 	}
 // }
 
-#[derive(Deserialize, Type, Debug)]
+#[derive(Deserialize, Type, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CursorOrderItem<T> {
 	pub order: SortOrder,
 	pub data: T,
 }
 
-#[derive(Deserialize, Type, Debug)]
+#[derive(Deserialize, Type, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum OrderAndPagination<TId, TOrder, TCursor> {
 	OrderOnly(TOrder),
@@ -93,6 +96,12 @@ pub enum TextMatch {
 	StartsWith(String),
 	EndsWith(String),
 	Equals(String),
+	/// Regular expression, matched case insensitively against the whole file name (extension included)
+	Regex(String),
+	/// Glob pattern, matched case insensitively against the whole file name (extension included)
+	Glob(String),
+	/// Typo tolerant match, allowing a few edits between the text and any part of the file name
+	Fuzzy(String),
 }
 
 impl TextMatch {
@@ -102,10 +111,20 @@ impl TextMatch {
 			Self::StartsWith(v) => v.is_empty(),
 			Self::EndsWith(v) => v.is_empty(),
 			Self::Equals(v) => v.is_empty(),
+			Self::Regex(v) => v.is_empty(),
+			Self::Glob(v) => v.is_empty(),
+			Self::Fuzzy(v) => v.is_empty(),
 		}
 	}
 
+	/// Pattern matches can't be expressed as prisma params in general, so they
+	/// must be evaluated in Rust through a [`TextMatcher`]
+	pub fn is_pattern(&self) -> bool {
+		matches!(self, Self::Regex(_) | Self::Glob(_) | Self::Fuzzy(_))
+	}
+
 	// 3. Update the to_param method of TextMatch
+	/// Returns `None` for empty and pattern matches, see [`TextMatch::is_pattern`]
 	pub fn into_param<TParam>(
 		self,
 		contains_fn: fn(String) -> TParam,
@@ -120,6 +139,146 @@ impl TextMatch {
 				Self::StartsWith(v) => Some(starts_with_fn(v)),
 				Self::EndsWith(v) => Some(ends_with_fn(v)),
 				Self::Equals(v) => Some(equals_fn(v)),
+				Self::Regex(_) | Self::Glob(_) | Self::Fuzzy(_) => None,
 			})
 	}
+
+	pub fn into_matcher(self) -> Result<TextMatcher, rspc::Error> {
+		Ok(match self {
+			Self::Contains(v) => TextMatcher::Contains(v.to_lowercase()),
+			Self::StartsWith(v) => TextMatcher::StartsWith(v.to_lowercase()),
+			Self::EndsWith(v) => TextMatcher::EndsWith(v.to_lowercase()),
+			Self::Equals(v) => TextMatcher::Equals(v.to_lowercase()),
+			Self::Regex(v) => TextMatcher::Regex(
+				RegexBuilder::new(&v)
+					.case_insensitive(true)
+					.build()
+					.map_err(|e| {
+						rspc::Error::with_cause(
+							ErrorCode::BadRequest,
+							"Invalid regex pattern".to_string(),
+							e,
+						)
+					})?,
+			),
+			Self::Glob(v) => TextMatcher::Glob(
+				GlobBuilder::new(&v)
+					.case_insensitive(true)
+					.build()
+					.map_err(|e| {
+						rspc::Error::with_cause(
+							ErrorCode::BadRequest,
+							"Invalid glob pattern".to_string(),
+							e,
+						)
+					})?
+					.compile_matcher(),
+			),
+			Self::Fuzzy(v) => {
+				let pattern = v.to_lowercase().chars().collect::<Vec<_>>();
+				TextMatcher::Fuzzy {
+					max_distance: fuzzy_max_distance(pattern.len()),
+					pattern,
+				}
+			}
+		})
+	}
+}
+
+#[derive(Debug)]
+pub enum TextMatcher {
+	Contains(String),
+	StartsWith(String),
+	EndsWith(String),
+	Equals(String),
+	Regex(Regex),
+	Glob(GlobMatcher),
+	Fuzzy {
+		pattern: Vec<char>,
+		max_distance: usize,
+	},
+}
+
+impl TextMatcher {
+	pub fn is_match(&self, text: &str) -> bool {
+		match self {
+			Self::Contains(v) => text.to_lowercase().contains(v),
+			Self::StartsWith(v) => text.to_lowercase().starts_with(v),
+			Self::EndsWith(v) => text.to_lowercase().ends_with(v),
+			Self::Equals(v) => text.to_lowercase() == *v,
+			Self::Regex(regex) => regex.is_match(text),
+			Self::Glob(glob) => glob.is_match(text),
+			Self::Fuzzy {
+				pattern,
+				max_distance,
+			} => {
+				substring_edit_distance(pattern, &text.to_lowercase().chars().collect::<Vec<_>>())
+					<= *max_distance
+			}
+		}
+	}
+}
+
+/// How many typos we tolerate for a fuzzy search term of the given length
+const fn fuzzy_max_distance(pattern_len: usize) -> usize {
+	match pattern_len {
+		0..=3 => 0,
+		4..=7 => 1,
+		_ => 2,
+	}
+}
+
+/// Smallest Levenshtein distance between `pattern` and any substring of `text`
+fn substring_edit_distance(pattern: &[char], text: &[char]) -> usize {
+	// Starting a match at any position of the text is free, so the first row is all zeros
+	let mut prev = vec![0; text.len() + 1];
+	let mut curr = vec![0; text.len() + 1];
+
+	for (i, p) in pattern.iter().enumerate() {
+		curr[0] = i + 1;
+
+		for (j, t) in text.iter().enumerate() {
+			let substitution = prev[j] + usize::from(p != t);
+			curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+		}
+
+		std::mem::swap(&mut prev, &mut curr);
+	}
+
+	prev.into_iter().min().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn distance(pattern: &str, text: &str) -> usize {
+		substring_edit_distance(
+			&pattern.chars().collect::<Vec<_>>(),
+			&text.chars().collect::<Vec<_>>(),
+		)
+	}
+
+	#[test]
+	fn substring_distance() {
+		assert_eq!(distance("", "anything"), 0);
+		assert_eq!(distance("holiday", "img_holiday_2023"), 0);
+		assert_eq!(distance("holiday", "img_holliday_2023"), 1);
+		assert_eq!(distance("report", "final_rpeort.pdf"), 2);
+		assert_eq!(distance("abc", ""), 3);
+	}
+
+	#[test]
+	fn matchers() {
+		let matcher = |m: TextMatch| m.into_matcher().unwrap();
+
+		assert!(matcher(TextMatch::Regex(r"^img_\d{4}\.jpe?g$".into())).is_match("IMG_0042.JPG"));
+		assert!(!matcher(TextMatch::Regex(r"^img_\d{4}$".into())).is_match("IMG_0042.JPG"));
+		assert!(matcher(TextMatch::Glob("*_v[0-9].psd".into())).is_match("Poster_V3.psd"));
+		assert!(!matcher(TextMatch::Glob("*.psd".into())).is_match("poster.png"));
+		assert!(matcher(TextMatch::Fuzzy("vacation".into())).is_match("2023_vacaton.mp4"));
+		assert!(!matcher(TextMatch::Fuzzy("cat".into())).is_match("cut.png"));
+		assert!(matcher(TextMatch::Contains("DUMP".into())).is_match("camera_dump.raw"));
+		assert!(TextMatch::Regex("(".into()).into_matcher().is_err());
+	}
 }
@@ -0,0 +1,370 @@
+use crate::{invalidate_query, library::Library};
+
+use sd_cache::{Normalise, NormalisedResult, NormalisedResults};
+use sd_prisma::{
+	prisma::{object, object_in_space, space},
+	prisma_sync,
+};
+use sd_sync::OperationFactory;
+use sd_utils::{chain_optional_iter, msgpack, uuid_to_bytes};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use rspc::{alpha::AlphaRouter, ErrorCode};
+use serde::Deserialize;
+use specta::Type;
+use uuid::Uuid;
+
+use super::{utils::library, Ctx, R};
+
+#[derive(Type, Deserialize)]
+#[specta(inline)]
+struct SpaceObjectsArgs {
+	space_id: space::id::Type,
+	object_ids: Vec<object::id::Type>,
+}
+
+async fn find_space_pub_id(
+	library: &Library,
+	space_id: space::id::Type,
+) -> Result<space::pub_id::Type, rspc::Error> {
+	Ok(library
+		.db
+		.space()
+		.find_unique(space::id::equals(space_id))
+		.select(space::select!({ pub_id }))
+		.exec()
+		.await?
+		.ok_or_else(|| rspc::Error::new(ErrorCode::NotFound, "Space not found".to_string()))?
+		.pub_id)
+}
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				let spaces = library.db.space().find_many(vec![]).exec().await?;
+
+				let (nodes, items) = spaces.normalise(|i| i.id.to_string());
+
+				Ok(NormalisedResults { nodes, items })
+			})
+		})
+		.procedure("getForObject", {
+			R.with2(library())
+				.query(|(_, library), object_id: object::id::Type| async move {
+					let spaces = library
+						.db
+						.space()
+						.find_many(vec![space::objects::some(vec![
+							object_in_space::object_id::equals(object_id),
+						])])
+						.exec()
+						.await?;
+
+					let (nodes, items) = spaces.normalise(|i| i.id.to_string());
+
+					Ok(NormalisedResults { nodes, items })
+				})
+		})
+		.procedure("get", {
+			R.with2(library())
+				.query(|(_, library), space_id: space::id::Type| async move {
+					Ok(library
+						.db
+						.space()
+						.find_unique(space::id::equals(space_id))
+						.exec()
+						.await?
+						.map(|space| NormalisedResult::from(space, |i| i.id.to_string())))
+				})
+		})
+		.procedure("create", {
+			#[derive(Type, Deserialize)]
+			#[specta(inline)]
+			struct SpaceCreateArgs {
+				name: String,
+				description: Option<String>,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: SpaceCreateArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let pub_id = uuid_to_bytes(Uuid::new_v4());
+					let date_created: DateTime<FixedOffset> = Utc::now().into();
+
+					let space = sync
+						.write_ops(
+							db,
+							(
+								sync.shared_create(
+									prisma_sync::space::SyncId {
+										pub_id: pub_id.clone(),
+									},
+									chain_optional_iter(
+										[
+											(space::name::NAME, msgpack!(&args.name)),
+											(space::date_created::NAME, msgpack!(&date_created)),
+										],
+										[args
+											.description
+											.as_ref()
+											.map(|v| (space::description::NAME, msgpack!(v)))],
+									),
+								),
+								db.space().create(
+									pub_id,
+									vec![
+										space::name::set(Some(args.name)),
+										space::description::set(args.description),
+										space::date_created::set(Some(date_created)),
+									],
+								),
+							),
+						)
+						.await?;
+
+					invalidate_query!(library, "spaces.list");
+
+					Ok(NormalisedResult::from(space, |i| i.id.to_string()))
+				})
+		})
+		.procedure("update", {
+			#[derive(Type, Deserialize)]
+			#[specta(inline)]
+			struct SpaceUpdateArgs {
+				id: space::id::Type,
+				name: Option<String>,
+				description: Option<String>,
+			}
+
+			R.with2(library())
+				.mutation(|(_, library), args: SpaceUpdateArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let pub_id = find_space_pub_id(&library, args.id).await?;
+					let date_modified: DateTime<FixedOffset> = Utc::now().into();
+
+					let (sync_params, db_params): (Vec<_>, Vec<_>) = [
+						args.name.map(|v| {
+							((space::name::NAME, msgpack!(&v)), space::name::set(Some(v)))
+						}),
+						args.description.map(|v| {
+							(
+								(space::description::NAME, msgpack!(&v)),
+								space::description::set(Some(v)),
+							)
+						}),
+						Some((
+							(space::date_modified::NAME, msgpack!(&date_modified)),
+							space::date_modified::set(Some(date_modified)),
+						)),
+					]
+					.into_iter()
+					.flatten()
+					.unzip();
+
+					sync.write_ops(
+						db,
+						(
+							sync_params
+								.into_iter()
+								.map(|(k, v)| {
+									sync.shared_update(
+										prisma_sync::space::SyncId {
+											pub_id: pub_id.clone(),
+										},
+										k,
+										v,
+									)
+								})
+								.collect(),
+							db.space().update(space::id::equals(args.id), db_params),
+						),
+					)
+					.await?;
+
+					invalidate_query!(library, "spaces.list");
+					invalidate_query!(library, "spaces.get");
+					invalidate_query!(library, "spaces.getForObject");
+
+					Ok(())
+				})
+		})
+		.procedure("delete", {
+			R.with2(library())
+				.mutation(|(_, library), space_id: space::id::Type| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let space = db
+						.space()
+						.find_unique(space::id::equals(space_id))
+						.select(space::select!({
+							pub_id
+							objects: select { object: select { pub_id } }
+						}))
+						.exec()
+						.await?
+						.ok_or_else(|| {
+							rspc::Error::new(ErrorCode::NotFound, "Space not found".to_string())
+						})?;
+
+					let relation_deletes = space
+						.objects
+						.into_iter()
+						.map(|o| {
+							sync.relation_delete(prisma_sync::object_in_space::SyncId {
+								space: prisma_sync::space::SyncId {
+									pub_id: space.pub_id.clone(),
+								},
+								object: prisma_sync::object::SyncId {
+									pub_id: o.object.pub_id,
+								},
+							})
+						})
+						.collect();
+
+					// Its objects are only unlinked if the space is deleted along with them
+					db._transaction()
+						.run(|tx| async move {
+							sync.write_ops(
+								&tx,
+								(
+									relation_deletes,
+									tx.object_in_space().delete_many(vec![
+										object_in_space::space_id::equals(space_id),
+									]),
+								),
+							)
+							.await?;
+
+							sync.write_op(
+								&tx,
+								sync.shared_delete(prisma_sync::space::SyncId {
+									pub_id: space.pub_id,
+								}),
+								tx.space().delete(space::id::equals(space_id)),
+							)
+							.await
+						})
+						.await?;
+
+					invalidate_query!(library, "spaces.list");
+					invalidate_query!(library, "spaces.get");
+					invalidate_query!(library, "spaces.getForObject");
+					invalidate_query!(library, "search.objects");
+
+					Ok(())
+				})
+		})
+		.procedure("addObjects", {
+			R.with2(library())
+				.mutation(|(_, library), args: SpaceObjectsArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let space_pub_id = find_space_pub_id(&library, args.space_id).await?;
+
+					let objects = db
+						.object()
+						.find_many(vec![object::id::in_vec(args.object_ids)])
+						.select(object::select!({ id pub_id }))
+						.exec()
+						.await?;
+
+					let (sync_ops, db_creates) = objects.into_iter().fold(
+						(vec![], vec![]),
+						|(mut sync_ops, mut db_creates), object| {
+							sync_ops.extend(sync.relation_create(
+								prisma_sync::object_in_space::SyncId {
+									space: prisma_sync::space::SyncId {
+										pub_id: space_pub_id.clone(),
+									},
+									object: prisma_sync::object::SyncId {
+										pub_id: object.pub_id,
+									},
+								},
+								[],
+							));
+
+							db_creates.push(object_in_space::CreateUnchecked {
+								space_id: args.space_id,
+								object_id: object.id,
+								_params: vec![],
+							});
+
+							(sync_ops, db_creates)
+						},
+					);
+
+					sync.write_ops(
+						db,
+						(
+							sync_ops,
+							db.object_in_space()
+								.create_many(db_creates)
+								.skip_duplicates(),
+						),
+					)
+					.await?;
+
+					invalidate_query!(library, "spaces.getForObject");
+					invalidate_query!(library, "search.objects");
+
+					Ok(())
+				})
+		})
+		.procedure("removeObjects", {
+			R.with2(library())
+				.mutation(|(_, library), args: SpaceObjectsArgs| async move {
+					let Library { db, sync, .. } = library.as_ref();
+
+					let space_pub_id = find_space_pub_id(&library, args.space_id).await?;
+
+					let objects = db
+						.object()
+						.find_many(vec![
+							object::id::in_vec(args.object_ids),
+							object::spaces::some(vec![object_in_space::space_id::equals(
+								args.space_id,
+							)]),
+						])
+						.select(object::select!({ id pub_id }))
+						.exec()
+						.await?;
+
+					let (sync_ops, object_ids): (Vec<_>, Vec<_>) = objects
+						.into_iter()
+						.map(|object| {
+							(
+								sync.relation_delete(prisma_sync::object_in_space::SyncId {
+									space: prisma_sync::space::SyncId {
+										pub_id: space_pub_id.clone(),
+									},
+									object: prisma_sync::object::SyncId {
+										pub_id: object.pub_id,
+									},
+								}),
+								object.id,
+							)
+						})
+						.unzip();
+
+					sync.write_ops(
+						db,
+						(
+							sync_ops,
+							db.object_in_space().delete_many(vec![
+								object_in_space::space_id::equals(args.space_id),
+								object_in_space::object_id::in_vec(object_ids),
+							]),
+						),
+					)
+					.await?;
+
+					invalidate_query!(library, "spaces.getForObject");
+					invalidate_query!(library, "search.objects");
+
+					Ok(())
+				})
+		})
+}
@@ -1,8 +1,14 @@
 use rspc::alpha::AlphaRouter;
 use sd_core_sync::GetOpsArgs;
-use std::sync::atomic::Ordering;
+use serde::Deserialize;
+use specta::Type;
+use std::{path::PathBuf, sync::atomic::Ordering};
 
-use crate::util::MaybeUndefined;
+use crate::{
+	library::{old_sync_compactor_job::OldSyncCompactorJobInit, sync_bundle, sync_diagnostics},
+	old_job::Job,
+	util::MaybeUndefined,
+};
 
 use super::{utils::library, Ctx, R};
 
@@ -75,6 +81,70 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
 					.load(Ordering::Relaxed))
 			})
 		})
+		.procedure("compact", {
+			R.with2(library())
+				.mutation(|(node, library), _: ()| async move {
+					Job::new(OldSyncCompactorJobInit {})
+						.spawn(&node, &library)
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("exportClocks", {
+			R.with2(library())
+				.mutation(|(_, library), path: PathBuf| async move {
+					sync_bundle::export_clocks(&library, path)
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("exportBundle", {
+			#[derive(Deserialize, Type)]
+			#[serde(rename_all = "camelCase")]
+			pub struct ExportBundleArgs {
+				path: PathBuf,
+				/// The clocks exported by the instance the bundle is for, every operation is
+				/// exported without them
+				clocks_path: Option<PathBuf>,
+			}
+
+			R.with2(library()).mutation(
+				|(_, library), ExportBundleArgs { path, clocks_path }: ExportBundleArgs| async move {
+					sync_bundle::export(&library, path, clocks_path)
+						.await
+						.map_err(Into::into)
+				},
+			)
+		})
+		.procedure("importBundle", {
+			R.with2(library())
+				.mutation(|(_, library), path: PathBuf| async move {
+					sync_bundle::import(&library, path)
+						.await
+						.map_err(Into::into)
+				})
+		})
+		.procedure("diagnostics", {
+			R.with2(library()).query(|(_, library), _: ()| async move {
+				Ok(sync_diagnostics::diagnostics(&library).await?)
+			})
+		})
+		.procedure("recordHistory", {
+			#[derive(Deserialize, Type)]
+			#[serde(rename_all = "camelCase")]
+			pub struct RecordHistoryArgs {
+				model: String,
+				/// As found in the operations, eg. `{ "pub_id": [..] }`
+				#[specta(type = serde_json::Value)]
+				record_id: rmpv::Value,
+			}
+
+			R.with2(library()).query(
+				|(_, library), RecordHistoryArgs { model, record_id }: RecordHistoryArgs| async move {
+					Ok(sync_diagnostics::record_history(&library, &model, record_id).await?)
+				},
+			)
+		})
 		.procedure("active", {
 			R.with2(library())
 				.subscription(|(_, library), _: ()| async move {
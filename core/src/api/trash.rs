@@ -0,0 +1,54 @@
+use crate::{api::utils::library, object::fs::trash};
+
+use sd_prisma::prisma::{location, trashed_item, SortOrder};
+
+use rspc::alpha::AlphaRouter;
+
+use super::{Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+	R.router()
+		.procedure("list", {
+			R.with2(library()).query(
+				|(_, library), location_id: Option<location::id::Type>| async move {
+					Ok(library
+						.db
+						.trashed_item()
+						.find_many(
+							location_id
+								.map(|location_id| {
+									vec![trashed_item::location_id::equals(Some(location_id))]
+								})
+								.unwrap_or_default(),
+						)
+						.order_by(trashed_item::date_trashed::order(SortOrder::Desc))
+						.select(trashed_item::select!({
+							id
+							name
+							is_dir
+							original_path
+							location_id
+							date_trashed
+						}))
+						.exec()
+						.await?)
+				},
+			)
+		})
+		.procedure("restore", {
+			R.with2(library()).mutation(
+				|(node, library), ids: Vec<trashed_item::id::Type>| async move {
+					trash::restore(&node, &library, ids)
+						.await
+						.map_err(Into::into)
+				},
+			)
+		})
+		.procedure("purge", {
+			R.with2(library()).mutation(
+				|(_, library), ids: Vec<trashed_item::id::Type>| async move {
+					trash::purge(&library, ids).await.map_err(Into::into)
+				},
+			)
+		})
+}
@@ -60,7 +60,6 @@ pub async fn declare_actors(
 
 				move || {
 					receive::run_actor(
-						node.libraries.clone(),
 						db.clone(),
 						library_id,
 						instance_uuid,
@@ -1,12 +1,13 @@
-use crate::{library::Libraries, Node};
+use crate::{p2p::operations::pairing::instance_node, Node};
 
 use super::{err_break, CompressedCRDTOperations};
 use sd_cloud_api::RequestConfigProvider;
+use sd_core_sync::diagnostics::{Direction, Transport};
 use sd_p2p::RemoteIdentity;
 use sd_prisma::prisma::{cloud_crdt_operation, instance, PrismaClient, SortOrder};
 use sd_sync::CRDTOperation;
 use sd_utils::uuid_to_bytes;
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use std::{
 	collections::{hash_map::Entry, HashMap},
@@ -27,7 +28,6 @@ use uuid::Uuid;
 
 #[allow(clippy::too_many_arguments)]
 pub async fn run_actor(
-	libraries: Arc<Libraries>,
 	db: Arc<PrismaClient>,
 	library_id: Uuid,
 	instance_uuid: Uuid,
@@ -162,11 +162,11 @@ pub async fn run_actor(
 							library_id,
 							&db,
 							&sync,
-							&libraries,
+							&node,
 							collection.instance_uuid,
 							instance.identity,
 							instance.node_id,
-							node.p2p.peer_metadata(),
+							instance.metadata.clone(),
 						)
 						.await
 					);
@@ -187,8 +187,17 @@ pub async fn run_actor(
 					operations.last().unwrap().timestamp.as_u64(),
 				);
 
+				let ops_count = operations.len();
+
 				err_break!(write_cloud_ops_to_db(operations, &db).await);
 
+				sync.diagnostics.record_exchange(
+					None,
+					Transport::Cloud,
+					Direction::Received,
+					ops_count,
+				);
+
 				let collection_timestamp: u64 =
 					collection.end_time.parse().expect("unable to parse time");
 
@@ -237,7 +246,7 @@ pub async fn upsert_instance(
 	library_id: Uuid,
 	db: &PrismaClient,
 	sync: &sd_core_sync::Manager,
-	libraries: &Libraries,
+	node: &Node,
 	uuid: Uuid,
 	identity: RemoteIdentity,
 	node_id: Uuid,
@@ -263,8 +272,15 @@ pub async fn upsert_instance(
 
 	sync.timestamps.write().await.entry(uuid).or_default();
 
+	// The node of the instance was let into the library, so it can sync with us like a paired peer
+	if let Some((identity, name)) = instance_node(&metadata) {
+		if let Err(e) = node.p2p.trust_library_peer(identity, name).await {
+			error!("Failed to trust the node of instance '{uuid}': {e:#?}");
+		}
+	}
+
 	// Called again so the new instances are picked up
-	libraries.update_instances_by_id(library_id).await;
+	node.libraries.update_instances_by_id(library_id).await;
 
 	Ok(())
 }
@@ -1,7 +1,10 @@
 use super::CompressedCRDTOperations;
 
 use sd_cloud_api::RequestConfigProvider;
-use sd_core_sync::{SyncMessage, NTP64};
+use sd_core_sync::{
+	diagnostics::{Direction, Transport},
+	SyncMessage, NTP64,
+};
 use tracing::debug;
 use uuid::Uuid;
 
@@ -109,6 +112,8 @@ pub async fn run_actor(
 				break;
 			}
 
+			let ops_count = instances.iter().map(|i| i.ops_count).sum();
+
 			// uses lock we acquired earlier to send the operations to the cloud
 			err_break!(
 				do_add(
@@ -118,6 +123,9 @@ pub async fn run_actor(
 				)
 				.await
 			);
+
+			sync.diagnostics
+				.record_exchange(None, Transport::Cloud, Direction::Sent, ops_count);
 		}
 
 		state.store(false, Ordering::Relaxed);
@@ -9,7 +9,7 @@ use crate::{
 #[cfg(feature = "ai")]
 use sd_ai::old_image_labeler::{DownloadModelError, OldImageLabeler, YoloV8};
 
-use api::notifications::{Notification, NotificationData, NotificationId};
+use api::notifications::{add_node_notification, Notification, NotificationData, NotificationId};
 use chrono::{DateTime, Utc};
 use node::config;
 use notifications::Notifications;
@@ -32,7 +32,6 @@ use tracing_subscriber::{filter::FromEnvError, prelude::*, EnvFilter};
 
 pub mod api;
 mod cloud;
-#[cfg(feature = "crypto")]
 pub(crate) mod crypto;
 pub mod custom_uri;
 mod env;
@@ -168,6 +167,7 @@ impl Node {
 		locations_actor.start(node.clone());
 		node.libraries.init(&node).await?;
 		jobs_actor.start(node.clone());
+		library::backup::start_scheduler(Arc::downgrade(&node));
 		start_p2p(
 			node.clone(),
 			axum::Router::new()
@@ -269,21 +269,28 @@ impl Node {
 		}
 	}
 
+	/// Notifies the user, replacing the notification of the same group if there's one
 	pub async fn emit_notification(&self, data: NotificationData, expires: Option<DateTime<Utc>>) {
 		let notification = Notification {
 			id: NotificationId::Node(self.notifications._internal_next_id()),
 			data,
 			read: false,
 			expires,
+			repeats: 0,
 		};
 
+		let mut stored = None;
 		match self
 			.config
-			.write(|cfg| cfg.notifications.push(notification.clone()))
+			.write(|cfg| {
+				stored = Some(add_node_notification(&mut cfg.notifications, notification));
+			})
 			.await
 		{
 			Ok(_) => {
-				self.notifications._internal_send(notification);
+				if let Some(notification) = stored {
+					self.notifications._internal_send(notification);
+				}
 			}
 			Err(err) => {
 				error!("Error saving notification to config: {:?}", err);
@@ -291,6 +298,20 @@ impl Node {
 		}
 	}
 
+	/// Removes the notifications of a group, once what they were about is dealt with
+	pub async fn dismiss_notification_group(&self, group: &str) {
+		if let Err(err) = self
+			.config
+			.write(|cfg| {
+				cfg.notifications
+					.retain(|n| n.data.group.as_deref() != Some(group))
+			})
+			.await
+		{
+			error!("Error removing notifications from config: {:?}", err);
+		}
+	}
+
 	pub async fn add_auth_header(&self, mut req: RequestBuilder) -> RequestBuilder {
 		if let Some(auth_token) = self.config.get().await.auth_token {
 			req = req.header("authorization", auth_token.to_header());
@@ -0,0 +1,132 @@
+//! Content defined chunking, so bytes inserted or removed in the middle of a file only change
+//! the chunks around them and the following ones can be reused by the next backup.
+
+use tokio::io::{self, AsyncRead, AsyncReadExt};
+
+const MIN_CHUNK_SIZE: usize = 256 * 1024;
+const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Chunks are cut where the rolling hash has these bits unset, so every 1 MiB on average
+const CUT_MASK: u64 = (1 << 20) - 1;
+
+/// Random values for the gear hash, generated with splitmix64 as they must never change between
+/// versions or chunks wouldn't be reused anymore
+const GEAR: [u64; 256] = {
+	let mut table = [0; 256];
+	let mut state: u64 = 0x5344_4241_434b_5550;
+	let mut i = 0;
+	while i < table.len() {
+		state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		table[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	table
+};
+
+/// Length of the chunk starting at the beginning of `data`, which holds at least
+/// `MAX_CHUNK_SIZE` bytes unless the end of the file is near
+fn chunk_len(data: &[u8]) -> usize {
+	if data.len() <= MIN_CHUNK_SIZE {
+		return data.len();
+	}
+
+	let end = data.len().min(MAX_CHUNK_SIZE);
+	let mut hash = 0u64;
+	for (i, byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE) {
+		hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+		if hash & CUT_MASK == 0 {
+			return i + 1;
+		}
+	}
+
+	end
+}
+
+pub(super) struct Chunker<R> {
+	reader: R,
+	buffer: Vec<u8>,
+	eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> Chunker<R> {
+	pub(super) fn new(reader: R) -> Self {
+		Self {
+			reader,
+			buffer: Vec::with_capacity(MAX_CHUNK_SIZE),
+			eof: false,
+		}
+	}
+
+	pub(super) async fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+		while !self.eof && self.buffer.len() < MAX_CHUNK_SIZE {
+			let len = self.buffer.len();
+			self.buffer.resize(MAX_CHUNK_SIZE, 0);
+			let read = self.reader.read(&mut self.buffer[len..]).await?;
+			self.buffer.truncate(len + read);
+			self.eof = read == 0;
+		}
+
+		if self.buffer.is_empty() {
+			return Ok(None);
+		}
+
+		let rest = self.buffer.split_off(chunk_len(&self.buffer));
+
+		Ok(Some(std::mem::replace(&mut self.buffer, rest)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn random_bytes(len: usize) -> Vec<u8> {
+		let mut state = 0x2545_f491_4f6c_dd1du64;
+		(0..len)
+			.map(|_| {
+				state ^= state << 13;
+				state ^= state >> 7;
+				state ^= state << 17;
+				state as u8
+			})
+			.collect()
+	}
+
+	async fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+		let mut chunker = Chunker::new(data);
+		let mut chunks = vec![];
+		while let Some(chunk) = chunker.next_chunk().await.unwrap() {
+			chunks.push(chunk);
+		}
+		chunks
+	}
+
+	#[tokio::test]
+	async fn chunks_cover_the_data() {
+		let data = random_bytes(20 * 1024 * 1024);
+		let chunks = chunks(&data).await;
+
+		assert_eq!(chunks.concat(), data);
+		assert!(chunks.iter().all(|chunk| chunk.len() <= MAX_CHUNK_SIZE));
+		assert!(chunks[..chunks.len() - 1]
+			.iter()
+			.all(|chunk| chunk.len() >= MIN_CHUNK_SIZE));
+
+		assert!(chunks(&[]).await.is_empty());
+	}
+
+	#[tokio::test]
+	async fn chunks_are_reused_after_an_insertion() {
+		let data = random_bytes(20 * 1024 * 1024);
+		let mut modified = data.clone();
+		modified.splice(3 * 1024 * 1024..3 * 1024 * 1024, random_bytes(4096));
+
+		let before = chunks(&data).await;
+		let after = chunks(&modified).await;
+
+		let reused = after.iter().filter(|chunk| before.contains(chunk)).count();
+		assert!(reused >= after.len() - 2);
+	}
+}
@@ -0,0 +1,145 @@
+//! Keys of encrypted backups are kept in the OS keyring (the Keychain on macOS and the Secret
+//! Service on Linux), so scheduled backups don't need the passphrase while the node's data
+//! directory, which holds the default destination, isn't enough to decrypt them. Where there is
+//! no keyring, eg. on Windows or without a Secret Service provider, the key is only kept in memory
+//! and the schedule is paused once the node restarts, until the passphrase is entered again.
+
+use crate::Node;
+
+use sd_crypto::types::Key;
+
+use std::{
+	collections::BTreeMap,
+	sync::{Mutex, PoisonError},
+};
+
+use tracing::{error, warn};
+use uuid::Uuid;
+
+static KEYS: Mutex<BTreeMap<Uuid, Key>> = Mutex::new(BTreeMap::new());
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+mod keyring {
+	use sd_crypto::{
+		keyring::{Identifier, Keyring, KeyringBackend},
+		types::Key,
+		Protected,
+	};
+
+	use uuid::Uuid;
+
+	#[cfg(target_os = "macos")]
+	const BACKEND: KeyringBackend = KeyringBackend::MacOS;
+	// The kernel keyring isn't used as it forgets keys after a reboot or a few days
+	#[cfg(target_os = "linux")]
+	const BACKEND: KeyringBackend =
+		KeyringBackend::Linux(sd_crypto::keyring::LinuxKeyring::SecretService);
+
+	fn identifier(library_id: Uuid) -> Identifier {
+		Identifier::new(&library_id.to_string(), "Backup key", "Spacedrive")
+	}
+
+	pub(super) fn get(library_id: Uuid) -> Result<Option<Key>, sd_crypto::Error> {
+		let keyring = Keyring::new(BACKEND)?;
+		let identifier = identifier(library_id);
+
+		if !keyring.contains_key(&identifier) {
+			return Ok(None);
+		}
+
+		Ok(keyring
+			.get(&identifier)?
+			.expose()
+			.as_slice()
+			.try_into()
+			.ok()
+			.map(Key::new))
+	}
+
+	pub(super) fn insert(library_id: Uuid, key: &Key) -> Result<(), sd_crypto::Error> {
+		Keyring::new(BACKEND)?.insert(
+			&identifier(library_id),
+			Protected::new(key.expose().to_vec()),
+		)
+	}
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+mod keyring {
+	use sd_crypto::types::Key;
+
+	use uuid::Uuid;
+
+	pub(super) fn get(_library_id: Uuid) -> Result<Option<Key>, sd_crypto::Error> {
+		Ok(None)
+	}
+
+	pub(super) fn insert(_library_id: Uuid, _key: &Key) -> Result<(), sd_crypto::Error> {
+		Ok(())
+	}
+}
+
+/// The key of the encrypted backups of a library, if this node knows it
+pub(super) async fn load(node: &Node, library_id: Uuid) -> Option<Key> {
+	if let Some(key) = KEYS
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.get(&library_id)
+	{
+		return Some(key.clone());
+	}
+
+	match keyring::get(library_id) {
+		Ok(Some(key)) => {
+			KEYS.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.insert(library_id, key.clone());
+
+			return Some(key);
+		}
+		Ok(None) => {}
+		Err(e) => {
+			warn!("Failed to read backup key of library '{library_id}' from the keyring: {e:#?}")
+		}
+	}
+
+	// Keys used to be stored in the node config, they're moved to the keyring once found there
+	let key = node
+		.config
+		.get()
+		.await
+		.backups
+		.get(&library_id)
+		.and_then(|config| config.legacy_key())?;
+
+	store(library_id, &key);
+
+	if let Err(e) = node
+		.config
+		.write(|config| {
+			if let Some(config) = config.backups.get_mut(&library_id) {
+				config.clear_legacy_key();
+			}
+		})
+		.await
+	{
+		error!(
+			"Failed to remove backup key of library '{library_id}' from the node config: {e:#?}"
+		);
+	}
+
+	Some(key)
+}
+
+pub(super) fn store(library_id: Uuid, key: &Key) {
+	if let Err(e) = keyring::insert(library_id, key) {
+		warn!(
+			"Failed to store backup key of library '{library_id}' in the keyring, it will be \
+			forgotten once the node restarts: {e:#?}"
+		);
+	}
+
+	KEYS.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.insert(library_id, key.clone());
+}
@@ -0,0 +1,44 @@
+//! Backups made before they were incremental: a tar.gz archive of the library config and
+//! database following the header. They can still be restored, but aren't made anymore.
+
+use sd_utils::error::FileIOError;
+
+use std::path::Path;
+
+use flate2::bufread::GzDecoder;
+use futures::executor::block_on;
+use tar::Archive;
+use tokio::{
+	fs::File,
+	io::{self, AsyncBufReadExt, AsyncReadExt, BufReader},
+};
+
+use super::BackupError;
+
+/// Unpacks the archive following the header, which was already read from `file`
+pub(super) fn unpack(file: BufReader<File>, to: impl AsRef<Path>) -> Result<(), BackupError> {
+	// Introducing this adapter here to bridge tokio stuff to std::io stuff
+	struct ReaderAdapter(BufReader<File>);
+
+	impl std::io::Read for ReaderAdapter {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			block_on(self.0.read(buf))
+		}
+	}
+
+	impl std::io::BufRead for ReaderAdapter {
+		fn fill_buf(&mut self) -> io::Result<&[u8]> {
+			block_on(self.0.fill_buf())
+		}
+
+		fn consume(&mut self, amt: usize) {
+			self.0.consume(amt)
+		}
+	}
+
+	let to = to.as_ref();
+
+	Archive::new(GzDecoder::new(ReaderAdapter(file)))
+		.unpack(to)
+		.map_err(|e| FileIOError::from((to, e, "Failed to unpack backup compressed data")).into())
+}
@@ -0,0 +1,1136 @@
+//! Libraries are backed up to repositories, one per library in each of the destinations set in
+//! its [`BackupSettings`]. Files are split in content defined chunks and only the chunks which
+//! aren't in the repository yet are written, so a backup only stores what changed since the
+//! previous ones. Backups can be encrypted with a key protected by a passphrase.
+//!
+//! Backups can also be made on a schedule, which is checked every [`BACKUP_CHECK_INTERVAL`],
+//! the older ones being removed according to a [`RetentionPolicy`].
+
+use crate::{
+	api::notifications::{NotificationCategory, NotificationData, NotificationKind},
+	invalidate_query,
+	location::schedule::ScanInterval,
+	node::config::NodeConfigError,
+	object::media::old_thumbnail::get_indexed_thumbnails_dir,
+	Node,
+};
+
+use sd_crypto::types::Key;
+use sd_prisma::prisma;
+use sd_utils::error::{FileIOError, NonUtf8PathError};
+
+use std::{
+	cmp,
+	collections::BTreeSet,
+	fmt,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex, PoisonError, Weak},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+use prisma_client_rust::{raw, NewClientError, PrismaValue, QueryError};
+use serde::{Deserialize, Serialize, Serializer};
+use specta::Type;
+use tempfile::tempdir_in;
+use thiserror::Error;
+use tokio::{
+	fs::{self, File},
+	io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+	time::sleep,
+};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use super::{Library, LibraryManagerError};
+
+mod chunker;
+mod keys;
+mod legacy;
+mod repository;
+
+pub use repository::RetentionPolicy;
+
+use repository::{Manifest, Repository};
+
+/// How often the scheduler checks if a backup is due
+const BACKUP_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+const BACKUPS_DIR: &str = "backups";
+const CONFIG_FILE: &str = "library.sdlibrary";
+const DB_FILE: &str = "library.db";
+const THUMBNAILS_DIR: &str = "thumbnails";
+
+/// Libraries being backed up, or whose backups are being removed
+static RUNNING: Mutex<BTreeSet<Uuid>> = Mutex::new(BTreeSet::new());
+
+/// Libraries whose scheduled backups are paused until the passphrase of their key is entered
+static AWAITING_KEY: Mutex<BTreeSet<Uuid>> = Mutex::new(BTreeSet::new());
+
+/// Where, when and what to back up of a library
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct BackupSettings {
+	/// Directories the backups are stored in, eg. on another drive. The `backups` directory of
+	/// the node is used when there is none.
+	pub destinations: Vec<PathBuf>,
+	/// Backups are made automatically when set
+	pub every: Option<ScanInterval>,
+	pub retention: RetentionPolicy,
+	pub include_thumbnails: bool,
+	/// The preferences of the library, eg. how each directory is displayed
+	pub include_preferences: bool,
+	/// Backups are encrypted with a key, itself protected by a passphrase
+	pub encrypted: bool,
+}
+
+impl Default for BackupSettings {
+	fn default() -> Self {
+		Self {
+			destinations: vec![],
+			every: None,
+			retention: RetentionPolicy::default(),
+			include_thumbnails: false,
+			include_preferences: true,
+			encrypted: false,
+		}
+	}
+}
+
+impl BackupSettings {
+	fn validate(&self) -> Result<(), BackupError> {
+		if let Some(every) = &self.every {
+			every
+				.validate()
+				.map_err(|e| BackupError::InvalidSettings(e.to_string()))?;
+		}
+
+		if let Some(destination) = self.destinations.iter().find(|d| !d.is_absolute()) {
+			return Err(BackupError::InvalidSettings(format!(
+				"destination '{}' isn't an absolute path",
+				destination.display()
+			)));
+		}
+
+		let RetentionPolicy {
+			keep_last,
+			keep_daily,
+			keep_weekly,
+			keep_monthly,
+		} = self.retention;
+
+		if [keep_last, keep_daily, keep_weekly, keep_monthly].contains(&Some(0)) {
+			return Err(BackupError::InvalidSettings(
+				"retention rules must keep at least one backup".to_string(),
+			));
+		}
+
+		Ok(())
+	}
+}
+
+/// The backup configuration of a library. It's stored in the node config, as the destinations
+/// are paths on this node.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LibraryBackupConfig {
+	#[serde(default)]
+	pub settings: BackupSettings,
+	/// The key of encrypted backups, as it used to be stored before being moved to the keyring.
+	/// See [`keys`].
+	#[serde(default, rename = "key", skip_serializing_if = "Option::is_none")]
+	legacy_key: Option<String>,
+	/// When a backup was last attempted
+	#[serde(default)]
+	pub last_backup: Option<DateTime<Utc>>,
+}
+
+impl fmt::Debug for LibraryBackupConfig {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("LibraryBackupConfig")
+			.field("settings", &self.settings)
+			.field(
+				"legacy_key",
+				&self.legacy_key.as_ref().map(|_| "<redacted>"),
+			)
+			.field("last_backup", &self.last_backup)
+			.finish()
+	}
+}
+
+impl LibraryBackupConfig {
+	fn legacy_key(&self) -> Option<Key> {
+		self.legacy_key
+			.as_ref()
+			.and_then(|key| base91::slice_decode(key.as_bytes()).try_into().ok())
+			.map(Key::new)
+	}
+
+	fn clear_legacy_key(&mut self) {
+		self.legacy_key = None;
+	}
+
+	fn next_backup(&self) -> Option<DateTime<Utc>> {
+		let every = self.settings.every.as_ref()?;
+
+		match self.last_backup {
+			Some(last_backup) => every.next_after(last_backup),
+			None => Some(Utc::now()),
+		}
+	}
+}
+
+/// The backup settings of a library, along with when it was last and will next be backed up
+#[derive(Serialize, Type, Debug)]
+pub struct LibraryBackupStatus {
+	pub settings: BackupSettings,
+	pub last_backup: Option<DateTime<Utc>>,
+	pub next_backup: Option<DateTime<Utc>>,
+	/// Whether the key of encrypted backups is known, otherwise the passphrase is required to
+	/// enable encryption and scheduled encrypted backups are paused
+	pub has_key: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+	#[error("a backup of this library is already running")]
+	AlreadyRunning,
+	#[error("malformed header")]
+	MalformedHeader,
+	#[error("malformed backup manifest")]
+	MalformedManifest,
+	#[error("the backup doesn't contain the library database and config")]
+	IncompleteBackup,
+	#[error("Library already exists, please remove it and try again!")]
+	LibraryAlreadyExists,
+	#[error("invalid backup settings: {0}")]
+	InvalidSettings(String),
+	#[error("wrong passphrase")]
+	WrongPassphrase,
+	#[error("the key of the encrypted backups isn't known, their passphrase is required")]
+	MissingKey,
+	#[error("backup chunk '{0}' is missing or corrupted")]
+	CorruptedChunk(String),
+	#[error("file '{0}' of the backup doesn't match its checksum")]
+	CorruptedFile(String),
+
+	// Internal errors
+	#[error("library manager error: {0}")]
+	LibraryManager(#[from] LibraryManagerError),
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error("failed to open the database snapshot: {0}")]
+	NewClient(#[from] Box<NewClientError>),
+	#[error("crypto error: {0}")]
+	Crypto(#[from] sd_crypto::Error),
+	#[error("compression error: {0}")]
+	Compression(io::Error),
+	#[error("failed to encode backup manifest: {0}")]
+	ManifestEncode(#[from] rmp_serde::encode::Error),
+	#[error("failed to decode backup manifest: {0}")]
+	ManifestDecode(#[from] rmp_serde::decode::Error),
+	#[error(transparent)]
+	NodeConfig(#[from] NodeConfigError),
+	#[error(transparent)]
+	NonUtf8Path(#[from] NonUtf8PathError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<BackupError> for rspc::Error {
+	fn from(e: BackupError) -> Self {
+		use rspc::ErrorCode;
+
+		match e {
+			BackupError::AlreadyRunning | BackupError::LibraryAlreadyExists => {
+				Self::with_cause(ErrorCode::Conflict, e.to_string(), e)
+			}
+			BackupError::MalformedHeader | BackupError::InvalidSettings(_) => {
+				Self::with_cause(ErrorCode::BadRequest, e.to_string(), e)
+			}
+			BackupError::WrongPassphrase | BackupError::MissingKey => {
+				Self::with_cause(ErrorCode::Unauthorized, e.to_string(), e)
+			}
+			_ => Self::with_cause(ErrorCode::InternalServerError, e.to_string(), e),
+		}
+	}
+}
+
+/// Prevents a backup and a removal of backups of the same library from running concurrently
+struct RunningGuard(Uuid);
+
+impl RunningGuard {
+	fn acquire(library_id: Uuid) -> Result<Self, BackupError> {
+		if RUNNING
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(library_id)
+		{
+			Ok(Self(library_id))
+		} else {
+			Err(BackupError::AlreadyRunning)
+		}
+	}
+}
+
+impl Drop for RunningGuard {
+	fn drop(&mut self) {
+		RUNNING
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&self.0);
+	}
+}
+
+fn destinations(node: &Node, settings: &BackupSettings) -> Vec<PathBuf> {
+	if settings.destinations.is_empty() {
+		vec![node.data_dir.join(BACKUPS_DIR)]
+	} else {
+		settings.destinations.clone()
+	}
+}
+
+/// Backs up a library to each of its destinations, returning the paths of the new backups.
+///
+/// A destination failing doesn't prevent backing up to the other ones, the first error is
+/// returned once they were all tried.
+pub async fn backup(node: &Node, library: &Library, id: Uuid) -> Result<Vec<PathBuf>, BackupError> {
+	let _guard = RunningGuard::acquire(library.id)?;
+
+	let config = node
+		.config
+		.get()
+		.await
+		.backups
+		.remove(&library.id)
+		.unwrap_or_default();
+	let settings = &config.settings;
+
+	let key = if settings.encrypted {
+		Some(
+			keys::load(node, library.id)
+				.await
+				.ok_or(BackupError::MissingKey)?,
+		)
+	} else {
+		None
+	};
+
+	// Staged in the data directory instead of the system one, as the database can be large
+	let staging = tempdir_in(&node.data_dir).map_err(|e| {
+		FileIOError::from((
+			&node.data_dir,
+			e,
+			"Failed to create backup staging directory",
+		))
+	})?;
+	let files = stage_files(node, library, settings, staging.path()).await?;
+
+	let header = Header {
+		id,
+		timestamp: SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.expect("Time went backwards")
+			.as_millis(),
+		library_id: library.id,
+		library_name: library.config().await.name.to_string(),
+		incremental: true,
+		encrypted: key.is_some(),
+	};
+
+	let mut paths = vec![];
+	let mut first_error = None;
+
+	for destination in destinations(node, settings) {
+		match backup_to(
+			&destination,
+			key.clone(),
+			&header,
+			&files,
+			&settings.retention,
+		)
+		.await
+		{
+			Ok(path) => paths.push(path),
+			Err(e) => {
+				error!(
+					"Failed to back up library '{}' to '{}': {e:#?}",
+					library.id,
+					destination.display()
+				);
+				first_error.get_or_insert(e);
+			}
+		}
+	}
+
+	node.config
+		.write(|config| {
+			config.backups.entry(library.id).or_default().last_backup = Some(Utc::now());
+		})
+		.await?;
+
+	first_error.map_or(Ok(paths), Err)
+}
+
+/// The files to back up along with their name in the backup, including a copy of the database
+/// made in `staging`
+async fn stage_files(
+	node: &Node,
+	library: &Library,
+	settings: &BackupSettings,
+	staging: &Path,
+) -> Result<Vec<(PathBuf, String)>, BackupError> {
+	let db_path = staging.join(DB_FILE);
+	let db_path_str = db_path
+		.to_str()
+		.ok_or_else(|| NonUtf8PathError(db_path.clone().into_boxed_path()))?
+		.to_string();
+
+	// Unlike copying its file, this is consistent even if the library is being written to
+	library
+		.db
+		._execute_raw(raw!(
+			"VACUUM INTO {}",
+			PrismaValue::String(db_path_str.clone())
+		))
+		.exec()
+		.await?;
+
+	if !settings.include_preferences {
+		prisma::new_client_with_url(&format!("file:{db_path_str}"))
+			.await
+			.map_err(Box::new)?
+			.preference()
+			.delete_many(vec![])
+			.exec()
+			.await?;
+	}
+
+	let mut files = vec![
+		(
+			node.libraries
+				.libraries_dir
+				.join(format!("{}.sdlibrary", library.id)),
+			CONFIG_FILE.to_string(),
+		),
+		(db_path, DB_FILE.to_string()),
+	];
+
+	if settings.include_thumbnails {
+		let thumbnails_dir = get_indexed_thumbnails_dir(node, library.id);
+
+		for path in walk_files(&thumbnails_dir).await? {
+			let name = path
+				.strip_prefix(&thumbnails_dir)
+				.ok()
+				.and_then(|relative| relative.iter().map(|part| part.to_str()).collect())
+				.map(|parts: Vec<_>| format!("{THUMBNAILS_DIR}/{}", parts.join("/")));
+
+			match name {
+				Some(name) => files.push((path, name)),
+				None => warn!(
+					"Skipping thumbnail with a non UTF-8 path: {}",
+					path.display()
+				),
+			}
+		}
+	}
+
+	Ok(files)
+}
+
+async fn backup_to(
+	destination: &Path,
+	key: Option<Key>,
+	header: &Header,
+	files: &[(PathBuf, String)],
+	retention: &RetentionPolicy,
+) -> Result<PathBuf, BackupError> {
+	let root = Repository::root(destination, header.library_id);
+
+	// Without its key file, the backups couldn't be restored on another node
+	if key.is_some() && !Repository::has_key(&root).await {
+		return Err(BackupError::MissingKey);
+	}
+
+	let repository = Repository::open(root, key).await?;
+
+	let mut manifest = Manifest::default();
+	for (path, name) in files {
+		manifest
+			.files
+			.push(repository.write_file(path, name.clone()).await?);
+	}
+
+	let path = repository.write_snapshot(header, &manifest).await?;
+
+	if let Err(e) = repository.apply_retention(retention).await {
+		warn!(
+			"Failed to remove old backups from '{}': {e:#?}",
+			destination.display()
+		);
+	}
+
+	Ok(path)
+}
+
+/// Backs up a library, notifying the user if it fails
+pub async fn backup_and_notify(node: &Node, library: &Library, id: Uuid) {
+	match backup(node, library, id).await {
+		Ok(paths) => {
+			info!(
+				"Backup '{id}' for library '{}' created at {paths:?}!",
+				library.id
+			);
+			library.dismiss_notification_group("backup").await;
+		}
+		Err(BackupError::AlreadyRunning) => {
+			debug!(
+				"Skipping backup '{id}' as library '{}' is already being backed up",
+				library.id
+			);
+			return;
+		}
+		Err(e) => {
+			error!(
+				"Error with backup '{id}' for library '{}': {e:?}",
+				library.id
+			);
+
+			library
+				.emit_notification(
+					NotificationData::new(
+						"Backup failed",
+						format!("The library couldn't be backed up: {e}"),
+						NotificationKind::Error,
+					)
+					.with_category(NotificationCategory::Backup)
+					.with_group("backup"),
+					None,
+				)
+				.await;
+		}
+	}
+
+	// Even a failed backup may have succeeded in some destinations
+	invalidate_query!(library, "backups.getAll");
+}
+
+/// Makes the scheduled backups of the loaded libraries once they're due
+pub(crate) fn start_scheduler(node: Weak<Node>) {
+	tokio::spawn(async move {
+		loop {
+			sleep(BACKUP_CHECK_INTERVAL).await;
+
+			let Some(node) = node.upgrade() else {
+				break;
+			};
+
+			let configs = node.config.get().await.backups;
+
+			for library in node.libraries.get_all().await {
+				let Some(config) = configs.get(&library.id) else {
+					continue;
+				};
+
+				let is_due = config
+					.next_backup()
+					.is_some_and(|next_backup| next_backup <= Utc::now());
+
+				if !is_due {
+					continue;
+				}
+
+				// Instead of failing on every check, the schedule waits for the passphrase
+				if config.settings.encrypted && keys::load(&node, library.id).await.is_none() {
+					pause_until_key_entered(&library).await;
+					continue;
+				}
+
+				backup_and_notify(&node, &library, Uuid::new_v4()).await;
+			}
+		}
+	});
+}
+
+/// Asks once for the passphrase of the encrypted backups of a library, which the node forgot
+async fn pause_until_key_entered(library: &Library) {
+	if !AWAITING_KEY
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.insert(library.id)
+	{
+		return;
+	}
+
+	warn!(
+		"Pausing scheduled backups of library '{}' until the passphrase is entered",
+		library.id
+	);
+
+	library
+		.emit_notification(
+			NotificationData::new(
+				"Backups paused",
+				"The key of the encrypted backups isn't known anymore, enter their passphrase in \
+				the backup settings to resume them",
+				NotificationKind::Warning,
+			)
+			.with_category(NotificationCategory::Backup)
+			.with_group("backup"),
+			None,
+		)
+		.await;
+}
+
+/// A backup, with the path to restore or delete it
+#[derive(Serialize, Type, Debug)]
+pub struct Backup {
+	#[serde(flatten)]
+	pub header: Header,
+	pub path: PathBuf,
+}
+
+/// The backups in the default directory and the destinations of all libraries, including the
+/// ones made before backups were incremental. Destinations which can't be read, eg. on an
+/// unplugged drive, are skipped.
+pub async fn list(node: &Node) -> Vec<Backup> {
+	let mut directories = BTreeSet::from([node.data_dir.join(BACKUPS_DIR)]);
+	directories.extend(
+		node.config
+			.get()
+			.await
+			.backups
+			.into_values()
+			.flat_map(|config| config.settings.destinations),
+	);
+
+	let mut backups = vec![];
+	for directory in directories {
+		match list_directory(&directory).await {
+			Ok(found) => backups.extend(found),
+			Err(e) => warn!(
+				"Failed to list backups in '{}': {e:#?}",
+				directory.display()
+			),
+		}
+	}
+
+	backups
+}
+
+async fn list_directory(directory: &Path) -> Result<Vec<Backup>, BackupError> {
+	let mut read_dir = match fs::read_dir(directory).await {
+		Ok(read_dir) => read_dir,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => {
+			return Err(
+				FileIOError::from((directory, e, "Failed to read backups directory")).into(),
+			)
+		}
+	};
+
+	let mut backups = vec![];
+
+	while let Some(entry) = read_dir
+		.next_entry()
+		.await
+		.map_err(|e| FileIOError::from((directory, e, "Failed to read next entry to backup")))?
+	{
+		let entry_path = entry.path();
+
+		let metadata = entry.metadata().await.map_err(|e| {
+			FileIOError::from((&entry_path, e, "Failed to read metadata from backup entry"))
+		})?;
+
+		if metadata.is_dir() {
+			backups.extend(
+				Repository::snapshots(&entry_path)
+					.await?
+					.into_iter()
+					.map(|(header, path)| Backup { header, path }),
+			);
+		} else if metadata.is_file() {
+			let mut file = File::open(&entry_path)
+				.await
+				.map_err(|e| FileIOError::from((&entry_path, e, "Failed to open backup entry")))?;
+
+			match Header::read(&mut file, &entry_path).await {
+				Ok(header) => backups.push(Backup {
+					header,
+					path: entry_path,
+				}),
+				Err(e) => debug!("Skipping '{}': {e:#?}", entry_path.display()),
+			}
+		}
+	}
+
+	Ok(backups)
+}
+
+/// Restores a backup as a new library. All of its files are checked against their checksum
+/// before any of them is restored.
+///
+/// Encrypted backups are decrypted with the passphrase if given, otherwise with the key of the
+/// library if this node made them.
+pub async fn restore(
+	node: &Arc<Node>,
+	path: impl AsRef<Path>,
+	passphrase: Option<String>,
+) -> Result<Header, BackupError> {
+	let path = path.as_ref();
+
+	let mut file = BufReader::new(File::open(path).await.map_err(|e| {
+		FileIOError::from((path, e, "Failed trying to open backup file to be restored"))
+	})?);
+
+	let header = Header::read(&mut file, path).await?;
+
+	// TODO: Actually handle restoring into a library that exists. For now it's easier to error out.
+	let None = node.libraries.get_library(&header.library_id).await else {
+		return Err(BackupError::LibraryAlreadyExists);
+	};
+
+	let staging = tempdir_in(&node.data_dir).map_err(|e| {
+		FileIOError::from((
+			&node.data_dir,
+			e,
+			"Failed to create backup staging directory",
+		))
+	})?;
+
+	if header.incremental {
+		let root = Repository::root_of_snapshot(path).ok_or(BackupError::MalformedHeader)?;
+
+		let key = match (header.encrypted, passphrase) {
+			(false, _) => None,
+			(true, Some(passphrase)) => Some(Repository::unlock(&root, &passphrase).await?),
+			(true, None) => Some(
+				keys::load(node, header.library_id)
+					.await
+					.ok_or(BackupError::MissingKey)?,
+			),
+		};
+
+		let repository = Repository::open(root, key).await?;
+		let (_, manifest) = repository.read_snapshot(path).await?;
+
+		for file in &manifest.files {
+			repository
+				.restore_file(&header, file, staging.path().join(file.relative_path()?))
+				.await?;
+		}
+	} else {
+		legacy::unpack(file, staging.path())?;
+	}
+
+	install(node, header.library_id, staging.path()).await?;
+
+	Ok(header)
+}
+
+/// Moves the verified files of a backup from `staging` into place, then loads the library
+async fn install(node: &Arc<Node>, library_id: Uuid, staging: &Path) -> Result<(), BackupError> {
+	let config_path = staging.join(CONFIG_FILE);
+	let db_path = staging.join(DB_FILE);
+
+	for path in [&config_path, &db_path] {
+		if fs::metadata(path).await.is_err() {
+			return Err(BackupError::IncompleteBackup);
+		}
+	}
+
+	let config_restored_path = node
+		.libraries
+		.libraries_dir
+		.join(format!("{library_id}.sdlibrary"));
+	move_file(&config_path, &config_restored_path).await?;
+
+	let db_restored_path = node
+		.libraries
+		.libraries_dir
+		.join(format!("{library_id}.db"));
+	move_file(&db_path, &db_restored_path).await?;
+
+	let thumbnails_dir = staging.join(THUMBNAILS_DIR);
+	let thumbnails_restored_dir = get_indexed_thumbnails_dir(node, library_id);
+	for path in walk_files(&thumbnails_dir).await? {
+		if let Ok(relative) = path.strip_prefix(&thumbnails_dir) {
+			move_file(&path, &thumbnails_restored_dir.join(relative)).await?;
+		}
+	}
+
+	node.libraries
+		.load(
+			library_id,
+			db_restored_path,
+			config_restored_path,
+			None,
+			true,
+			node,
+		)
+		.await?;
+
+	Ok(())
+}
+
+/// Removes a backup, along with the chunks no other backup of its library uses
+pub async fn delete(node: &Node, path: impl AsRef<Path>) -> Result<(), BackupError> {
+	let path = path.as_ref();
+
+	let header = {
+		let mut file = File::open(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to open backup")))?;
+
+		Header::read(&mut file, path).await?
+	};
+
+	let root = if header.incremental {
+		Repository::root_of_snapshot(path)
+	} else {
+		None
+	};
+
+	let Some(root) = root else {
+		return fs::remove_file(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to remove backup")).into());
+	};
+
+	let _guard = RunningGuard::acquire(header.library_id)?;
+
+	let key = keys::load(node, header.library_id).await;
+
+	let repository = Repository::open(root, key).await?;
+	repository.remove_snapshot(path).await?;
+
+	// Chunks of encrypted backups can't be collected without their key, they're kept until then
+	if let Err(e) = repository.collect_garbage().await {
+		warn!("Failed to remove unused backup chunks: {e:#?}");
+	}
+
+	Ok(())
+}
+
+pub async fn get_settings(node: &Node, library_id: Uuid) -> LibraryBackupStatus {
+	let config = node
+		.config
+		.get()
+		.await
+		.backups
+		.remove(&library_id)
+		.unwrap_or_default();
+
+	LibraryBackupStatus {
+		next_backup: config.next_backup(),
+		has_key: keys::load(node, library_id).await.is_some(),
+		last_backup: config.last_backup,
+		settings: config.settings,
+	}
+}
+
+/// Sets the backup settings of a library.
+///
+/// Each destination stores the key of encrypted backups encrypted with the passphrase, so it's
+/// required to enable encryption, to change the passphrase and to add destinations. Backups made
+/// by another node of the same library in the destinations keep being readable, as their key is
+/// reused if the passphrase opens it.
+pub async fn set_settings(
+	node: &Node,
+	library_id: Uuid,
+	settings: BackupSettings,
+	passphrase: Option<String>,
+) -> Result<(), BackupError> {
+	settings.validate()?;
+
+	let known_key = keys::load(node, library_id).await;
+
+	let roots = destinations(node, &settings)
+		.into_iter()
+		.map(|destination| Repository::root(destination, library_id))
+		.collect::<Vec<_>>();
+
+	let mut new_key = None;
+
+	if settings.encrypted {
+		match (known_key, passphrase) {
+			(known_key, Some(passphrase)) => {
+				let key = match known_key {
+					Some(key) => key,
+					None => find_key(&roots, &passphrase)
+						.await?
+						.unwrap_or_else(Key::generate),
+				};
+
+				for root in &roots {
+					Repository::lock(root, &key, &passphrase).await?;
+				}
+
+				new_key = Some(key);
+			}
+			(Some(_), None) => {
+				for root in &roots {
+					if !Repository::has_key(root).await {
+						return Err(BackupError::MissingKey);
+					}
+				}
+			}
+			(None, None) => return Err(BackupError::MissingKey),
+		}
+	}
+
+	node.config
+		.write(|config| {
+			config.backups.entry(library_id).or_default().settings = settings;
+		})
+		.await?;
+
+	if let Some(key) = &new_key {
+		keys::store(library_id, key);
+
+		if AWAITING_KEY
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&library_id)
+		{
+			info!("Resuming scheduled backups of library '{library_id}'");
+
+			if let Some(library) = node.libraries.get_library(&library_id).await {
+				library.dismiss_notification_group("backup").await;
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// The key of existing backups in the destinations, if any
+async fn find_key(roots: &[PathBuf], passphrase: &str) -> Result<Option<Key>, BackupError> {
+	for root in roots {
+		match Repository::unlock(root, passphrase).await {
+			Ok(key) => return Ok(Some(key)),
+			Err(BackupError::MissingKey) => continue,
+			Err(e) => return Err(e),
+		}
+	}
+
+	Ok(None)
+}
+
+async fn walk_files(dir: &Path) -> Result<Vec<PathBuf>, BackupError> {
+	let mut files = vec![];
+	let mut dirs = vec![dir.to_path_buf()];
+
+	while let Some(dir) = dirs.pop() {
+		let mut read_dir = match fs::read_dir(&dir).await {
+			Ok(read_dir) => read_dir,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+			Err(e) => {
+				return Err(FileIOError::from((&dir, e, "Failed to read directory")).into());
+			}
+		};
+
+		while let Some(entry) = read_dir
+			.next_entry()
+			.await
+			.map_err(|e| FileIOError::from((&dir, e, "Failed to read directory entry")))?
+		{
+			let path = entry.path();
+			let file_type = entry
+				.file_type()
+				.await
+				.map_err(|e| FileIOError::from((&path, e, "Failed to read file type")))?;
+
+			if file_type.is_dir() {
+				dirs.push(path);
+			} else if file_type.is_file() {
+				files.push(path);
+			}
+		}
+	}
+
+	Ok(files)
+}
+
+/// Renames the file, falling back to copying it in case it's on another file system
+async fn move_file(from: &Path, to: &Path) -> Result<(), BackupError> {
+	if let Some(parent) = to.parent() {
+		fs::create_dir_all(parent)
+			.await
+			.map_err(|e| FileIOError::from((parent, e, "Failed to create directory")))?;
+	}
+
+	if fs::rename(from, to).await.is_err() {
+		fs::copy(from, to)
+			.await
+			.map_err(|e| FileIOError::from((to, e, "Failed to restore file from backup")))?;
+	}
+
+	Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Type)]
+pub struct Header {
+	// Backup unique id
+	pub id: Uuid,
+	// Time since epoch the backup was created at
+	#[specta(type = String)]
+	#[serde(serialize_with = "as_string")]
+	pub timestamp: u128,
+	// Library id
+	pub library_id: Uuid,
+	// Library display name
+	pub library_name: String,
+	// Whether the backup is stored in a repository along with the previous ones, otherwise it's
+	// a self-sufficient archive
+	pub incremental: bool,
+	// Whether the backup is encrypted
+	pub encrypted: bool,
+}
+
+fn as_string<T: ToString, S>(x: &T, s: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	s.serialize_str(&x.to_string())
+}
+
+impl Header {
+	const INCREMENTAL: u8 = 1;
+	const ENCRYPTED: u8 = 1 << 1;
+
+	fn date(&self) -> Option<DateTime<Local>> {
+		Local
+			.timestamp_millis_opt(i64::try_from(self.timestamp).ok()?)
+			.single()
+	}
+
+	async fn write(&self, file: &mut (impl AsyncWrite + Unpin)) -> Result<(), io::Error> {
+		// Version 1 had no flags and was always a tar.gz archive following the header
+		file.write_all(b"sdbkp2").await?;
+		file.write_all(&self.id.to_bytes_le()).await?;
+		file.write_all(&self.timestamp.to_le_bytes()).await?;
+		file.write_all(&self.library_id.to_bytes_le()).await?;
+		{
+			let mut flags = 0;
+			if self.incremental {
+				flags |= Self::INCREMENTAL;
+			}
+			if self.encrypted {
+				flags |= Self::ENCRYPTED;
+			}
+			file.write_all(&[flags]).await?;
+		}
+		{
+			let bytes = &self.library_name.as_bytes()
+				[..cmp::min(u32::MAX as usize, self.library_name.len())];
+			file.write_all(&(bytes.len() as u32).to_le_bytes()).await?;
+			file.write_all(bytes).await?;
+		}
+
+		Ok(())
+	}
+
+	async fn read(
+		file: &mut (impl AsyncRead + Unpin),
+		path: impl AsRef<Path>,
+	) -> Result<Self, BackupError> {
+		let mut buf = vec![0u8; 6 + 16 + 16 + 16];
+		let path = path.as_ref();
+		file.read_exact(&mut buf)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		let flags = match &buf[..6] {
+			b"sdbkp1" => 0,
+			b"sdbkp2" => file
+				.read_u8()
+				.await
+				.map_err(|e| FileIOError::from((path, e)))?,
+			_ => return Err(BackupError::MalformedHeader),
+		};
+
+		Ok(Self {
+			id: Uuid::from_bytes_le(
+				buf[6..22]
+					.try_into()
+					.map_err(|_| BackupError::MalformedHeader)?,
+			),
+			timestamp: u128::from_le_bytes(
+				buf[22..38]
+					.try_into()
+					.map_err(|_| BackupError::MalformedHeader)?,
+			),
+			library_id: Uuid::from_bytes_le(
+				buf[38..54]
+					.try_into()
+					.map_err(|_| BackupError::MalformedHeader)?,
+			),
+
+			library_name: {
+				let len = file
+					.read_u32_le()
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+
+				let mut name = vec![0; len as usize];
+				file.read_exact(&mut name)
+					.await
+					.map_err(|e| FileIOError::from((path, e)))?;
+
+				String::from_utf8(name).map_err(|_| BackupError::MalformedHeader)?
+			},
+			incremental: flags & Self::INCREMENTAL != 0,
+			encrypted: flags & Self::ENCRYPTED != 0,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_backup_header() {
+		let original = Header {
+			id: Uuid::new_v4(),
+			timestamp: 1234567890,
+			library_id: Uuid::new_v4(),
+			library_name: "Test Library".to_string(),
+			incremental: true,
+			encrypted: true,
+		};
+
+		let mut buf = Vec::new();
+		original.write(&mut buf).await.unwrap();
+
+		let decoded = Header::read(&mut buf.as_slice(), "").await.unwrap();
+		assert_eq!(original, decoded);
+	}
+
+	#[tokio::test]
+	async fn test_legacy_backup_header() {
+		let id = Uuid::new_v4();
+		let library_id = Uuid::new_v4();
+		let name = "Test Library";
+
+		let mut buf = b"sdbkp1".to_vec();
+		buf.extend(id.to_bytes_le());
+		buf.extend(1234567890u128.to_le_bytes());
+		buf.extend(library_id.to_bytes_le());
+		buf.extend((name.len() as u32).to_le_bytes());
+		buf.extend(name.as_bytes());
+
+		let decoded = Header::read(&mut buf.as_slice(), "").await.unwrap();
+		assert_eq!(
+			decoded,
+			Header {
+				id,
+				timestamp: 1234567890,
+				library_id,
+				library_name: name.to_string(),
+				incremental: false,
+				encrypted: false,
+			}
+		);
+	}
+}
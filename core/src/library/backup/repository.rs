@@ -0,0 +1,790 @@
+//! A backup repository holds the backups of a library in a destination directory:
+//!
+//! ```text
+//! <destination>/<library_id>/
+//!     key                     the master key, encrypted with the passphrase (encrypted only)
+//!     snapshots/<id>.bkp      a header followed by the manifest of the backup
+//!     chunks/<ab>/<abcd...>   compressed and optionally encrypted file chunks
+//! ```
+//!
+//! Chunks are named after the hash of their content, so a chunk already present in the
+//! repository is never written again and each backup only stores what changed since the
+//! previous ones.
+
+use sd_crypto::{
+	crypto::{Decryptor, Encryptor},
+	encoding::{decode, encode},
+	encrypted::Encrypted,
+	hashing::Hasher,
+	types::{Aad, Algorithm, HashingAlgorithm, Key, Nonce, Params, Salt, SecretKey},
+	Protected,
+};
+use sd_utils::error::FileIOError;
+
+use std::{
+	collections::HashSet,
+	io::{Read, Write},
+	path::{Component, Path, PathBuf},
+};
+
+use chrono::{DateTime, Datelike, TimeZone};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::{
+	fs::{self, File},
+	io::{self, AsyncReadExt, AsyncWriteExt, BufReader},
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{chunker::Chunker, BackupError, Header};
+
+const KEY_FILE: &str = "key";
+const SNAPSHOTS_DIR: &str = "snapshots";
+const CHUNKS_DIR: &str = "chunks";
+const SNAPSHOT_EXTENSION: &str = "bkp";
+
+const ALGORITHM: Algorithm = Algorithm::XChaCha20Poly1305;
+const CHUNK_ID_CONTEXT: &str = "spacedrive 2024-04-21 backup chunk ids";
+
+/// Everything needed to restore a backup, sealed like the chunks it references
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct Manifest {
+	pub files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct ManifestFile {
+	/// Relative path inside the backup, with `/` separators
+	pub path: String,
+	pub size: u64,
+	/// BLAKE3 hash of the whole file, checked once it's reassembled
+	pub checksum: String,
+	pub chunks: Vec<String>,
+}
+
+impl ManifestFile {
+	/// Paths come from the manifest, which isn't authenticated in unencrypted backups
+	pub fn relative_path(&self) -> Result<PathBuf, BackupError> {
+		let path = PathBuf::from(&self.path);
+
+		if path.as_os_str().is_empty()
+			|| !path.components().all(|c| matches!(c, Component::Normal(_)))
+		{
+			return Err(BackupError::MalformedManifest);
+		}
+
+		Ok(path)
+	}
+}
+
+/// How many backups are kept when a new one is made. A backup is kept if any of the rules keeps
+/// it, and nothing is ever removed when no rule is set.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+	/// The most recent backups
+	pub keep_last: Option<u32>,
+	/// The most recent backup of each of the last days which had one
+	pub keep_daily: Option<u32>,
+	/// The most recent backup of each of the last weeks which had one
+	pub keep_weekly: Option<u32>,
+	/// The most recent backup of each of the last months which had one
+	pub keep_monthly: Option<u32>,
+}
+
+impl RetentionPolicy {
+	fn is_unlimited(&self) -> bool {
+		self.keep_last.is_none()
+			&& self.keep_daily.is_none()
+			&& self.keep_weekly.is_none()
+			&& self.keep_monthly.is_none()
+	}
+
+	/// The backups which aren't kept by any rule
+	pub fn expired<Tz: TimeZone>(&self, backups: &[(Uuid, DateTime<Tz>)]) -> Vec<Uuid> {
+		if self.is_unlimited() {
+			return vec![];
+		}
+
+		let mut sorted = backups.iter().collect::<Vec<_>>();
+		sorted.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+		let mut kept = HashSet::new();
+
+		fn keep_per_period<Tz: TimeZone, P: PartialEq>(
+			sorted: &[&(Uuid, DateTime<Tz>)],
+			count: Option<u32>,
+			period: impl Fn(&DateTime<Tz>) -> P,
+			kept: &mut HashSet<Uuid>,
+		) {
+			let Some(count) = count else {
+				return;
+			};
+
+			let mut last_period = None;
+			let mut remaining = count;
+			for (id, date) in sorted {
+				if remaining == 0 {
+					break;
+				}
+
+				let current = period(date);
+				if last_period.as_ref() != Some(&current) {
+					kept.insert(*id);
+					remaining -= 1;
+					last_period = Some(current);
+				}
+			}
+		}
+
+		if let Some(keep_last) = self.keep_last {
+			kept.extend(sorted.iter().take(keep_last as usize).map(|(id, _)| *id));
+		}
+		keep_per_period(
+			&sorted,
+			self.keep_daily,
+			|date| date.date_naive(),
+			&mut kept,
+		);
+		keep_per_period(&sorted, self.keep_weekly, |date| date.iso_week(), &mut kept);
+		keep_per_period(
+			&sorted,
+			self.keep_monthly,
+			|date| (date.year(), date.month()),
+			&mut kept,
+		);
+
+		sorted
+			.into_iter()
+			.filter(|(id, _)| !kept.contains(id))
+			.map(|(id, _)| *id)
+			.collect()
+	}
+}
+
+pub(super) struct Repository {
+	root: PathBuf,
+	/// The master key of the library's backups, `None` if they aren't encrypted
+	key: Option<Key>,
+}
+
+impl Repository {
+	pub fn root(destination: impl AsRef<Path>, library_id: Uuid) -> PathBuf {
+		destination.as_ref().join(library_id.to_string())
+	}
+
+	/// The repository holding a snapshot, from the path of the snapshot
+	pub fn root_of_snapshot(snapshot_path: impl AsRef<Path>) -> Option<PathBuf> {
+		let snapshots_dir = snapshot_path.as_ref().parent()?;
+
+		if snapshots_dir.file_name()? != SNAPSHOTS_DIR {
+			return None;
+		}
+
+		snapshots_dir.parent().map(Path::to_path_buf)
+	}
+
+	pub async fn open(root: PathBuf, key: Option<Key>) -> Result<Self, BackupError> {
+		for dir in [root.join(SNAPSHOTS_DIR), root.join(CHUNKS_DIR)] {
+			fs::create_dir_all(&dir)
+				.await
+				.map_err(|e| FileIOError::from((&dir, e, "Failed to create backup repository")))?;
+		}
+
+		Ok(Self { root, key })
+	}
+
+	/// Reads the master key of a repository, encrypted with the passphrase in its key file
+	pub async fn unlock(root: impl AsRef<Path>, passphrase: &str) -> Result<Key, BackupError> {
+		let key_path = root.as_ref().join(KEY_FILE);
+
+		let bytes = match fs::read(&key_path).await {
+			Ok(bytes) => bytes,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(BackupError::MissingKey),
+			Err(e) => {
+				return Err(FileIOError::from((&key_path, e, "Failed to read backup key")).into())
+			}
+		};
+
+		let (salt, encrypted_key): (Salt, Encrypted<Key>) = decode(&bytes)?;
+
+		encrypted_key
+			.decrypt(&hash_passphrase(passphrase, salt)?)
+			.map_err(|e| match e {
+				sd_crypto::Error::Decrypt => BackupError::WrongPassphrase,
+				e => e.into(),
+			})
+	}
+
+	/// Stores the master key in the key file of a repository, encrypted with the passphrase,
+	/// replacing the previous one if any
+	pub async fn lock(
+		root: impl AsRef<Path>,
+		key: &Key,
+		passphrase: &str,
+	) -> Result<(), BackupError> {
+		let root = root.as_ref();
+		fs::create_dir_all(root)
+			.await
+			.map_err(|e| FileIOError::from((root, e, "Failed to create backup repository")))?;
+
+		let salt = Salt::generate();
+		let encrypted_key = Encrypted::new(&hash_passphrase(passphrase, salt)?, key, ALGORITHM)?;
+
+		write_atomically(&root.join(KEY_FILE), &encode(&(salt, encrypted_key))?).await
+	}
+
+	pub async fn has_key(root: impl AsRef<Path>) -> bool {
+		fs::metadata(root.as_ref().join(KEY_FILE)).await.is_ok()
+	}
+
+	/// The key to read a snapshot with, as the repository may also hold snapshots made before
+	/// encryption was enabled or after it was disabled
+	fn key_for(&self, header: &Header) -> Result<Option<&Key>, BackupError> {
+		if header.encrypted {
+			self.key.as_ref().map(Some).ok_or(BackupError::MissingKey)
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn chunk_id(key: Option<&Key>, plain: &[u8]) -> String {
+		match key {
+			Some(key) => {
+				blake3::keyed_hash(&blake3::derive_key(CHUNK_ID_CONTEXT, key.expose()), plain)
+			}
+			None => blake3::hash(plain),
+		}
+		.to_hex()
+		.to_string()
+	}
+
+	fn chunk_path(&self, id: &str) -> PathBuf {
+		self.root.join(CHUNKS_DIR).join(&id[..2]).join(id)
+	}
+
+	fn snapshot_path(&self, id: Uuid) -> PathBuf {
+		self.root
+			.join(SNAPSHOTS_DIR)
+			.join(format!("{id}.{SNAPSHOT_EXTENSION}"))
+	}
+
+	/// Compresses, then encrypts if a key is given
+	fn seal(key: Option<&Key>, plain: &[u8]) -> Result<Vec<u8>, BackupError> {
+		let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+		encoder
+			.write_all(plain)
+			.and_then(|()| encoder.finish())
+			.map_err(BackupError::Compression)
+			.and_then(|compressed| match key {
+				Some(key) => {
+					let nonce = Nonce::generate(ALGORITHM);
+					let encrypted =
+						Encryptor::encrypt_bytes(key, &nonce, ALGORITHM, &compressed, Aad::Null)?;
+
+					encode(&(nonce, encrypted)).map_err(Into::into)
+				}
+				None => Ok(compressed),
+			})
+	}
+
+	fn open_sealed(key: Option<&Key>, sealed: &[u8]) -> Result<Vec<u8>, BackupError> {
+		let compressed = match key {
+			Some(key) => {
+				let (nonce, encrypted): (Nonce, Vec<u8>) = decode(sealed)?;
+
+				Decryptor::decrypt_bytes(key, &nonce, ALGORITHM, &encrypted, Aad::Null)?
+					.into_inner()
+			}
+			None => sealed.to_vec(),
+		};
+
+		let mut plain = vec![];
+		DeflateDecoder::new(compressed.as_slice())
+			.read_to_end(&mut plain)
+			.map_err(BackupError::Compression)?;
+
+		Ok(plain)
+	}
+
+	/// Stores a file in the repository, only writing the chunks it doesn't already have
+	pub async fn write_file(
+		&self,
+		path: impl AsRef<Path>,
+		name: String,
+	) -> Result<ManifestFile, BackupError> {
+		let path = path.as_ref();
+
+		let file = File::open(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to open file to back up")))?;
+
+		let mut chunker = Chunker::new(BufReader::new(file));
+		let mut hasher = blake3::Hasher::new();
+		let mut size = 0;
+		let mut chunks = vec![];
+
+		while let Some(chunk) = chunker
+			.next_chunk()
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to read file to back up")))?
+		{
+			hasher.update(&chunk);
+			size += chunk.len() as u64;
+
+			let id = Self::chunk_id(self.key.as_ref(), &chunk);
+			let chunk_path = self.chunk_path(&id);
+
+			if fs::metadata(&chunk_path).await.is_err() {
+				if let Some(parent) = chunk_path.parent() {
+					fs::create_dir_all(parent).await.map_err(|e| {
+						FileIOError::from((parent, e, "Failed to create backup chunks directory"))
+					})?;
+				}
+
+				write_atomically(&chunk_path, &Self::seal(self.key.as_ref(), &chunk)?).await?;
+			} else {
+				debug!("Reusing backup chunk '{id}'");
+			}
+
+			chunks.push(id);
+		}
+
+		Ok(ManifestFile {
+			path: name,
+			size,
+			checksum: hasher.finalize().to_hex().to_string(),
+			chunks,
+		})
+	}
+
+	/// Reads a chunk, checking that its content still matches its id
+	async fn read_chunk(&self, key: Option<&Key>, id: &str) -> Result<Vec<u8>, BackupError> {
+		if id.len() < 2 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+			return Err(BackupError::MalformedManifest);
+		}
+
+		let chunk_path = self.chunk_path(id);
+		let sealed = match fs::read(&chunk_path).await {
+			Ok(sealed) => sealed,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				return Err(BackupError::CorruptedChunk(id.to_string()))
+			}
+			Err(e) => {
+				return Err(
+					FileIOError::from((&chunk_path, e, "Failed to read backup chunk")).into(),
+				)
+			}
+		};
+
+		let plain = Self::open_sealed(key, &sealed)
+			.map_err(|_| BackupError::CorruptedChunk(id.to_string()))?;
+
+		if Self::chunk_id(key, &plain) != id {
+			return Err(BackupError::CorruptedChunk(id.to_string()));
+		}
+
+		Ok(plain)
+	}
+
+	/// Reassembles a file of a backup at `path`, checking its size and checksum
+	pub async fn restore_file(
+		&self,
+		header: &Header,
+		file: &ManifestFile,
+		path: impl AsRef<Path>,
+	) -> Result<(), BackupError> {
+		let path = path.as_ref();
+		let key = self.key_for(header)?;
+
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)
+				.await
+				.map_err(|e| FileIOError::from((parent, e, "Failed to create directory")))?;
+		}
+
+		let mut out = File::create(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to create restored file")))?;
+		let mut hasher = blake3::Hasher::new();
+		let mut size = 0;
+
+		for id in &file.chunks {
+			let chunk = self.read_chunk(key, id).await?;
+			hasher.update(&chunk);
+			size += chunk.len() as u64;
+
+			out.write_all(&chunk)
+				.await
+				.map_err(|e| FileIOError::from((path, e, "Failed to write restored file")))?;
+		}
+
+		out.flush()
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to write restored file")))?;
+
+		if size != file.size || hasher.finalize().to_hex().as_str() != file.checksum {
+			return Err(BackupError::CorruptedFile(file.path.clone()));
+		}
+
+		Ok(())
+	}
+
+	pub async fn write_snapshot(
+		&self,
+		header: &Header,
+		manifest: &Manifest,
+	) -> Result<PathBuf, BackupError> {
+		let mut bytes = vec![];
+		header
+			.write(&mut bytes)
+			.await
+			.expect("writing to a Vec never fails");
+		bytes.extend(Self::seal(
+			self.key_for(header)?,
+			&rmp_serde::to_vec_named(manifest)?,
+		)?);
+
+		let path = self.snapshot_path(header.id);
+		write_atomically(&path, &bytes).await?;
+
+		Ok(path)
+	}
+
+	pub async fn read_snapshot(
+		&self,
+		path: impl AsRef<Path>,
+	) -> Result<(Header, Manifest), BackupError> {
+		let path = path.as_ref();
+
+		let bytes = fs::read(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to read backup")))?;
+
+		let mut reader = bytes.as_slice();
+		let header = Header::read(&mut reader, path).await?;
+		let manifest = rmp_serde::from_slice(
+			&Self::open_sealed(self.key_for(&header)?, reader)
+				.map_err(|_| BackupError::MalformedManifest)?,
+		)?;
+
+		Ok((header, manifest))
+	}
+
+	/// The headers of the snapshots in the repository, along with their path
+	pub async fn snapshots(root: impl AsRef<Path>) -> Result<Vec<(Header, PathBuf)>, BackupError> {
+		let snapshots_dir = root.as_ref().join(SNAPSHOTS_DIR);
+
+		let mut read_dir = match fs::read_dir(&snapshots_dir).await {
+			Ok(read_dir) => read_dir,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => {
+				return Err(FileIOError::from((
+					&snapshots_dir,
+					e,
+					"Failed to read backups directory",
+				))
+				.into())
+			}
+		};
+
+		let mut snapshots = vec![];
+		while let Some(entry) = read_dir.next_entry().await.map_err(|e| {
+			FileIOError::from((&snapshots_dir, e, "Failed to read next backup entry"))
+		})? {
+			let path = entry.path();
+			if path.extension().and_then(|ext| ext.to_str()) != Some(SNAPSHOT_EXTENSION) {
+				continue;
+			}
+
+			let mut file = File::open(&path)
+				.await
+				.map_err(|e| FileIOError::from((&path, e, "Failed to open backup")))?;
+
+			match Header::read(&mut file, &path).await {
+				Ok(header) => snapshots.push((header, path)),
+				Err(e) => warn!("Skipping unreadable backup '{}': {e:#?}", path.display()),
+			}
+		}
+
+		Ok(snapshots)
+	}
+
+	pub async fn remove_snapshot(&self, path: impl AsRef<Path>) -> Result<(), BackupError> {
+		let path = path.as_ref();
+
+		fs::remove_file(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e, "Failed to remove backup")).into())
+	}
+
+	/// Removes the snapshots the retention policy doesn't keep, then the chunks only they used
+	pub async fn apply_retention(&self, retention: &RetentionPolicy) -> Result<(), BackupError> {
+		let snapshots = Self::snapshots(&self.root).await?;
+
+		let expired = retention.expired(
+			&snapshots
+				.iter()
+				.filter_map(|(header, _)| Some((header.id, header.date()?)))
+				.collect::<Vec<_>>(),
+		);
+
+		if expired.is_empty() {
+			return Ok(());
+		}
+
+		for (header, path) in &snapshots {
+			if expired.contains(&header.id) {
+				self.remove_snapshot(path).await?;
+			}
+		}
+
+		self.collect_garbage().await
+	}
+
+	/// Removes the chunks no snapshot references anymore
+	pub async fn collect_garbage(&self) -> Result<(), BackupError> {
+		let mut referenced = HashSet::new();
+
+		for (_, path) in Self::snapshots(&self.root).await? {
+			// A snapshot we can't read could reference any chunk, so we don't remove any
+			let (_, manifest) = self.read_snapshot(&path).await.inspect_err(|e| {
+				warn!(
+					"Not removing unused backup chunks as '{}' couldn't be read: {e:#?}",
+					path.display()
+				);
+			})?;
+
+			referenced.extend(manifest.files.into_iter().flat_map(|file| file.chunks));
+		}
+
+		let chunks_dir = self.root.join(CHUNKS_DIR);
+		let mut shards = fs::read_dir(&chunks_dir)
+			.await
+			.map_err(|e| FileIOError::from((&chunks_dir, e, "Failed to read backup chunks")))?;
+
+		let mut removed = 0;
+		while let Some(shard) = shards
+			.next_entry()
+			.await
+			.map_err(|e| FileIOError::from((&chunks_dir, e, "Failed to read backup chunks")))?
+		{
+			let shard_path = shard.path();
+			let mut chunks = fs::read_dir(&shard_path)
+				.await
+				.map_err(|e| FileIOError::from((&shard_path, e, "Failed to read backup chunks")))?;
+
+			while let Some(chunk) = chunks
+				.next_entry()
+				.await
+				.map_err(|e| FileIOError::from((&shard_path, e, "Failed to read backup chunks")))?
+			{
+				let is_referenced = chunk
+					.file_name()
+					.to_str()
+					.map_or(false, |id| referenced.contains(id));
+
+				if !is_referenced {
+					let chunk_path = chunk.path();
+					fs::remove_file(&chunk_path).await.map_err(|e| {
+						FileIOError::from((&chunk_path, e, "Failed to remove backup chunk"))
+					})?;
+					removed += 1;
+				}
+			}
+		}
+
+		debug!(
+			"Removed {removed} unused chunks from backup repository '{}'",
+			self.root.display()
+		);
+
+		Ok(())
+	}
+}
+
+fn hash_passphrase(passphrase: &str, salt: Salt) -> Result<Key, BackupError> {
+	Hasher::hash_password(
+		HashingAlgorithm::Argon2id(Params::Standard),
+		&Protected::new(passphrase.as_bytes().to_vec()),
+		salt,
+		&SecretKey::Null,
+	)
+	.map_err(Into::into)
+}
+
+/// Writes to a temporary file first so an interrupted backup never leaves a truncated file,
+/// which could be mistaken for a complete chunk or snapshot
+async fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), BackupError> {
+	let tmp_path = path.with_extension("tmp");
+
+	fs::write(&tmp_path, bytes)
+		.await
+		.map_err(|e| FileIOError::from((&tmp_path, e, "Failed to write backup file")))?;
+
+	fs::rename(&tmp_path, path)
+		.await
+		.map_err(|e| FileIOError::from((path, e, "Failed to write backup file")).into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::Utc;
+
+	fn backups(dates: &[&str]) -> Vec<(Uuid, DateTime<Utc>)> {
+		dates
+			.iter()
+			.map(|date| (Uuid::new_v4(), date.parse().unwrap()))
+			.collect()
+	}
+
+	#[test]
+	fn retention_without_rules_keeps_everything() {
+		let backups = backups(&["2024-04-01T12:00:00Z", "2024-04-02T12:00:00Z"]);
+
+		assert!(RetentionPolicy::default().expired(&backups).is_empty());
+	}
+
+	#[test]
+	fn retention_keeps_the_last_backups() {
+		let backups = backups(&[
+			"2024-04-01T12:00:00Z",
+			"2024-04-03T12:00:00Z",
+			"2024-04-02T12:00:00Z",
+		]);
+
+		let policy = RetentionPolicy {
+			keep_last: Some(2),
+			..Default::default()
+		};
+
+		assert_eq!(policy.expired(&backups), vec![backups[0].0]);
+	}
+
+	#[test]
+	fn retention_keeps_the_most_recent_backup_of_each_period() {
+		let backups = backups(&[
+			"2024-02-10T12:00:00Z",
+			"2024-03-20T12:00:00Z",
+			"2024-04-01T09:00:00Z",
+			"2024-04-01T18:00:00Z",
+			"2024-04-02T12:00:00Z",
+			"2024-04-09T12:00:00Z",
+		]);
+
+		let daily = RetentionPolicy {
+			keep_daily: Some(2),
+			..Default::default()
+		};
+		let mut expired = daily.expired(&backups);
+		expired.sort();
+		let mut expected = vec![backups[0].0, backups[1].0, backups[2].0, backups[3].0];
+		expected.sort();
+		assert_eq!(expired, expected);
+
+		let weekly_and_monthly = RetentionPolicy {
+			keep_weekly: Some(2),
+			keep_monthly: Some(3),
+			..Default::default()
+		};
+		let mut expired = weekly_and_monthly.expired(&backups);
+		expired.sort();
+		let mut expected = vec![backups[2].0, backups[3].0];
+		expected.sort();
+		assert_eq!(expired, expected);
+	}
+
+	#[tokio::test]
+	async fn encrypted_repository_round_trip() {
+		let dir = tempfile::tempdir().unwrap();
+		let library_id = Uuid::new_v4();
+		let root = Repository::root(dir.path(), library_id);
+		let key = Key::generate();
+
+		Repository::lock(&root, &key, "correct horse")
+			.await
+			.unwrap();
+		assert!(matches!(
+			Repository::unlock(&root, "battery staple").await,
+			Err(BackupError::WrongPassphrase)
+		));
+		let key = Repository::unlock(&root, "correct horse").await.unwrap();
+
+		let repository = Repository::open(root, Some(key)).await.unwrap();
+
+		let source = dir.path().join("source");
+		let content = (0..3 * 1024 * 1024)
+			.map(|i| (i % 251) as u8)
+			.collect::<Vec<_>>();
+		fs::write(&source, &content).await.unwrap();
+
+		let file = repository
+			.write_file(&source, "library.db".to_string())
+			.await
+			.unwrap();
+		// Writing the same file again reuses all its chunks
+		let again = repository
+			.write_file(&source, "library.db".to_string())
+			.await
+			.unwrap();
+		assert_eq!(file.chunks, again.chunks);
+
+		let header = Header {
+			id: Uuid::new_v4(),
+			timestamp: 1234567890,
+			library_id,
+			library_name: "Test Library".to_string(),
+			incremental: true,
+			encrypted: true,
+		};
+		let snapshot_path = repository
+			.write_snapshot(&header, &Manifest { files: vec![file] })
+			.await
+			.unwrap();
+		assert_eq!(
+			Repository::root_of_snapshot(&snapshot_path).as_deref(),
+			Some(repository.root.as_path())
+		);
+
+		let (read_header, mut manifest) = repository.read_snapshot(&snapshot_path).await.unwrap();
+		assert_eq!(read_header, header);
+		let file = manifest.files.remove(0);
+
+		let restored = dir.path().join("restored");
+		repository
+			.restore_file(&header, &file, &restored)
+			.await
+			.unwrap();
+		assert_eq!(fs::read(&restored).await.unwrap(), content);
+
+		// Tampering with a chunk is detected
+		let chunk_path = repository.chunk_path(&file.chunks[0]);
+		let mut sealed = fs::read(&chunk_path).await.unwrap();
+		let last = sealed.len() - 1;
+		sealed[last] ^= 1;
+		fs::write(&chunk_path, sealed).await.unwrap();
+
+		assert!(matches!(
+			repository.restore_file(&header, &file, &restored).await,
+			Err(BackupError::CorruptedChunk(_))
+		));
+	}
+
+	#[test]
+	fn manifest_paths_stay_inside_the_backup() {
+		let file = |path: &str| ManifestFile {
+			path: path.to_string(),
+			size: 0,
+			checksum: String::new(),
+			chunks: vec![],
+		};
+
+		assert!(file("thumbnails/ab/abcdef.webp").relative_path().is_ok());
+		assert!(file("../library.db").relative_path().is_err());
+		assert!(file("/etc/passwd").relative_path().is_err());
+		assert!(file("").relative_path().is_err());
+	}
+}
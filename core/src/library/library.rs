@@ -1,10 +1,19 @@
 use crate::{
-	api::CoreEvent, cloud, object::media::old_thumbnail::get_indexed_thumbnail_path, sync, Node,
+	api::{
+		notifications::{
+			save_library_notification, Notification, NotificationData, NotificationId,
+		},
+		CoreEvent,
+	},
+	cloud,
+	notifications::Notifications,
+	object::media::old_thumbnail::get_indexed_thumbnail_path,
+	sync, Node,
 };
 
 use sd_file_path_helper::{file_path_to_full_path, IsolatedFilePathData};
 use sd_p2p::Identity;
-use sd_prisma::prisma::{file_path, location, PrismaClient};
+use sd_prisma::prisma::{file_path, location, notification, PrismaClient};
 use sd_utils::{db::maybe_missing, error::FileIOError};
 
 use std::{
@@ -14,8 +23,9 @@ use std::{
 	sync::Arc,
 };
 
+use chrono::{DateTime, Utc};
 use tokio::{fs, io, sync::broadcast, sync::RwLock};
-use tracing::warn;
+use tracing::{error, warn};
 use uuid::Uuid;
 
 use super::{LibraryConfig, LibraryManagerError};
@@ -52,6 +62,7 @@ pub struct Library {
 	// Look, I think this shouldn't be here but our current invalidation system needs it.
 	// TODO(@Oscar): Get rid of this with the new invalidation system.
 	event_bus_tx: broadcast::Sender<CoreEvent>,
+	notifications: Notifications,
 
 	pub actors: Arc<sd_actors::Actors>,
 }
@@ -96,6 +107,7 @@ impl Library {
 			do_cloud_sync,
 			env: node.env.clone(),
 			event_bus_tx: node.event_bus.0.clone(),
+			notifications: node.notifications.clone(),
 			actors,
 		})
 	}
@@ -123,6 +135,47 @@ impl Library {
 		}
 	}
 
+	/// Notifies the user, replacing the notification of the same group if there's one
+	pub async fn emit_notification(&self, data: NotificationData, expires: Option<DateTime<Utc>>) {
+		let bytes = match rmp_serde::to_vec_named(&data) {
+			Ok(bytes) => bytes,
+			Err(err) => {
+				error!("Error serializing notification data: {:?}", err);
+				return;
+			}
+		};
+
+		match save_library_notification(&self.db, &data, bytes, expires).await {
+			Ok(result) => {
+				self.notifications._internal_send(Notification {
+					id: NotificationId::Library(self.id, result.id as u32),
+					data,
+					read: false,
+					expires,
+					repeats: result.repeats as u32,
+				});
+			}
+			Err(err) => {
+				error!("Error saving notification to library db: {:?}", err);
+			}
+		}
+	}
+
+	/// Removes the notifications of a group, once what they were about is dealt with
+	pub async fn dismiss_notification_group(&self, group: &str) {
+		if let Err(err) = self
+			.db
+			.notification()
+			.delete_many(vec![notification::group_key::equals(Some(
+				group.to_string(),
+			))])
+			.exec()
+			.await
+		{
+			error!("Error removing notifications from library db: {:?}", err);
+		}
+	}
+
 	pub async fn thumbnail_exists(&self, node: &Node, cas_id: &str) -> Result<bool, FileIOError> {
 		let thumb_path = get_indexed_thumbnail_path(node, cas_id, self.id);
 
@@ -1,11 +1,15 @@
 use crate::{
-	api::{utils::InvalidateOperationEvent, CoreEvent},
+	api::{
+		notifications::{NotificationCategory, NotificationData, NotificationKind},
+		utils::InvalidateOperationEvent,
+		CoreEvent,
+	},
 	cloud, invalidate_query,
 	location::{
 		indexer,
 		metadata::{LocationMetadataError, SpacedriveLocationMetadataFile},
 	},
-	object::tag,
+	object::{fs::trash, tag},
 	p2p, sync,
 	util::{mpscrr, MaybeUndefined},
 	Node,
@@ -523,6 +527,20 @@ impl Libraries {
 			.await
 			.insert(library.id, Arc::clone(&library));
 
+		// The nodes of the other instances can sync with us, as they were let into the library
+		for (identity, name) in instances
+			.iter()
+			.filter(|i| i.id != instance.id)
+			.filter_map(|i| {
+				serde_json::from_slice::<HashMap<String, String>>(i.metadata.as_deref()?).ok()
+			})
+			.filter_map(|metadata| p2p::operations::pairing::instance_node(&metadata))
+		{
+			if let Err(e) = node.p2p.trust_library_peer(identity, name).await {
+				error!("Failed to trust the node of a library instance: {e:#?}");
+			}
+		}
+
 		if should_seed {
 			// library.orphan_remover.invoke().await;
 			indexer::rules::seed::new_or_existing_library(&library).await?;
@@ -547,6 +565,21 @@ impl Libraries {
 			error!("Failed to resume jobs for library. {:#?}", e);
 		}
 
+		tokio::spawn({
+			let library = Arc::downgrade(&library);
+			async move {
+				// Until the library is unloaded
+				while let Some(library) = library.upgrade() {
+					if let Err(e) = trash::purge_expired(&library).await {
+						error!("Failed to purge expired items from the trash: {e:#?}");
+					}
+					drop(library);
+
+					sleep(trash::TRASH_PURGE_INTERVAL).await;
+				}
+			}
+		});
+
 		tokio::spawn({
 			let this = self.clone();
 			let node = node.clone();
@@ -619,7 +652,7 @@ impl Libraries {
 											library.id,
 											&library.db,
 											&library.sync,
-											&node.libraries,
+											&node,
 											instance.uuid,
 											instance.identity,
 											instance.node_id,
@@ -700,7 +733,24 @@ async fn sync_rx_actor(
 				InvalidateOperationEvent::all(),
 			)),
 			SyncMessage::Created => {
-				p2p::sync::originator(library.id, &library.sync, &node.p2p).await
+				p2p::sync::originator(library.id, &library.identity, &library.sync, &node.p2p).await
+			}
+			SyncMessage::Quarantined(count) => {
+				library
+					.emit_notification(
+						NotificationData::new(
+							"Sync operations quarantined",
+							format!(
+								"{count} sync operations couldn't be applied and were set aside, \
+								the others keep syncing"
+							),
+							NotificationKind::Warning,
+						)
+						.with_category(NotificationCategory::Sync)
+						.with_group("sync-quarantine"),
+						None,
+					)
+					.await
 			}
 		}
 	}
@@ -1,9 +1,13 @@
+pub mod backup;
 mod config;
 #[allow(clippy::module_inception)]
 mod library;
 mod manager;
 mod name;
+pub mod old_sync_compactor_job;
 mod statistics;
+pub mod sync_bundle;
+pub mod sync_diagnostics;
 
 pub use config::*;
 pub use library::*;
@@ -0,0 +1,175 @@
+use crate::{
+	library::Library,
+	old_job::{
+		CurrentStep, JobError, JobInitOutput, JobReportUpdate, JobResult, JobRunMetadata,
+		JobStepOutput, StatefulJob, WorkerContext,
+	},
+};
+
+use sd_core_sync::compaction::{self, OperationLog, OperationLogStats};
+use sd_prisma::prisma::{location, PrismaClient};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct SyncCompactionStats {
+	pub operations: OperationLogStats,
+	pub cloud_operations: OperationLogStats,
+	pub database_bytes: u64,
+}
+
+impl SyncCompactionStats {
+	async fn get(db: &PrismaClient) -> Result<Self, JobError> {
+		Ok(Self {
+			operations: compaction::stats(db, OperationLog::Local).await?,
+			cloud_operations: compaction::stats(db, OperationLog::Cloud).await?,
+			database_bytes: compaction::database_size(db).await?,
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OldSyncCompactorJobData {
+	before: SyncCompactionStats,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SyncCompactorJobStep {
+	CollapseUpdates(OperationLog),
+	DropDeletedRecords(OperationLog),
+	DropIngestedCloudOperations,
+	Vacuum,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct OldSyncCompactorMetadata {
+	updates_collapsed: u64,
+	deleted_records_operations_dropped: u64,
+	ingested_cloud_operations_dropped: u64,
+}
+
+impl JobRunMetadata for OldSyncCompactorMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.updates_collapsed += new_data.updates_collapsed;
+		self.deleted_records_operations_dropped += new_data.deleted_records_operations_dropped;
+		self.ingested_cloud_operations_dropped += new_data.ingested_cloud_operations_dropped;
+	}
+}
+
+/// Compacts the sync operations logs of a library, see [`compaction`], and vacuums its database
+/// to give the freed space back.
+///
+/// The sizes before and after are kept in the job report's metadata.
+#[derive(Serialize, Deserialize, Hash, Debug)]
+pub struct OldSyncCompactorJobInit {}
+
+#[async_trait::async_trait]
+impl StatefulJob for OldSyncCompactorJobInit {
+	type Data = OldSyncCompactorJobData;
+	type Step = SyncCompactorJobStep;
+	type RunMetadata = OldSyncCompactorMetadata;
+
+	const NAME: &'static str = "sync_compactor";
+
+	/// Acts on the whole library, location ids start at 1 so it never matches a location
+	fn target_location(&self) -> location::id::Type {
+		0
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library { db, .. } = &*ctx.library;
+
+		*data = Some(OldSyncCompactorJobData {
+			before: SyncCompactionStats::get(db).await?,
+		});
+
+		Ok(vec![
+			SyncCompactorJobStep::CollapseUpdates(OperationLog::Local),
+			SyncCompactorJobStep::DropDeletedRecords(OperationLog::Local),
+			SyncCompactorJobStep::DropIngestedCloudOperations,
+			SyncCompactorJobStep::CollapseUpdates(OperationLog::Cloud),
+			SyncCompactorJobStep::DropDeletedRecords(OperationLog::Cloud),
+			SyncCompactorJobStep::Vacuum,
+		]
+		.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		CurrentStep { step, .. }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, sync, .. } = &*ctx.library;
+
+		let mut run_metadata = OldSyncCompactorMetadata::default();
+
+		match step {
+			SyncCompactorJobStep::CollapseUpdates(log) => {
+				ctx.progress(vec![JobReportUpdate::Message(
+					"Collapsing superseded updates".to_string(),
+				)]);
+
+				run_metadata.updates_collapsed =
+					compaction::collapse_superseded_updates(db, *log).await?;
+			}
+			SyncCompactorJobStep::DropDeletedRecords(log) => {
+				ctx.progress(vec![JobReportUpdate::Message(
+					"Dropping operations of deleted records".to_string(),
+				)]);
+
+				run_metadata.deleted_records_operations_dropped =
+					compaction::drop_deleted_records(sync, *log).await?;
+			}
+			SyncCompactorJobStep::DropIngestedCloudOperations => {
+				ctx.progress(vec![JobReportUpdate::Message(
+					"Dropping ingested cloud operations".to_string(),
+				)]);
+
+				run_metadata.ingested_cloud_operations_dropped =
+					compaction::drop_ingested_cloud_operations(sync).await?;
+			}
+			SyncCompactorJobStep::Vacuum => {
+				ctx.progress(vec![JobReportUpdate::Message(
+					"Vacuuming the database".to_string(),
+				)]);
+
+				compaction::vacuum(db).await?;
+			}
+		}
+
+		Ok(run_metadata.into())
+	}
+
+	async fn finalize(
+		&self,
+		ctx: &WorkerContext,
+		data: &Option<Self::Data>,
+		run_metadata: &Self::RunMetadata,
+	) -> JobResult {
+		let data = data
+			.as_ref()
+			.expect("critical error: missing data on job state");
+
+		let after = SyncCompactionStats::get(&ctx.library.db).await?;
+
+		info!(
+			"finalizing sync compactor job: {run_metadata:?}, database went from {} to {} bytes",
+			data.before.database_bytes, after.database_bytes,
+		);
+
+		Ok(Some(json!({
+			"init": self,
+			"run_metadata": run_metadata,
+			"before": data.before,
+			"after": after,
+		})))
+	}
+}
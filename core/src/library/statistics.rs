@@ -1,26 +1,48 @@
 use crate::{api::utils::get_size, library::Library, volume::get_volumes, Node};
 
-use sd_prisma::prisma::statistics;
+use sd_prisma::prisma::{location, statistics, statistics_history, PrismaClient, SortOrder};
+
+use std::{collections::HashSet, path::Path, time::Duration};
 
 use chrono::Utc;
+use prisma_client_rust::raw;
+use serde::{Deserialize, Serialize};
+use specta::Type;
 use tracing::info;
 
 use super::LibraryManagerError;
 
+/// A snapshot is added to the statistics history at most this often
+const STATISTICS_HISTORY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Deserialize)]
+struct RawBytes {
+	bytes: Option<f64>,
+}
+
+/// Statistics of the files of a location
+#[derive(Serialize, Deserialize, Type, Debug)]
+pub struct LocationStatistics {
+	pub location_id: location::id::Type,
+	pub file_count: i32,
+	pub total_bytes: String,
+	/// Bytes of the distinct files in the location, counting each duplicate only once
+	pub unique_bytes: String,
+}
+
+/// Statistics of the objects of an `ObjectKind`
+#[derive(Debug)]
+pub struct ObjectKindStatistics {
+	pub kind: i32,
+	pub count: i32,
+	pub total_bytes: String,
+}
+
 pub async fn update_library_statistics(
 	node: &Node,
 	library: &Library,
 ) -> Result<statistics::Data, LibraryManagerError> {
-	let volumes = get_volumes().await;
-
-	let mut total_capacity: u64 = 0;
-	let mut available_capacity: u64 = 0;
-	for volume in volumes {
-		total_capacity += volume.total_capacity;
-		available_capacity += volume.available_capacity;
-	}
-
-	let total_bytes_used = total_capacity - available_capacity;
+	let (total_capacity, available_capacity) = library_volumes_capacity(library).await?;
 
 	let library_db_size = get_size(
 		node.config
@@ -35,21 +57,42 @@ pub async fn update_library_statistics(
 		.await
 		.unwrap_or(0);
 
+	Ok(save_statistics(
+		&library.db,
+		total_capacity,
+		available_capacity,
+		library_db_size,
+		thumbnail_folder_size,
+	)
+	.await?)
+}
+
+/// Counts the objects and bytes of the library, storing them with the given sizes
+async fn save_statistics(
+	db: &PrismaClient,
+	total_capacity: u64,
+	available_capacity: u64,
+	library_db_size: u64,
+	thumbnail_folder_size: u64,
+) -> Result<statistics::Data, prisma_client_rust::QueryError> {
+	let total_object_count = db.object().count(vec![]).exec().await?;
+	let total_bytes_used = count_bytes_used(db).await?;
+	let total_unique_bytes = count_unique_bytes(db).await?;
+
 	use statistics::*;
 	let params = vec![
 		id::set(1), // Each library is a database so only one of these ever exists
 		date_captured::set(Utc::now().into()),
-		total_object_count::set(0),
+		total_object_count::set(total_object_count as i32),
 		library_db_size::set(library_db_size.to_string()),
 		total_bytes_used::set(total_bytes_used.to_string()),
 		total_bytes_capacity::set(total_capacity.to_string()),
-		total_unique_bytes::set(0.to_string()),
+		total_unique_bytes::set(total_unique_bytes.to_string()),
 		total_bytes_free::set(available_capacity.to_string()),
 		preview_media_bytes::set(thumbnail_folder_size.to_string()),
 	];
 
-	let stats = library
-		.db
+	let stats = db
 		.statistics()
 		.upsert(
 			// Each library is a database so only one of these ever exists
@@ -62,5 +105,404 @@ pub async fn update_library_statistics(
 
 	info!("Updated library statistics: {:?}", stats);
 
+	record_statistics_history(db, &stats).await?;
+
 	Ok(stats)
 }
+
+/// Capacity and free space of the volumes the locations of this library are on
+async fn library_volumes_capacity(library: &Library) -> Result<(u64, u64), LibraryManagerError> {
+	let locations_paths = library
+		.db
+		.location()
+		.find_many(vec![location::instance_id::equals(Some(
+			library.config().await.instance_id,
+		))])
+		.select(location::select!({ path }))
+		.exec()
+		.await?
+		.into_iter()
+		.filter_map(|location| location.path)
+		.collect::<Vec<_>>();
+
+	let volumes = get_volumes().await;
+
+	// A location is on the volume with the deepest mount point containing it
+	let library_volumes = locations_paths
+		.iter()
+		.filter_map(|location_path| {
+			volumes
+				.iter()
+				.enumerate()
+				.flat_map(|(idx, volume)| {
+					volume
+						.mount_points
+						.iter()
+						.filter(|mount_point| Path::new(location_path).starts_with(mount_point))
+						.map(move |mount_point| (idx, mount_point.components().count()))
+				})
+				.max_by_key(|(_, depth)| *depth)
+				.map(|(idx, _)| idx)
+		})
+		.collect::<HashSet<_>>();
+
+	Ok(library_volumes.into_iter().map(|idx| &volumes[idx]).fold(
+		(0, 0),
+		|(total, available), volume| {
+			(
+				total + volume.total_capacity,
+				available + volume.available_capacity,
+			)
+		},
+	))
+}
+
+/// Size of all the files of the library, counting duplicates as many times as they appear
+async fn count_bytes_used(db: &PrismaClient) -> Result<u64, prisma_client_rust::QueryError> {
+	let bytes: Vec<RawBytes> = db
+		._query_raw(raw!(
+			"SELECT SUM(size_in_bytes_float) AS bytes FROM file_path WHERE is_dir = 0"
+		))
+		.exec()
+		.await?;
+
+	Ok(bytes.first().and_then(|raw| raw.bytes).unwrap_or(0.0) as u64)
+}
+
+/// Size of the distinct files of the library, counting each `cas_id` only once
+async fn count_unique_bytes(db: &PrismaClient) -> Result<u64, prisma_client_rust::QueryError> {
+	let bytes: Vec<RawBytes> = db
+		._query_raw(raw!(
+			"SELECT SUM(size) AS bytes FROM (
+				SELECT MAX(size_in_bytes_float) AS size FROM file_path
+				WHERE is_dir = 0 AND cas_id IS NOT NULL
+				GROUP BY cas_id
+			)"
+		))
+		.exec()
+		.await?;
+
+	Ok(bytes.first().and_then(|raw| raw.bytes).unwrap_or(0.0) as u64)
+}
+
+/// Adds the statistics to the history, unless the last snapshot is too recent
+async fn record_statistics_history(
+	db: &PrismaClient,
+	stats: &statistics::Data,
+) -> Result<(), prisma_client_rust::QueryError> {
+	let last_captured = db
+		.statistics_history()
+		.find_first(vec![])
+		.order_by(statistics_history::date_captured::order(SortOrder::Desc))
+		.select(statistics_history::select!({ date_captured }))
+		.exec()
+		.await?
+		.map(|snapshot| snapshot.date_captured);
+
+	if last_captured.is_some_and(|last_captured| {
+		Utc::now().signed_duration_since(last_captured.with_timezone(&Utc))
+			< chrono::Duration::from_std(STATISTICS_HISTORY_INTERVAL).expect("valid interval")
+	}) {
+		return Ok(());
+	}
+
+	use statistics_history::*;
+	db.statistics_history()
+		.create(vec![
+			date_captured::set(stats.date_captured),
+			total_object_count::set(stats.total_object_count),
+			library_db_size::set(stats.library_db_size.clone()),
+			total_bytes_used::set(stats.total_bytes_used.clone()),
+			total_unique_bytes::set(stats.total_unique_bytes.clone()),
+			preview_media_bytes::set(stats.preview_media_bytes.clone()),
+		])
+		.exec()
+		.await?;
+
+	Ok(())
+}
+
+pub async fn get_location_statistics(
+	db: &PrismaClient,
+) -> Result<Vec<LocationStatistics>, prisma_client_rust::QueryError> {
+	#[derive(Deserialize)]
+	struct RawLocationStatistics {
+		location_id: location::id::Type,
+		file_count: i64,
+		total_bytes: Option<f64>,
+		unique_bytes: Option<f64>,
+	}
+
+	let statistics: Vec<RawLocationStatistics> = db
+		._query_raw(raw!(
+			"SELECT totals.location_id AS location_id,
+				totals.file_count AS file_count,
+				totals.total_bytes AS total_bytes,
+				uniques.unique_bytes AS unique_bytes
+			FROM (
+				SELECT location_id, COUNT(*) AS file_count, SUM(size_in_bytes_float) AS total_bytes
+				FROM file_path
+				WHERE is_dir = 0 AND location_id IS NOT NULL
+				GROUP BY location_id
+			) AS totals
+			LEFT JOIN (
+				SELECT location_id, SUM(size) AS unique_bytes FROM (
+					SELECT location_id, MAX(size_in_bytes_float) AS size FROM file_path
+					WHERE is_dir = 0 AND location_id IS NOT NULL AND cas_id IS NOT NULL
+					GROUP BY location_id, cas_id
+				)
+				GROUP BY location_id
+			) AS uniques ON uniques.location_id = totals.location_id"
+		))
+		.exec()
+		.await?;
+
+	Ok(statistics
+		.into_iter()
+		.map(
+			|RawLocationStatistics {
+			     location_id,
+			     file_count,
+			     total_bytes,
+			     unique_bytes,
+			 }| LocationStatistics {
+				location_id,
+				file_count: file_count as i32,
+				total_bytes: (total_bytes.unwrap_or(0.0) as u64).to_string(),
+				unique_bytes: (unique_bytes.unwrap_or(0.0) as u64).to_string(),
+			},
+		)
+		.collect())
+}
+
+/// Object count and size of each `ObjectKind`, the size of an object being the one of its
+/// largest file. Objects without a kind are counted as `ObjectKind::Unknown`.
+pub async fn get_kind_statistics(
+	db: &PrismaClient,
+) -> Result<Vec<ObjectKindStatistics>, prisma_client_rust::QueryError> {
+	#[derive(Deserialize)]
+	struct RawKindStatistics {
+		kind: i32,
+		count: i64,
+		total_bytes: Option<f64>,
+	}
+
+	let statistics: Vec<RawKindStatistics> = db
+		._query_raw(raw!(
+			"SELECT kind, COUNT(*) AS count, SUM(size) AS total_bytes FROM (
+				SELECT COALESCE(object.kind, 0) AS kind, MAX(file_path.size_in_bytes_float) AS size
+				FROM object
+				LEFT JOIN file_path ON file_path.object_id = object.id AND file_path.is_dir = 0
+				GROUP BY object.id
+			)
+			GROUP BY kind"
+		))
+		.exec()
+		.await?;
+
+	Ok(statistics
+		.into_iter()
+		.map(
+			|RawKindStatistics {
+			     kind,
+			     count,
+			     total_bytes,
+			 }| ObjectKindStatistics {
+				kind,
+				count: count as i32,
+				total_bytes: (total_bytes.unwrap_or(0.0) as u64).to_string(),
+			},
+		)
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sd_file_ext::kind::ObjectKind;
+	use sd_prisma::prisma::{file_path, object};
+
+	use uuid::Uuid;
+
+	async fn new_location(db: &PrismaClient) -> location::id::Type {
+		db.location()
+			.create(Uuid::new_v4().as_bytes().to_vec(), vec![])
+			.exec()
+			.await
+			.unwrap()
+			.id
+	}
+
+	async fn new_object(db: &PrismaClient, kind: Option<ObjectKind>) -> object::id::Type {
+		db.object()
+			.create(
+				Uuid::new_v4().as_bytes().to_vec(),
+				vec![object::kind::set(kind.map(|kind| kind as i32))],
+			)
+			.exec()
+			.await
+			.unwrap()
+			.id
+	}
+
+	async fn new_file_path(
+		db: &PrismaClient,
+		location_id: location::id::Type,
+		object_id: Option<object::id::Type>,
+		cas_id: Option<&str>,
+		is_dir: bool,
+		size: u64,
+	) {
+		db.file_path()
+			.create(
+				Uuid::new_v4().as_bytes().to_vec(),
+				[
+					file_path::location::connect(location::id::equals(location_id)),
+					file_path::cas_id::set(cas_id.map(str::to_string)),
+					file_path::is_dir::set(Some(is_dir)),
+					file_path::size_in_bytes_bytes::set(Some(size.to_be_bytes().to_vec())),
+					// Set by a trigger of the migrations, which the test database doesn't have
+					file_path::size_in_bytes_float::set(Some(size as f64)),
+				]
+				.into_iter()
+				.chain(object_id.map(|id| file_path::object::connect(object::id::equals(id))))
+				.collect(),
+			)
+			.exec()
+			.await
+			.unwrap();
+	}
+
+	/// Two locations, with a photo copied three times across both of them, a document, a directory,
+	/// a file which wasn't identified yet and an object without files
+	async fn seeded_db() -> (
+		std::sync::Arc<PrismaClient>,
+		location::id::Type,
+		location::id::Type,
+	) {
+		let db = sd_prisma::test_db().await;
+		db._db_push().await.unwrap();
+
+		let first = new_location(&db).await;
+		let second = new_location(&db).await;
+
+		let photo = new_object(&db, Some(ObjectKind::Image)).await;
+		new_file_path(&db, first, Some(photo), Some("photo"), false, 100).await;
+		new_file_path(&db, first, Some(photo), Some("photo"), false, 100).await;
+		new_file_path(&db, second, Some(photo), Some("photo"), false, 100).await;
+
+		let document = new_object(&db, Some(ObjectKind::Document)).await;
+		new_file_path(&db, second, Some(document), Some("document"), false, 50).await;
+
+		new_file_path(&db, first, None, None, true, 4096).await;
+		new_file_path(&db, second, None, None, false, 30).await;
+
+		new_object(&db, None).await;
+
+		(db, first, second)
+	}
+
+	#[tokio::test]
+	async fn duplicates_are_counted_once_in_unique_bytes() {
+		let (db, _, _) = seeded_db().await;
+
+		let stats = save_statistics(&db, 1000, 500, 10, 20).await.unwrap();
+
+		assert_eq!(stats.total_object_count, 3);
+		assert_eq!(stats.total_bytes_used, "380");
+		assert_eq!(stats.total_unique_bytes, "150");
+		assert_eq!(stats.total_bytes_capacity, "1000");
+		assert_eq!(stats.total_bytes_free, "500");
+	}
+
+	#[tokio::test]
+	async fn location_statistics_dedup_within_each_location() {
+		let (db, first, second) = seeded_db().await;
+
+		let mut statistics = get_location_statistics(&db).await.unwrap();
+		statistics.sort_by_key(|statistics| statistics.location_id);
+
+		let statistics = statistics
+			.into_iter()
+			.map(|statistics| {
+				(
+					statistics.location_id,
+					statistics.file_count,
+					statistics.total_bytes,
+					statistics.unique_bytes,
+				)
+			})
+			.collect::<Vec<_>>();
+
+		assert_eq!(
+			statistics,
+			vec![
+				(first, 2, "200".to_string(), "100".to_string()),
+				(second, 3, "180".to_string(), "150".to_string()),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn kind_statistics_count_objects_once() {
+		let (db, _, _) = seeded_db().await;
+
+		let mut statistics = get_kind_statistics(&db)
+			.await
+			.unwrap()
+			.into_iter()
+			.map(|statistics| (statistics.kind, statistics.count, statistics.total_bytes))
+			.collect::<Vec<_>>();
+		statistics.sort();
+
+		assert_eq!(
+			statistics,
+			vec![
+				(ObjectKind::Unknown as i32, 1, "0".to_string()),
+				(ObjectKind::Document as i32, 1, "50".to_string()),
+				(ObjectKind::Image as i32, 1, "100".to_string()),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn history_snapshots_are_not_recorded_twice() {
+		let (db, _, _) = seeded_db().await;
+
+		save_statistics(&db, 0, 0, 0, 0).await.unwrap();
+		save_statistics(&db, 0, 0, 0, 0).await.unwrap();
+
+		let history = db
+			.statistics_history()
+			.find_many(vec![])
+			.exec()
+			.await
+			.unwrap();
+		assert_eq!(history.len(), 1);
+		assert_eq!(history[0].total_object_count, 3);
+		assert_eq!(history[0].total_unique_bytes, "150");
+
+		// Once the last snapshot is old enough, another one is taken
+		db.statistics_history()
+			.update(
+				statistics_history::id::equals(history[0].id),
+				vec![statistics_history::date_captured::set(
+					(Utc::now()
+						- chrono::Duration::from_std(STATISTICS_HISTORY_INTERVAL).unwrap()
+						- chrono::Duration::minutes(1))
+					.into(),
+				)],
+			)
+			.exec()
+			.await
+			.unwrap();
+
+		save_statistics(&db, 0, 0, 0, 0).await.unwrap();
+
+		assert_eq!(
+			db.statistics_history().count(vec![]).exec().await.unwrap(),
+			2
+		);
+	}
+}
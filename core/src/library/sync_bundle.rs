@@ -0,0 +1,794 @@
+//! Sync operations can be carried between instances that never share a network in bundles:
+//! files with the operations an instance is missing, signed by the instance that exported them.
+//!
+//! The operations missing are found from the clocks of the importing instance, which it exports
+//! to a file of its own beforehand. Without them, a bundle with every operation is exported.
+//! Bundles are imported through the ingest actor, just like operations received from a peer.
+
+use crate::sync::{
+	diagnostics::{Direction, Transport},
+	ingest, GetOpsArgs, NTP64,
+};
+
+use sd_p2p::{Identity, RemoteIdentity};
+use sd_prisma::prisma::{crdt_operation, instance};
+use sd_sync::{CRDTOperation, CompressedCRDTOperations};
+use sd_utils::{error::FileIOError, uuid_to_bytes};
+
+use std::{
+	collections::{HashMap, VecDeque},
+	io::{self, Read, Write},
+	path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	fs,
+	io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::Library;
+
+const BUNDLE_MAGIC: &[u8; 8] = b"sdsyncb1";
+const CLOCKS_MAGIC: &[u8; 8] = b"sdsyncc1";
+const SIGNATURE_LEN: usize = 64;
+
+/// Pages larger than this are rejected before being read, a page of operations is far smaller
+const MAX_PAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// Operations read from the log or sent to the ingest actor at once
+const OPS_PER_REQUEST: u32 = 1000;
+
+/// Timestamps queried at once when counting the applied operations
+const TIMESTAMPS_PER_QUERY: usize = 500;
+
+#[derive(Error, Debug)]
+pub enum SyncBundleError {
+	#[error("malformed sync bundle")]
+	MalformedBundle,
+	#[error("malformed sync clocks")]
+	MalformedClocks,
+	#[error("the file belongs to library '{0}'")]
+	WrongLibrary(Uuid),
+	#[error("the bundle was exported by this instance")]
+	OwnBundle,
+	#[error(
+		"the bundle was exported by unknown instance '{0}', it must be paired with this one first"
+	)]
+	UnknownInstance(Uuid),
+	#[error("the bundle's signature doesn't match the instance that exported it")]
+	InvalidSignature,
+
+	// Internal errors
+	#[error("the ingest actor stopped")]
+	IngestStopped,
+	#[error("database error: {0}")]
+	Database(#[from] QueryError),
+	#[error("compression error: {0}")]
+	Compression(std::io::Error),
+	#[error("failed to encode sync bundle: {0}")]
+	Encode(#[from] rmp_serde::encode::Error),
+	#[error("failed to decode sync bundle: {0}")]
+	Decode(#[from] rmp_serde::decode::Error),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+}
+
+impl From<SyncBundleError> for rspc::Error {
+	fn from(e: SyncBundleError) -> Self {
+		use rspc::ErrorCode;
+
+		match e {
+			SyncBundleError::MalformedBundle
+			| SyncBundleError::MalformedClocks
+			| SyncBundleError::WrongLibrary(_)
+			| SyncBundleError::OwnBundle => Self::with_cause(ErrorCode::BadRequest, e.to_string(), e),
+			SyncBundleError::UnknownInstance(_) | SyncBundleError::InvalidSignature => {
+				Self::with_cause(ErrorCode::Unauthorized, e.to_string(), e)
+			}
+			_ => Self::with_cause(ErrorCode::InternalServerError, e.to_string(), e),
+		}
+	}
+}
+
+/// The position of an instance in the operations of every instance it knows of
+#[derive(Serialize, Deserialize)]
+struct Clocks {
+	library_id: Uuid,
+	instance_id: Uuid,
+	clocks: Vec<(Uuid, NTP64)>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct BundleHeader {
+	id: Uuid,
+	library_id: Uuid,
+	/// The instance that exported and signed the bundle
+	instance_id: Uuid,
+	created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct BundleExport {
+	pub id: Uuid,
+	pub operations: u32,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct BundleImportReport {
+	pub id: Uuid,
+	pub exported_by: Uuid,
+	pub created_at: DateTime<Utc>,
+	pub instances: Vec<BundleInstanceReport>,
+}
+
+/// What was imported of the operations created by an instance
+#[derive(Serialize, Type, Debug)]
+pub struct BundleInstanceReport {
+	pub instance_id: Uuid,
+	/// Operations in the bundle
+	pub operations: u32,
+	/// Operations this instance didn't have yet, which were ingested
+	pub ingested: u32,
+	/// Ingested operations which were applied, the others being superseded by newer ones or
+	/// created by an instance this one doesn't know of
+	pub applied: u32,
+}
+
+/// Writes the clocks of this instance to a file, for the instance exporting a bundle to it
+pub async fn export_clocks(
+	library: &Library,
+	path: impl AsRef<Path>,
+) -> Result<(), SyncBundleError> {
+	let path = path.as_ref();
+
+	let clocks = Clocks {
+		library_id: library.id,
+		instance_id: library.sync.instance,
+		clocks: library
+			.sync
+			.timestamps
+			.read()
+			.await
+			.iter()
+			.map(|(&instance_id, &timestamp)| (instance_id, timestamp))
+			.collect(),
+	};
+
+	let mut bytes = CLOCKS_MAGIC.to_vec();
+	bytes.extend(rmp_serde::to_vec_named(&clocks)?);
+
+	fs::write(path, bytes)
+		.await
+		.map_err(|e| FileIOError::from((path, e, "Failed to write sync clocks")).into())
+}
+
+/// Writes the operations missing from the instance whose clocks are given, or every operation
+/// otherwise, to a bundle signed by this instance
+pub async fn export(
+	library: &Library,
+	path: impl AsRef<Path>,
+	clocks_path: Option<impl AsRef<Path>>,
+) -> Result<BundleExport, SyncBundleError> {
+	let path = path.as_ref();
+
+	let (recipient, mut clocks) = match clocks_path {
+		Some(clocks_path) => {
+			let clocks = read_clocks(clocks_path).await?;
+
+			if clocks.library_id != library.id {
+				return Err(SyncBundleError::WrongLibrary(clocks.library_id));
+			}
+
+			let mut positions = clocks.clocks;
+
+			// An instance always has its own operations, timestamps are stored as i64
+			positions.retain(|(instance_id, _)| *instance_id != clocks.instance_id);
+			positions.push((clocks.instance_id, NTP64(i64::MAX as u64)));
+
+			(Some(clocks.instance_id), positions)
+		}
+		None => (None, vec![]),
+	};
+
+	let header = BundleHeader {
+		id: Uuid::new_v4(),
+		library_id: library.id,
+		instance_id: library.sync.instance,
+		created_at: Utc::now(),
+	};
+
+	let operations = match write_bundle(library, &header, clocks, path).await {
+		Ok(operations) => operations,
+		Err(e) => {
+			// A bundle missing operations mustn't be left behind
+			if let Err(e) = fs::remove_file(path).await {
+				warn!(
+					"Failed to remove incomplete sync bundle at {}: {e:#?}",
+					path.display()
+				);
+			}
+
+			return Err(e);
+		}
+	};
+
+	library.sync.diagnostics.record_exchange(
+		recipient,
+		Transport::Bundle,
+		Direction::Sent,
+		operations as usize,
+	);
+
+	info!(
+		"Exported {operations} sync operations of library '{}' to bundle '{}'",
+		library.id, header.id
+	);
+
+	Ok(BundleExport {
+		id: header.id,
+		operations,
+	})
+}
+
+/// Verifies a bundle was signed by a known instance and ingests the operations this instance
+/// doesn't have yet
+pub async fn import(
+	library: &Library,
+	path: impl AsRef<Path>,
+) -> Result<BundleImportReport, SyncBundleError> {
+	let path = path.as_ref();
+
+	let file = fs::File::open(path)
+		.await
+		.map_err(|e| FileIOError::from((path, e, "Failed to read sync bundle")))?;
+
+	let bundle = UnverifiedBundle::read(BufReader::new(file), path).await?;
+	let header = bundle.header.clone();
+
+	if header.library_id != library.id {
+		return Err(SyncBundleError::WrongLibrary(header.library_id));
+	}
+
+	if header.instance_id == library.sync.instance {
+		return Err(SyncBundleError::OwnBundle);
+	}
+
+	// Only the operations of paired instances are accepted, as they are over P2P
+	let exporter = library
+		.db
+		.instance()
+		.find_unique(instance::pub_id::equals(uuid_to_bytes(header.instance_id)))
+		.select(instance::select!({ remote_identity }))
+		.exec()
+		.await?
+		.ok_or(SyncBundleError::UnknownInstance(header.instance_id))?;
+
+	let mut pages = RemoteIdentity::from_bytes(&exporter.remote_identity)
+		.map_err(|_| SyncBundleError::InvalidSignature)
+		.and_then(|identity| bundle.verify(identity))?;
+
+	let mut instances = HashMap::<Uuid, BundleInstanceReport>::new();
+
+	let ingested_timestamps = feed_ingester(library, &mut pages, &mut instances).await?;
+
+	// The ingester may be done before the end of the bundle, whose operations are still reported
+	while read_page(&mut pages, &mut instances).await?.is_some() {}
+
+	library.sync.diagnostics.record_exchange(
+		Some(header.instance_id),
+		Transport::Bundle,
+		Direction::Received,
+		instances
+			.values()
+			.map(|report| report.operations as usize)
+			.sum(),
+	);
+
+	for (instance_id, timestamps) in ingested_timestamps {
+		let report = instances
+			.get_mut(&instance_id)
+			.expect("ingested operations are from the bundle");
+
+		report.ingested = timestamps.len() as u32;
+
+		for timestamps in timestamps.chunks(TIMESTAMPS_PER_QUERY) {
+			report.applied += library
+				.db
+				.crdt_operation()
+				.count(vec![
+					crdt_operation::instance::is(vec![instance::pub_id::equals(uuid_to_bytes(
+						instance_id,
+					))]),
+					crdt_operation::timestamp::in_vec(timestamps.to_vec()),
+				])
+				.exec()
+				.await? as u32;
+		}
+	}
+
+	info!(
+		"Imported sync bundle '{}' exported by instance '{}' into library '{}'",
+		header.id, header.instance_id, library.id
+	);
+
+	Ok(BundleImportReport {
+		id: header.id,
+		exported_by: header.instance_id,
+		created_at: header.created_at,
+		instances: instances.into_values().collect(),
+	})
+}
+
+/// Reads the next page of operations, counting them in the report of the instance that created them
+async fn read_page<R: AsyncRead + Unpin>(
+	pages: &mut BundlePages<'_, R>,
+	instances: &mut HashMap<Uuid, BundleInstanceReport>,
+) -> Result<Option<Vec<CRDTOperation>>, SyncBundleError> {
+	let Some(ops) = pages.next_page().await? else {
+		return Ok(None);
+	};
+
+	for op in &ops {
+		instances
+			.entry(op.instance)
+			.or_insert_with(|| BundleInstanceReport {
+				instance_id: op.instance,
+				operations: 0,
+				ingested: 0,
+				applied: 0,
+			})
+			.operations += 1;
+	}
+
+	Ok(Some(ops))
+}
+
+/// Feeds the operations to the ingest actor as it requests them, reading the bundle one page at a
+/// time, and returns the timestamps of the ones it received by instance
+async fn feed_ingester<R: AsyncRead + Unpin>(
+	library: &Library,
+	pages: &mut BundlePages<'_, R>,
+	instances: &mut HashMap<Uuid, BundleInstanceReport>,
+) -> Result<HashMap<Uuid, Vec<i64>>, SyncBundleError> {
+	let sync = &library.sync;
+
+	// Waits for P2P or the cloud to be done ingesting, if they are
+	let mut rx = sync.ingest.req_rx.lock().await;
+
+	sync.ingest
+		.event_tx
+		.send(ingest::Event::Notification)
+		.await
+		.map_err(|_| SyncBundleError::IngestStopped)?;
+
+	let mut ingested = HashMap::<Uuid, Vec<i64>>::new();
+	// Operations of the last page read which weren't sent yet. The clocks only move forward, so
+	// operations before the last sent one are never needed again
+	let mut pending = VecDeque::new();
+
+	while let Some(req) = rx.recv().await {
+		let timestamps = match req {
+			ingest::Request::FinishedIngesting => break,
+			ingest::Request::Messages { timestamps, .. } => {
+				timestamps.into_iter().collect::<HashMap<_, _>>()
+			}
+			_ => continue,
+		};
+
+		let mut messages = vec![];
+
+		while messages.len() < OPS_PER_REQUEST as usize {
+			let Some(op) = pending.pop_front() else {
+				match read_page(pages, instances).await? {
+					Some(ops) => {
+						pending.extend(ops);
+						continue;
+					}
+					None => break,
+				}
+			};
+
+			if timestamps
+				.get(&op.instance)
+				.map_or(true, |timestamp| op.timestamp > *timestamp)
+			{
+				messages.push(op);
+			}
+		}
+
+		// Dropping the request lets the actor know there's nothing else to ingest
+		if messages.is_empty() {
+			break;
+		}
+
+		debug!(
+			"Sending {} operations of the sync bundle to the ingester",
+			messages.len()
+		);
+
+		for op in &messages {
+			ingested
+				.entry(op.instance)
+				.or_default()
+				.push(op.timestamp.as_u64() as i64);
+		}
+
+		sync.ingest
+			.event_tx
+			.send(ingest::Event::Messages(ingest::MessagesEvent {
+				instance_id: sync.instance,
+				has_more: !pending.is_empty() || !pages.done,
+				messages,
+			}))
+			.await
+			.map_err(|_| SyncBundleError::IngestStopped)?;
+	}
+
+	Ok(ingested)
+}
+
+/// Writes the operations after the given clocks to a bundle one page at a time, returning how many
+/// there were
+async fn write_bundle(
+	library: &Library,
+	header: &BundleHeader,
+	mut clocks: Vec<(Uuid, NTP64)>,
+	path: &Path,
+) -> Result<u32, SyncBundleError> {
+	let write_error = |e| FileIOError::from((path, e, "Failed to write sync bundle"));
+
+	let mut file = BufWriter::new(fs::File::create(path).await.map_err(write_error)?);
+
+	file.write_all(&encode_header(header, &library.identity)?)
+		.await
+		.map_err(write_error)?;
+
+	let mut operations = 0;
+	let mut index = 0;
+
+	loop {
+		let page = library
+			.sync
+			.get_ops(GetOpsArgs {
+				clocks: clocks.clone(),
+				count: OPS_PER_REQUEST,
+			})
+			.await?;
+
+		if page.is_empty() {
+			break;
+		}
+
+		let has_more = page.len() == OPS_PER_REQUEST as usize;
+
+		for op in &page {
+			match clocks
+				.iter_mut()
+				.find(|(instance_id, _)| *instance_id == op.instance)
+			{
+				Some((_, timestamp)) => *timestamp = NTP64::max(*timestamp, op.timestamp),
+				None => clocks.push((op.instance, op.timestamp)),
+			}
+		}
+
+		operations += page.len() as u32;
+
+		file.write_all(&encode_page(header.id, index, page, &library.identity)?)
+			.await
+			.map_err(write_error)?;
+
+		index += 1;
+
+		if !has_more {
+			break;
+		}
+	}
+
+	file.write_all(&encode_page(header.id, index, vec![], &library.identity)?)
+		.await
+		.map_err(write_error)?;
+	file.flush().await.map_err(write_error)?;
+
+	Ok(operations)
+}
+
+fn encode_header(header: &BundleHeader, identity: &Identity) -> Result<Vec<u8>, SyncBundleError> {
+	let header = rmp_serde::to_vec_named(header)?;
+
+	let mut bytes = BUNDLE_MAGIC.to_vec();
+	bytes.extend((header.len() as u32).to_le_bytes());
+	bytes.extend(identity.sign(&header));
+	bytes.extend(header);
+
+	Ok(bytes)
+}
+
+/// Encodes a page of operations, or the end of the bundle if there are none. Every page is signed
+/// along with its position in the bundle, so pages can't be dropped, reordered or moved between
+/// bundles, and the operations can be ingested as they're read.
+fn encode_page(
+	bundle_id: Uuid,
+	index: u32,
+	ops: Vec<CRDTOperation>,
+	identity: &Identity,
+) -> Result<Vec<u8>, SyncBundleError> {
+	let page = if ops.is_empty() {
+		vec![]
+	} else {
+		let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+		encoder
+			.write_all(&rmp_serde::to_vec_named(&CompressedCRDTOperations::new(
+				ops,
+			))?)
+			.map_err(SyncBundleError::Compression)?;
+		encoder.finish().map_err(SyncBundleError::Compression)?
+	};
+
+	let mut bytes = (page.len() as u32).to_le_bytes().to_vec();
+	bytes.extend(identity.sign(&page_message(bundle_id, index, &page)));
+	bytes.extend(page);
+
+	Ok(bytes)
+}
+
+fn page_message(bundle_id: Uuid, index: u32, page: &[u8]) -> Vec<u8> {
+	let mut message = Vec::with_capacity(16 + 4 + page.len());
+	message.extend(bundle_id.as_bytes());
+	message.extend(index.to_le_bytes());
+	message.extend(page);
+	message
+}
+
+fn read_error(path: &Path, e: io::Error) -> SyncBundleError {
+	if e.kind() == io::ErrorKind::UnexpectedEof {
+		SyncBundleError::MalformedBundle
+	} else {
+		FileIOError::from((path, e, "Failed to read sync bundle")).into()
+	}
+}
+
+/// Reads the length of a header or page followed by its signature
+async fn read_signed_len<R: AsyncRead + Unpin>(
+	reader: &mut R,
+	path: &Path,
+) -> Result<(usize, [u8; SIGNATURE_LEN]), SyncBundleError> {
+	let len = reader
+		.read_u32_le()
+		.await
+		.map_err(|e| read_error(path, e))? as usize;
+
+	if len > MAX_PAGE_LEN {
+		return Err(SyncBundleError::MalformedBundle);
+	}
+
+	let mut signature = [0; SIGNATURE_LEN];
+	reader
+		.read_exact(&mut signature)
+		.await
+		.map_err(|e| read_error(path, e))?;
+
+	Ok((len, signature))
+}
+
+/// A bundle whose header was read, which has to be verified before reading its operations
+struct UnverifiedBundle<'path, R> {
+	reader: R,
+	path: &'path Path,
+	header: BundleHeader,
+	signed_header: Vec<u8>,
+	signature: [u8; SIGNATURE_LEN],
+}
+
+impl<'path, R: AsyncRead + Unpin> UnverifiedBundle<'path, R> {
+	async fn read(mut reader: R, path: &'path Path) -> Result<Self, SyncBundleError> {
+		let mut magic = [0; BUNDLE_MAGIC.len()];
+		reader
+			.read_exact(&mut magic)
+			.await
+			.map_err(|e| read_error(path, e))?;
+
+		if &magic != BUNDLE_MAGIC {
+			return Err(SyncBundleError::MalformedBundle);
+		}
+
+		let (len, signature) = read_signed_len(&mut reader, path).await?;
+
+		let mut signed_header = vec![0; len];
+		reader
+			.read_exact(&mut signed_header)
+			.await
+			.map_err(|e| read_error(path, e))?;
+
+		Ok(Self {
+			header: rmp_serde::from_slice(&signed_header)
+				.map_err(|_| SyncBundleError::MalformedBundle)?,
+			reader,
+			path,
+			signed_header,
+			signature,
+		})
+	}
+
+	fn verify(self, identity: RemoteIdentity) -> Result<BundlePages<'path, R>, SyncBundleError> {
+		if !identity.verify(&self.signed_header, &self.signature) {
+			return Err(SyncBundleError::InvalidSignature);
+		}
+
+		Ok(BundlePages {
+			reader: self.reader,
+			path: self.path,
+			identity,
+			bundle_id: self.header.id,
+			index: 0,
+			done: false,
+		})
+	}
+}
+
+/// The pages of operations of a verified bundle, each verified as it's read
+struct BundlePages<'path, R> {
+	reader: R,
+	path: &'path Path,
+	identity: RemoteIdentity,
+	bundle_id: Uuid,
+	index: u32,
+	/// Whether the end of the bundle was read
+	done: bool,
+}
+
+impl<R: AsyncRead + Unpin> BundlePages<'_, R> {
+	async fn next_page(&mut self) -> Result<Option<Vec<CRDTOperation>>, SyncBundleError> {
+		if self.done {
+			return Ok(None);
+		}
+
+		let (len, signature) = read_signed_len(&mut self.reader, self.path).await?;
+
+		let mut page = vec![0; len];
+		self.reader
+			.read_exact(&mut page)
+			.await
+			.map_err(|e| read_error(self.path, e))?;
+
+		if !self
+			.identity
+			.verify(&page_message(self.bundle_id, self.index, &page), &signature)
+		{
+			return Err(SyncBundleError::InvalidSignature);
+		}
+
+		self.index += 1;
+
+		if page.is_empty() {
+			self.done = true;
+			return Ok(None);
+		}
+
+		let mut decompressed = vec![];
+		DeflateDecoder::new(page.as_slice())
+			.read_to_end(&mut decompressed)
+			.map_err(SyncBundleError::Compression)?;
+
+		Ok(Some(
+			rmp_serde::from_slice::<CompressedCRDTOperations>(&decompressed)?.into_ops(),
+		))
+	}
+}
+
+async fn read_clocks(path: impl AsRef<Path>) -> Result<Clocks, SyncBundleError> {
+	let path = path.as_ref();
+
+	let bytes = fs::read(path)
+		.await
+		.map_err(|e| FileIOError::from((path, e, "Failed to read sync clocks")))?;
+
+	bytes
+		.strip_prefix(CLOCKS_MAGIC)
+		.ok_or(SyncBundleError::MalformedClocks)
+		.and_then(|clocks| {
+			rmp_serde::from_slice(clocks).map_err(|_| SyncBundleError::MalformedClocks)
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn bundle(
+		header: &BundleHeader,
+		pages: Vec<Vec<CRDTOperation>>,
+		identity: &Identity,
+	) -> Vec<u8> {
+		let mut bytes = encode_header(header, identity).unwrap();
+
+		for (index, ops) in pages.into_iter().enumerate() {
+			bytes.extend(encode_page(header.id, index as u32, ops, identity).unwrap());
+		}
+
+		bytes
+	}
+
+	async fn read_all(
+		bytes: &[u8],
+		identity: &Identity,
+	) -> Result<Vec<CRDTOperation>, SyncBundleError> {
+		let mut pages = UnverifiedBundle::read(bytes, Path::new("bundle"))
+			.await?
+			.verify(identity.to_remote_identity())?;
+
+		let mut ops = vec![];
+		while let Some(page) = pages.next_page().await? {
+			ops.extend(page);
+		}
+
+		Ok(ops)
+	}
+
+	#[tokio::test]
+	async fn signed_bundle_round_trip() {
+		let identity = Identity::new();
+
+		let header = BundleHeader {
+			id: Uuid::new_v4(),
+			library_id: Uuid::new_v4(),
+			instance_id: Uuid::new_v4(),
+			created_at: Utc::now(),
+		};
+
+		let ops = (0..3)
+			.map(|i| CRDTOperation {
+				instance: header.instance_id,
+				timestamp: NTP64(i),
+				model: "Location".to_string(),
+				record_id: rmpv::Value::Nil,
+				data: sd_sync::CRDTOperationData::Create,
+			})
+			.collect::<Vec<_>>();
+
+		let mut bytes = bundle(
+			&header,
+			vec![ops[..2].to_vec(), ops[2..].to_vec(), vec![]],
+			&identity,
+		);
+
+		let bundle_header = UnverifiedBundle::read(bytes.as_slice(), Path::new("bundle"))
+			.await
+			.unwrap()
+			.header;
+		assert_eq!(bundle_header.id, header.id);
+
+		assert_eq!(read_all(&bytes, &identity).await.unwrap(), ops);
+		assert!(matches!(
+			read_all(&bytes, &Identity::new()).await,
+			Err(SyncBundleError::InvalidSignature)
+		));
+
+		// A bundle cut short is rejected, even between pages
+		let end = encode_page(header.id, 2, vec![], &identity).unwrap();
+		assert!(matches!(
+			read_all(&bytes[..bytes.len() - end.len()], &identity).await,
+			Err(SyncBundleError::MalformedBundle)
+		));
+
+		// Any change to the operations invalidates the signature of their page
+		let last = bytes.len() - end.len() - 1;
+		bytes[last] ^= 1;
+		assert!(matches!(
+			read_all(&bytes, &identity).await,
+			Err(SyncBundleError::InvalidSignature)
+		));
+
+		assert!(matches!(
+			UnverifiedBundle::read(b"sdsyncb1".as_slice(), Path::new("bundle")).await,
+			Err(SyncBundleError::MalformedBundle)
+		));
+	}
+}
@@ -0,0 +1,270 @@
+//! Reports on the health of sync, to debug it without opening the library database by hand.
+
+use crate::sync::{
+	diagnostics::{self, Exchange, Exchanges, Transport},
+	NTP64,
+};
+
+use sd_sync::{CRDTOperation, CRDTOperationData};
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use prisma_client_rust::QueryError;
+use serde::Serialize;
+use specta::Type;
+use uuid::Uuid;
+
+use super::Library;
+
+/// The timestamp of an operation of an instance
+#[derive(Serialize, Type, Debug)]
+pub struct SyncTimestamp {
+	pub instance_id: Uuid,
+	/// As a string, as NTP64 timestamps don't fit in a JS number
+	pub timestamp: String,
+	pub at: DateTime<Utc>,
+}
+
+impl SyncTimestamp {
+	fn new(instance_id: Uuid, timestamp: NTP64) -> Self {
+		Self {
+			instance_id,
+			timestamp: timestamp.as_u64().to_string(),
+			at: timestamp.to_system_time().into(),
+		}
+	}
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct SyncDiagnostics {
+	pub instance_id: Uuid,
+	/// The latest operation of each instance this one has
+	pub clocks: Vec<SyncTimestamp>,
+	pub backlogs: Vec<Backlog>,
+	pub exchanges: Vec<ExchangeReport>,
+	/// Newest first, only the latest ones since the library was loaded are kept
+	pub rejected: Vec<RejectedOperation>,
+}
+
+/// Operations waiting to be exchanged with a peer, `None` when it can't be known from here
+#[derive(Serialize, Type, Debug)]
+pub struct Backlog {
+	/// `None` for the cloud
+	pub peer: Option<Uuid>,
+	pub transport: Transport,
+	pub outgoing: Option<u32>,
+	pub incoming: Option<u32>,
+}
+
+/// The last successful exchanges with a peer over a transport
+#[derive(Serialize, Type, Debug)]
+pub struct ExchangeReport {
+	/// `None` for the cloud and for bundles not exported for a given instance
+	pub peer: Option<Uuid>,
+	pub transport: Transport,
+	pub last_sent: Option<LastExchange>,
+	pub last_received: Option<LastExchange>,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct LastExchange {
+	pub at: DateTime<Utc>,
+	pub operations: u32,
+}
+
+impl From<Exchange> for LastExchange {
+	fn from(Exchange { at, operations }: Exchange) -> Self {
+		Self {
+			at: at.into(),
+			operations: operations as u32,
+		}
+	}
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct RejectedOperation {
+	pub at: DateTime<Utc>,
+	pub operation: CRDTOperation,
+	pub reason: String,
+}
+
+impl From<diagnostics::RejectedOperation> for RejectedOperation {
+	fn from(
+		diagnostics::RejectedOperation {
+			at,
+			operation,
+			reason,
+		}: diagnostics::RejectedOperation,
+	) -> Self {
+		Self {
+			at: at.into(),
+			operation,
+			reason,
+		}
+	}
+}
+
+/// The operations of a record, as far back as the operations log goes since it's compacted
+#[derive(Serialize, Type, Debug, Default)]
+pub struct RecordHistory {
+	pub created: Option<SyncTimestamp>,
+	pub deleted: Option<SyncTimestamp>,
+	pub fields: Vec<FieldHistory>,
+}
+
+/// The values a field of a record was set to, newest first so the first one is the current value
+#[derive(Serialize, Type, Debug)]
+pub struct FieldHistory {
+	pub field: String,
+	pub values: Vec<FieldValue>,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct FieldValue {
+	pub set_by: SyncTimestamp,
+	#[specta(type = serde_json::Value)]
+	pub value: rmpv::Value,
+}
+
+pub async fn diagnostics(library: &Library) -> Result<SyncDiagnostics, QueryError> {
+	let sync = &library.sync;
+
+	let clocks = sync
+		.timestamps
+		.read()
+		.await
+		.iter()
+		.map(|(&instance_id, &timestamp)| (instance_id, timestamp))
+		.sorted()
+		.collect::<Vec<_>>();
+
+	let mut backlogs = vec![];
+
+	// What a peer is missing is only known from the clocks it sent along its last request, and
+	// what it has for us only once we request it
+	for (peer, peer_clocks) in sync.diagnostics.peer_clocks() {
+		backlogs.push(Backlog {
+			peer: Some(peer),
+			transport: Transport::P2P,
+			outgoing: Some(sync.count_ops(&peer_clocks).await? as u32),
+			incoming: None,
+		});
+	}
+
+	// Operations received from the cloud are kept until ingested, but what it's missing isn't
+	// known without asking it
+	backlogs.push(Backlog {
+		peer: None,
+		transport: Transport::Cloud,
+		outgoing: None,
+		incoming: Some(sync.count_cloud_ops(&clocks).await? as u32),
+	});
+
+	Ok(SyncDiagnostics {
+		instance_id: sync.instance,
+		clocks: clocks
+			.into_iter()
+			.map(|(instance_id, timestamp)| SyncTimestamp::new(instance_id, timestamp))
+			.collect(),
+		backlogs,
+		exchanges: sync
+			.diagnostics
+			.exchanges()
+			.into_iter()
+			.map(
+				|(peer, transport, Exchanges { sent, received })| ExchangeReport {
+					peer,
+					transport,
+					last_sent: sent.map(Into::into),
+					last_received: received.map(Into::into),
+				},
+			)
+			.collect(),
+		rejected: sync
+			.diagnostics
+			.rejected()
+			.into_iter()
+			.map(Into::into)
+			.collect(),
+	})
+}
+
+pub async fn record_history(
+	library: &Library,
+	model: &str,
+	record_id: rmpv::Value,
+) -> Result<RecordHistory, QueryError> {
+	let mut ops = vec![];
+	for record_id in record_id_orderings(record_id) {
+		ops.extend(library.sync.get_record_ops(model, &record_id).await?);
+	}
+
+	ops.sort_by_key(|op| (op.timestamp, op.instance));
+
+	let mut history = RecordHistory::default();
+	let mut fields = BTreeMap::<String, Vec<FieldValue>>::new();
+
+	for op in ops.into_iter().rev() {
+		let timestamp = SyncTimestamp::new(op.instance, op.timestamp);
+
+		match op.data {
+			CRDTOperationData::Create => {
+				history.created.get_or_insert(timestamp);
+			}
+			CRDTOperationData::Update { field, value } => {
+				fields.entry(field).or_default().push(FieldValue {
+					set_by: timestamp,
+					value,
+				});
+			}
+			CRDTOperationData::Delete => {
+				history.deleted.get_or_insert(timestamp);
+			}
+		}
+	}
+
+	history.fields = fields
+		.into_iter()
+		.map(|(field, values)| FieldHistory { field, values })
+		.collect();
+
+	Ok(history)
+}
+
+/// Record ids are stored as msgpack maps in the order of the fields of the model's sync id, which
+/// JSON objects don't keep, so the record is looked up with every order of them
+fn record_id_orderings(record_id: rmpv::Value) -> Vec<rmpv::Value> {
+	match record_id {
+		rmpv::Value::Map(fields) if fields.len() > 1 => {
+			let len = fields.len();
+
+			fields
+				.into_iter()
+				.permutations(len)
+				.map(rmpv::Value::Map)
+				.collect()
+		}
+		record_id => vec![record_id],
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn record_ids_are_looked_up_in_every_order() {
+		let item = (rmpv::Value::from("item"), rmpv::Value::from(1));
+		let group = (rmpv::Value::from("group"), rmpv::Value::from(2));
+
+		let orderings = record_id_orderings(rmpv::Value::Map(vec![group.clone(), item.clone()]));
+
+		assert_eq!(orderings.len(), 2);
+		assert!(orderings.contains(&rmpv::Value::Map(vec![item, group])));
+
+		let pub_id = rmpv::Value::Map(vec![(rmpv::Value::from("pub_id"), rmpv::Value::Nil)]);
+		assert_eq!(record_id_orderings(pub_id.clone()), vec![pub_id]);
+	}
+}
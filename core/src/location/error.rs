@@ -49,6 +49,8 @@ pub enum LocationError {
 	LocationAlreadyExists(Box<Path>),
 	#[error("nested location currently not supported <path='{}'>", .0.display())]
 	NestedLocation(Box<Path>),
+	#[error("invalid scan schedule: {0}")]
+	InvalidScanSchedule(String),
 	#[error(transparent)]
 	NonUtf8Path(#[from] NonUtf8PathError),
 
@@ -91,9 +93,10 @@ impl From<LocationError> for rspc::Error {
 			}
 
 			// User's fault errors
-			NotDirectory(_) | NestedLocation(_) | LocationAlreadyExists(_) => {
-				Self::with_cause(ErrorCode::BadRequest, err.to_string(), err)
-			}
+			NotDirectory(_)
+			| NestedLocation(_)
+			| LocationAlreadyExists(_)
+			| InvalidScanSchedule(_) => Self::with_cause(ErrorCode::BadRequest, err.to_string(), err),
 
 			// Custom error message is used to differentiate these errors in the frontend
 			// TODO: A better solution would be for rspc to support sending custom data alongside errors
@@ -1,3 +1,5 @@
+use crate::object::fs::trash::TRASH_DIR_NAME;
+
 use sd_file_path_helper::{
 	file_path_pub_and_cas_ids, file_path_walker, FilePathMetadata, IsolatedFilePathData,
 };
@@ -493,6 +495,12 @@ where
 
 		let current_path = entry.path();
 
+		// The location's own trash is never indexed, but other `.trash` directories are
+		if iso_file_path_to_walk.is_root() && entry.file_name() == TRASH_DIR_NAME {
+			trace!("Path {} is the location's trash", current_path.display());
+			continue 'entries;
+		}
+
 		// Just sending updates if we found more paths since the last loop
 		let current_found_paths_count = paths_buffer.len();
 		if found_paths_counts != current_found_paths_count {
@@ -844,6 +852,69 @@ mod tests {
 		}
 	}
 
+	#[tokio::test]
+	async fn test_skip_location_trash() {
+		let root = tempdir().unwrap();
+		let root_path = root.path();
+
+		// The location's trash, and a regular directory which happens to share its name
+		let trashed_item = root_path.join(".trash").join(Uuid::new_v4().to_string());
+		fs::create_dir_all(&trashed_item).await.unwrap();
+		fs::File::create(trashed_item.join("photo1.png"))
+			.await
+			.unwrap();
+		let photos_trash = root_path.join("photos/.trash");
+		fs::create_dir_all(&photos_trash).await.unwrap();
+		fs::File::create(photos_trash.join("photo2.jpg"))
+			.await
+			.unwrap();
+
+		let metadata = FilePathMetadata {
+			inode: 0,
+			size_in_bytes: 0,
+			created_at: Utc::now(),
+			modified_at: Utc::now(),
+			hidden: false,
+		};
+
+		let f = |path, is_dir| IsolatedFilePathData::new(0, root_path, path, is_dir).unwrap();
+		let pub_id = Uuid::new_v4();
+		let maybe_object_id = None;
+
+		#[rustfmt::skip]
+		let expected = [
+			WalkedEntry { pub_id, maybe_object_id, iso_file_path: f(root_path.join("photos"), true), metadata },
+			WalkedEntry { pub_id, maybe_object_id, iso_file_path: f(root_path.join("photos/.trash"), true), metadata },
+			WalkedEntry { pub_id, maybe_object_id, iso_file_path: f(root_path.join("photos/.trash/photo2.jpg"), false), metadata },
+		]
+		.into_iter()
+		.collect::<HashSet<_>>();
+
+		let walk_result = walk(
+			root_path.to_path_buf(),
+			&[],
+			|_, _| {},
+			|_| async { Ok(vec![]) },
+			|_, _| async { Ok(vec![]) },
+			|path, is_dir| {
+				IsolatedFilePathData::new(0, root_path, path, is_dir).map_err(Into::into)
+			},
+			420,
+		)
+		.await
+		.unwrap();
+
+		if !walk_result.errors.is_empty() {
+			panic!("errors: {:#?}", walk_result.errors);
+		}
+
+		let actual = walk_result.walked.collect::<HashSet<_>>();
+
+		if actual != expected {
+			panic!("difference: {:#?}", expected.symmetric_difference(&actual));
+		}
+	}
+
 	#[tokio::test]
 	// #[traced_test]
 	async fn test_only_photos() {
@@ -1,5 +1,6 @@
 use crate::{
 	library::{Library, LibraryManagerEvent},
+	location::indexer::rules::IndexerRuleError,
 	old_job::JobManagerError,
 	Node,
 };
@@ -27,6 +28,8 @@ use uuid::Uuid;
 
 mod watcher;
 
+pub use watcher::WatcherMode;
+
 mod helpers;
 
 #[derive(Clone, Copy, Debug)]
@@ -103,6 +106,8 @@ pub enum LocationManagerError {
 	JobManager(#[from] JobManagerError),
 	#[error("missing-field")]
 	MissingField(#[from] MissingFieldError),
+	#[error("Indexer rule error: (error: {0})")]
+	IndexerRule(#[from] IndexerRuleError),
 
 	#[error(transparent)]
 	FileIO(#[from] FileIOError),
@@ -356,7 +361,7 @@ impl Locations {
 		use std::collections::{HashMap, HashSet};
 
 		use futures::stream::{FuturesUnordered, StreamExt};
-		use tokio::select;
+		use tokio::{select, time::Instant};
 		use tracing::warn;
 
 		use helpers::{
@@ -366,11 +371,14 @@ impl Locations {
 		};
 		use watcher::LocationWatcher;
 
+		use super::schedule::{run_scheduled_scan, SCHEDULE_CHECK_INTERVAL};
+
 		let mut to_check_futures = FuturesUnordered::new();
 		let mut to_remove = HashSet::new();
 		let mut locations_watched = HashMap::new();
 		let mut locations_unwatched = HashMap::new();
 		let mut forced_unwatch = HashSet::new();
+		let mut last_schedule_checks = HashMap::new();
 
 		loop {
 			select! {
@@ -496,6 +504,7 @@ impl Locations {
 					if to_remove.contains(&key) {
 						// The time to check came for an already removed library, so we just ignore it
 						to_remove.remove(&key);
+						last_schedule_checks.remove(&key);
 					} else if let Some(location) = get_location(location_id, &library).await {
 						// TODO(N): This isn't gonna work with removable media and this will likely permanently break if the DB is restored from a backup.
 						if location.instance_id == Some(library.config().await.instance_id) {
@@ -524,6 +533,22 @@ impl Locations {
 									&mut locations_unwatched,
 								);
 							}
+
+							if is_online
+								&& last_schedule_checks
+									.get(&key)
+									.map_or(true, |checked_at: &Instant| {
+										checked_at.elapsed() >= SCHEDULE_CHECK_INTERVAL
+									})
+							{
+								last_schedule_checks.insert(key, Instant::now());
+								tokio::spawn(run_scheduled_scan(
+									node.clone(),
+									library.clone(),
+									location_id,
+								));
+							}
+
 							to_check_futures.push(location_check_sleep(location_id, library));
 						} else {
 							drop_location(
@@ -535,6 +560,7 @@ impl Locations {
 								&mut locations_unwatched
 							);
 							forced_unwatch.remove(&key);
+							last_schedule_checks.remove(&key);
 						}
 					} else {
 						drop_location(
@@ -545,6 +571,7 @@ impl Locations {
 							&mut locations_unwatched,
 						);
 						forced_unwatch.remove(&key);
+						last_schedule_checks.remove(&key);
 					}
 				}
 
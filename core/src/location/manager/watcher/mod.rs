@@ -1,4 +1,4 @@
-use crate::{library::Library, Node};
+use crate::{library::Library, volume::get_volumes, Node};
 
 use sd_prisma::prisma::location;
 use sd_utils::db::maybe_missing;
@@ -12,10 +12,12 @@ use std::{
 
 use async_trait::async_trait;
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use specta::Type;
 use tokio::{
 	runtime::Handle,
 	select,
-	sync::{mpsc, oneshot},
+	sync::{mpsc, oneshot, watch},
 	task::{block_in_place, JoinHandle},
 	time::{interval_at, Instant, MissedTickBehavior},
 };
@@ -28,10 +30,12 @@ mod android;
 mod ios;
 mod linux;
 mod macos;
+mod poll;
 mod windows;
 
 mod utils;
 
+use poll::{is_polled_file_system, PollEventHandler};
 use utils::check_event;
 
 #[cfg(target_os = "linux")]
@@ -73,13 +77,73 @@ trait EventHandler<'lib> {
 	/// As Event Handlers have some inner state, from time to time we need to call this tick method
 	/// so the event handler can update its state.
 	async fn tick(&mut self);
+
+	/// Whether the location is being watched. Only needed by event handlers looking for changes
+	/// by themselves, as the others just stop receiving events.
+	fn set_watching(&mut self, _watching: bool) {}
+
+	/// Paths being changed by Spacedrive itself. Their events never reach the event handler, so
+	/// this is only needed by event handlers looking for changes by themselves.
+	fn ignore_path(&mut self, _path: &Path, _ignore: bool) {}
+}
+
+/// How the changes of a location are watched
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Type, Eq, PartialEq)]
+pub enum WatcherMode {
+	/// Polling locations on file systems without native events, like network mounts
+	#[default]
+	Auto = 0,
+	/// Native file system events
+	Native = 1,
+	/// Periodically comparing the location with the database
+	Polling = 2,
+}
+
+impl From<Option<i32>> for WatcherMode {
+	fn from(value: Option<i32>) -> Self {
+		match value {
+			Some(1) => Self::Native,
+			Some(2) => Self::Polling,
+			_ => Self::Auto,
+		}
+	}
+}
+
+impl WatcherMode {
+	/// Whether a location at `location_path` must be polled
+	async fn should_poll(self, location_path: &str) -> bool {
+		match self {
+			Self::Native => false,
+			Self::Polling => true,
+			Self::Auto => {
+				let volumes = get_volumes().await;
+
+				// A location is on the volume with the deepest mount point containing it
+				volumes
+					.iter()
+					.flat_map(|volume| {
+						volume
+							.mount_points
+							.iter()
+							.filter(|mount_point| Path::new(location_path).starts_with(mount_point))
+							.map(move |mount_point| (volume, mount_point.components().count()))
+					})
+					.max_by_key(|(_, depth)| *depth)
+					.and_then(|(volume, _)| volume.file_system.as_deref())
+					.map_or(false, is_polled_file_system)
+			}
+		}
+	}
 }
 
 #[derive(Debug)]
 pub(super) struct LocationWatcher {
 	id: i32,
 	path: String,
-	watcher: RecommendedWatcher,
+	/// `None` when the location is polled instead
+	watcher: Option<RecommendedWatcher>,
+	watching_tx: watch::Sender<bool>,
 	ignore_path_tx: mpsc::UnboundedSender<IgnorePath>,
 	handle: Option<JoinHandle<()>>,
 	stop_tx: Option<oneshot::Sender<()>>,
@@ -93,58 +157,99 @@ impl LocationWatcher {
 	) -> Result<Self, LocationManagerError> {
 		let (events_tx, events_rx) = mpsc::unbounded_channel();
 		let (ignore_path_tx, ignore_path_rx) = mpsc::unbounded_channel();
+		let (watching_tx, watching_rx) = watch::channel(false);
 		let (stop_tx, stop_rx) = oneshot::channel();
 
-		let watcher = RecommendedWatcher::new(
-			move |result| {
-				if !events_tx.is_closed() {
-					if events_tx.send(result).is_err() {
+		let location_id = location.id;
+		let location_pub_id = Uuid::from_slice(&location.pub_id)?;
+		let path = maybe_missing(location.path, "location.path")?;
+
+		let polling = WatcherMode::from(location.watcher_mode)
+			.should_poll(&path)
+			.await;
+
+		let watcher = if polling {
+			debug!("Polling location instead of watching it: <id='{location_id}'>");
+			None
+		} else {
+			Some(RecommendedWatcher::new(
+				move |result| {
+					if !events_tx.is_closed() {
+						if events_tx.send(result).is_err() {
+							error!(
+							"Unable to send watcher event to location manager for location: <id='{}'>",
+							location_id
+						);
+						}
+					} else {
 						error!(
-						"Unable to send watcher event to location manager for location: <id='{}'>",
-						location.id
-					);
+							"Tried to send location file system events to a closed channel: <id='{}'",
+							location_id
+						);
 					}
-				} else {
-					error!(
-						"Tried to send location file system events to a closed channel: <id='{}'",
-						location.id
-					);
-				}
-			},
-			Config::default(),
-		)?;
-
-		let handle = tokio::spawn(Self::handle_watch_events(
-			location.id,
-			Uuid::from_slice(&location.pub_id)?,
-			node,
-			library,
-			events_rx,
-			ignore_path_rx,
-			stop_rx,
-		));
+				},
+				Config::default(),
+			)?)
+		};
+
+		let location_path = PathBuf::from(&path);
+
+		let handle = tokio::spawn(async move {
+			if polling {
+				Self::handle_watch_events(
+					location_id,
+					location_pub_id,
+					&location_path,
+					PollEventHandler::new(location_id, &library, &node),
+					&node,
+					&library,
+					events_rx,
+					ignore_path_rx,
+					watching_rx,
+					stop_rx,
+				)
+				.await
+			} else {
+				Self::handle_watch_events(
+					location_id,
+					location_pub_id,
+					&location_path,
+					Handler::new(location_id, &library, &node),
+					&node,
+					&library,
+					events_rx,
+					ignore_path_rx,
+					watching_rx,
+					stop_rx,
+				)
+				.await
+			}
+		});
 
 		Ok(Self {
-			id: location.id,
-			path: maybe_missing(location.path, "location.path")?,
+			id: location_id,
+			path,
 			watcher,
+			watching_tx,
 			ignore_path_tx,
 			handle: Some(handle),
 			stop_tx: Some(stop_tx),
 		})
 	}
 
-	async fn handle_watch_events(
+	#[allow(clippy::too_many_arguments)]
+	async fn handle_watch_events<'lib>(
 		location_id: location::id::Type,
 		location_pub_id: Uuid,
-		node: Arc<Node>,
-		library: Arc<Library>,
+		location_path: &Path,
+		mut event_handler: impl EventHandler<'lib>,
+		node: &'lib Arc<Node>,
+		library: &'lib Arc<Library>,
 		mut events_rx: mpsc::UnboundedReceiver<notify::Result<Event>>,
 		mut ignore_path_rx: mpsc::UnboundedReceiver<IgnorePath>,
+		mut watching_rx: watch::Receiver<bool>,
 		mut stop_rx: oneshot::Receiver<()>,
 	) {
-		let mut event_handler = Handler::new(location_id, &library, &node);
-
 		let mut paths_to_ignore = HashSet::new();
 
 		let mut handler_interval = interval_at(Instant::now() + HUNDRED_MILLIS, HUNDRED_MILLIS);
@@ -159,10 +264,11 @@ impl LocationWatcher {
 							if let Err(e) = Self::handle_single_event(
 								location_id,
 								location_pub_id,
+								location_path,
 								event,
 								&mut event_handler,
-								&node,
-								&library,
+								node,
+								library,
 								&paths_to_ignore,
 							).await {
 								error!("Failed to handle location file system event: \
@@ -177,6 +283,7 @@ impl LocationWatcher {
 				}
 
 				Some((path, ignore)) = ignore_path_rx.recv() => {
+					event_handler.ignore_path(&path, ignore);
 					if ignore {
 						paths_to_ignore.insert(path);
 					} else {
@@ -184,6 +291,10 @@ impl LocationWatcher {
 					}
 				}
 
+				Ok(()) = watching_rx.changed() => {
+					event_handler.set_watching(*watching_rx.borrow_and_update());
+				}
+
 				_ = handler_interval.tick() => {
 					event_handler.tick().await;
 				}
@@ -196,9 +307,11 @@ impl LocationWatcher {
 		}
 	}
 
+	#[allow(clippy::too_many_arguments)]
 	async fn handle_single_event<'lib>(
 		location_id: location::id::Type,
 		location_pub_id: Uuid,
+		location_path: &Path,
 		event: Event,
 		event_handler: &mut impl EventHandler<'lib>,
 		node: &'lib Node,
@@ -206,7 +319,7 @@ impl LocationWatcher {
 		ignore_paths: &HashSet<PathBuf>,
 	) -> Result<(), LocationManagerError> {
 		debug!("Event: {:#?}", event);
-		if !check_event(&event, ignore_paths) {
+		if !check_event(&event, location_path, ignore_paths) {
 			return Ok(());
 		}
 
@@ -245,28 +358,34 @@ impl LocationWatcher {
 		let path = &self.path;
 		debug!("Start watching location: (path: {path})");
 
-		if let Err(e) = self
-			.watcher
-			.watch(Path::new(path), RecursiveMode::Recursive)
-		{
-			error!("Unable to watch location: (path: {path}, error: {e:#?})");
-		} else {
-			debug!("Now watching location: (path: {path})");
+		if let Some(watcher) = &mut self.watcher {
+			if let Err(e) = watcher.watch(Path::new(path), RecursiveMode::Recursive) {
+				error!("Unable to watch location: (path: {path}, error: {e:#?})");
+				return;
+			}
 		}
+
+		self.watching_tx.send_replace(true);
+		debug!("Now watching location: (path: {path})");
 	}
 
 	pub(super) fn unwatch(&mut self) {
 		let path = &self.path;
-		if let Err(e) = self.watcher.unwatch(Path::new(path)) {
-			/**************************************** TODO: ****************************************
-			 * According to an unit test, this error may occur when a subdirectory is removed	   *
-			 * and we try to unwatch the parent directory then we have to check the implications   *
-			 * of unwatch error for this case.   												   *
-			 **************************************************************************************/
-			error!("Unable to unwatch location: (path: {path}, error: {e:#?})",);
-		} else {
-			debug!("Stop watching location: (path: {path})");
+		self.watching_tx.send_replace(false);
+
+		if let Some(watcher) = &mut self.watcher {
+			if let Err(e) = watcher.unwatch(Path::new(path)) {
+				/**************************************** TODO: ****************************************
+				 * According to an unit test, this error may occur when a subdirectory is removed	   *
+				 * and we try to unwatch the parent directory then we have to check the implications   *
+				 * of unwatch error for this case.   												   *
+				 **************************************************************************************/
+				error!("Unable to unwatch location: (path: {path}, error: {e:#?})",);
+				return;
+			}
 		}
+
+		debug!("Stop watching location: (path: {path})");
 	}
 }
 
@@ -0,0 +1,616 @@
+//! Network file systems (NFS, SMB, sshfs, ...) and most FUSE mounts don't deliver native file
+//! system events, so locations on them are polled instead. Every [`POLLING_INTERVAL`] we walk the
+//! location and diff each directory against its `file_path` rows: entries missing on either side
+//! are created or removed, unless a removed row and a new entry share an inode, meaning it was
+//! renamed, and files whose size or modification date changed are updated.
+//! We only walk into directories that are already indexed, as `create_dir` scans the new ones.
+//! Directories whose modification time and inode didn't change since the last poll aren't listed
+//! again, except every [`FULL_POLLING_INTERVAL`] to find files modified in place.
+
+use crate::{
+	library::Library,
+	location::{
+		find_location,
+		indexer::rules::{IndexerRule, IndexerRuleError, RuleKind},
+		location_with_indexer_rules,
+		manager::LocationManagerError,
+	},
+	Node,
+};
+
+use sd_file_path_helper::{file_path_walker, FilePathMetadata, IsolatedFilePathData, MetadataExt};
+use sd_prisma::prisma::{file_path, location};
+use sd_utils::{
+	db::{inode_from_db, maybe_missing},
+	error::FileIOError,
+};
+
+use std::{
+	collections::{HashMap, HashSet},
+	fs::Metadata,
+	mem,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use notify::Event;
+use tokio::{fs, task::JoinHandle, time::Instant};
+use tracing::{debug, error, trace};
+use uuid::Uuid;
+
+use super::{
+	utils::{
+		check_path, create_dir, create_file, is_in_location_trash, recalculate_directories_size,
+		remove, rename, update_file,
+	},
+	EventHandler, INode,
+};
+
+/// How long we wait between the end of a poll and the start of the next one
+const POLLING_INTERVAL: Duration = Duration::from_secs(30);
+/// How often every directory is listed, instead of only those whose modification time changed
+const FULL_POLLING_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// File systems, as reported by `sysinfo`, that don't deliver native file system events
+pub(super) fn is_polled_file_system(file_system: &str) -> bool {
+	let file_system = file_system.to_lowercase();
+
+	// `fuseblk` is used by local block devices, like NTFS through ntfs-3g, which deliver events
+	(file_system.starts_with("fuse") && file_system != "fuseblk")
+		|| matches!(
+			file_system.as_str(),
+			"nfs"
+				| "nfs4" | "cifs"
+				| "smb" | "smb2"
+				| "smb3" | "smbfs"
+				| "afpfs" | "webdav"
+				| "davfs" | "sshfs"
+				| "9p" | "ceph"
+				| "glusterfs"
+				| "lustre" | "osxfuse"
+				| "macfuse"
+		)
+}
+
+#[derive(Debug)]
+pub(super) struct PollEventHandler<'lib> {
+	location_id: location::id::Type,
+	library: &'lib Arc<Library>,
+	node: &'lib Arc<Node>,
+	watching: bool,
+	last_poll: Instant,
+	last_full_poll: Option<Instant>,
+	/// The walk of the current poll, running in its own task so the watcher keeps handling messages
+	walk: Option<JoinHandle<Option<Walk>>>,
+	/// Directories found unchanged by the last poll, so they aren't listed again while they stay so
+	directories: HashMap<PathBuf, DirectoryState>,
+	paths_to_ignore: HashSet<PathBuf>,
+	to_recalculate_size: HashMap<PathBuf, Instant>,
+	path_and_instant_buffer: Vec<(PathBuf, Instant)>,
+}
+
+/// Differences found between the location on disk and its `file_path` rows
+#[derive(Debug, Default)]
+struct Changes {
+	created: Vec<(PathBuf, Metadata, INode)>,
+	removed: Vec<(PathBuf, Option<INode>)>,
+	updated: Vec<PathBuf>,
+}
+
+/// A directory as it was when its entries were last listed
+#[derive(Debug)]
+struct DirectoryState {
+	modified: SystemTime,
+	inode: INode,
+	subdirectories: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+struct Walk {
+	changes: Changes,
+	directories: HashMap<PathBuf, DirectoryState>,
+	changed_directories: Vec<PathBuf>,
+}
+
+#[async_trait]
+impl<'lib> EventHandler<'lib> for PollEventHandler<'lib> {
+	fn new(
+		location_id: location::id::Type,
+		library: &'lib Arc<Library>,
+		node: &'lib Arc<Node>,
+	) -> Self {
+		Self {
+			location_id,
+			library,
+			node,
+			watching: false,
+			last_poll: Instant::now(),
+			last_full_poll: None,
+			walk: None,
+			directories: HashMap::new(),
+			paths_to_ignore: HashSet::new(),
+			to_recalculate_size: HashMap::new(),
+			path_and_instant_buffer: Vec::new(),
+		}
+	}
+
+	async fn handle_event(&mut self, event: Event) -> Result<(), LocationManagerError> {
+		// Polled locations aren't registered with a native watcher, so there is nothing to handle
+		trace!("Ignoring event on polled location: {event:#?}");
+
+		Ok(())
+	}
+
+	async fn tick(&mut self) {
+		if self.walk.as_ref().is_some_and(JoinHandle::is_finished) {
+			match self.walk.take().expect("just checked").await {
+				Ok(Some(walk)) => self.apply(walk).await,
+				Ok(None) => {}
+				Err(e) => error!(
+					"Location poll task failed: <id='{}', error='{e:#?}'>",
+					self.location_id
+				),
+			}
+
+			self.last_poll = Instant::now();
+		} else if self.walk.is_none()
+			&& self.watching
+			&& self.last_poll.elapsed() > POLLING_INTERVAL
+		{
+			self.start_walk();
+		}
+
+		if !self.to_recalculate_size.is_empty() {
+			if let Err(e) = recalculate_directories_size(
+				&mut self.to_recalculate_size,
+				&mut self.path_and_instant_buffer,
+				self.location_id,
+				self.library,
+			)
+			.await
+			{
+				error!("Failed to recalculate directories size: {e:#?}");
+			}
+		}
+	}
+
+	fn set_watching(&mut self, watching: bool) {
+		self.watching = watching;
+
+		if !watching {
+			if let Some(walk) = self.walk.take() {
+				walk.abort();
+			}
+		}
+	}
+
+	fn ignore_path(&mut self, path: &Path, ignore: bool) {
+		if ignore {
+			self.paths_to_ignore.insert(path.to_path_buf());
+		} else {
+			self.paths_to_ignore.remove(path);
+		}
+	}
+}
+
+impl Drop for PollEventHandler<'_> {
+	fn drop(&mut self) {
+		if let Some(walk) = self.walk.take() {
+			walk.abort();
+		}
+	}
+}
+
+impl PollEventHandler<'_> {
+	fn start_walk(&mut self) {
+		// Files modified in place don't change the modification time of their directory, so once
+		// in a while every directory is listed again
+		let directories = if self.last_full_poll.map_or(true, |last_full_poll| {
+			last_full_poll.elapsed() > FULL_POLLING_INTERVAL
+		}) {
+			self.last_full_poll = Some(Instant::now());
+			HashMap::new()
+		} else {
+			mem::take(&mut self.directories)
+		};
+
+		let location_id = self.location_id;
+		let library = Arc::clone(self.library);
+		let node = Arc::clone(self.node);
+		let paths_to_ignore = self.paths_to_ignore.clone();
+
+		self.walk = Some(tokio::spawn(async move {
+			walk(location_id, library, node, paths_to_ignore, directories)
+				.await
+				.map_err(|e| {
+					error!("Failed to poll location: <id='{location_id}', error='{e:#?}'>");
+				})
+				.ok()
+				.flatten()
+		}));
+	}
+
+	async fn apply(&mut self, walk: Walk) {
+		let Walk {
+			changes: Changes {
+				mut created,
+				mut removed,
+				mut updated,
+			},
+			directories,
+			changed_directories,
+		} = walk;
+
+		self.directories = directories;
+
+		let now = Instant::now();
+		self.to_recalculate_size
+			.extend(changed_directories.into_iter().map(|dir| (dir, now)));
+
+		// Paths may have been ignored since the walk started
+		created.retain(|(path, _, _)| check_path(path, &self.paths_to_ignore));
+		removed.retain(|(path, _)| check_path(path, &self.paths_to_ignore));
+		updated.retain(|path| check_path(path, &self.paths_to_ignore));
+
+		if created.is_empty() && removed.is_empty() && updated.is_empty() {
+			return;
+		}
+
+		debug!(
+			"Poll found changes at location: <id='{}', created={}, removed={}, updated={}>",
+			self.location_id,
+			created.len(),
+			removed.len(),
+			updated.len()
+		);
+
+		let mut removed_by_inode = HashMap::with_capacity(removed.len());
+		let mut removed_without_inode = vec![];
+		for (path, maybe_inode) in removed {
+			if let Some(inode) = maybe_inode {
+				removed_by_inode.insert(inode, path);
+			} else {
+				removed_without_inode.push(path);
+			}
+		}
+
+		let mut created_without_rename = Vec::with_capacity(created.len());
+		for (path, metadata, inode) in created {
+			if let Some(old_path) = removed_by_inode.remove(&inode) {
+				if let Some(parent) = old_path.parent() {
+					self.to_recalculate_size
+						.insert(parent.to_path_buf(), Instant::now());
+				}
+
+				if let Err(e) =
+					rename(self.location_id, &path, &old_path, metadata, self.library).await
+				{
+					error!(
+						"Failed to rename file_path: <from='{}', to='{}', error='{e:#?}'>",
+						old_path.display(),
+						path.display()
+					);
+				}
+			} else {
+				created_without_rename.push((path, metadata));
+			}
+		}
+
+		// Removing before creating, so reused inodes don't match the removed rows
+		for path in removed_by_inode.into_values().chain(removed_without_inode) {
+			if let Err(e) = remove(self.location_id, &path, self.library).await {
+				error!(
+					"Failed to remove file_path: <path='{}', error='{e:#?}'>",
+					path.display()
+				);
+			}
+		}
+
+		for (path, metadata) in created_without_rename {
+			if let Err(e) = if metadata.is_dir() {
+				create_dir(self.location_id, &path, &metadata, self.node, self.library).await
+			} else {
+				create_file(self.location_id, &path, &metadata, self.node, self.library).await
+			} {
+				error!(
+					"Failed to create file_path: <path='{}', error='{e:#?}'>",
+					path.display()
+				);
+			}
+		}
+
+		for path in updated {
+			if let Err(e) = update_file(self.location_id, &path, self.node, self.library).await {
+				error!(
+					"Failed to update file_path: <path='{}', error='{e:#?}'>",
+					path.display()
+				);
+			}
+		}
+	}
+}
+
+/// Walks the location, only listing the directories which changed since `previous` was recorded
+async fn walk(
+	location_id: location::id::Type,
+	library: Arc<Library>,
+	node: Arc<Node>,
+	paths_to_ignore: HashSet<PathBuf>,
+	mut previous: HashMap<PathBuf, DirectoryState>,
+) -> Result<Option<Walk>, LocationManagerError> {
+	let location = find_location(&library, location_id)
+		.include(location_with_indexer_rules::include())
+		.exec()
+		.await?
+		.ok_or(LocationManagerError::MissingLocation(location_id))?;
+
+	if !node
+		.locations
+		.is_online(&Uuid::from_slice(&location.pub_id)?)
+		.await
+	{
+		return Ok(None);
+	}
+
+	let location_path = PathBuf::from(maybe_missing(&location.path, "location.path")?);
+
+	let indexer_rules = location
+		.indexer_rules
+		.iter()
+		.map(|rule| IndexerRule::try_from(&rule.indexer_rule))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	debug!("Polling location: <id='{location_id}'>");
+
+	let walker = Walker {
+		location_id,
+		location_path: &location_path,
+		library: &library,
+		indexer_rules: &indexer_rules,
+		paths_to_ignore: &paths_to_ignore,
+	};
+
+	let mut walk = Walk::default();
+	let mut dirs_to_visit = vec![location_path.clone()];
+
+	while let Some(dir) = dirs_to_visit.pop() {
+		match walker.visit(&dir, &mut previous, &mut walk).await {
+			Ok(subdirectories) => dirs_to_visit.extend(subdirectories),
+			Err(e) => error!(
+				"Failed to poll directory: <path='{}', error='{e:#?}'>",
+				dir.display()
+			),
+		}
+	}
+
+	Ok(Some(walk))
+}
+
+struct Walker<'a> {
+	location_id: location::id::Type,
+	location_path: &'a Path,
+	library: &'a Library,
+	indexer_rules: &'a [IndexerRule],
+	paths_to_ignore: &'a HashSet<PathBuf>,
+}
+
+impl Walker<'_> {
+	fn check_path(&self, path: &Path) -> bool {
+		check_path(path, self.paths_to_ignore) && !is_in_location_trash(path, self.location_path)
+	}
+
+	/// Diffs `dir` unless its modification time and inode are the same as in `previous`, returning
+	/// its indexed subdirectories either way, as changes deeper down don't show up on `dir`
+	async fn visit(
+		&self,
+		dir: &Path,
+		previous: &mut HashMap<PathBuf, DirectoryState>,
+		walk: &mut Walk,
+	) -> Result<Vec<PathBuf>, LocationManagerError> {
+		let metadata = fs::metadata(dir)
+			.await
+			.map_err(|e| FileIOError::from((dir, e)))?;
+		let modified = metadata.modified().ok();
+		let inode = FilePathMetadata::from_path(dir, &metadata).await?.inode;
+
+		if let Some(state) = previous
+			.remove(dir)
+			.filter(|state| Some(state.modified) == modified && state.inode == inode)
+		{
+			let subdirectories = state.subdirectories.clone();
+			walk.directories.insert(dir.to_path_buf(), state);
+
+			return Ok(subdirectories);
+		}
+
+		let (subdirectories, changed) = self.diff_directory(dir, &mut walk.changes).await?;
+
+		if changed {
+			// Not recorded, so the directories created by the changes are found by the next poll
+			walk.changed_directories.push(dir.to_path_buf());
+		} else if let Some(modified) = modified {
+			walk.directories.insert(
+				dir.to_path_buf(),
+				DirectoryState {
+					modified,
+					inode,
+					subdirectories: subdirectories.clone(),
+				},
+			);
+		}
+
+		Ok(subdirectories)
+	}
+
+	/// Returns the indexed subdirectories of `dir` and whether it changed
+	async fn diff_directory(
+		&self,
+		dir: &Path,
+		changes: &mut Changes,
+	) -> Result<(Vec<PathBuf>, bool), LocationManagerError> {
+		let children_materialized_path =
+			IsolatedFilePathData::new(self.location_id, self.location_path, dir, true)?
+				.materialized_path_for_children()
+				.expect("We're diffing a directory");
+
+		let mut file_paths = HashMap::new();
+		for file_path in self
+			.library
+			.db
+			.file_path()
+			.find_many(vec![
+				file_path::location_id::equals(Some(self.location_id)),
+				file_path::materialized_path::equals(Some(children_materialized_path)),
+			])
+			.select(file_path_walker::select())
+			.exec()
+			.await?
+		{
+			let full_name = IsolatedFilePathData::try_from(&file_path)?.full_name();
+			file_paths.insert(dir.join(full_name), file_path);
+		}
+
+		let mut read_dir = fs::read_dir(dir)
+			.await
+			.map_err(|e| FileIOError::from((dir, e)))?;
+
+		let mut subdirectories = vec![];
+		let mut changed = false;
+
+		while let Some(entry) = read_dir
+			.next_entry()
+			.await
+			.map_err(|e| FileIOError::from((dir, e)))?
+		{
+			let path = entry.path();
+
+			let Some(file_path) = file_paths.remove(&path) else {
+				if !self.check_path(&path) {
+					continue;
+				}
+
+				let metadata = entry
+					.metadata()
+					.await
+					.map_err(|e| FileIOError::from((&path, e)))?;
+
+				// Just like the indexer, we ignore symlinks for now
+				if metadata.is_symlink()
+					|| !is_indexable(self.indexer_rules, &path, metadata.is_dir()).await?
+				{
+					continue;
+				}
+
+				let inode = FilePathMetadata::from_path(&path, &metadata).await?.inode;
+				changes.created.push((path, metadata, inode));
+				changed = true;
+
+				continue;
+			};
+
+			if !self.check_path(&path) {
+				continue;
+			}
+
+			if maybe_missing(file_path.is_dir, "file_path.is_dir")? {
+				subdirectories.push(path);
+				continue;
+			}
+
+			let metadata = entry
+				.metadata()
+				.await
+				.map_err(|e| FileIOError::from((&path, e)))?;
+
+			let size_changed = file_path
+				.size_in_bytes_bytes
+				.as_deref()
+				.and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+				.map(u64::from_be_bytes)
+				!= Some(metadata.len());
+
+			// Datetimes stored in DB loses a bit of precision, so we need to check against a delta
+			let modified_at = DateTime::<Utc>::from(metadata.modified_or_now());
+			let date_changed = file_path.date_modified.map_or(true, |date_modified| {
+				(modified_at - date_modified.with_timezone(&Utc))
+					.num_milliseconds()
+					.abs() > 1
+			});
+
+			if size_changed || date_changed {
+				changes.updated.push(path);
+				changed = true;
+			}
+		}
+
+		// Whatever is left wasn't found on disk
+		for (path, file_path) in file_paths {
+			if self.check_path(&path) {
+				changes.removed.push((
+					path,
+					file_path
+						.inode
+						.as_deref()
+						.map(|inode| inode_from_db(&inode[0..8])),
+				));
+				changed = true;
+			}
+		}
+
+		Ok((subdirectories, changed))
+	}
+}
+
+/// Whether the indexer would index a new entry, given the location's indexer rules
+async fn is_indexable(
+	indexer_rules: &[IndexerRule],
+	path: &Path,
+	is_dir: bool,
+) -> Result<bool, IndexerRuleError> {
+	let rules_per_kind = IndexerRule::apply_all(indexer_rules, path).await?;
+
+	let rejected_by = |kind: RuleKind| {
+		rules_per_kind
+			.get(&kind)
+			.map_or(false, |results| results.iter().any(|accept| !accept))
+	};
+
+	if rejected_by(RuleKind::RejectFilesByGlob) {
+		return Ok(false);
+	}
+
+	Ok(if is_dir {
+		!rejected_by(RuleKind::RejectIfChildrenDirectoriesArePresent)
+	} else {
+		rules_per_kind
+			.get(&RuleKind::AcceptFilesByGlob)
+			.map_or(true, |results| results.iter().any(|accept| *accept))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::is_polled_file_system;
+
+	#[test]
+	fn polled_file_systems() {
+		for file_system in [
+			"nfs",
+			"NFS4",
+			"cifs",
+			"smbfs",
+			"fuse.sshfs",
+			"fuse.rclone",
+			"9p",
+		] {
+			assert!(is_polled_file_system(file_system), "{file_system}");
+		}
+
+		for file_system in ["ext4", "APFS", "NTFS", "btrfs", "fuseblk", "tmpfs"] {
+			assert!(!is_polled_file_system(file_system), "{file_system}");
+		}
+	}
+}
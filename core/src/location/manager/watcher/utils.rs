@@ -7,6 +7,7 @@ use crate::{
 		manager::LocationManagerError, scan_location_sub_path, update_location_size,
 	},
 	object::{
+		fs::trash::TRASH_DIR_NAME,
 		media::{
 			media_data_extractor::{can_extract_media_data_for_image, extract_media_data},
 			media_data_image_to_query_params,
@@ -64,14 +65,33 @@ use uuid::Uuid;
 
 use super::{INode, HUNDRED_MILLIS};
 
-pub(super) fn check_event(event: &Event, ignore_paths: &HashSet<PathBuf>) -> bool {
+pub(super) fn check_event(
+	event: &Event,
+	location_path: &Path,
+	ignore_paths: &HashSet<PathBuf>,
+) -> bool {
+	event
+		.paths
+		.iter()
+		.all(|p| check_path(p, ignore_paths) && !is_in_location_trash(p, location_path))
+}
+
+pub(super) fn check_path(path: &Path, ignore_paths: &HashSet<PathBuf>) -> bool {
 	// if path includes .DS_Store, .spacedrive file creation or is in the `ignore_paths` set, we ignore
-	!event.paths.iter().any(|p| {
-		p.file_name()
-			.and_then(OsStr::to_str)
-			.map_or(false, |name| name == ".DS_Store" || name == ".spacedrive")
-			|| ignore_paths.contains(p)
-	})
+	!(path
+		.file_name()
+		.and_then(OsStr::to_str)
+		.map_or(false, |name| name == ".DS_Store" || name == ".spacedrive")
+		|| ignore_paths.contains(path))
+}
+
+/// Whether `path` is the location's own trash directory or inside it, other `.trash` directories
+/// in the location are regular directories
+pub(super) fn is_in_location_trash(path: &Path, location_path: &Path) -> bool {
+	path.strip_prefix(location_path)
+		.ok()
+		.and_then(|relative_path| relative_path.components().next())
+		.map_or(false, |component| component.as_os_str() == TRASH_DIR_NAME)
 }
 
 pub(super) async fn create_dir(
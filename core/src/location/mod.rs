@@ -2,6 +2,7 @@ use crate::{
 	invalidate_query,
 	library::Library,
 	object::{
+		content::old_content_indexer_job::OldContentIndexerJobInit,
 		media::{old_media_processor, OldMediaProcessorJobInit},
 		old_file_identifier::{self, old_file_identifier_job::OldFileIdentifierJobInit},
 	},
@@ -45,10 +46,11 @@ pub mod indexer;
 mod manager;
 pub mod metadata;
 pub mod non_indexed;
+pub mod schedule;
 
 pub use error::LocationError;
 use indexer::OldIndexerJobInit;
-pub use manager::{LocationManagerError, Locations};
+pub use manager::{LocationManagerError, Locations, WatcherMode};
 use metadata::SpacedriveLocationMetadataFile;
 
 pub type LocationPubId = Uuid;
@@ -277,6 +279,7 @@ pub struct LocationUpdateArgs {
 	generate_preview_media: Option<bool>,
 	sync_preview_media: Option<bool>,
 	hidden: Option<bool>,
+	watcher_mode: Option<WatcherMode>,
 	indexer_rules_ids: Vec<i32>,
 	path: Option<String>,
 }
@@ -293,6 +296,10 @@ impl LocationUpdateArgs {
 
 		let name = self.name.clone();
 
+		let watcher_mode = self
+			.watcher_mode
+			.filter(|mode| WatcherMode::from(location.watcher_mode) != *mode);
+
 		let (sync_params, db_params): (Vec<_>, Vec<_>) = [
 			self.name
 				.filter(|name| location.name.as_ref() != Some(name))
@@ -320,6 +327,12 @@ impl LocationUpdateArgs {
 					location::hidden::set(Some(v)),
 				)
 			}),
+			watcher_mode.map(|v| {
+				(
+					(location::watcher_mode::NAME, msgpack!(v as i32)),
+					location::watcher_mode::set(Some(v as i32)),
+				)
+			}),
 			self.path.clone().map(|v| {
 				(
 					(location::path::NAME, msgpack!(v)),
@@ -366,7 +379,8 @@ impl LocationUpdateArgs {
 				}
 			}
 
-			if self.path.is_some() {
+			// The watcher is picked when it's created, so it has to be recreated to switch modes
+			if self.path.is_some() || watcher_mode.is_some() {
 				node.locations.remove(self.id, library.clone()).await?;
 				node.locations.add(self.id, library.clone()).await?;
 			}
@@ -465,11 +479,16 @@ pub async fn scan_location(
 		sub_path: None,
 	})
 	.queue_next(OldMediaProcessorJobInit {
-		location: location_base_data,
+		location: location_base_data.clone(),
 		sub_path: None,
 		regenerate_thumbnails: false,
 		regenerate_labels: false,
 	})
+	.queue_next(OldContentIndexerJobInit {
+		location: location_base_data,
+		sub_path: None,
+		regenerate: false,
+	})
 	.spawn(node, library)
 	.await
 	.map_err(Into::into)
@@ -505,11 +524,16 @@ pub async fn scan_location_sub_path(
 		sub_path: Some(sub_path.clone()),
 	})
 	.queue_next(OldMediaProcessorJobInit {
-		location: location_base_data,
-		sub_path: Some(sub_path),
+		location: location_base_data.clone(),
+		sub_path: Some(sub_path.clone()),
 		regenerate_thumbnails: false,
 		regenerate_labels: false,
 	})
+	.queue_next(OldContentIndexerJobInit {
+		location: location_base_data,
+		sub_path: Some(sub_path),
+		regenerate: false,
+	})
 	.spawn(node, library)
 	.await
 	.map_err(Into::into)
@@ -663,7 +687,8 @@ async fn create_location(
 		.location()
 		.count(vec![location::path::equals(Some(path.clone()))])
 		.exec()
-		.await? > 0
+		.await?
+		> 0
 	{
 		return Err(LocationError::LocationAlreadyExists(location_path.into()));
 	}
@@ -0,0 +1,642 @@
+//! Locations can be rescanned on a schedule, for the ones where the watcher can't be relied on,
+//! like network shares and removable drives which often don't emit events.
+//!
+//! The location manager checks the schedule of its online locations every
+//! [`SCHEDULE_CHECK_INTERVAL`] and queues a scan once one is due and its conditions are met.
+//! The same goes for integrity verifications, which hash the files of the location again to
+//! detect silent corruption.
+
+use crate::{
+	invalidate_query,
+	library::Library,
+	location::{
+		find_location, indexer::OldIndexerJobInit, light_scan_location,
+		location_with_indexer_rules, scan_location, LocationError, LocationManagerError,
+	},
+	object::validation::old_integrity_verifier_job::OldIntegrityVerifierJobInit,
+	old_job::{Job, StatefulJob},
+	Node,
+};
+
+use sd_prisma::{prisma::location, prisma_sync};
+use sd_sync::OperationFactory;
+use sd_utils::msgpack;
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, FixedOffset, Local, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use sysinfo::{CpuExt, System, SystemExt};
+use tracing::{debug, error, info};
+
+/// How often the location manager checks if a scheduled scan is due
+pub(super) const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The system is considered idle below this global CPU usage, in percent
+const IDLE_CPU_USAGE: f32 = 20.0;
+
+/// When a location is rescanned, and under which conditions
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct ScanSchedule {
+	pub every: ScanInterval,
+	/// Full scans index the whole location, otherwise only its root directory is quickly rescanned
+	pub full: bool,
+	pub only_on_ac_power: bool,
+	pub only_when_idle: bool,
+	/// Scans due during the quiet hours are delayed until they end
+	pub quiet_hours: Option<QuietHours>,
+	/// When the integrity of the files of the location is verified, under the same conditions
+	#[serde(default)]
+	pub verify_integrity: Option<ScanInterval>,
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ScanInterval {
+	Seconds(u32),
+	/// A cron expression, in local time. A leading seconds field is optional.
+	Cron(String),
+}
+
+/// A daily time range in local time, in minutes after midnight. It can span midnight,
+/// eg. from 22:00 to 07:00.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct QuietHours {
+	pub start: u16,
+	pub end: u16,
+}
+
+impl ScanInterval {
+	pub(crate) fn validate(&self) -> Result<(), LocationError> {
+		match self {
+			Self::Seconds(0) => Err(LocationError::InvalidScanSchedule(
+				"the interval must be greater than zero".to_string(),
+			)),
+			Self::Seconds(_) => Ok(()),
+			Self::Cron(expression) => parse_cron(expression).map(|_| ()),
+		}
+	}
+
+	/// When the interval is next due, after the previous time it was
+	pub(crate) fn next_after(&self, last: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		match self {
+			Self::Seconds(seconds) => Some(last + chrono::Duration::seconds(i64::from(*seconds))),
+			Self::Cron(expression) => parse_cron(expression)
+				.ok()?
+				.after(&last.with_timezone(&Local))
+				.next()
+				.map(|next| next.with_timezone(&Utc)),
+		}
+	}
+}
+
+impl QuietHours {
+	fn contains(&self, minute: u16) -> bool {
+		if self.start <= self.end {
+			self.start <= minute && minute < self.end
+		} else {
+			self.start <= minute || minute < self.end
+		}
+	}
+}
+
+/// The scan schedule of a location, along with when it was last and will next be scanned
+/// and verified
+#[derive(Serialize, Type, Debug)]
+pub struct LocationScanSchedule {
+	pub schedule: ScanSchedule,
+	pub last_scan: Option<DateTime<Utc>>,
+	pub next_scan: Option<DateTime<Utc>>,
+	pub last_integrity_check: Option<DateTime<Utc>>,
+	pub next_integrity_check: Option<DateTime<Utc>>,
+}
+
+impl ScanSchedule {
+	fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		rmp_serde::from_slice(bytes)
+			.map_err(|e| error!("Invalid location scan schedule: {e:#?}"))
+			.ok()
+	}
+
+	fn validate(&self) -> Result<(), LocationError> {
+		const MINUTES_IN_A_DAY: u16 = 24 * 60;
+
+		if let Some(quiet_hours) = &self.quiet_hours {
+			if quiet_hours.start >= MINUTES_IN_A_DAY || quiet_hours.end >= MINUTES_IN_A_DAY {
+				return Err(LocationError::InvalidScanSchedule(
+					"quiet hours must be within a day".to_string(),
+				));
+			}
+		}
+
+		if self.only_on_ac_power && !CAN_CHECK_AC_POWER {
+			return Err(LocationError::InvalidScanSchedule(
+				"the power source can't be checked on this platform".to_string(),
+			));
+		}
+
+		self.every.validate()?;
+
+		if let Some(verify_integrity) = &self.verify_integrity {
+			verify_integrity.validate()?;
+		}
+
+		Ok(())
+	}
+
+	/// When the next scan is due, after the previous one
+	fn next_scan(&self, last_scan: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		self.every.next_after(last_scan)
+	}
+
+	/// When the next integrity verification is due, after the previous one
+	fn next_integrity_check(&self, last_check: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		self.verify_integrity.as_ref()?.next_after(last_check)
+	}
+
+	/// Why a scan or verification that is due has to wait, if it does
+	async fn delay_reason(&self) -> Option<&'static str> {
+		if self.quiet_hours.as_ref().is_some_and(|quiet_hours| {
+			quiet_hours.contains((Local::now().num_seconds_from_midnight() / 60) as u16)
+		}) {
+			return Some("during quiet hours");
+		}
+
+		if self.only_on_ac_power && !on_ac_power().await {
+			return Some("until on AC power");
+		}
+
+		if self.only_when_idle && !is_idle().await {
+			return Some("until the system is idle");
+		}
+
+		None
+	}
+}
+
+fn parse_cron(expression: &str) -> Result<cron::Schedule, LocationError> {
+	// The cron crate wants a seconds field, which usual cron expressions don't have
+	let expression = if expression.split_whitespace().count() == 5 {
+		format!("0 {expression}")
+	} else {
+		expression.to_string()
+	};
+
+	cron::Schedule::from_str(&expression)
+		.map_err(|e| LocationError::InvalidScanSchedule(e.to_string()))
+}
+
+pub async fn get_scan_schedule(
+	library: &Library,
+	location_id: location::id::Type,
+) -> Result<Option<LocationScanSchedule>, LocationError> {
+	let location = find_location(library, location_id)
+		.select(location::select!({
+			scan_schedule
+			date_last_scheduled_scan
+			date_last_integrity_check
+		}))
+		.exec()
+		.await?
+		.ok_or(LocationError::IdNotFound(location_id))?;
+
+	let last_scan = location
+		.date_last_scheduled_scan
+		.map(|date| date.with_timezone(&Utc));
+	let last_integrity_check = location
+		.date_last_integrity_check
+		.map(|date| date.with_timezone(&Utc));
+
+	Ok(location
+		.scan_schedule
+		.as_deref()
+		.and_then(ScanSchedule::from_bytes)
+		.map(|schedule| LocationScanSchedule {
+			next_scan: schedule.next_scan(last_scan.unwrap_or_else(Utc::now)),
+			last_scan,
+			next_integrity_check: schedule
+				.next_integrity_check(last_integrity_check.unwrap_or_else(Utc::now)),
+			last_integrity_check,
+			schedule,
+		}))
+}
+
+/// Sets or removes the scan schedule of a location, the first scheduled scan being due one
+/// interval from now
+pub async fn set_scan_schedule(
+	library: &Library,
+	location_id: location::id::Type,
+	schedule: Option<ScanSchedule>,
+) -> Result<(), LocationError> {
+	let Library { db, sync, .. } = library;
+
+	if let Some(schedule) = &schedule {
+		schedule.validate()?;
+	}
+
+	let location = find_location(library, location_id)
+		.select(location::select!({ pub_id }))
+		.exec()
+		.await?
+		.ok_or(LocationError::IdNotFound(location_id))?;
+
+	let scan_schedule = schedule.map(|schedule| {
+		rmp_serde::to_vec_named(&schedule).expect("scan schedule is always serializable")
+	});
+
+	sync.write_op(
+		db,
+		sync.shared_update(
+			prisma_sync::location::SyncId {
+				pub_id: location.pub_id,
+			},
+			location::scan_schedule::NAME,
+			msgpack!(&scan_schedule),
+		),
+		db.location().update(
+			location::id::equals(location_id),
+			vec![location::scan_schedule::set(scan_schedule)],
+		),
+	)
+	.await?;
+
+	// Only local, each instance tracks when it last scanned and verified its own locations
+	let date_now: DateTime<FixedOffset> = Utc::now().into();
+	db.location()
+		.update(
+			location::id::equals(location_id),
+			vec![
+				location::date_last_scheduled_scan::set(Some(date_now)),
+				location::date_last_integrity_check::set(Some(date_now)),
+			],
+		)
+		.exec()
+		.await?;
+
+	invalidate_query!(library, "locations.getScanSchedule");
+
+	Ok(())
+}
+
+/// Queues a scan or an integrity verification of the location if its schedule says one is due
+/// and its conditions are met
+pub(super) async fn run_scheduled_scan(
+	node: Arc<Node>,
+	library: Arc<Library>,
+	location_id: location::id::Type,
+) {
+	if let Err(e) = try_run_scheduled_scan(&node, &library, location_id).await {
+		error!("Failed to run scheduled scan of location <id='{location_id}'>: {e:#?}");
+	}
+}
+
+async fn try_run_scheduled_scan(
+	node: &Arc<Node>,
+	library: &Arc<Library>,
+	location_id: location::id::Type,
+) -> Result<(), LocationError> {
+	let Some(location) = find_location(library, location_id)
+		.include(location_with_indexer_rules::include())
+		.exec()
+		.await?
+	else {
+		return Ok(());
+	};
+
+	let Some(schedule) = location
+		.scan_schedule
+		.as_deref()
+		.and_then(ScanSchedule::from_bytes)
+	else {
+		return Ok(());
+	};
+
+	let now = Utc::now();
+	let date_now: DateTime<FixedOffset> = now.into();
+
+	let (Some(last_scan), Some(last_integrity_check)) = (
+		location.date_last_scheduled_scan,
+		location.date_last_integrity_check,
+	) else {
+		// The schedule was set on another instance, so we start counting from now
+		library
+			.db
+			.location()
+			.update(
+				location::id::equals(location_id),
+				vec![
+					location::date_last_scheduled_scan::set(Some(
+						location.date_last_scheduled_scan.unwrap_or(date_now),
+					)),
+					location::date_last_integrity_check::set(Some(
+						location.date_last_integrity_check.unwrap_or(date_now),
+					)),
+				],
+			)
+			.exec()
+			.await?;
+
+		return Ok(());
+	};
+
+	let is_due = |next: Option<DateTime<Utc>>| next.is_some_and(|next| next <= now);
+
+	let scan_due = is_due(schedule.next_scan(last_scan.with_timezone(&Utc)));
+	let integrity_check_due =
+		is_due(schedule.next_integrity_check(last_integrity_check.with_timezone(&Utc)));
+
+	if !scan_due && !integrity_check_due {
+		return Ok(());
+	}
+
+	if let Some(reason) = schedule.delay_reason().await {
+		debug!("Delaying scheduled work on location <id='{location_id}'> {reason}");
+		return Ok(());
+	}
+
+	if integrity_check_due {
+		run_scheduled_integrity_check(node, library, location_id, date_now).await?;
+	}
+
+	if !scan_due {
+		return Ok(());
+	}
+
+	if node
+		.old_jobs
+		.has_job_running(|job_identity| {
+			job_identity.target_location == location_id
+				&& job_identity.name == <OldIndexerJobInit as StatefulJob>::NAME
+		})
+		.await
+	{
+		debug!("Skipping scheduled scan of location <id='{location_id}'> as it's being indexed");
+		return Ok(());
+	}
+
+	library
+		.db
+		.location()
+		.update(
+			location::id::equals(location_id),
+			vec![location::date_last_scheduled_scan::set(Some(date_now))],
+		)
+		.exec()
+		.await?;
+
+	invalidate_query!(library, "locations.getScanSchedule");
+
+	info!(
+		"Running scheduled {} scan of location <id='{location_id}'>",
+		if schedule.full { "full" } else { "quick" }
+	);
+
+	if schedule.full {
+		scan_location(node, library, location)
+			.await
+			.map_err(LocationManagerError::from)?;
+	} else {
+		tokio::spawn({
+			let node = Arc::clone(node);
+			let library = Arc::clone(library);
+			async move {
+				if let Err(e) = light_scan_location(node, library, location, "").await {
+					error!("Scheduled quick scan of location <id='{location_id}'> failed: {e:#?}");
+				}
+			}
+		});
+	}
+
+	Ok(())
+}
+
+async fn run_scheduled_integrity_check(
+	node: &Arc<Node>,
+	library: &Arc<Library>,
+	location_id: location::id::Type,
+	date_now: DateTime<FixedOffset>,
+) -> Result<(), LocationError> {
+	if node
+		.old_jobs
+		.has_job_running(|job_identity| {
+			job_identity.target_location == location_id
+				&& job_identity.name == <OldIntegrityVerifierJobInit as StatefulJob>::NAME
+		})
+		.await
+	{
+		debug!(
+			"Skipping scheduled integrity check of location <id='{location_id}'> as it's running"
+		);
+		return Ok(());
+	}
+
+	let Some(location) = find_location(library, location_id).exec().await? else {
+		return Ok(());
+	};
+
+	library
+		.db
+		.location()
+		.update(
+			location::id::equals(location_id),
+			vec![location::date_last_integrity_check::set(Some(date_now))],
+		)
+		.exec()
+		.await?;
+
+	invalidate_query!(library, "locations.getScanSchedule");
+
+	info!("Running scheduled integrity check of location <id='{location_id}'>");
+
+	Job::new(OldIntegrityVerifierJobInit {
+		location,
+		sub_path: None,
+	})
+	.spawn(node, library)
+	.await
+	.map_err(LocationManagerError::from)?;
+
+	Ok(())
+}
+
+/// Whether the CPU usage is low enough for a background scan not to get in the way
+async fn is_idle() -> bool {
+	let mut system = System::new();
+	system.refresh_cpu();
+	// CPU usage is computed between two refreshes
+	tokio::time::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL).await;
+	system.refresh_cpu();
+
+	system.global_cpu_info().cpu_usage() < IDLE_CPU_USAGE
+}
+
+/// Whether the power source of the device can be checked, see [`on_ac_power`]
+const CAN_CHECK_AC_POWER: bool = cfg!(any(
+	target_os = "linux",
+	target_os = "macos",
+	target_os = "windows"
+));
+
+/// Whether the device runs on AC power. Devices without a battery always do.
+async fn on_ac_power() -> bool {
+	#[cfg(target_os = "linux")]
+	{
+		let Ok(mut read_dir) = tokio::fs::read_dir("/sys/class/power_supply").await else {
+			return true;
+		};
+
+		let mut has_battery = false;
+		while let Ok(Some(entry)) = read_dir.next_entry().await {
+			let path = entry.path();
+			let Ok(kind) = tokio::fs::read_to_string(path.join("type")).await else {
+				continue;
+			};
+
+			match kind.trim() {
+				"Mains" => {
+					if tokio::fs::read_to_string(path.join("online"))
+						.await
+						.is_ok_and(|online| online.trim() == "1")
+					{
+						return true;
+					}
+				}
+				"Battery" => has_battery = true,
+				_ => {}
+			}
+		}
+
+		!has_battery
+	}
+
+	#[cfg(target_os = "macos")]
+	{
+		// The first line of the output is eg. `Now drawing from 'AC Power'`
+		tokio::process::Command::new("pmset")
+			.args(["-g", "batt"])
+			.output()
+			.await
+			.map_or(true, |output| {
+				!String::from_utf8_lossy(&output.stdout).contains("'Battery Power'")
+			})
+	}
+
+	#[cfg(target_os = "windows")]
+	{
+		use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+		/// `ACLineStatus` when the device runs on battery, it's 1 on AC power and 255 if unknown
+		const AC_LINE_OFFLINE: u8 = 0;
+
+		// SAFETY: `SYSTEM_POWER_STATUS` only holds integers, so all zeroes is a valid value
+		let mut status = unsafe { std::mem::zeroed::<SYSTEM_POWER_STATUS>() };
+
+		// SAFETY: `status` is a valid `SYSTEM_POWER_STATUS` for the call to write to
+		if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+			return true;
+		}
+
+		status.ACLineStatus != AC_LINE_OFFLINE
+	}
+
+	#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+	{
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn schedule(every: ScanInterval) -> ScanSchedule {
+		ScanSchedule {
+			every,
+			full: false,
+			only_on_ac_power: false,
+			only_when_idle: false,
+			quiet_hours: None,
+			verify_integrity: None,
+		}
+	}
+
+	#[test]
+	fn quiet_hours() {
+		let time = |h: u16, m: u16| h * 60 + m;
+
+		let night = QuietHours {
+			start: time(22, 0),
+			end: time(7, 0),
+		};
+		assert!(night.contains(time(23, 30)));
+		assert!(night.contains(time(3, 0)));
+		assert!(!night.contains(time(7, 0)));
+		assert!(!night.contains(time(12, 0)));
+
+		let lunch = QuietHours {
+			start: time(12, 0),
+			end: time(13, 0),
+		};
+		assert!(lunch.contains(time(12, 30)));
+		assert!(!lunch.contains(time(13, 30)));
+	}
+
+	#[test]
+	fn next_scan() {
+		let last_scan = Utc::now();
+
+		assert_eq!(
+			schedule(ScanInterval::Seconds(3600)).next_scan(last_scan),
+			Some(last_scan + chrono::Duration::hours(1))
+		);
+
+		let next_scan = schedule(ScanInterval::Cron("*/15 * * * *".to_string()))
+			.next_scan(last_scan)
+			.unwrap();
+		assert!(next_scan > last_scan);
+		assert!(next_scan <= last_scan + chrono::Duration::minutes(15));
+
+		assert_eq!(
+			schedule(ScanInterval::Seconds(60)).next_integrity_check(last_scan),
+			None
+		);
+		assert_eq!(
+			ScanSchedule {
+				verify_integrity: Some(ScanInterval::Seconds(86400)),
+				..schedule(ScanInterval::Seconds(60))
+			}
+			.next_integrity_check(last_scan),
+			Some(last_scan + chrono::Duration::days(1))
+		);
+	}
+
+	#[test]
+	fn validate() {
+		assert!(ScanSchedule {
+			quiet_hours: Some(QuietHours {
+				start: 22 * 60,
+				end: 24 * 60,
+			}),
+			..schedule(ScanInterval::Seconds(60))
+		}
+		.validate()
+		.is_err());
+		assert!(schedule(ScanInterval::Seconds(0)).validate().is_err());
+		assert!(ScanSchedule {
+			verify_integrity: Some(ScanInterval::Seconds(0)),
+			..schedule(ScanInterval::Seconds(60))
+		}
+		.validate()
+		.is_err());
+		assert!(schedule(ScanInterval::Cron("not cron".to_string()))
+			.validate()
+			.is_err());
+		assert!(schedule(ScanInterval::Cron("0 3 * * *".to_string()))
+			.validate()
+			.is_ok());
+		assert!(schedule(ScanInterval::Cron("30 0 3 * * *".to_string()))
+			.validate()
+			.is_ok());
+	}
+}
@@ -1,17 +1,20 @@
 use crate::{
 	api::{notifications::Notification, BackendFeature},
+	library::backup::LibraryBackupConfig,
 	object::media::old_thumbnail::preferences::ThumbnailerPreferences,
 	util::version_manager::{Kind, ManagedVersion, VersionManager, VersionManagerError},
 };
 
-use sd_p2p::Identity;
+use sd_p2p::{Identity, RemoteIdentity};
 use sd_utils::error::FileIOError;
 
 use std::{
+	collections::HashMap,
 	path::{Path, PathBuf},
 	sync::Arc,
 };
 
+use chrono::{DateTime, Utc};
 use int_enum::IntEnum;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
@@ -72,6 +75,13 @@ pub struct NodeConfig {
 	pub p2p_ipv6_port: Port,
 	#[serde(default)]
 	pub p2p_discovery: P2PDiscoveryState,
+	/// Peers paired with this node or the nodes of our library instances, only they are allowed to
+	/// sync with it or make rspc requests to it
+	#[serde(default)]
+	pub trusted_peers: Vec<TrustedPeer>,
+	/// The backup configuration of each library
+	#[serde(default)]
+	pub backups: HashMap<Uuid, LibraryBackupConfig>,
 	/// Feature flags enabled on the node
 	#[serde(default)]
 	pub features: Vec<BackendFeature>,
@@ -88,6 +98,14 @@ pub struct NodeConfig {
 	version: NodeConfigVersion,
 }
 
+/// A peer which was paired with this node
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct TrustedPeer {
+	pub identity: RemoteIdentity,
+	pub name: String,
+	pub paired_at: DateTime<Utc>,
+}
+
 mod identity_serde {
 	use sd_p2p::Identity;
 	use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -156,6 +174,8 @@ impl ManagedVersion<NodeConfigVersion> for NodeConfig {
 			p2p_ipv4_port: Port::Random,
 			p2p_ipv6_port: Port::Random,
 			p2p_discovery: P2PDiscoveryState::Everyone,
+			trusted_peers: vec![],
+			backups: HashMap::new(),
 			version: Self::LATEST_VERSION,
 			features: vec![],
 			notifications: vec![],
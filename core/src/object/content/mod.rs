@@ -0,0 +1,392 @@
+use crate::old_job::{JobRunErrors, JobRunMetadata};
+
+use sd_file_ext::kind::ObjectKind;
+use sd_file_path_helper::{file_path_for_media_processor, FilePathError, IsolatedFilePathData};
+use sd_prisma::prisma::{content_index, file_path, location, PrismaClient};
+use sd_utils::error::FileIOError;
+
+use std::{collections::HashMap, path::Path};
+
+use chrono::Utc;
+use futures_concurrency::future::Join;
+use prisma_client_rust::{raw, PrismaValue};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::{fs, io::AsyncReadExt, task::spawn_blocking};
+use tracing::error;
+
+pub mod old_content_indexer_job;
+
+/// Object kinds whose files are plain text and can be indexed as they are
+pub const TEXT_CONTENT_KINDS: [ObjectKind; 3] =
+	[ObjectKind::Text, ObjectKind::Code, ObjectKind::Config];
+
+/// Extensions of documents that we know how to extract text from
+pub const DOCUMENT_CONTENT_EXTENSIONS: [&str; 1] = ["pdf"];
+
+/// We only store this many bytes of text per file, anything after it isn't searchable
+const MAX_CONTENT_LEN: usize = 1024 * 1024; // 1 MiB
+/// Text files bigger than this are probably logs or data dumps, so we only read their beginning
+const MAX_TEXT_FILE_READ: u64 = 4 * 1024 * 1024; // 4 MiB
+
+// Markers used by FTS5's `snippet` function, they're control characters so they can't
+// collide with the indexed content after `sanitize_content`
+const SNIPPET_MATCH_START: char = '\u{2}';
+const SNIPPET_MATCH_END: char = '\u{3}';
+const SNIPPET_TOKENS: i64 = 16;
+
+#[derive(Error, Debug)]
+pub enum ContentIndexerError {
+	#[error("sub path not found: <path='{}'>", .0.display())]
+	SubPathNotFound(Box<Path>),
+
+	// Internal errors
+	#[error("database error: {0}")]
+	Database(#[from] prisma_client_rust::QueryError),
+	#[error(transparent)]
+	FilePath(#[from] FilePathError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error(transparent)]
+	Pdf(#[from] sd_images::Error),
+	#[error("failed to join tokio task: {0}")]
+	TokioJoinHandle(#[from] tokio::task::JoinError),
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct OldContentIndexerMetadata {
+	pub indexed: u32,
+	pub skipped: u32,
+}
+
+impl JobRunMetadata for OldContentIndexerMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.indexed += new_data.indexed;
+		self.skipped += new_data.skipped;
+	}
+}
+
+pub fn is_document_with_content(extension: &str) -> bool {
+	DOCUMENT_CONTENT_EXTENSIONS
+		.iter()
+		.any(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Extracts the searchable text of a file, returns `None` for files without textual content
+pub async fn extract_content(
+	path: impl AsRef<Path>,
+	extension: &str,
+) -> Result<Option<String>, ContentIndexerError> {
+	let path = path.as_ref();
+
+	let content = if is_document_with_content(extension) {
+		let path = path.to_path_buf();
+
+		// Running in a separated blocking thread as pdfium is a sync C library
+		spawn_blocking(move || sd_images::extract_pdf_text(path, MAX_CONTENT_LEN)).await??
+	} else {
+		let file = fs::File::open(path)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		let mut buf = Vec::new();
+		file.take(MAX_TEXT_FILE_READ)
+			.read_to_end(&mut buf)
+			.await
+			.map_err(|e| FileIOError::from((path, e)))?;
+
+		// A NUL byte in the first chunk is a pretty good hint that this isn't really text
+		if buf.iter().take(8 * 1024).any(|b| *b == 0) {
+			return Ok(None);
+		}
+
+		String::from_utf8_lossy(&buf).into_owned()
+	};
+
+	let content = sanitize_content(&content);
+
+	Ok((!content.is_empty()).then_some(content))
+}
+
+/// Removes control characters (keeping line breaks and tabs), and truncates the content
+/// to `MAX_CONTENT_LEN` bytes, always on a char boundary
+fn sanitize_content(content: &str) -> String {
+	let mut sanitized = String::with_capacity(content.len().min(MAX_CONTENT_LEN));
+
+	for c in content
+		.chars()
+		.filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+	{
+		if sanitized.len() + c.len_utf8() > MAX_CONTENT_LEN {
+			break;
+		}
+		sanitized.push(c);
+	}
+
+	sanitized.trim().to_string()
+}
+
+pub async fn process(
+	files_paths: &[file_path_for_media_processor::Data],
+	location_id: location::id::Type,
+	location_path: impl AsRef<Path>,
+	regenerate: bool,
+	db: &PrismaClient,
+	ctx_update_fn: &impl Fn(usize),
+) -> Result<(OldContentIndexerMetadata, JobRunErrors), ContentIndexerError> {
+	let mut run_metadata = OldContentIndexerMetadata::default();
+	if files_paths.is_empty() {
+		return Ok((run_metadata, JobRunErrors::default()));
+	}
+
+	let location_path = location_path.as_ref();
+
+	// Entries are keyed by the cas_id the file had when indexed, so we can
+	// skip files that didn't change since then
+	let already_indexed = if regenerate {
+		HashMap::new()
+	} else {
+		db.content_index()
+			.find_many(vec![content_index::file_path_id::in_vec(
+				files_paths.iter().map(|file_path| file_path.id).collect(),
+			)])
+			.select(content_index::select!({ file_path_id cas_id }))
+			.exec()
+			.await?
+			.into_iter()
+			.map(|entry| (entry.file_path_id, entry.cas_id))
+			.collect::<HashMap<_, _>>()
+	};
+
+	let maybe_contents = files_paths
+		.iter()
+		.enumerate()
+		.filter(|(_, file_path)| {
+			let up_to_date = already_indexed
+				.get(&file_path.id)
+				.is_some_and(|cas_id| cas_id.is_some() && *cas_id == file_path.cas_id);
+
+			if up_to_date {
+				run_metadata.skipped += 1;
+			}
+
+			!up_to_date
+		})
+		.filter_map(|(idx, file_path)| {
+			IsolatedFilePathData::try_from((location_id, file_path))
+				.map_err(|e| error!("{e:#?}"))
+				.ok()
+				.map(|iso_file_path| {
+					let extension = iso_file_path.extension().to_string();
+					(idx, location_path.join(iso_file_path), extension, file_path)
+				})
+		})
+		.map(|(idx, path, extension, file_path)| async move {
+			let res = extract_content(&path, &extension).await;
+			ctx_update_fn(idx + 1);
+			(res, path, file_path)
+		})
+		.collect::<Vec<_>>()
+		.join()
+		.await;
+
+	let mut errors = vec![];
+	let mut upserts = vec![];
+	let date_indexed = Utc::now();
+
+	for (maybe_content, path, file_path) in maybe_contents {
+		match maybe_content {
+			Ok(Some(content)) => upserts.push(db.content_index().upsert(
+				content_index::file_path_id::equals(file_path.id),
+				content_index::create_unchecked(
+					content.clone(),
+					date_indexed.into(),
+					file_path.id,
+					vec![content_index::cas_id::set(file_path.cas_id.clone())],
+				),
+				vec![
+					content_index::content::set(content),
+					content_index::cas_id::set(file_path.cas_id.clone()),
+					content_index::date_indexed::set(date_indexed.into()),
+				],
+			)),
+			Ok(None) => run_metadata.skipped += 1,
+			Err(e) => {
+				run_metadata.skipped += 1;
+				errors.push(format!(
+					"Couldn't extract content from file: \"{}\"; Error: {e}",
+					path.display()
+				));
+			}
+		}
+	}
+
+	run_metadata.indexed = upserts.len() as u32;
+
+	db._batch(upserts).await?;
+
+	Ok((run_metadata, errors.into()))
+}
+
+#[derive(Serialize, Type, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentSnippetPart {
+	pub text: String,
+	pub highlighted: bool,
+}
+
+#[derive(Debug)]
+pub struct ContentMatch {
+	pub file_path_id: file_path::id::Type,
+	/// bm25 score from FTS5, lower is better
+	pub rank: f64,
+	pub snippet: Vec<ContentSnippetPart>,
+}
+
+/// Turns user input into a FTS5 query, quoting every term so FTS5 operators and
+/// syntax characters typed by the user are matched literally instead of raising syntax errors.
+/// The last term is treated as a prefix, so results show up while the user is still typing.
+pub fn to_fts_query(input: &str) -> Option<String> {
+	let terms = input
+		.split_whitespace()
+		.map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+		.collect::<Vec<_>>();
+
+	(!terms.is_empty()).then(|| format!("{}*", terms.join(" ")))
+}
+
+/// Returns the file paths with content matching `query`, best matches first
+pub async fn search_content(
+	db: &PrismaClient,
+	query: &str,
+	take: usize,
+) -> Result<Vec<ContentMatch>, prisma_client_rust::QueryError> {
+	#[derive(Deserialize)]
+	struct RawContentMatch {
+		file_path_id: file_path::id::Type,
+		rank: f64,
+		snippet: String,
+	}
+
+	let Some(fts_query) = to_fts_query(query) else {
+		return Ok(vec![]);
+	};
+
+	let matches: Vec<RawContentMatch> = db
+		._query_raw(raw!(
+			&format!(
+				"SELECT ci.file_path_id, bm25(content_index_fts) AS rank,
+					snippet(content_index_fts, 0, '{SNIPPET_MATCH_START}', '{SNIPPET_MATCH_END}', '…', {SNIPPET_TOKENS}) AS snippet
+				FROM content_index_fts
+				INNER JOIN content_index ci ON ci.id = content_index_fts.rowid
+				WHERE content_index_fts MATCH {{}}
+				ORDER BY rank
+				LIMIT {{}}"
+			),
+			PrismaValue::String(fts_query),
+			PrismaValue::Int(take as i64)
+		))
+		.exec()
+		.await?;
+
+	Ok(matches
+		.into_iter()
+		.map(
+			|RawContentMatch {
+			     file_path_id,
+			     rank,
+			     snippet,
+			 }| ContentMatch {
+				file_path_id,
+				rank,
+				snippet: split_snippet(&snippet),
+			},
+		)
+		.collect())
+}
+
+fn split_snippet(snippet: &str) -> Vec<ContentSnippetPart> {
+	let mut parts = vec![];
+	let mut current = String::new();
+	let mut highlighted = false;
+
+	for c in snippet.chars() {
+		let toggle = match c {
+			SNIPPET_MATCH_START => !highlighted,
+			SNIPPET_MATCH_END => highlighted,
+			_ => false,
+		};
+
+		if toggle {
+			if !current.is_empty() {
+				parts.push(ContentSnippetPart {
+					text: std::mem::take(&mut current),
+					highlighted,
+				});
+			}
+			highlighted = !highlighted;
+		} else if c != SNIPPET_MATCH_START && c != SNIPPET_MATCH_END {
+			current.push(c);
+		}
+	}
+
+	if !current.is_empty() {
+		parts.push(ContentSnippetPart {
+			text: current,
+			highlighted,
+		});
+	}
+
+	parts
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fts_query_quotes_terms() {
+		assert_eq!(to_fts_query("   "), None);
+		assert_eq!(to_fts_query("hello"), Some("\"hello\"*".to_string()));
+		assert_eq!(
+			to_fts_query("fn main() OR \"x"),
+			Some("\"fn\" \"main()\" \"OR\" \"\"\"x\"*".to_string())
+		);
+	}
+
+	#[test]
+	fn snippet_is_split_on_markers() {
+		assert_eq!(
+			split_snippet("…the \u{2}quick\u{3} brown \u{2}fox\u{3}"),
+			vec![
+				ContentSnippetPart {
+					text: "…the ".to_string(),
+					highlighted: false
+				},
+				ContentSnippetPart {
+					text: "quick".to_string(),
+					highlighted: true
+				},
+				ContentSnippetPart {
+					text: " brown ".to_string(),
+					highlighted: false
+				},
+				ContentSnippetPart {
+					text: "fox".to_string(),
+					highlighted: true
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn content_is_sanitized_and_truncated() {
+		assert_eq!(sanitize_content(" a\u{2}b\tc\n "), "ab\tc");
+
+		let long = "é".repeat(MAX_CONTENT_LEN);
+		let sanitized = sanitize_content(&long);
+		assert!(sanitized.len() <= MAX_CONTENT_LEN);
+		assert_eq!(sanitized.len() % 'é'.len_utf8(), 0);
+	}
+}
@@ -0,0 +1,210 @@
+use crate::{
+	library::Library,
+	old_job::{
+		CurrentStep, JobError, JobInitOutput, JobReportUpdate, JobResult, JobStepOutput,
+		StatefulJob, WorkerContext,
+	},
+};
+
+use sd_file_path_helper::{
+	ensure_file_path_exists, ensure_sub_path_is_directory, ensure_sub_path_is_in_location,
+	file_path_for_media_processor, IsolatedFilePathData,
+};
+use sd_prisma::prisma::{file_path, location, object};
+use sd_utils::db::maybe_missing;
+
+use std::{
+	hash::{Hash, Hasher},
+	path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use prisma_client_rust::or;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+use super::{
+	process, ContentIndexerError, OldContentIndexerMetadata, DOCUMENT_CONTENT_EXTENSIONS,
+	TEXT_CONTENT_KINDS,
+};
+
+const BATCH_SIZE: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OldContentIndexerJobData {
+	location_path: PathBuf,
+	task_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OldContentIndexerJobInit {
+	pub location: location::Data,
+	pub sub_path: Option<PathBuf>,
+	pub regenerate: bool,
+}
+
+impl Hash for OldContentIndexerJobInit {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.location.id.hash(state);
+		if let Some(ref sub_path) = self.sub_path {
+			sub_path.hash(state);
+		}
+	}
+}
+
+// The content indexer extracts the text of text, code, config and PDF files,
+// storing it in the `content_index` table to be used by full-text search
+#[async_trait::async_trait]
+impl StatefulJob for OldContentIndexerJobInit {
+	type Data = OldContentIndexerJobData;
+	type Step = Vec<file_path_for_media_processor::Data>;
+	type RunMetadata = OldContentIndexerMetadata;
+
+	const NAME: &'static str = "content_indexer";
+	const IS_BATCHED: bool = true;
+
+	fn target_location(&self) -> location::id::Type {
+		self.location.id
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library { db, .. } = &*ctx.library;
+
+		let location_id = self.location.id;
+		let location_path =
+			maybe_missing(&self.location.path, "location.path").map(PathBuf::from)?;
+
+		let maybe_sub_iso_file_path = match &self.sub_path {
+			Some(sub_path) if sub_path != Path::new("") => {
+				let full_path = ensure_sub_path_is_in_location(&location_path, sub_path)
+					.await
+					.map_err(ContentIndexerError::from)?;
+				ensure_sub_path_is_directory(&location_path, sub_path)
+					.await
+					.map_err(ContentIndexerError::from)?;
+
+				let sub_iso_file_path =
+					IsolatedFilePathData::new(location_id, &location_path, &full_path, true)
+						.map_err(ContentIndexerError::from)?;
+
+				ensure_file_path_exists(
+					sub_path,
+					&sub_iso_file_path,
+					db,
+					ContentIndexerError::SubPathNotFound,
+				)
+				.await?;
+
+				Some(sub_iso_file_path)
+			}
+			_ => None,
+		};
+
+		let file_paths = db
+			.file_path()
+			.find_many(sd_utils::chain_optional_iter(
+				[
+					file_path::location_id::equals(Some(location_id)),
+					file_path::is_dir::equals(Some(false)),
+					file_path::cas_id::not(None),
+					or![
+						file_path::object::is(vec![object::kind::in_vec(
+							TEXT_CONTENT_KINDS.iter().map(|kind| *kind as i32).collect()
+						)]),
+						file_path::extension::in_vec(
+							DOCUMENT_CONTENT_EXTENSIONS
+								.iter()
+								.map(ToString::to_string)
+								.collect()
+						),
+					],
+				],
+				[maybe_sub_iso_file_path.and_then(|iso_sub_path| {
+					iso_sub_path
+						.materialized_path_for_children()
+						.map(file_path::materialized_path::starts_with)
+				})],
+			))
+			.select(file_path_for_media_processor::select())
+			.exec()
+			.await?;
+
+		let task_count = file_paths.len();
+
+		let steps = file_paths
+			.into_iter()
+			.chunks(BATCH_SIZE)
+			.into_iter()
+			.map(Iterator::collect)
+			.collect::<Vec<_>>();
+
+		ctx.progress(vec![
+			JobReportUpdate::TaskCount(task_count),
+			JobReportUpdate::Message(format!(
+				"Preparing to extract content of {task_count} files in {} chunks",
+				steps.len()
+			)),
+		]);
+
+		*data = Some(OldContentIndexerJobData {
+			location_path,
+			task_count,
+		});
+
+		Ok(steps.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		CurrentStep { step, step_number }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		process(
+			step,
+			self.location.id,
+			&data.location_path,
+			self.regenerate,
+			&ctx.library.db,
+			&|completed_count| {
+				ctx.progress(vec![JobReportUpdate::CompletedTaskCount(
+					step_number * BATCH_SIZE + completed_count,
+				)]);
+			},
+		)
+		.await
+		.map(Into::into)
+		.map_err(Into::into)
+	}
+
+	async fn finalize(
+		&self,
+		_: &WorkerContext,
+		data: &Option<Self::Data>,
+		run_metadata: &Self::RunMetadata,
+	) -> JobResult {
+		let data = data
+			.as_ref()
+			.expect("critical error: missing data on job state");
+
+		info!(
+			"finalizing content indexer job at {}{}: {} tasks, {} indexed, {} skipped",
+			data.location_path.display(),
+			self.sub_path
+				.as_ref()
+				.map(|p| format!("{}", p.display()))
+				.unwrap_or_default(),
+			data.task_count,
+			run_metadata.indexed,
+			run_metadata.skipped,
+		);
+
+		Ok(Some(json!({ "init": self, "run_metadata": run_metadata })))
+	}
+}
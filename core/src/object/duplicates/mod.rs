@@ -0,0 +1,848 @@
+use crate::{
+	library::Library,
+	object::{fs::old_delete::OldFileDeleterJobInit, validation::hash::file_checksum},
+	old_job::{Job, JobManagerError, JobRunMetadata},
+	Node,
+};
+
+use sd_file_path_helper::IsolatedFilePathData;
+use sd_prisma::prisma::{file_path, instance, location, PrismaClient};
+use sd_utils::{
+	db::{maybe_missing, MissingFieldError},
+	error::FileIOError,
+};
+
+use std::{
+	borrow::Cow,
+	collections::{HashMap, HashSet},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use prisma_client_rust::{raw, PrismaValue};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tokio::fs;
+use tracing::warn;
+
+pub mod old_duplicate_finder_job;
+
+/// How many cas_ids we fetch file paths for in a single query
+const CAS_IDS_CHUNK_SIZE: usize = 500;
+
+file_path::select!(file_path_for_duplicates {
+	id
+	pub_id
+	location_id
+	materialized_path
+	is_dir
+	name
+	extension
+	cas_id
+	integrity_checksum
+	size_in_bytes_bytes
+	inode
+	date_modified
+	location: select { id path instance_id }
+});
+
+#[derive(Error, Debug)]
+pub enum DuplicatesError {
+	#[error("no confirmed duplicates found with checksum <checksum='{0}'>")]
+	GroupNotFound(String),
+	#[error("file path <id='{0}'> isn't part of the duplicate group")]
+	KeepNotInGroup(file_path::id::Type),
+	#[error("file path <id='{0}'> is in a location of another device")]
+	RemoteFilePath(file_path::id::Type),
+	#[error("file path <id='{0}'> was modified since its checksum was computed")]
+	ModifiedFilePath(file_path::id::Type),
+
+	// Internal errors
+	#[error("database error: {0}")]
+	Database(#[from] prisma_client_rust::QueryError),
+	#[error(transparent)]
+	FileIO(#[from] FileIOError),
+	#[error(transparent)]
+	MissingField(#[from] MissingFieldError),
+	#[error(transparent)]
+	JobManager(#[from] JobManagerError),
+}
+
+impl From<DuplicatesError> for rspc::Error {
+	fn from(e: DuplicatesError) -> Self {
+		match e {
+			DuplicatesError::GroupNotFound(_)
+			| DuplicatesError::KeepNotInGroup(_)
+			| DuplicatesError::RemoteFilePath(_)
+			| DuplicatesError::ModifiedFilePath(_) => {
+				Self::with_cause(rspc::ErrorCode::BadRequest, e.to_string(), e)
+			}
+			_ => Self::with_cause(rspc::ErrorCode::InternalServerError, e.to_string(), e),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct OldDuplicateFinderMetadata {
+	pub duplicated_cas_ids: u32,
+	pub checksums_computed: u32,
+}
+
+impl JobRunMetadata for OldDuplicateFinderMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.duplicated_cas_ids += new_data.duplicated_cas_ids;
+		self.checksums_computed += new_data.checksums_computed;
+	}
+}
+
+/// A set of files that share the same content.
+///
+/// Files are first matched by their `cas_id`, which is computed from a sample of the file,
+/// so a group is only `confirmed` after every member had its full `integrity_checksum` computed
+/// and they all match.
+#[derive(Serialize, Type, Debug)]
+pub struct DuplicateGroup {
+	pub cas_id: String,
+	pub integrity_checksum: Option<String>,
+	pub confirmed: bool,
+	/// Size of a single copy as big endian bytes
+	pub size_in_bytes_bytes: Vec<u8>,
+	/// Bytes freed by keeping a single copy, hard links to the same file aren't counted twice
+	pub reclaimable_bytes: Vec<u8>,
+	pub file_paths: Vec<file_path_for_duplicates::Data>,
+}
+
+#[derive(Deserialize, Type, Debug, Clone, Copy)]
+pub enum KeepStrategy {
+	Newest,
+	Oldest,
+	FilePath(file_path::id::Type),
+}
+
+#[derive(Deserialize, Type, Debug, Clone, Copy)]
+pub enum DuplicateAction {
+	/// Move every copy but the kept one to the trash, their space is freed once it's purged
+	Delete,
+	/// Replace every copy with a hard link to the kept one
+	HardLink,
+}
+
+#[derive(Serialize, Type, Debug, Default)]
+pub struct DuplicatesResolution {
+	pub kept: file_path::id::Type,
+	/// Copies moved to the trash
+	pub deleted: u32,
+	/// Bytes taken by the copies moved to the trash as big endian bytes, which are only freed
+	/// once they're purged from it
+	pub trashed_bytes: Vec<u8>,
+	pub linked: u32,
+	/// Copies left untouched, with the reason why
+	pub skipped: Vec<(file_path::id::Type, String)>,
+}
+
+/// Returns the `cas_id`s shared by more than one file, optionally only the ones present in a location
+pub async fn find_duplicated_cas_ids(
+	db: &PrismaClient,
+	location_id: Option<location::id::Type>,
+) -> Result<Vec<String>, prisma_client_rust::QueryError> {
+	#[derive(Deserialize)]
+	struct RawCasId {
+		cas_id: String,
+	}
+
+	let raw_cas_ids: Vec<RawCasId> = if let Some(location_id) = location_id {
+		db._query_raw(raw!(
+			"SELECT cas_id FROM file_path
+			WHERE is_dir = 0 AND cas_id IN (
+				SELECT cas_id FROM file_path WHERE location_id = {} AND cas_id IS NOT NULL
+			)
+			GROUP BY cas_id
+			HAVING COUNT(*) > 1",
+			PrismaValue::Int(location_id as i64)
+		))
+		.exec()
+		.await?
+	} else {
+		db._query_raw(raw!(
+			"SELECT cas_id FROM file_path
+			WHERE is_dir = 0 AND cas_id IS NOT NULL
+			GROUP BY cas_id
+			HAVING COUNT(*) > 1"
+		))
+		.exec()
+		.await?
+	};
+
+	Ok(raw_cas_ids
+		.into_iter()
+		.map(|RawCasId { cas_id }| cas_id)
+		.collect())
+}
+
+pub async fn get_duplicate_groups(
+	db: &PrismaClient,
+	location_id: Option<location::id::Type>,
+) -> Result<Vec<DuplicateGroup>, DuplicatesError> {
+	let cas_ids = find_duplicated_cas_ids(db, location_id).await?;
+
+	let mut by_cas_id = HashMap::<_, Vec<_>>::with_capacity(cas_ids.len());
+	for chunk in cas_ids.chunks(CAS_IDS_CHUNK_SIZE) {
+		for file_path in db
+			.file_path()
+			.find_many(vec![
+				file_path::cas_id::in_vec(chunk.to_vec()),
+				file_path::is_dir::equals(Some(false)),
+			])
+			.select(file_path_for_duplicates::select())
+			.exec()
+			.await?
+		{
+			if let Some(cas_id) = file_path.cas_id.clone() {
+				by_cas_id.entry(cas_id).or_default().push(file_path);
+			}
+		}
+	}
+
+	let mut groups = by_cas_id
+		.into_iter()
+		.flat_map(|(cas_id, file_paths)| {
+			// Until every file has its full checksum, all we know is that they look alike
+			if file_paths
+				.iter()
+				.any(|file_path| file_path.integrity_checksum.is_none())
+			{
+				return vec![new_group(cas_id, None, file_paths)];
+			}
+
+			let mut by_checksum = HashMap::<_, Vec<_>>::new();
+			for file_path in file_paths {
+				if let Some(checksum) = file_path.integrity_checksum.clone() {
+					by_checksum.entry(checksum).or_default().push(file_path);
+				}
+			}
+
+			by_checksum
+				.into_iter()
+				.filter(|(_, file_paths)| file_paths.len() > 1)
+				.map(|(checksum, file_paths)| new_group(cas_id.clone(), Some(checksum), file_paths))
+				.collect()
+		})
+		.filter(|group| group.file_paths.len() > 1)
+		.collect::<Vec<_>>();
+
+	groups.sort_by(|a, b| {
+		size_from_be_bytes(&b.reclaimable_bytes).cmp(&size_from_be_bytes(&a.reclaimable_bytes))
+	});
+
+	Ok(groups)
+}
+
+fn new_group(
+	cas_id: String,
+	integrity_checksum: Option<String>,
+	file_paths: Vec<file_path_for_duplicates::Data>,
+) -> DuplicateGroup {
+	let size = file_paths
+		.iter()
+		.filter_map(|file_path| file_path.size_in_bytes_bytes.as_deref())
+		.map(size_from_be_bytes)
+		.max()
+		.unwrap_or_default();
+
+	// Paths pointing to the same inode in the same location are hard links to a single file
+	let mut physical_copies = HashSet::with_capacity(file_paths.len());
+	let mut unknown_inodes = 0;
+	for file_path in &file_paths {
+		match (&file_path.location_id, &file_path.inode) {
+			(Some(location_id), Some(inode)) => {
+				physical_copies.insert((*location_id, inode.clone()));
+			}
+			_ => unknown_inodes += 1,
+		}
+	}
+	let copies = (physical_copies.len() + unknown_inodes) as u64;
+
+	DuplicateGroup {
+		cas_id,
+		confirmed: integrity_checksum.is_some(),
+		integrity_checksum,
+		size_in_bytes_bytes: size.to_be_bytes().to_vec(),
+		reclaimable_bytes: (size * copies.saturating_sub(1)).to_be_bytes().to_vec(),
+		file_paths,
+	}
+}
+
+fn size_from_be_bytes(bytes: &[u8]) -> u64 {
+	if bytes.len() > 8 {
+		return 0;
+	}
+
+	let mut buf = [0; 8];
+	buf[8 - bytes.len()..].copy_from_slice(bytes);
+	u64::from_be_bytes(buf)
+}
+
+fn full_path(file_path: &file_path_for_duplicates::Data) -> Result<PathBuf, MissingFieldError> {
+	let location_id = maybe_missing(file_path.location_id, "file_path.location_id")?;
+	let location_path = maybe_missing(&file_path.location, "file_path.location")
+		.and_then(|location| maybe_missing(&location.path, "location.path"))?;
+
+	Ok(
+		PathBuf::from(location_path).join(IsolatedFilePathData::from_db_data(
+			location_id,
+			maybe_missing(file_path.is_dir, "file_path.is_dir")?,
+			Cow::Borrowed(maybe_missing(
+				&file_path.materialized_path,
+				"file_path.materialized_path",
+			)?),
+			Cow::Borrowed(maybe_missing(&file_path.name, "file_path.name")?),
+			Cow::Borrowed(maybe_missing(&file_path.extension, "file_path.extension")?),
+		)),
+	)
+}
+
+/// Keeps a single copy of a confirmed duplicate group, deleting or hard linking the others.
+///
+/// Every copy is checksummed again before being touched, so files modified since the
+/// duplicate finder ran are skipped instead of being lost. The whole group is left alone if the
+/// kept copy is missing, modified or in another device.
+pub async fn resolve_duplicates(
+	node: &Arc<Node>,
+	library: &Arc<Library>,
+	integrity_checksum: String,
+	keep: KeepStrategy,
+	action: DuplicateAction,
+) -> Result<DuplicatesResolution, DuplicatesError> {
+	let (resolution, to_delete) = resolve_group(
+		&library.db,
+		library.config().await.instance_id,
+		integrity_checksum,
+		keep,
+		action,
+	)
+	.await?;
+
+	for (location_id, file_path_ids) in to_delete {
+		Job::new(OldFileDeleterJobInit {
+			location_id,
+			file_path_ids,
+			// Moved to the trash so a wrong pick can still be recovered
+			trash: true,
+		})
+		.spawn(node, library)
+		.await?;
+	}
+
+	Ok(resolution)
+}
+
+/// Hard links the copies of a duplicate group, returning the ones to delete by location
+async fn resolve_group(
+	db: &PrismaClient,
+	instance_id: instance::id::Type,
+	integrity_checksum: String,
+	keep: KeepStrategy,
+	action: DuplicateAction,
+) -> Result<
+	(
+		DuplicatesResolution,
+		HashMap<location::id::Type, Vec<file_path::id::Type>>,
+	),
+	DuplicatesError,
+> {
+	let file_paths = db
+		.file_path()
+		.find_many(vec![
+			file_path::integrity_checksum::equals(Some(integrity_checksum.clone())),
+			file_path::is_dir::equals(Some(false)),
+		])
+		.select(file_path_for_duplicates::select())
+		.exec()
+		.await?;
+
+	if file_paths.len() < 2 {
+		return Err(DuplicatesError::GroupNotFound(integrity_checksum));
+	}
+
+	let is_local = |file_path: &file_path_for_duplicates::Data| {
+		file_path
+			.location
+			.as_ref()
+			.and_then(|location| location.instance_id)
+			== Some(instance_id)
+	};
+
+	let kept = match keep {
+		KeepStrategy::FilePath(id) => file_paths
+			.iter()
+			.find(|file_path| file_path.id == id)
+			.ok_or(DuplicatesError::KeepNotInGroup(id))?,
+		KeepStrategy::Newest => file_paths
+			.iter()
+			.max_by_key(|file_path| file_path.date_modified)
+			.expect("group has at least 2 file paths"),
+		KeepStrategy::Oldest => file_paths
+			.iter()
+			.min_by_key(|file_path| file_path.date_modified)
+			.expect("group has at least 2 file paths"),
+	};
+
+	let mut resolution = DuplicatesResolution {
+		kept: kept.id,
+		..Default::default()
+	};
+
+	// The other copies are only expendable if the kept one is really there and still the same
+	if !is_local(kept) {
+		return Err(DuplicatesError::RemoteFilePath(kept.id));
+	}
+
+	let kept_path = full_path(kept)?;
+	if file_checksum(&kept_path)
+		.await
+		.map_err(|e| FileIOError::from((&kept_path, e)))?
+		!= integrity_checksum
+	{
+		return Err(DuplicatesError::ModifiedFilePath(kept.id));
+	}
+
+	let mut to_delete = HashMap::<_, Vec<_>>::new();
+	// Deleting a hard link to a file that stays around doesn't free anything
+	let mut physical_copies = HashSet::from([(kept.location_id, kept.inode.clone())]);
+	let mut trashed_bytes = 0;
+
+	for file_path in file_paths
+		.iter()
+		.filter(|file_path| file_path.id != kept.id)
+	{
+		if !is_local(file_path) {
+			resolution
+				.skipped
+				.push((file_path.id, "located in another device".to_string()));
+			continue;
+		}
+
+		if matches!(action, DuplicateAction::HardLink)
+			&& file_path.location_id == kept.location_id
+			&& file_path.inode.is_some()
+			&& file_path.inode == kept.inode
+		{
+			resolution
+				.skipped
+				.push((file_path.id, "already linked".to_string()));
+			continue;
+		}
+
+		let path = full_path(file_path)?;
+
+		match file_checksum(&path).await {
+			Ok(checksum) if checksum == integrity_checksum => {}
+			Ok(_) => {
+				resolution
+					.skipped
+					.push((file_path.id, "modified since last checked".to_string()));
+				continue;
+			}
+			Err(e) => {
+				resolution.skipped.push((file_path.id, e.to_string()));
+				continue;
+			}
+		}
+
+		match action {
+			DuplicateAction::Delete => {
+				let location_id = maybe_missing(file_path.location_id, "file_path.location_id")?;
+				to_delete.entry(location_id).or_default().push(file_path.id);
+				resolution.deleted += 1;
+
+				if file_path.inode.is_none()
+					|| physical_copies.insert((file_path.location_id, file_path.inode.clone()))
+				{
+					trashed_bytes += file_path
+						.size_in_bytes_bytes
+						.as_deref()
+						.map(size_from_be_bytes)
+						.unwrap_or_default();
+				}
+			}
+			DuplicateAction::HardLink => match replace_with_hard_link(&kept_path, &path).await {
+				Ok(()) => resolution.linked += 1,
+				Err(e) => {
+					warn!("Failed to replace duplicate with a hard link: {e:#?}");
+					resolution.skipped.push((file_path.id, e.to_string()));
+				}
+			},
+		}
+	}
+
+	resolution.trashed_bytes = trashed_bytes.to_be_bytes().to_vec();
+
+	Ok((resolution, to_delete))
+}
+
+/// Links `target` to `source`, going through a temporary file so `target` is never left missing
+async fn replace_with_hard_link(source: &Path, target: &Path) -> Result<(), FileIOError> {
+	let mut temp_name = target.file_name().unwrap_or_default().to_os_string();
+	temp_name.push(".sd-dedup");
+	let temp_path = target.with_file_name(temp_name);
+
+	// Fails with a cross-device error if the files aren't in the same volume
+	fs::hard_link(source, &temp_path)
+		.await
+		.map_err(|e| FileIOError::from((&temp_path, e, "Failed to create hard link")))?;
+
+	if let Err(e) = fs::rename(&temp_path, target).await {
+		if let Err(e) = fs::remove_file(&temp_path).await {
+			warn!(
+				"Failed to remove temporary hard link at {}: {e:#?}",
+				temp_path.display()
+			);
+		}
+		return Err(FileIOError::from((
+			target,
+			e,
+			"Failed to replace file with hard link",
+		)));
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::Utc;
+	use uuid::Uuid;
+
+	struct TestLocation {
+		db: Arc<PrismaClient>,
+		instance_id: instance::id::Type,
+		path: PathBuf,
+	}
+
+	impl TestLocation {
+		async fn new() -> Self {
+			let db = sd_prisma::test_db().await;
+			db._db_push().await.unwrap();
+
+			let path = std::env::temp_dir().join(format!("sd-duplicates-{}", Uuid::new_v4()));
+			fs::create_dir(&path).await.unwrap();
+
+			let instance = db
+				.instance()
+				.create(
+					Uuid::new_v4().as_bytes().to_vec(),
+					vec![],
+					Uuid::new_v4().as_bytes().to_vec(),
+					Utc::now().into(),
+					Utc::now().into(),
+					vec![],
+				)
+				.exec()
+				.await
+				.unwrap();
+
+			db.location()
+				.create(
+					Uuid::new_v4().as_bytes().to_vec(),
+					vec![
+						location::path::set(Some(path.to_string_lossy().to_string())),
+						location::instance::connect(instance::id::equals(instance.id)),
+					],
+				)
+				.exec()
+				.await
+				.unwrap();
+
+			Self {
+				db,
+				instance_id: instance.id,
+				path,
+			}
+		}
+
+		/// Writes `<name>.txt` and indexes it, with its full checksum computed if `checksummed`
+		async fn add_file(
+			&self,
+			name: &str,
+			content: &str,
+			cas_id: &str,
+			checksummed: bool,
+		) -> file_path::id::Type {
+			fs::write(self.file(name), content).await.unwrap();
+			self.index(name, cas_id, checksummed).await
+		}
+
+		/// Creates `<name>.txt` as a hard link to `<source>.txt` and indexes it
+		async fn add_hard_link(
+			&self,
+			name: &str,
+			source: &str,
+			cas_id: &str,
+		) -> file_path::id::Type {
+			fs::hard_link(self.file(source), self.file(name))
+				.await
+				.unwrap();
+			self.index(name, cas_id, true).await
+		}
+
+		async fn index(&self, name: &str, cas_id: &str, checksummed: bool) -> file_path::id::Type {
+			let path = self.file(name);
+			let metadata = fs::metadata(&path).await.unwrap();
+
+			let location = self
+				.db
+				.location()
+				.find_first(vec![])
+				.exec()
+				.await
+				.unwrap()
+				.unwrap();
+
+			self.db
+				.file_path()
+				.create(
+					Uuid::new_v4().as_bytes().to_vec(),
+					vec![
+						file_path::location::connect(location::id::equals(location.id)),
+						file_path::materialized_path::set(Some("/".to_string())),
+						file_path::is_dir::set(Some(false)),
+						file_path::name::set(Some(name.to_string())),
+						file_path::extension::set(Some("txt".to_string())),
+						file_path::cas_id::set(Some(cas_id.to_string())),
+						file_path::integrity_checksum::set(if checksummed {
+							Some(file_checksum(&path).await.unwrap())
+						} else {
+							None
+						}),
+						file_path::size_in_bytes_bytes::set(Some(
+							metadata.len().to_be_bytes().to_vec(),
+						)),
+						file_path::inode::set(inode(&metadata)),
+						file_path::date_modified::set(Some(Utc::now().into())),
+					],
+				)
+				.exec()
+				.await
+				.unwrap()
+				.id
+		}
+
+		fn file(&self, name: &str) -> PathBuf {
+			self.path.join(format!("{name}.txt"))
+		}
+
+		async fn checksum(&self, name: &str) -> String {
+			file_checksum(self.file(name)).await.unwrap()
+		}
+
+		async fn resolve(
+			&self,
+			name: &str,
+			keep: KeepStrategy,
+			action: DuplicateAction,
+		) -> (
+			DuplicatesResolution,
+			HashMap<location::id::Type, Vec<file_path::id::Type>>,
+		) {
+			resolve_group(
+				&self.db,
+				self.instance_id,
+				self.checksum(name).await,
+				keep,
+				action,
+			)
+			.await
+			.unwrap()
+		}
+	}
+
+	impl Drop for TestLocation {
+		fn drop(&mut self) {
+			std::fs::remove_dir_all(&self.path).ok();
+		}
+	}
+
+	#[cfg(unix)]
+	fn inode(metadata: &std::fs::Metadata) -> Option<Vec<u8>> {
+		use sd_utils::db::inode_to_db;
+		use std::os::unix::fs::MetadataExt;
+
+		Some(inode_to_db(metadata.ino()))
+	}
+
+	#[cfg(not(unix))]
+	fn inode(_: &std::fs::Metadata) -> Option<Vec<u8>> {
+		None
+	}
+
+	#[tokio::test]
+	async fn groups_are_split_by_checksum() {
+		let location = TestLocation::new().await;
+
+		// Same sample, but only two of them have the same content
+		let a = location.add_file("a", "duplicate", "cas", true).await;
+		let b = location.add_file("b", "duplicate", "cas", true).await;
+		location.add_file("c", "different", "cas", true).await;
+
+		// Alike, but not confirmed until the second one is checksummed
+		location.add_file("d", "unchecked", "other", true).await;
+		location.add_file("e", "unchecked", "other", false).await;
+
+		let groups = get_duplicate_groups(&location.db, None).await.unwrap();
+		assert_eq!(groups.len(), 2);
+
+		let confirmed = groups.iter().find(|group| group.cas_id == "cas").unwrap();
+		assert!(confirmed.confirmed);
+		assert_eq!(
+			confirmed.integrity_checksum,
+			Some(location.checksum("a").await)
+		);
+		let mut ids = confirmed
+			.file_paths
+			.iter()
+			.map(|file_path| file_path.id)
+			.collect::<Vec<_>>();
+		ids.sort();
+		assert_eq!(ids, vec![a, b]);
+
+		let unconfirmed = groups.iter().find(|group| group.cas_id == "other").unwrap();
+		assert!(!unconfirmed.confirmed);
+		assert_eq!(unconfirmed.file_paths.len(), 2);
+	}
+
+	#[tokio::test]
+	async fn reclaimable_bytes_count_hard_links_once() {
+		let location = TestLocation::new().await;
+
+		location.add_file("a", "duplicate", "cas", true).await;
+		location.add_file("b", "duplicate", "cas", true).await;
+		location.add_file("c", "duplicate", "cas", true).await;
+		location.add_hard_link("d", "a", "cas").await;
+
+		let groups = get_duplicate_groups(&location.db, None).await.unwrap();
+		assert_eq!(groups.len(), 1);
+		assert_eq!(groups[0].file_paths.len(), 4);
+
+		let size = "duplicate".len() as u64;
+		assert_eq!(size_from_be_bytes(&groups[0].size_in_bytes_bytes), size);
+
+		let copies = if cfg!(unix) { 3 } else { 4 };
+		assert_eq!(
+			size_from_be_bytes(&groups[0].reclaimable_bytes),
+			size * (copies - 1)
+		);
+	}
+
+	#[tokio::test]
+	async fn delete_keeps_chosen_file_and_skips_modified_copies() {
+		let location = TestLocation::new().await;
+
+		let a = location.add_file("a", "duplicate", "cas", true).await;
+		let b = location.add_file("b", "duplicate", "cas", true).await;
+		let c = location.add_file("c", "duplicate", "cas", true).await;
+
+		// Changed after the duplicate finder checksummed it
+		fs::write(location.file("c"), "edited!!!").await.unwrap();
+
+		let (resolution, to_delete) = location
+			.resolve("b", KeepStrategy::FilePath(b), DuplicateAction::Delete)
+			.await;
+
+		assert_eq!(resolution.kept, b);
+		assert_eq!(resolution.deleted, 1);
+		assert_eq!(
+			size_from_be_bytes(&resolution.trashed_bytes),
+			"duplicate".len() as u64
+		);
+		assert_eq!(
+			resolution.skipped,
+			vec![(c, "modified since last checked".to_string())]
+		);
+		assert_eq!(to_delete.into_values().collect::<Vec<_>>(), vec![vec![a]]);
+
+		// Only the deleter job touches the files
+		assert!(fs::metadata(location.file("a")).await.is_ok());
+		assert!(fs::metadata(location.file("b")).await.is_ok());
+	}
+
+	#[tokio::test]
+	async fn modified_kept_file_leaves_group_alone() {
+		let location = TestLocation::new().await;
+
+		let a = location.add_file("a", "duplicate", "cas", true).await;
+		location.add_file("b", "duplicate", "cas", true).await;
+		let checksum = location.checksum("a").await;
+
+		fs::write(location.file("a"), "edited!!!").await.unwrap();
+
+		assert!(matches!(
+			resolve_group(
+				&location.db,
+				location.instance_id,
+				checksum,
+				KeepStrategy::FilePath(a),
+				DuplicateAction::Delete,
+			)
+			.await,
+			Err(DuplicatesError::ModifiedFilePath(id)) if id == a
+		));
+	}
+
+	#[tokio::test]
+	async fn hard_link_replaces_copies() {
+		let location = TestLocation::new().await;
+
+		let a = location.add_file("a", "duplicate", "cas", true).await;
+		location.add_file("b", "duplicate", "cas", true).await;
+
+		let (resolution, to_delete) = location
+			.resolve("a", KeepStrategy::FilePath(a), DuplicateAction::HardLink)
+			.await;
+
+		assert_eq!(resolution.kept, a);
+		assert_eq!(resolution.linked, 1);
+		assert!(resolution.skipped.is_empty());
+		assert!(to_delete.is_empty());
+
+		assert_eq!(
+			fs::read_to_string(location.file("b")).await.unwrap(),
+			"duplicate"
+		);
+		#[cfg(unix)]
+		assert_eq!(
+			inode(&fs::metadata(location.file("a")).await.unwrap()),
+			inode(&fs::metadata(location.file("b")).await.unwrap())
+		);
+
+		let mut entries = fs::read_dir(&location.path).await.unwrap();
+		let mut names = vec![];
+		while let Some(entry) = entries.next_entry().await.unwrap() {
+			names.push(entry.file_name().to_string_lossy().to_string());
+		}
+		names.sort();
+		assert_eq!(names, vec!["a.txt", "b.txt"]);
+	}
+
+	#[tokio::test]
+	async fn failed_hard_link_removes_temporary_file() {
+		let location = TestLocation::new().await;
+
+		fs::write(location.file("a"), "duplicate").await.unwrap();
+		// A file can't replace a directory
+		let target = location.path.join("b");
+		fs::create_dir(&target).await.unwrap();
+		fs::write(target.join("inner.txt"), "").await.unwrap();
+
+		assert!(replace_with_hard_link(&location.file("a"), &target)
+			.await
+			.is_err());
+
+		assert!(fs::metadata(location.path.join("b.sd-dedup"))
+			.await
+			.is_err());
+		assert!(fs::metadata(target.join("inner.txt")).await.is_ok());
+	}
+}
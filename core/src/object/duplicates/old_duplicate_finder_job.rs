@@ -0,0 +1,188 @@
+use crate::{
+	invalidate_query,
+	library::Library,
+	object::validation::hash::file_checksum,
+	old_job::{
+		CurrentStep, JobError, JobInitOutput, JobReportUpdate, JobResult, JobRunErrors,
+		JobStepOutput, StatefulJob, WorkerContext,
+	},
+};
+
+use sd_prisma::{
+	prisma::{file_path, location},
+	prisma_sync,
+};
+use sd_sync::OperationFactory;
+use sd_utils::{error::FileIOError, msgpack};
+
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
+
+use super::{
+	file_path_for_duplicates, find_duplicated_cas_ids, full_path, OldDuplicateFinderMetadata,
+};
+
+const BATCH_SIZE: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OldDuplicateFinderJobData {
+	task_count: usize,
+}
+
+/// Looks for files sharing a `cas_id` with files of this location and confirms them as duplicates
+/// by computing their full `integrity_checksum`.
+///
+/// Only files in locations of this instance are checksummed, the other instances compute their own
+/// and sync them over.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OldDuplicateFinderJobInit {
+	pub location: location::Data,
+}
+
+impl Hash for OldDuplicateFinderJobInit {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.location.id.hash(state);
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for OldDuplicateFinderJobInit {
+	type Data = OldDuplicateFinderJobData;
+	type Step = Vec<String>;
+	type RunMetadata = OldDuplicateFinderMetadata;
+
+	const NAME: &'static str = "duplicate_finder";
+	const IS_BATCHED: bool = true;
+
+	fn target_location(&self) -> location::id::Type {
+		self.location.id
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let Library { db, .. } = &*ctx.library;
+
+		let cas_ids = find_duplicated_cas_ids(db, Some(self.location.id)).await?;
+		let task_count = cas_ids.len();
+
+		let steps = cas_ids
+			.chunks(BATCH_SIZE)
+			.map(<[_]>::to_vec)
+			.collect::<Vec<_>>();
+
+		ctx.progress(vec![
+			JobReportUpdate::TaskCount(task_count),
+			JobReportUpdate::Message(format!(
+				"Found {task_count} possibly duplicated files, confirming them"
+			)),
+		]);
+
+		*data = Some(OldDuplicateFinderJobData { task_count });
+
+		Ok((
+			OldDuplicateFinderMetadata {
+				duplicated_cas_ids: task_count as u32,
+				checksums_computed: 0,
+			},
+			steps,
+		)
+			.into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		CurrentStep { step, step_number }: CurrentStep<'_, Self::Step>,
+		_: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, sync, .. } = &*ctx.library;
+		let instance_id = ctx.library.config().await.instance_id;
+
+		let file_paths = db
+			.file_path()
+			.find_many(vec![
+				file_path::cas_id::in_vec(step.clone()),
+				file_path::is_dir::equals(Some(false)),
+				file_path::integrity_checksum::equals(None),
+				file_path::location::is(vec![location::instance_id::equals(Some(instance_id))]),
+			])
+			.select(file_path_for_duplicates::select())
+			.exec()
+			.await?;
+
+		let mut run_metadata = OldDuplicateFinderMetadata::default();
+		let mut errors = vec![];
+
+		for file_path in file_paths {
+			let full_path = match full_path(&file_path) {
+				Ok(full_path) => full_path,
+				Err(e) => {
+					error!("Failed to get the path of duplicate candidate: {e:#?}");
+					errors.push(e.to_string());
+					continue;
+				}
+			};
+
+			let checksum = match file_checksum(&full_path).await {
+				Ok(checksum) => checksum,
+				Err(e) => {
+					let e = FileIOError::from((&full_path, e));
+					error!("Failed to compute checksum of duplicate candidate: {e:#?}");
+					errors.push(e.to_string());
+					continue;
+				}
+			};
+
+			sync.write_op(
+				db,
+				sync.shared_update(
+					prisma_sync::file_path::SyncId {
+						pub_id: file_path.pub_id.clone(),
+					},
+					file_path::integrity_checksum::NAME,
+					msgpack!(&checksum),
+				),
+				db.file_path().update(
+					file_path::pub_id::equals(file_path.pub_id),
+					vec![file_path::integrity_checksum::set(Some(checksum))],
+				),
+			)
+			.await?;
+
+			run_metadata.checksums_computed += 1;
+		}
+
+		ctx.progress(vec![JobReportUpdate::CompletedTaskCount(
+			step_number * BATCH_SIZE + step.len(),
+		)]);
+
+		Ok((run_metadata, JobRunErrors(errors)).into())
+	}
+
+	async fn finalize(
+		&self,
+		ctx: &WorkerContext,
+		data: &Option<Self::Data>,
+		run_metadata: &Self::RunMetadata,
+	) -> JobResult {
+		let data = data
+			.as_ref()
+			.expect("critical error: missing data on job state");
+
+		info!(
+			"finalizing duplicate finder job at location <id='{}'>: {} tasks, {} checksums computed",
+			self.location.id, data.task_count, run_metadata.checksums_computed,
+		);
+
+		invalidate_query!(ctx.library, "duplicates.list");
+
+		Ok(Some(json!({ "init": self, "run_metadata": run_metadata })))
+	}
+}
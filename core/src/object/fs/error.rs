@@ -41,6 +41,20 @@ pub enum FileSystemJobsError {
 	NonUTF8Path(#[from] NonUtf8PathError),
 	#[error("failed to find an available name to avoid duplication: <path='{}'>", .0.display())]
 	FailedToFindAvailableName(Box<Path>),
+	#[error("no trash available for a file outside of locations: <path='{}'>", .0.display())]
+	TrashUnavailable(Box<Path>),
+	#[error(
+		"failed to restore {} trashed items: {}",
+		.0.len(),
+		.0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+	)]
+	Restore(Vec<FileSystemJobsError>),
+	#[error(
+		"failed to purge {} trashed items: {}",
+		.0.len(),
+		.0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+	)]
+	Purge(Vec<FileSystemJobsError>),
 }
 
 impl From<FileSystemJobsError> for rspc::Error {
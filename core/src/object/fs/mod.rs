@@ -18,6 +18,7 @@ use serde::{Deserialize, Serialize};
 
 pub mod old_delete;
 pub mod old_erase;
+pub mod trash;
 
 pub mod old_copy;
 pub mod old_cut;
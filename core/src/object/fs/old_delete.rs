@@ -20,14 +20,22 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use specta::Type;
 use tokio::{fs, io};
-use tracing::warn;
+use tracing::{error, warn};
 
-use super::{error::FileSystemJobsError, get_many_files_datas, FileData};
+use super::{
+	error::FileSystemJobsError,
+	get_many_files_datas,
+	trash::{purge_expired, trash_file_path},
+	FileData,
+};
 
 #[derive(Serialize, Deserialize, Hash, Type, Debug)]
 pub struct OldFileDeleterJobInit {
 	pub location_id: location::id::Type,
 	pub file_path_ids: Vec<file_path::id::Type>,
+	/// Move the files to the trash instead of deleting them, so they can be restored
+	#[serde(default)]
+	pub trash: bool,
 }
 
 #[async_trait::async_trait]
@@ -76,6 +84,18 @@ impl StatefulJob for OldFileDeleterJobInit {
 
 		let Library { db, sync, .. } = ctx.library.as_ref();
 
+		if self.trash {
+			trash_file_path(
+				&ctx.library,
+				self.location_id,
+				get_location_path_from_location_id(db, self.location_id).await?,
+				step,
+			)
+			.await?;
+
+			return Ok(().into());
+		}
+
 		match if maybe_missing(step.file_path.is_dir, "file_path.is_dir")? {
 			fs::remove_dir_all(&step.full_path).await
 		} else {
@@ -114,6 +134,14 @@ impl StatefulJob for OldFileDeleterJobInit {
 		let init = self;
 		invalidate_query!(ctx.library, "search.paths");
 
+		if init.trash {
+			invalidate_query!(ctx.library, "trash.list");
+
+			if let Err(e) = purge_expired(&ctx.library).await {
+				error!("Failed to purge expired items from the trash: {e:#?}");
+			}
+		}
+
 		// ctx.library.orphan_remover.invoke().await;
 
 		Ok(Some(json!({ "init": init })))
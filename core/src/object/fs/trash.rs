@@ -0,0 +1,1000 @@
+//! Deleted files can be moved to a trash instead, so they can be restored later.
+//!
+//! On Linux they go to the user's freedesktop.org trash, where any file manager can see them. On
+//! other platforms, or when the file is on another volume than the user's home, they go to a
+//! `.trash` directory at the root of their location. Either way the `file_path` row of the item is
+//! kept in the `trashed_item` table, so restoring it recreates the row with the same `pub_id` and
+//! relinks its object.
+
+use crate::{
+	invalidate_query,
+	library::Library,
+	location::{
+		delete_directory, find_location, get_location_path_from_location_id,
+		location_with_indexer_rules, scan_location_sub_path,
+	},
+	sync, Node,
+};
+
+use sd_file_path_helper::IsolatedFilePathData;
+use sd_prisma::{
+	prisma::{file_path, location, object, trashed_item, PrismaClient},
+	prisma_sync,
+};
+use sd_sync::OperationFactory;
+use sd_utils::{db::maybe_missing, error::FileIOError, msgpack, uuid_to_bytes};
+
+use std::{
+	borrow::Cow,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::Duration,
+};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use super::{error::FileSystemJobsError, FileData};
+
+/// Directory at the root of each location where trashed items are kept
+pub const TRASH_DIR_NAME: &str = ".trash";
+
+/// Trashed items are purged after this long
+pub const TRASH_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+/// How often the expired items of a loaded library are purged
+pub const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+trashed_item::select!(trashed_item_for_restore {
+	id
+	is_dir
+	original_path
+	trash_path
+	location_id
+	file_path
+});
+
+/// The `file_path` row of a trashed item
+#[derive(Serialize, Deserialize, Debug)]
+struct TrashedFilePath {
+	pub_id: Vec<u8>,
+	materialized_path: String,
+	name: String,
+	extension: String,
+	cas_id: Option<String>,
+	integrity_checksum: Option<String>,
+	size_in_bytes_bytes: Option<Vec<u8>>,
+	inode: Option<Vec<u8>>,
+	hidden: Option<bool>,
+	date_created: Option<DateTime<FixedOffset>>,
+	date_modified: Option<DateTime<FixedOffset>>,
+	date_indexed: Option<DateTime<FixedOffset>>,
+	object_pub_id: Option<Vec<u8>>,
+}
+
+/// Moves an indexed file or directory to the trash and removes it from its location
+pub async fn trash_file_path(
+	library: &Library,
+	location_id: location::id::Type,
+	location_path: impl AsRef<Path>,
+	file_data: &FileData,
+) -> Result<(), FileSystemJobsError> {
+	let Library { db, sync, .. } = library;
+
+	if move_file_path_to_trash(db, sync, location_id, location_path, file_data).await? {
+		delete_directory(
+			library,
+			location_id,
+			Some(&IsolatedFilePathData::try_from(&file_data.file_path)?),
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+/// Moves an indexed file or directory to the trash and deletes its `file_path` row.
+///
+/// Returns if it was a directory, whose contents are still in the location.
+async fn move_file_path_to_trash(
+	db: &PrismaClient,
+	sync: &sync::Manager,
+	location_id: location::id::Type,
+	location_path: impl AsRef<Path>,
+	FileData {
+		file_path,
+		full_path,
+	}: &FileData,
+) -> Result<bool, FileSystemJobsError> {
+	let is_dir = maybe_missing(file_path.is_dir, "file_path.is_dir")?;
+	let trashed_file_path = TrashedFilePath {
+		pub_id: file_path.pub_id.clone(),
+		materialized_path: maybe_missing(
+			&file_path.materialized_path,
+			"file_path.materialized_path",
+		)?
+		.clone(),
+		name: maybe_missing(&file_path.name, "file_path.name")?.clone(),
+		extension: maybe_missing(&file_path.extension, "file_path.extension")?.clone(),
+		cas_id: file_path.cas_id.clone(),
+		integrity_checksum: file_path.integrity_checksum.clone(),
+		size_in_bytes_bytes: file_path.size_in_bytes_bytes.clone(),
+		inode: file_path.inode.clone(),
+		hidden: file_path.hidden,
+		date_created: file_path.date_created,
+		date_modified: file_path.date_modified,
+		date_indexed: file_path.date_indexed,
+		object_pub_id: file_path
+			.object
+			.as_ref()
+			.map(|object| object.pub_id.clone()),
+	};
+
+	let id = Uuid::new_v4();
+	let trash_path = move_to_trash(full_path, Some(location_path.as_ref()), id).await?;
+
+	create_trashed_item(
+		db,
+		id,
+		is_dir,
+		full_path,
+		&trash_path,
+		vec![
+			trashed_item::location::connect(location::id::equals(location_id)),
+			trashed_item::file_path::set(Some(
+				rmp_serde::to_vec_named(&trashed_file_path)
+					.expect("trashed file_path is always serializable"),
+			)),
+		],
+	)
+	.await?;
+
+	sync.write_op(
+		db,
+		sync.shared_delete(prisma_sync::file_path::SyncId {
+			pub_id: file_path.pub_id.clone(),
+		}),
+		// The watcher may have already removed it if the trash is outside of the location
+		db.file_path()
+			.delete_many(vec![file_path::id::equals(file_path.id)]),
+	)
+	.await?;
+
+	Ok(is_dir)
+}
+
+/// Moves a non-indexed file or directory to the trash.
+///
+/// They don't belong to any location, so this is only possible with the freedesktop.org trash.
+pub async fn trash_ephemeral_path(
+	db: &PrismaClient,
+	path: impl AsRef<Path>,
+) -> Result<(), FileSystemJobsError> {
+	let path = path.as_ref();
+	let is_dir = fs::metadata(path)
+		.await
+		.map_err(|e| FileIOError::from((path, e, "Failed to get file metadata for trashing")))?
+		.is_dir();
+
+	let id = Uuid::new_v4();
+	let trash_path = move_to_trash(path, None, id).await?;
+
+	create_trashed_item(db, id, is_dir, path, &trash_path, vec![]).await
+}
+
+async fn create_trashed_item(
+	db: &PrismaClient,
+	id: Uuid,
+	is_dir: bool,
+	original_path: &Path,
+	trash_path: &Path,
+	params: Vec<trashed_item::SetParam>,
+) -> Result<(), FileSystemJobsError> {
+	db.trashed_item()
+		.create(
+			uuid_to_bytes(id),
+			original_path
+				.file_name()
+				.map(|name| name.to_string_lossy().to_string())
+				.unwrap_or_default(),
+			is_dir,
+			original_path.to_string_lossy().to_string(),
+			trash_path.to_string_lossy().to_string(),
+			Utc::now().into(),
+			params,
+		)
+		.exec()
+		.await?;
+
+	Ok(())
+}
+
+/// Moves a file or directory to the trash, returning where it's kept
+async fn move_to_trash(
+	path: &Path,
+	location_path: Option<&Path>,
+	id: Uuid,
+) -> Result<PathBuf, FileSystemJobsError> {
+	#[cfg(target_os = "linux")]
+	match xdg::move_to_trash(path, id).await {
+		Ok(trash_path) => return Ok(trash_path),
+		Err(e) => debug!(
+			"Couldn't move '{}' to the freedesktop.org trash: {e:#?}",
+			path.display()
+		),
+	}
+
+	let Some(location_path) = location_path else {
+		return Err(FileSystemJobsError::TrashUnavailable(path.into()));
+	};
+
+	let trash_dir = location_path.join(TRASH_DIR_NAME).join(id.to_string());
+	fs::create_dir_all(&trash_dir)
+		.await
+		.map_err(|e| FileIOError::from((&trash_dir, e, "Failed to create trash directory")))?;
+
+	let trash_path = trash_dir.join(path.file_name().unwrap_or_default());
+	if let Err(e) = fs::rename(path, &trash_path).await {
+		fs::remove_dir(&trash_dir).await.ok();
+		return Err(FileIOError::from((path, e, "Failed to move file to the trash")).into());
+	}
+
+	Ok(trash_path)
+}
+
+/// Moves trashed items back to where they were, reindexing them if they belonged to a location.
+///
+/// An item that can't be restored doesn't stop the others, their errors are returned together.
+pub async fn restore(
+	node: &Arc<Node>,
+	library: &Arc<Library>,
+	ids: Vec<trashed_item::id::Type>,
+) -> Result<(), FileSystemJobsError> {
+	let items = library
+		.db
+		.trashed_item()
+		.find_many(vec![trashed_item::id::in_vec(ids)])
+		.select(trashed_item_for_restore::select())
+		.exec()
+		.await?;
+
+	let mut errors = vec![];
+	for item in items {
+		let id = item.id;
+		if let Err(e) = restore_item(node, library, item).await {
+			error!("Failed to restore trashed item <id='{id}'>: {e:#?}");
+			errors.push(e);
+		}
+	}
+
+	// The other items were restored even if some failed
+	invalidate_query!(library, "trash.list");
+	invalidate_query!(library, "search.paths");
+	invalidate_query!(library, "search.objects");
+
+	if errors.is_empty() {
+		Ok(())
+	} else {
+		Err(FileSystemJobsError::Restore(errors))
+	}
+}
+
+/// Moves a trashed item back to where it was
+async fn restore_item(
+	node: &Arc<Node>,
+	library: &Arc<Library>,
+	item: trashed_item_for_restore::Data,
+) -> Result<(), FileSystemJobsError> {
+	let Library { db, sync, .. } = library.as_ref();
+
+	let is_dir = item.is_dir;
+	let (original_path, location_id) = move_back_from_trash(db, sync, item).await?;
+
+	if let (Some(location_id), true) = (location_id, is_dir) {
+		// The contents of the directory were removed from the location when it was trashed
+		if let Some(location) = find_location(library, location_id)
+			.include(location_with_indexer_rules::include())
+			.exec()
+			.await?
+		{
+			if let Err(e) = scan_location_sub_path(node, library, location, &original_path).await {
+				error!("Failed to scan restored directory: {e:#?}");
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Moves a trashed item back to where it was and recreates its `file_path` row.
+///
+/// Returns where it was restored, with its location if it had one.
+async fn move_back_from_trash(
+	db: &PrismaClient,
+	sync: &sync::Manager,
+	item: trashed_item_for_restore::Data,
+) -> Result<(PathBuf, Option<location::id::Type>), FileSystemJobsError> {
+	let trashed_file_path = item
+		.file_path
+		.as_deref()
+		.map(rmp_serde::from_slice::<TrashedFilePath>)
+		.transpose()
+		.map_err(|e| {
+			error!("Corrupted trashed file_path <id='{}'>: {e:#?}", item.id);
+		})
+		.ok()
+		.flatten();
+
+	let (original_path, location) = match (item.location_id, &trashed_file_path) {
+		(Some(location_id), Some(trashed_file_path)) => {
+			let location_path = get_location_path_from_location_id(db, location_id).await?;
+			let iso_file_path = IsolatedFilePathData::from_db_data(
+				location_id,
+				item.is_dir,
+				Cow::Borrowed(&trashed_file_path.materialized_path),
+				Cow::Borrowed(&trashed_file_path.name),
+				Cow::Borrowed(&trashed_file_path.extension),
+			);
+
+			(location_path.join(iso_file_path), Some(location_id))
+		}
+		_ => (PathBuf::from(&item.original_path), None),
+	};
+
+	if fs::metadata(&original_path).await.is_ok() {
+		return Err(FileSystemJobsError::WouldOverwrite(original_path.into()));
+	}
+
+	if let Some(parent) = original_path.parent() {
+		fs::create_dir_all(parent).await.map_err(|e| {
+			FileIOError::from((parent, e, "Failed to create parent directory to restore"))
+		})?;
+	}
+
+	// The row is created first, so the watcher finds it instead of indexing the file again
+	let restored_file_path = match (location, trashed_file_path) {
+		(Some(location_id), Some(trashed_file_path)) => Some((
+			location_id,
+			restore_file_path(db, sync, location_id, item.is_dir, trashed_file_path).await?,
+		)),
+		_ => None,
+	};
+
+	let trash_path = Path::new(&item.trash_path);
+	if let Err(e) = fs::rename(trash_path, &original_path).await {
+		if let Some((_, pub_id)) = restored_file_path {
+			sync.write_op(
+				db,
+				sync.shared_delete(prisma_sync::file_path::SyncId {
+					pub_id: pub_id.clone(),
+				}),
+				db.file_path()
+					.delete_many(vec![file_path::pub_id::equals(pub_id)]),
+			)
+			.await?;
+		}
+
+		return Err(FileIOError::from((trash_path, e, "Failed to restore file from trash")).into());
+	}
+	clean_up_trash_entry(trash_path).await;
+
+	db.trashed_item()
+		.delete(trashed_item::id::equals(item.id))
+		.exec()
+		.await?;
+
+	Ok((original_path, location))
+}
+
+/// Recreates the `file_path` row of a trashed item, relinking its object if it still exists.
+///
+/// Returns the `pub_id` of the row.
+async fn restore_file_path(
+	db: &PrismaClient,
+	sync: &sync::Manager,
+	location_id: location::id::Type,
+	is_dir: bool,
+	trashed_file_path: TrashedFilePath,
+) -> Result<Vec<u8>, FileSystemJobsError> {
+	let location_pub_id = db
+		.location()
+		.find_unique(location::id::equals(location_id))
+		.select(location::select!({ pub_id }))
+		.exec()
+		.await?
+		.ok_or(FileSystemJobsError::Location(
+			crate::location::LocationError::IdNotFound(location_id),
+		))?
+		.pub_id;
+
+	let object_pub_id = match trashed_file_path.object_pub_id {
+		Some(object_pub_id) => db
+			.object()
+			.find_unique(object::pub_id::equals(object_pub_id))
+			.select(object::select!({ pub_id }))
+			.exec()
+			.await?
+			.map(|object| object.pub_id),
+		None => None,
+	};
+
+	let TrashedFilePath {
+		pub_id,
+		materialized_path,
+		name,
+		extension,
+		cas_id,
+		integrity_checksum,
+		size_in_bytes_bytes,
+		inode,
+		hidden,
+		date_created,
+		date_modified,
+		date_indexed,
+		..
+	} = trashed_file_path;
+
+	let (sync_params, db_params): (Vec<_>, Vec<_>) = {
+		use file_path::*;
+
+		sd_utils::chain_optional_iter(
+			[
+				(
+					(
+						location::NAME,
+						msgpack!(prisma_sync::location::SyncId {
+							pub_id: location_pub_id
+						}),
+					),
+					location::connect(sd_prisma::prisma::location::id::equals(location_id)),
+				),
+				((cas_id::NAME, msgpack!(cas_id)), cas_id::set(cas_id)),
+				(
+					(integrity_checksum::NAME, msgpack!(integrity_checksum)),
+					integrity_checksum::set(integrity_checksum),
+				),
+				(
+					(materialized_path::NAME, msgpack!(materialized_path)),
+					materialized_path::set(Some(materialized_path)),
+				),
+				((name::NAME, msgpack!(name)), name::set(Some(name))),
+				(
+					(extension::NAME, msgpack!(extension)),
+					extension::set(Some(extension)),
+				),
+				(
+					(size_in_bytes_bytes::NAME, msgpack!(size_in_bytes_bytes)),
+					size_in_bytes_bytes::set(size_in_bytes_bytes),
+				),
+				((inode::NAME, msgpack!(inode)), inode::set(inode)),
+				((is_dir::NAME, msgpack!(is_dir)), is_dir::set(Some(is_dir))),
+				((hidden::NAME, msgpack!(hidden)), hidden::set(hidden)),
+				(
+					(date_created::NAME, msgpack!(date_created)),
+					date_created::set(date_created),
+				),
+				(
+					(date_modified::NAME, msgpack!(date_modified)),
+					date_modified::set(date_modified),
+				),
+				(
+					(date_indexed::NAME, msgpack!(date_indexed)),
+					date_indexed::set(date_indexed),
+				),
+			],
+			[object_pub_id.map(|object_pub_id| {
+				(
+					(
+						object::NAME,
+						msgpack!(prisma_sync::object::SyncId {
+							pub_id: object_pub_id.clone()
+						}),
+					),
+					object::connect(sd_prisma::prisma::object::pub_id::equals(object_pub_id)),
+				)
+			})],
+		)
+		.into_iter()
+		.unzip()
+	};
+
+	sync.write_ops(
+		db,
+		(
+			sync.shared_create(
+				prisma_sync::file_path::SyncId {
+					pub_id: pub_id.clone(),
+				},
+				sync_params,
+			),
+			db.file_path().create(pub_id.clone(), db_params),
+		),
+	)
+	.await?;
+
+	Ok(pub_id)
+}
+
+/// Permanently deletes trashed items.
+///
+/// An item that can't be deleted doesn't stop the others, their errors are returned together.
+pub async fn purge(
+	library: &Library,
+	ids: Vec<trashed_item::id::Type>,
+) -> Result<(), FileSystemJobsError> {
+	let res = purge_items(&library.db, ids).await;
+
+	// The items which are gone from the trash were purged even if some failed
+	invalidate_query!(library, "trash.list");
+
+	res
+}
+
+async fn purge_items(
+	db: &PrismaClient,
+	ids: Vec<trashed_item::id::Type>,
+) -> Result<(), FileSystemJobsError> {
+	let items = db
+		.trashed_item()
+		.find_many(vec![trashed_item::id::in_vec(ids)])
+		.select(trashed_item::select!({ id is_dir trash_path }))
+		.exec()
+		.await?;
+
+	let mut purged = Vec::with_capacity(items.len());
+	let mut errors = vec![];
+	for item in items {
+		let trash_path = Path::new(&item.trash_path);
+		match if item.is_dir {
+			fs::remove_dir_all(trash_path).await
+		} else {
+			fs::remove_file(trash_path).await
+		} {
+			Ok(()) => {}
+			// It was already emptied from the trash by something else
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => {
+				error!("Failed to purge trashed item <id='{}'>: {e:#?}", item.id);
+				errors.push(
+					FileIOError::from((trash_path, e, "Failed to delete file from trash")).into(),
+				);
+				continue;
+			}
+		}
+		clean_up_trash_entry(trash_path).await;
+		purged.push(item.id);
+	}
+
+	// The rows of the items which are gone from the trash are deleted even if some failed
+	if !purged.is_empty() {
+		db.trashed_item()
+			.delete_many(vec![trashed_item::id::in_vec(purged)])
+			.exec()
+			.await?;
+	}
+
+	if errors.is_empty() {
+		Ok(())
+	} else {
+		Err(FileSystemJobsError::Purge(errors))
+	}
+}
+
+/// Purges the items which have been in the trash for longer than [`TRASH_RETENTION`]
+pub async fn purge_expired(library: &Library) -> Result<(), FileSystemJobsError> {
+	let expired = find_expired(&library.db).await?;
+
+	if expired.is_empty() {
+		return Ok(());
+	}
+
+	debug!("Purging {} expired items from the trash", expired.len());
+	purge(library, expired).await
+}
+
+async fn find_expired(
+	db: &PrismaClient,
+) -> Result<Vec<trashed_item::id::Type>, prisma_client_rust::QueryError> {
+	Ok(db
+		.trashed_item()
+		.find_many(vec![trashed_item::date_trashed::lt(
+			(Utc::now()
+				- chrono::Duration::from_std(TRASH_RETENTION).expect("valid retention period"))
+			.into(),
+		)])
+		.select(trashed_item::select!({ id }))
+		.exec()
+		.await?
+		.into_iter()
+		.map(|item| item.id)
+		.collect())
+}
+
+/// Removes what's left of an item which left the trash
+async fn clean_up_trash_entry(trash_path: &Path) {
+	let Some(parent) = trash_path.parent() else {
+		return;
+	};
+
+	// Items in a location's trash are kept in their own directory
+	if parent
+		.parent()
+		.and_then(Path::file_name)
+		.is_some_and(|name| name == TRASH_DIR_NAME)
+	{
+		if let Err(e) = fs::remove_dir(parent).await {
+			warn!(
+				"Failed to remove trash directory '{}': {e:#?}",
+				parent.display()
+			);
+		}
+		return;
+	}
+
+	#[cfg(target_os = "linux")]
+	xdg::remove_trash_info(trash_path).await;
+}
+
+/// The freedesktop.org trash, see <https://specifications.freedesktop.org/trash-spec/trashspec-latest.html>
+#[cfg(target_os = "linux")]
+mod xdg {
+	use std::{
+		os::unix::ffi::OsStrExt,
+		path::{Path, PathBuf},
+	};
+
+	use chrono::Local;
+	use directories::BaseDirs;
+	use tokio::{
+		fs::{self, OpenOptions},
+		io::{self, AsyncWriteExt},
+	};
+	use tracing::warn;
+	use uuid::Uuid;
+
+	const TRASH_INFO_EXTENSION: &str = "trashinfo";
+
+	/// The home trash, in `$XDG_DATA_HOME/Trash`
+	fn home_trash() -> io::Result<PathBuf> {
+		BaseDirs::new()
+			.map(|dirs| dirs.data_dir().join("Trash"))
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))
+	}
+
+	fn trash_info_path(trash_path: &Path) -> Option<PathBuf> {
+		let files_dir = trash_path.parent()?;
+		let mut info_name = trash_path.file_name()?.to_os_string();
+		info_name.push(".");
+		info_name.push(TRASH_INFO_EXTENSION);
+
+		(files_dir.file_name()? == "files")
+			.then(|| {
+				files_dir
+					.parent()
+					.map(|trash| trash.join("info").join(info_name))
+			})
+			.flatten()
+	}
+
+	/// Paths in trash info files are percent-encoded like in URLs
+	fn encode_path(path: &Path) -> String {
+		path.as_os_str()
+			.as_bytes()
+			.iter()
+			.map(|byte| match byte {
+				b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+					char::from(*byte).to_string()
+				}
+				_ => format!("%{byte:02X}"),
+			})
+			.collect()
+	}
+
+	/// Fails if the file is on another volume than the home trash, as moving it would mean copying it
+	pub async fn move_to_trash(path: &Path, id: Uuid) -> io::Result<PathBuf> {
+		let trash = home_trash()?;
+		let files_dir = trash.join("files");
+		fs::create_dir_all(&files_dir).await?;
+		fs::create_dir_all(trash.join("info")).await?;
+
+		let absolute_path = fs::canonicalize(path).await?;
+		let name = path.file_name().unwrap_or_default();
+		let mut unique_name = name.to_os_string();
+		unique_name.push(format!(".{}", id.simple()));
+
+		// Names in the trash must be unique, which is ensured by creating the info file first
+		for name in [name.to_os_string(), unique_name] {
+			let trash_path = files_dir.join(&name);
+			let Some(info_path) = trash_info_path(&trash_path) else {
+				continue;
+			};
+
+			let mut info_file = match OpenOptions::new()
+				.write(true)
+				.create_new(true)
+				.open(&info_path)
+				.await
+			{
+				Ok(file) => file,
+				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+				Err(e) => return Err(e),
+			};
+
+			info_file
+				.write_all(
+					format!(
+						"[Trash Info]\nPath={}\nDeletionDate={}\n",
+						encode_path(&absolute_path),
+						Local::now().format("%Y-%m-%dT%H:%M:%S")
+					)
+					.as_bytes(),
+				)
+				.await?;
+
+			if let Err(e) = fs::rename(path, &trash_path).await {
+				fs::remove_file(&info_path).await.ok();
+				return Err(e);
+			}
+
+			return Ok(trash_path);
+		}
+
+		Err(io::Error::new(
+			io::ErrorKind::AlreadyExists,
+			format!("no free name in the trash for {name:?}"),
+		))
+	}
+
+	pub async fn remove_trash_info(trash_path: &Path) {
+		let Some(info_path) = trash_info_path(trash_path) else {
+			return;
+		};
+
+		if let Err(e) = fs::remove_file(&info_path).await {
+			if e.kind() != io::ErrorKind::NotFound {
+				warn!(
+					"Failed to remove trash info file '{}': {e:#?}",
+					info_path.display()
+				);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::object::fs::get_many_files_datas;
+
+	use std::{collections::HashMap, sync::atomic::AtomicBool};
+
+	struct TestLocation {
+		db: Arc<PrismaClient>,
+		sync: sync::Manager,
+		id: location::id::Type,
+		path: PathBuf,
+	}
+
+	impl TestLocation {
+		async fn new() -> Self {
+			// Keeps the freedesktop.org trash of the tests away from the user's one
+			std::env::set_var(
+				"XDG_DATA_HOME",
+				std::env::temp_dir().join(format!("sd-trash-data-{}", std::process::id())),
+			);
+
+			let db = sd_prisma::test_db().await;
+			db._db_push().await.unwrap();
+
+			let sync = sync::Manager::new(
+				&db,
+				Uuid::new_v4(),
+				&Arc::new(AtomicBool::new(false)),
+				HashMap::new(),
+			)
+			.manager;
+
+			let path = std::env::temp_dir().join(format!("sd-trash-{}", Uuid::new_v4()));
+			fs::create_dir(&path).await.unwrap();
+
+			let location = db
+				.location()
+				.create(
+					uuid_to_bytes(Uuid::new_v4()),
+					vec![location::path::set(Some(
+						path.to_string_lossy().to_string(),
+					))],
+				)
+				.exec()
+				.await
+				.unwrap();
+
+			Self {
+				db,
+				sync,
+				id: location.id,
+				path,
+			}
+		}
+
+		/// Writes `<name>.txt` and indexes it with an object
+		async fn add_file(&self, name: &str) -> (file_path::id::Type, object::id::Type) {
+			fs::write(self.file(name), name).await.unwrap();
+
+			let object = self
+				.db
+				.object()
+				.create(uuid_to_bytes(Uuid::new_v4()), vec![])
+				.exec()
+				.await
+				.unwrap();
+
+			let file_path = self
+				.db
+				.file_path()
+				.create(
+					uuid_to_bytes(Uuid::new_v4()),
+					vec![
+						file_path::location::connect(location::id::equals(self.id)),
+						file_path::object::connect(object::id::equals(object.id)),
+						file_path::materialized_path::set(Some("/".to_string())),
+						file_path::is_dir::set(Some(false)),
+						file_path::name::set(Some(name.to_string())),
+						file_path::extension::set(Some("txt".to_string())),
+					],
+				)
+				.exec()
+				.await
+				.unwrap();
+
+			(file_path.id, object.id)
+		}
+
+		fn file(&self, name: &str) -> PathBuf {
+			self.path.join(format!("{name}.txt"))
+		}
+
+		async fn trash(&self, id: file_path::id::Type) -> trashed_item_for_restore::Data {
+			let file_data = get_many_files_datas(&self.db, &self.path, &[id])
+				.await
+				.unwrap()
+				.remove(0);
+
+			assert!(!move_file_path_to_trash(
+				&self.db, &self.sync, self.id, &self.path, &file_data
+			)
+			.await
+			.unwrap());
+
+			self.db
+				.trashed_item()
+				.find_first(vec![trashed_item::original_path::equals(
+					file_data.full_path.to_string_lossy().to_string(),
+				)])
+				.select(trashed_item_for_restore::select())
+				.exec()
+				.await
+				.unwrap()
+				.unwrap()
+		}
+	}
+
+	impl Drop for TestLocation {
+		fn drop(&mut self) {
+			std::fs::remove_dir_all(&self.path).ok();
+		}
+	}
+
+	#[tokio::test]
+	async fn restored_file_gets_its_row_and_object_back() {
+		let location = TestLocation::new().await;
+		let (id, object_id) = location.add_file("restored").await;
+		let pub_id = location
+			.db
+			.file_path()
+			.find_unique(file_path::id::equals(id))
+			.exec()
+			.await
+			.unwrap()
+			.unwrap()
+			.pub_id;
+
+		let item = location.trash(id).await;
+		let trash_path = PathBuf::from(&item.trash_path);
+
+		assert!(fs::metadata(location.file("restored")).await.is_err());
+		assert!(fs::metadata(&trash_path).await.is_ok());
+		assert_eq!(
+			location.db.file_path().count(vec![]).exec().await.unwrap(),
+			0
+		);
+
+		let (original_path, location_id) = move_back_from_trash(&location.db, &location.sync, item)
+			.await
+			.unwrap();
+
+		assert_eq!(original_path, location.file("restored"));
+		assert_eq!(location_id, Some(location.id));
+		assert_eq!(
+			fs::read_to_string(location.file("restored")).await.unwrap(),
+			"restored"
+		);
+		assert!(fs::metadata(&trash_path).await.is_err());
+
+		let file_path = location
+			.db
+			.file_path()
+			.find_unique(file_path::pub_id::equals(pub_id))
+			.exec()
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(file_path.object_id, Some(object_id));
+		assert_eq!(file_path.location_id, Some(location.id));
+		assert_eq!(
+			location
+				.db
+				.trashed_item()
+				.count(vec![])
+				.exec()
+				.await
+				.unwrap(),
+			0
+		);
+	}
+
+	#[tokio::test]
+	async fn expired_files_are_purged() {
+		let location = TestLocation::new().await;
+		let (expired_id, _) = location.add_file("expired").await;
+		let (recent_id, _) = location.add_file("recent").await;
+
+		let expired = location.trash(expired_id).await;
+		let recent = location.trash(recent_id).await;
+
+		location
+			.db
+			.trashed_item()
+			.update(
+				trashed_item::id::equals(expired.id),
+				vec![trashed_item::date_trashed::set(
+					(Utc::now()
+						- chrono::Duration::from_std(TRASH_RETENTION).unwrap()
+						- chrono::Duration::days(1))
+					.into(),
+				)],
+			)
+			.exec()
+			.await
+			.unwrap();
+
+		let ids = find_expired(&location.db).await.unwrap();
+		assert_eq!(ids, vec![expired.id]);
+
+		purge_items(&location.db, ids).await.unwrap();
+
+		assert!(fs::metadata(&expired.trash_path).await.is_err());
+		assert!(fs::metadata(location.file("expired")).await.is_err());
+		assert_eq!(
+			location.db.file_path().count(vec![]).exec().await.unwrap(),
+			0
+		);
+
+		let remaining = location
+			.db
+			.trashed_item()
+			.find_many(vec![])
+			.exec()
+			.await
+			.unwrap();
+		assert_eq!(remaining.len(), 1);
+		assert_eq!(remaining[0].id, recent.id);
+		assert!(fs::metadata(&recent.trash_path).await.is_ok());
+
+		purge_items(&location.db, vec![recent.id]).await.unwrap();
+	}
+}
@@ -1,6 +1,7 @@
 pub mod media_data_extractor;
 pub mod old_media_processor;
 pub mod old_thumbnail;
+pub mod perceptual_hasher;
 
 pub use old_media_processor::OldMediaProcessorJobInit;
 use sd_media_metadata::ImageMetadata;
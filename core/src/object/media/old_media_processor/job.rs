@@ -44,7 +44,7 @@ use tracing::{debug, error, info, trace, warn};
 use super::{
 	media_data_extractor,
 	old_thumbnail::{self, GenerateThumbnailArgs},
-	process, BatchToProcess, MediaProcessorError, OldMediaProcessorMetadata,
+	perceptual_hasher, process, BatchToProcess, MediaProcessorError, OldMediaProcessorMetadata,
 };
 
 const BATCH_SIZE: usize = 10;
@@ -72,6 +72,10 @@ pub struct OldMediaProcessorJobData {
 	to_process_path: PathBuf,
 	#[serde(skip, default)]
 	maybe_thumbnailer_progress_rx: Option<chan::Receiver<(u32, u32)>>,
+	#[serde(default)]
+	perceptual_hashes_first_step: usize,
+	#[serde(default)]
+	perceptual_hashes_count: usize,
 	#[cfg(feature = "ai")]
 	labeler_batch_token: ImageLabelerBatchToken,
 	#[cfg(feature = "ai")]
@@ -83,6 +87,7 @@ pub struct OldMediaProcessorJobData {
 pub enum OldMediaProcessorJobStep {
 	ExtractMediaData(Vec<file_path_for_media_processor::Data>),
 	WaitThumbnails(usize),
+	ComputePerceptualHashes(Vec<file_path_for_media_processor::Data>),
 	#[cfg(feature = "ai")]
 	WaitLabels(usize),
 }
@@ -175,6 +180,16 @@ impl StatefulJob for OldMediaProcessorJobInit {
 
 		let file_paths = get_files_for_media_data_extraction(db, &iso_file_path).await?;
 
+		// Perceptual hashes are computed from the thumbnails, so we need every thumbnailable file
+		let file_paths_for_perceptual_hashing = get_all_children_files_by_extensions(
+			db,
+			&iso_file_path,
+			&old_thumbnail::ALL_THUMBNAILABLE_EXTENSIONS,
+		)
+		.await?;
+
+		let perceptual_hashes_count = file_paths_for_perceptual_hashing.len();
+
 		#[cfg(feature = "ai")]
 		let file_paths_for_labeling =
 			get_files_for_labeling(db, &iso_file_path, self.regenerate_labels).await?;
@@ -201,6 +216,9 @@ impl StatefulJob for OldMediaProcessorJobInit {
 
 		let total_files = file_paths.len();
 
+		let perceptual_hashes_first_step =
+			total_files.div_ceil(BATCH_SIZE) + usize::from(thumbs_to_process_count > 0);
+
 		let chunked_files = file_paths
 			.into_iter()
 			.chunks(BATCH_SIZE)
@@ -214,6 +232,14 @@ impl StatefulJob for OldMediaProcessorJobInit {
 				.into_iter()
 				.flatten(),
 			)
+			.chain(
+				file_paths_for_perceptual_hashing
+					.into_iter()
+					.chunks(BATCH_SIZE)
+					.into_iter()
+					.map(|chunk| chunk.collect::<Vec<_>>())
+					.map(OldMediaProcessorJobStep::ComputePerceptualHashes),
+			)
 			.chain(
 				[
 					#[cfg(feature = "ai")]
@@ -245,6 +271,8 @@ impl StatefulJob for OldMediaProcessorJobInit {
 			location_path,
 			to_process_path,
 			maybe_thumbnailer_progress_rx,
+			perceptual_hashes_first_step,
+			perceptual_hashes_count,
 			#[cfg(feature = "ai")]
 			labeler_batch_token,
 			#[cfg(feature = "ai")]
@@ -329,6 +357,40 @@ impl StatefulJob for OldMediaProcessorJobInit {
 				Ok(None.into())
 			}
 
+			OldMediaProcessorJobStep::ComputePerceptualHashes(file_paths) => {
+				let completed_before =
+					(step_number - data.perceptual_hashes_first_step) * BATCH_SIZE;
+
+				if completed_before == 0 {
+					ctx.progress(vec![
+						JobReportUpdate::TaskCount(data.perceptual_hashes_count),
+						JobReportUpdate::Phase("perceptual_hashes".to_string()),
+						JobReportUpdate::Message(format!(
+							"Computing perceptual hashes of {} files",
+							data.perceptual_hashes_count
+						)),
+					]);
+				}
+
+				perceptual_hasher::process(
+					file_paths,
+					&ctx.node,
+					ctx.library.id,
+					self.regenerate_thumbnails,
+					&ctx.library.db,
+					&|completed_count| {
+						ctx.progress(vec![JobReportUpdate::CompletedTaskCount(
+							completed_before + completed_count,
+						)]);
+					},
+				)
+				.await
+				.map(|(perceptual_hashes, errors)| {
+					(OldMediaProcessorMetadata::from(perceptual_hashes), errors).into()
+				})
+				.map_err(|e| MediaProcessorError::from(e).into())
+			}
+
 			#[cfg(feature = "ai")]
 			OldMediaProcessorJobStep::WaitLabels(total_labels) => {
 				let Some(image_labeller) = ctx.node.old_image_labeller.as_ref() else {
@@ -12,6 +12,7 @@ use tracing::error;
 use super::{
 	media_data_extractor::{self, MediaDataError, OldMediaDataExtractorMetadata},
 	old_thumbnail::{self, BatchToProcess, ThumbnailerError},
+	perceptual_hasher::OldPerceptualHasherMetadata,
 };
 
 mod job;
@@ -41,14 +42,24 @@ pub struct OldMediaProcessorMetadata {
 	media_data: OldMediaDataExtractorMetadata,
 	thumbs_processed: u32,
 	labels_extracted: u32,
+	#[serde(default)]
+	perceptual_hashes: OldPerceptualHasherMetadata,
 }
 
 impl From<OldMediaDataExtractorMetadata> for OldMediaProcessorMetadata {
 	fn from(media_data: OldMediaDataExtractorMetadata) -> Self {
 		Self {
 			media_data,
-			thumbs_processed: 0,
-			labels_extracted: 0,
+			..Default::default()
+		}
+	}
+}
+
+impl From<OldPerceptualHasherMetadata> for OldMediaProcessorMetadata {
+	fn from(perceptual_hashes: OldPerceptualHasherMetadata) -> Self {
+		Self {
+			perceptual_hashes,
+			..Default::default()
 		}
 	}
 }
@@ -59,6 +70,8 @@ impl JobRunMetadata for OldMediaProcessorMetadata {
 		self.media_data.skipped += new_data.media_data.skipped;
 		self.thumbs_processed += new_data.thumbs_processed;
 		self.labels_extracted += new_data.labels_extracted;
+		self.perceptual_hashes.computed += new_data.perceptual_hashes.computed;
+		self.perceptual_hashes.skipped += new_data.perceptual_hashes.skipped;
 	}
 }
 
@@ -63,6 +63,14 @@ pub fn get_indexed_thumbnail_path(node: &Node, cas_id: &str, library_id: Library
 	get_thumbnail_path(node, cas_id, ThumbnailKind::Indexed(library_id))
 }
 
+/// The directory holding the thumbnails of the indexed files of a library
+pub fn get_indexed_thumbnails_dir(node: &Node, library_id: LibraryId) -> PathBuf {
+	node.config
+		.data_directory()
+		.join(THUMBNAIL_CACHE_DIR_NAME)
+		.join(library_id.to_string())
+}
+
 /// This does not check if a thumbnail exists, it just returns the path that it would exist at
 fn get_thumbnail_path(node: &Node, cas_id: &str, kind: ThumbnailKind) -> PathBuf {
 	let mut thumb_path = node.config.data_directory();
@@ -0,0 +1,289 @@
+use crate::{library::LibraryId, old_job::JobRunErrors, Node};
+
+use sd_file_path_helper::file_path_for_media_processor;
+use sd_images::{hamming_distance, perceptual_hash};
+use sd_prisma::prisma::{object, perceptual_hash, PrismaClient, SortOrder};
+
+use std::collections::{HashMap, HashSet};
+
+use futures_concurrency::future::Join;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, task::spawn_blocking};
+use tracing::error;
+
+use super::{media_data_extractor::MediaDataError, old_thumbnail::get_indexed_thumbnail_path};
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct OldPerceptualHasherMetadata {
+	pub computed: u32,
+	pub skipped: u32,
+}
+
+pub fn perceptual_hash_to_bytes(hash: u64) -> Vec<u8> {
+	hash.to_be_bytes().to_vec()
+}
+
+pub fn perceptual_hash_from_bytes(bytes: &[u8]) -> Option<u64> {
+	<[u8; 8]>::try_from(bytes).ok().map(u64::from_be_bytes)
+}
+
+/// Computes the perceptual hashes of the objects of these file paths from their thumbnails.
+///
+/// Thumbnails are already decoded and downscaled versions of every image, video and document we
+/// support, so hashing them is cheap and gives the same result regardless of the original format.
+/// Objects without a thumbnail are skipped.
+pub async fn process(
+	files_paths: &[file_path_for_media_processor::Data],
+	node: &Node,
+	library_id: LibraryId,
+	regenerate: bool,
+	db: &PrismaClient,
+	ctx_update_fn: &impl Fn(usize),
+) -> Result<(OldPerceptualHasherMetadata, JobRunErrors), MediaDataError> {
+	let mut run_metadata = OldPerceptualHasherMetadata::default();
+
+	// Many file paths can point to the same object, we only need to hash it once
+	let objects = files_paths
+		.iter()
+		.filter_map(|file_path| {
+			file_path
+				.object_id
+				.zip(file_path.cas_id.as_ref())
+				.map(|(object_id, cas_id)| (object_id, cas_id.as_str()))
+		})
+		.collect::<HashMap<_, _>>();
+
+	if objects.is_empty() {
+		return Ok((run_metadata, JobRunErrors::default()));
+	}
+
+	let objects_already_hashed = if regenerate {
+		HashSet::new()
+	} else {
+		db.perceptual_hash()
+			.find_many(vec![perceptual_hash::object_id::in_vec(
+				objects.keys().copied().collect(),
+			)])
+			.select(perceptual_hash::select!({ object_id }))
+			.exec()
+			.await?
+			.into_iter()
+			.map(|perceptual_hash| perceptual_hash.object_id)
+			.collect()
+	};
+
+	run_metadata.skipped = objects_already_hashed.len() as u32;
+
+	let maybe_hashes = objects
+		.into_iter()
+		.filter(|(object_id, _)| !objects_already_hashed.contains(object_id))
+		.enumerate()
+		.map(|(idx, (object_id, cas_id))| {
+			let thumb_path = get_indexed_thumbnail_path(node, cas_id, library_id);
+			async move {
+				let res = if fs::metadata(&thumb_path).await.is_ok() {
+					let path = thumb_path.clone();
+					spawn_blocking(move || image::open(path).map(|img| perceptual_hash(&img)))
+						.await
+						.map(|res| res.map(Some))
+				} else {
+					Ok(Ok(None))
+				};
+				ctx_update_fn(idx + 1);
+				(res, thumb_path, object_id)
+			}
+		})
+		.collect::<Vec<_>>()
+		.join()
+		.await;
+
+	let mut hashes: Vec<(object::id::Type, u64)> = Vec::with_capacity(maybe_hashes.len());
+	let mut errors = vec![];
+
+	for (res, thumb_path, object_id) in maybe_hashes {
+		match res? {
+			Ok(Some(hash)) => hashes.push((object_id, hash)),
+			// The thumbnailer couldn't generate a thumbnail for this file
+			Ok(None) => run_metadata.skipped += 1,
+			Err(e) => {
+				error!(
+					"Failed to compute perceptual hash of thumbnail at {}: {e:#?}",
+					thumb_path.display()
+				);
+				errors.push(format!(
+					"Couldn't compute perceptual hash of thumbnail: \"{}\"; Error: {e}",
+					thumb_path.display()
+				));
+			}
+		}
+	}
+
+	// The hashes are only kept locally, as each instance computes them from its own thumbnails
+	if !hashes.is_empty() {
+		db._batch(
+			hashes
+				.iter()
+				.map(|(object_id, hash)| {
+					db.perceptual_hash().upsert(
+						perceptual_hash::object_id::equals(*object_id),
+						perceptual_hash::create_unchecked(
+							perceptual_hash_to_bytes(*hash),
+							*object_id,
+							vec![],
+						),
+						vec![perceptual_hash::hash::set(perceptual_hash_to_bytes(*hash))],
+					)
+				})
+				.collect::<Vec<_>>(),
+		)
+		.await?;
+	}
+
+	run_metadata.computed = hashes.len() as u32;
+	run_metadata.skipped += errors.len() as u32;
+
+	Ok((run_metadata, errors.into()))
+}
+
+/// Media data rows are scanned in batches of this size when comparing hashes
+const SIMILARITY_SCAN_BATCH_SIZE: i64 = 10_000;
+
+/// Returns the objects whose perceptual hash is at most `max_distance` bits away from the one
+/// of `object_id`, closest first, or `None` if that object doesn't have a perceptual hash yet
+pub async fn find_similar_objects(
+	db: &PrismaClient,
+	object_id: object::id::Type,
+	max_distance: u32,
+	take: usize,
+) -> Result<Option<Vec<(object::id::Type, u32)>>, prisma_client_rust::QueryError> {
+	let Some(target_hash) = db
+		.perceptual_hash()
+		.find_unique(perceptual_hash::object_id::equals(object_id))
+		.select(perceptual_hash::select!({ hash }))
+		.exec()
+		.await?
+		.and_then(|perceptual_hash| perceptual_hash_from_bytes(&perceptual_hash.hash))
+	else {
+		return Ok(None);
+	};
+
+	let mut similar = vec![];
+	let mut last_id = None;
+
+	// SQLite has no popcount, so hashes are compared here instead of in a query
+	loop {
+		let batch = db
+			.perceptual_hash()
+			.find_many(last_id.map(perceptual_hash::id::gt).into_iter().collect())
+			.order_by(perceptual_hash::id::order(SortOrder::Asc))
+			.take(SIMILARITY_SCAN_BATCH_SIZE)
+			.exec()
+			.await?;
+
+		let Some(last) = batch.last() else {
+			break;
+		};
+		last_id = Some(last.id);
+		let is_last_batch = (batch.len() as i64) < SIMILARITY_SCAN_BATCH_SIZE;
+
+		similar.extend(batch.into_iter().filter_map(|perceptual_hash| {
+			(perceptual_hash.object_id != object_id)
+				.then(|| perceptual_hash_from_bytes(&perceptual_hash.hash))
+				.flatten()
+				.map(|hash| {
+					(
+						perceptual_hash.object_id,
+						hamming_distance(target_hash, hash),
+					)
+				})
+				.filter(|(_, distance)| *distance <= max_distance)
+		}));
+
+		if is_last_batch {
+			break;
+		}
+	}
+
+	similar.sort_by_key(|(_, distance)| *distance);
+	similar.truncate(take);
+
+	Ok(Some(similar))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use uuid::Uuid;
+
+	const HASH: u64 = 0x0123_4567_89ab_cdef;
+
+	async fn new_object(db: &PrismaClient, hash: Option<u64>) -> object::id::Type {
+		let object = db
+			.object()
+			.create(Uuid::new_v4().as_bytes().to_vec(), vec![])
+			.exec()
+			.await
+			.unwrap();
+
+		if let Some(hash) = hash {
+			db.perceptual_hash()
+				.create_unchecked(perceptual_hash_to_bytes(hash), object.id, vec![])
+				.exec()
+				.await
+				.unwrap();
+		}
+
+		object.id
+	}
+
+	#[tokio::test]
+	async fn similar_objects_are_within_distance_closest_first() {
+		let db = sd_prisma::test_db().await;
+		db._db_push().await.unwrap();
+
+		let target = new_object(&db, Some(HASH)).await;
+		let three_bits = new_object(&db, Some(HASH ^ 0b111)).await;
+		let same = new_object(&db, Some(HASH)).await;
+		let one_bit = new_object(&db, Some(HASH ^ (1 << 40))).await;
+		new_object(&db, Some(HASH ^ 0xffff)).await;
+		new_object(&db, Some(!HASH)).await;
+		new_object(&db, None).await;
+
+		assert_eq!(
+			find_similar_objects(&db, target, 10, 10).await.unwrap(),
+			Some(vec![(same, 0), (one_bit, 1), (three_bits, 3)])
+		);
+		assert_eq!(
+			find_similar_objects(&db, target, 10, 2).await.unwrap(),
+			Some(vec![(same, 0), (one_bit, 1)])
+		);
+		assert_eq!(
+			find_similar_objects(&db, target, 0, 10).await.unwrap(),
+			Some(vec![(same, 0)])
+		);
+	}
+
+	#[tokio::test]
+	async fn objects_without_hash_have_no_similar_objects() {
+		let db = sd_prisma::test_db().await;
+		db._db_push().await.unwrap();
+
+		new_object(&db, Some(HASH)).await;
+		let unhashed = new_object(&db, None).await;
+
+		assert_eq!(
+			find_similar_objects(&db, unhashed, 10, 10).await.unwrap(),
+			None
+		);
+	}
+
+	#[test]
+	fn hash_round_trips_through_bytes() {
+		assert_eq!(
+			perceptual_hash_from_bytes(&perceptual_hash_to_bytes(HASH)),
+			Some(HASH)
+		);
+		assert_eq!(perceptual_hash_from_bytes(&[0; 7]), None);
+	}
+}
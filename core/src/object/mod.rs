@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use specta::Type;
 
 pub mod cas;
+pub mod content;
+pub mod duplicates;
 pub mod fs;
 pub mod media;
 pub mod old_file_identifier;
@@ -1,4 +1,4 @@
-use sd_prisma::prisma::{object, tag_on_object, PrismaClient};
+use sd_prisma::prisma::{object, object_in_album, object_in_space, tag_on_object, PrismaClient};
 
 use std::{sync::Arc, time::Duration};
 
@@ -88,6 +88,14 @@ impl OrphanRemoverActor {
 				._batch((
 					db.tag_on_object()
 						.delete_many(vec![tag_on_object::object_id::in_vec(objects_ids.clone())]),
+					db.object_in_album()
+						.delete_many(vec![object_in_album::object_id::in_vec(
+							objects_ids.clone(),
+						)]),
+					db.object_in_space()
+						.delete_many(vec![object_in_space::object_id::in_vec(
+							objects_ids.clone(),
+						)]),
 					db.object()
 						.delete_many(vec![object::id::in_vec(objects_ids)]),
 				))
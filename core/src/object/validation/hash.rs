@@ -13,11 +13,12 @@ pub async fn file_checksum(path: impl AsRef<Path>) -> Result<String, io::Error>
 	let mut context = Hasher::new();
 	let mut buffer = vec![0; BLOCK_LEN].into_boxed_slice();
 	loop {
+		// Reads can be short before the end of the file, eg. on network file systems
 		let read_count = reader.read(&mut buffer).await?;
-		context.update(&buffer[..read_count]);
-		if read_count != BLOCK_LEN {
+		if read_count == 0 {
 			break;
 		}
+		context.update(&buffer[..read_count]);
 	}
 	let hex = context.finalize().to_hex();
 
@@ -1,17 +1,56 @@
-use sd_file_path_helper::FilePathError;
-use sd_utils::error::FileIOError;
+use crate::{library::Library, old_job::JobRunMetadata, sync};
+
+use sd_file_path_helper::{
+	file_path_for_integrity_check, FilePathError, IsolatedFilePathData, MetadataExt,
+};
+use sd_prisma::{
+	prisma::{file_path, integrity_check, location, PrismaClient, SortOrder},
+	prisma_sync,
+};
+use sd_sync::OperationFactory;
+use sd_utils::{
+	db::{maybe_missing, MissingFieldError},
+	error::FileIOError,
+	msgpack,
+};
 
 use std::path::Path;
 
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::fs;
+
+use hash::file_checksum;
 
 pub mod hash;
+pub mod old_integrity_verifier_job;
 pub mod old_validator_job;
 
+integrity_check::select!(corrupted_file {
+	checksum
+	date_modified
+	date_checked
+	date_corrupted
+	file_path: select {
+		id
+		pub_id
+		location_id
+		materialized_path
+		is_dir
+		name
+		extension
+		integrity_checksum
+		object_id
+	}
+});
+
 #[derive(Error, Debug)]
 pub enum ValidatorError {
 	#[error("sub path not found: <path='{}'>", .0.display())]
 	SubPathNotFound(Box<Path>),
+	#[error("file path <id='{0}'> isn't suspected to be corrupted")]
+	NotCorrupted(file_path::id::Type),
 
 	// Internal errors
 	#[error("database error: {0}")]
@@ -20,4 +59,463 @@ pub enum ValidatorError {
 	FilePath(#[from] FilePathError),
 	#[error(transparent)]
 	FileIO(#[from] FileIOError),
+	#[error(transparent)]
+	MissingField(#[from] MissingFieldError),
+}
+
+impl From<ValidatorError> for rspc::Error {
+	fn from(e: ValidatorError) -> Self {
+		match e {
+			ValidatorError::SubPathNotFound(_) | ValidatorError::NotCorrupted(_) => {
+				Self::with_cause(rspc::ErrorCode::NotFound, e.to_string(), e)
+			}
+			_ => Self::with_cause(rspc::ErrorCode::InternalServerError, e.to_string(), e),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct OldIntegrityVerifierMetadata {
+	pub computed: u32,
+	pub verified: u32,
+	pub corrupted: u32,
+	pub newly_corrupted: u32,
+	pub skipped: u32,
+}
+
+impl JobRunMetadata for OldIntegrityVerifierMetadata {
+	fn update(&mut self, new_data: Self) {
+		self.computed += new_data.computed;
+		self.verified += new_data.verified;
+		self.corrupted += new_data.corrupted;
+		self.newly_corrupted += new_data.newly_corrupted;
+		self.skipped += new_data.skipped;
+	}
+}
+
+impl OldIntegrityVerifierMetadata {
+	fn record(&mut self, outcome: IntegrityOutcome) {
+		match outcome {
+			IntegrityOutcome::Computed => self.computed += 1,
+			IntegrityOutcome::Verified => self.verified += 1,
+			IntegrityOutcome::Corrupted { first_detected } => {
+				self.corrupted += 1;
+				if first_detected {
+					self.newly_corrupted += 1;
+				}
+			}
+			IntegrityOutcome::Skipped => self.skipped += 1,
+		}
+	}
+}
+
+/// What verifying the content integrity of a file found
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityOutcome {
+	/// The file had no checksum we could compare against, so it was computed
+	Computed,
+	/// The file still matches its checksum
+	Verified,
+	/// The file no longer matches its checksum although it wasn't modified
+	Corrupted { first_detected: bool },
+	/// The file was modified since it was last indexed, so it will be verified once reindexed
+	Skipped,
+}
+
+/// Hashes a file again and compares it to its `integrity_checksum`.
+///
+/// A checksum is only compared against if it was computed or verified while the file had the
+/// same `date_modified`, otherwise the file was legitimately modified and its checksum is
+/// computed again. Checksums computed before verifications existed are trusted only if they
+/// still match, as we can't tell if the file was modified since.
+pub async fn verify_file_integrity(
+	library: &Library,
+	location_path: impl AsRef<Path>,
+	file_path: &file_path_for_integrity_check::Data,
+) -> Result<IntegrityOutcome, ValidatorError> {
+	check_integrity(&library.db, &library.sync, location_path, file_path).await
+}
+
+async fn check_integrity(
+	db: &PrismaClient,
+	sync: &sync::Manager,
+	location_path: impl AsRef<Path>,
+	file_path: &file_path_for_integrity_check::Data,
+) -> Result<IntegrityOutcome, ValidatorError> {
+	let full_path = location_path
+		.as_ref()
+		.join(IsolatedFilePathData::try_from(file_path)?);
+
+	let metadata = fs::metadata(&full_path)
+		.await
+		.map_err(|e| FileIOError::from((&full_path, e)))?;
+
+	let date_modified = maybe_missing(file_path.date_modified, "file_path.date_modified")?;
+
+	// Datetimes stored in DB loses a bit of precision, so we need to check against a delta
+	if (DateTime::<Utc>::from(metadata.modified_or_now()) - date_modified.with_timezone(&Utc))
+		.num_milliseconds()
+		.abs() > 1
+	{
+		return Ok(IntegrityOutcome::Skipped);
+	}
+
+	let checksum = file_checksum(&full_path)
+		.await
+		.map_err(|e| FileIOError::from((&full_path, e)))?;
+
+	let date_checked: DateTime<FixedOffset> = Utc::now().into();
+	let previous_check = file_path.integrity_check.as_ref();
+
+	let outcome = match (&file_path.integrity_checksum, previous_check) {
+		(Some(expected), Some(check)) if check.date_modified == date_modified => {
+			if *expected == checksum {
+				IntegrityOutcome::Verified
+			} else {
+				IntegrityOutcome::Corrupted {
+					first_detected: check.date_corrupted.is_none(),
+				}
+			}
+		}
+		(Some(expected), None) if *expected == checksum => IntegrityOutcome::Verified,
+		_ => IntegrityOutcome::Computed,
+	};
+
+	if outcome == IntegrityOutcome::Computed
+		&& file_path.integrity_checksum.as_ref() != Some(&checksum)
+	{
+		sync.write_op(
+			db,
+			sync.shared_update(
+				prisma_sync::file_path::SyncId {
+					pub_id: file_path.pub_id.clone(),
+				},
+				file_path::integrity_checksum::NAME,
+				msgpack!(&checksum),
+			),
+			db.file_path().update(
+				file_path::id::equals(file_path.id),
+				vec![file_path::integrity_checksum::set(Some(checksum.clone()))],
+			),
+		)
+		.await?;
+	}
+
+	let date_corrupted = match outcome {
+		IntegrityOutcome::Corrupted { .. } => previous_check
+			.and_then(|check| check.date_corrupted)
+			.or(Some(date_checked)),
+		_ => None,
+	};
+
+	db.integrity_check()
+		.upsert(
+			integrity_check::file_path_id::equals(file_path.id),
+			integrity_check::create(
+				date_modified,
+				date_checked,
+				checksum.clone(),
+				file_path::id::equals(file_path.id),
+				vec![integrity_check::date_corrupted::set(date_corrupted)],
+			),
+			vec![
+				integrity_check::date_modified::set(date_modified),
+				integrity_check::date_checked::set(date_checked),
+				integrity_check::checksum::set(checksum),
+				integrity_check::date_corrupted::set(date_corrupted),
+			],
+		)
+		.exec()
+		.await?;
+
+	Ok(outcome)
+}
+
+/// Files suspected to be corrupted, optionally only the ones of a location
+pub async fn get_corrupted_files(
+	db: &PrismaClient,
+	location_id: Option<location::id::Type>,
+) -> Result<Vec<corrupted_file::Data>, ValidatorError> {
+	db.integrity_check()
+		.find_many(sd_utils::chain_optional_iter(
+			[integrity_check::date_corrupted::not(None)],
+			[location_id.map(|location_id| {
+				integrity_check::file_path::is(vec![file_path::location_id::equals(Some(
+					location_id,
+				))])
+			})],
+		))
+		.order_by(integrity_check::date_corrupted::order(SortOrder::Desc))
+		.select(corrupted_file::select())
+		.exec()
+		.await
+		.map_err(Into::into)
+}
+
+/// Accepts the current content of a file suspected to be corrupted as the right one, eg. when
+/// it was modified on purpose without its modification date changing
+pub async fn accept_file_checksum(
+	library: &Library,
+	file_path_id: file_path::id::Type,
+) -> Result<(), ValidatorError> {
+	accept_checksum(&library.db, &library.sync, file_path_id).await
+}
+
+async fn accept_checksum(
+	db: &PrismaClient,
+	sync: &sync::Manager,
+	file_path_id: file_path::id::Type,
+) -> Result<(), ValidatorError> {
+	let check = db
+		.integrity_check()
+		.find_first(vec![
+			integrity_check::file_path_id::equals(file_path_id),
+			integrity_check::date_corrupted::not(None),
+		])
+		.select(integrity_check::select!({ checksum file_path: select { pub_id } }))
+		.exec()
+		.await?
+		.ok_or(ValidatorError::NotCorrupted(file_path_id))?;
+
+	sync.write_op(
+		db,
+		sync.shared_update(
+			prisma_sync::file_path::SyncId {
+				pub_id: check.file_path.pub_id,
+			},
+			file_path::integrity_checksum::NAME,
+			msgpack!(&check.checksum),
+		),
+		db.file_path().update(
+			file_path::id::equals(file_path_id),
+			vec![file_path::integrity_checksum::set(Some(check.checksum))],
+		),
+	)
+	.await?;
+
+	db.integrity_check()
+		.update(
+			integrity_check::file_path_id::equals(file_path_id),
+			vec![integrity_check::date_corrupted::set(None)],
+		)
+		.exec()
+		.await?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::{
+		collections::HashMap,
+		path::PathBuf,
+		sync::{atomic::AtomicBool, Arc},
+		time::{Duration, SystemTime, UNIX_EPOCH},
+	};
+
+	use uuid::Uuid;
+
+	struct TestFile {
+		db: Arc<PrismaClient>,
+		sync: sync::Manager,
+		location_path: PathBuf,
+		id: file_path::id::Type,
+	}
+
+	impl TestFile {
+		/// An indexed file, whose content is written with the given modification time
+		async fn new(content: &str, modified: SystemTime) -> Self {
+			let db = sd_prisma::test_db().await;
+			db._db_push().await.unwrap();
+
+			let sync = sync::Manager::new(
+				&db,
+				Uuid::new_v4(),
+				&Arc::new(AtomicBool::new(false)),
+				HashMap::new(),
+			)
+			.manager;
+
+			let location_path =
+				std::env::temp_dir().join(format!("sd-integrity-{}", Uuid::new_v4()));
+			fs::create_dir(&location_path).await.unwrap();
+
+			let location = db
+				.location()
+				.create(Uuid::new_v4().as_bytes().to_vec(), vec![])
+				.exec()
+				.await
+				.unwrap();
+
+			let file_path = db
+				.file_path()
+				.create(
+					Uuid::new_v4().as_bytes().to_vec(),
+					vec![
+						file_path::location::connect(location::id::equals(location.id)),
+						file_path::materialized_path::set(Some("/".to_string())),
+						file_path::is_dir::set(Some(false)),
+						file_path::name::set(Some("file".to_string())),
+						file_path::extension::set(Some("txt".to_string())),
+					],
+				)
+				.exec()
+				.await
+				.unwrap();
+
+			let file = Self {
+				db,
+				sync,
+				location_path,
+				id: file_path.id,
+			};
+
+			file.write(content, modified).await;
+			file.reindex(modified).await;
+
+			file
+		}
+
+		async fn write(&self, content: &str, modified: SystemTime) {
+			let path = self.location_path.join("file.txt");
+
+			fs::write(&path, content).await.unwrap();
+			std::fs::File::options()
+				.write(true)
+				.open(&path)
+				.unwrap()
+				.set_modified(modified)
+				.unwrap();
+		}
+
+		/// Updates the modification date of the file in the database, as the indexer would
+		async fn reindex(&self, modified: SystemTime) {
+			self.db
+				.file_path()
+				.update(
+					file_path::id::equals(self.id),
+					vec![file_path::date_modified::set(Some(
+						DateTime::<Utc>::from(modified).into(),
+					))],
+				)
+				.exec()
+				.await
+				.unwrap();
+		}
+
+		async fn verify(&self) -> IntegrityOutcome {
+			let file_path = self
+				.db
+				.file_path()
+				.find_unique(file_path::id::equals(self.id))
+				.select(file_path_for_integrity_check::select())
+				.exec()
+				.await
+				.unwrap()
+				.unwrap();
+
+			check_integrity(&self.db, &self.sync, &self.location_path, &file_path)
+				.await
+				.unwrap()
+		}
+	}
+
+	impl Drop for TestFile {
+		fn drop(&mut self) {
+			std::fs::remove_dir_all(&self.location_path).ok();
+		}
+	}
+
+	fn time(secs: u64) -> SystemTime {
+		UNIX_EPOCH + Duration::from_secs(secs)
+	}
+
+	#[tokio::test]
+	async fn content_changed_without_modification_is_corrupted() {
+		let file = TestFile::new("original", time(1_700_000_000)).await;
+
+		assert_eq!(file.verify().await, IntegrityOutcome::Computed);
+		assert_eq!(file.verify().await, IntegrityOutcome::Verified);
+
+		// Same size and modification time, as bit rot would leave it
+		file.write("origina!", time(1_700_000_000)).await;
+
+		assert_eq!(
+			file.verify().await,
+			IntegrityOutcome::Corrupted {
+				first_detected: true
+			}
+		);
+
+		let corrupted = get_corrupted_files(&file.db, None).await.unwrap();
+		assert_eq!(corrupted.len(), 1);
+		assert_eq!(corrupted[0].file_path.id, file.id);
+	}
+
+	#[tokio::test]
+	async fn corruption_is_only_first_detected_once() {
+		let file = TestFile::new("original", time(1_700_000_000)).await;
+
+		assert_eq!(file.verify().await, IntegrityOutcome::Computed);
+
+		file.write("origina!", time(1_700_000_000)).await;
+
+		assert_eq!(
+			file.verify().await,
+			IntegrityOutcome::Corrupted {
+				first_detected: true
+			}
+		);
+		let date_corrupted = get_corrupted_files(&file.db, None).await.unwrap()[0].date_corrupted;
+
+		assert_eq!(
+			file.verify().await,
+			IntegrityOutcome::Corrupted {
+				first_detected: false
+			}
+		);
+		// The file is still reported as corrupted since it was first detected
+		assert_eq!(
+			get_corrupted_files(&file.db, None).await.unwrap()[0].date_corrupted,
+			date_corrupted
+		);
+
+		// Once accepted, the current content is the one verified against
+		accept_checksum(&file.db, &file.sync, file.id)
+			.await
+			.unwrap();
+		assert!(get_corrupted_files(&file.db, None)
+			.await
+			.unwrap()
+			.is_empty());
+		assert_eq!(file.verify().await, IntegrityOutcome::Verified);
+
+		assert!(matches!(
+			accept_checksum(&file.db, &file.sync, file.id).await,
+			Err(ValidatorError::NotCorrupted(id)) if id == file.id
+		));
+	}
+
+	#[tokio::test]
+	async fn modified_file_is_not_corrupted() {
+		let file = TestFile::new("original", time(1_700_000_000)).await;
+
+		assert_eq!(file.verify().await, IntegrityOutcome::Computed);
+
+		file.write("modified content", time(1_700_000_100)).await;
+
+		// Until it's reindexed, the new content can't be told apart from a corruption
+		assert_eq!(file.verify().await, IntegrityOutcome::Skipped);
+
+		file.reindex(time(1_700_000_100)).await;
+
+		assert_eq!(file.verify().await, IntegrityOutcome::Computed);
+		assert_eq!(file.verify().await, IntegrityOutcome::Verified);
+		assert!(get_corrupted_files(&file.db, None)
+			.await
+			.unwrap()
+			.is_empty());
+	}
 }
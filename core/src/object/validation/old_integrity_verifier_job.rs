@@ -0,0 +1,258 @@
+use crate::{
+	api::notifications::{NotificationCategory, NotificationData, NotificationKind},
+	invalidate_query,
+	library::Library,
+	old_job::{
+		CurrentStep, JobError, JobInitOutput, JobReportUpdate, JobResult, JobRunErrors,
+		JobStepOutput, StatefulJob, WorkerContext,
+	},
+};
+
+use sd_file_path_helper::{
+	ensure_file_path_exists, ensure_sub_path_is_directory, ensure_sub_path_is_in_location,
+	file_path_for_integrity_check, IsolatedFilePathData,
+};
+use sd_prisma::prisma::{file_path, location};
+use sd_utils::db::maybe_missing;
+
+use std::{
+	hash::{Hash, Hasher},
+	path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use super::{
+	verify_file_integrity, IntegrityOutcome, OldIntegrityVerifierMetadata, ValidatorError,
+};
+
+const BATCH_SIZE: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OldIntegrityVerifierJobData {
+	location_path: PathBuf,
+	task_count: usize,
+}
+
+/// Hashes the files of a location again to detect silent corruption (bit rot).
+///
+/// Files that were modified since they were last indexed are skipped, they'll have their
+/// checksum computed again on the next verification after being reindexed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OldIntegrityVerifierJobInit {
+	pub location: location::Data,
+	pub sub_path: Option<PathBuf>,
+}
+
+impl Hash for OldIntegrityVerifierJobInit {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.location.id.hash(state);
+		if let Some(ref sub_path) = self.sub_path {
+			sub_path.hash(state);
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl StatefulJob for OldIntegrityVerifierJobInit {
+	type Data = OldIntegrityVerifierJobData;
+	type Step = Vec<file_path::id::Type>;
+	type RunMetadata = OldIntegrityVerifierMetadata;
+
+	const NAME: &'static str = "integrity_verifier";
+	const IS_BATCHED: bool = true;
+
+	fn target_location(&self) -> location::id::Type {
+		self.location.id
+	}
+
+	async fn init(
+		&self,
+		ctx: &WorkerContext,
+		data: &mut Option<Self::Data>,
+	) -> Result<JobInitOutput<Self::RunMetadata, Self::Step>, JobError> {
+		let init = self;
+		let Library { db, .. } = &*ctx.library;
+
+		let location_id = init.location.id;
+
+		let location_path =
+			maybe_missing(&init.location.path, "location.path").map(PathBuf::from)?;
+
+		let maybe_sub_iso_file_path = match &init.sub_path {
+			Some(sub_path) if sub_path != Path::new("") => {
+				let full_path = ensure_sub_path_is_in_location(&location_path, sub_path)
+					.await
+					.map_err(ValidatorError::from)?;
+				ensure_sub_path_is_directory(&location_path, sub_path)
+					.await
+					.map_err(ValidatorError::from)?;
+
+				let sub_iso_file_path =
+					IsolatedFilePathData::new(location_id, &location_path, &full_path, true)
+						.map_err(ValidatorError::from)?;
+
+				ensure_file_path_exists(
+					sub_path,
+					&sub_iso_file_path,
+					db,
+					ValidatorError::SubPathNotFound,
+				)
+				.await?;
+
+				Some(sub_iso_file_path)
+			}
+			_ => None,
+		};
+
+		let file_path_ids = db
+			.file_path()
+			.find_many(sd_utils::chain_optional_iter(
+				[
+					file_path::location_id::equals(Some(location_id)),
+					file_path::is_dir::equals(Some(false)),
+				],
+				[maybe_sub_iso_file_path.and_then(|iso_sub_path| {
+					iso_sub_path
+						.materialized_path_for_children()
+						.map(file_path::materialized_path::starts_with)
+				})],
+			))
+			.select(file_path::select!({ id }))
+			.exec()
+			.await?
+			.into_iter()
+			.map(|file_path| file_path.id)
+			.collect::<Vec<_>>();
+
+		let task_count = file_path_ids.len();
+
+		let steps = file_path_ids
+			.chunks(BATCH_SIZE)
+			.map(<[_]>::to_vec)
+			.collect::<Vec<_>>();
+
+		ctx.progress(vec![
+			JobReportUpdate::TaskCount(task_count),
+			JobReportUpdate::Message(format!("Verifying the integrity of {task_count} files")),
+		]);
+
+		*data = Some(OldIntegrityVerifierJobData {
+			location_path,
+			task_count,
+		});
+
+		Ok((OldIntegrityVerifierMetadata::default(), steps).into())
+	}
+
+	async fn execute_step(
+		&self,
+		ctx: &WorkerContext,
+		CurrentStep { step, step_number }: CurrentStep<'_, Self::Step>,
+		data: &Self::Data,
+		_: &Self::RunMetadata,
+	) -> Result<JobStepOutput<Self::Step, Self::RunMetadata>, JobError> {
+		let Library { db, .. } = &*ctx.library;
+
+		let file_paths = db
+			.file_path()
+			.find_many(vec![file_path::id::in_vec(step.clone())])
+			.select(file_path_for_integrity_check::select())
+			.exec()
+			.await?;
+
+		let mut run_metadata = OldIntegrityVerifierMetadata::default();
+		let mut errors = vec![];
+
+		for file_path in &file_paths {
+			match verify_file_integrity(&ctx.library, &data.location_path, file_path).await {
+				Ok(outcome) => {
+					if let IntegrityOutcome::Corrupted { .. } = outcome {
+						warn!(
+							"File path <id='{}'> doesn't match its checksum anymore",
+							file_path.id
+						);
+					}
+					run_metadata.record(outcome);
+				}
+				Err(e) => {
+					error!(
+						"Failed to verify integrity of file path <id='{}'>: {e:#?}",
+						file_path.id
+					);
+					errors.push(e.to_string());
+				}
+			}
+		}
+
+		ctx.progress(vec![JobReportUpdate::CompletedTaskCount(
+			step_number * BATCH_SIZE + step.len(),
+		)]);
+
+		Ok((run_metadata, JobRunErrors(errors)).into())
+	}
+
+	async fn finalize(
+		&self,
+		ctx: &WorkerContext,
+		data: &Option<Self::Data>,
+		run_metadata: &Self::RunMetadata,
+	) -> JobResult {
+		let init = self;
+		let data = data
+			.as_ref()
+			.expect("critical error: missing data on job state");
+
+		info!(
+			"finalizing integrity verifier job at {}{}: {} tasks, {:?}",
+			data.location_path.display(),
+			init.sub_path
+				.as_ref()
+				.map(|p| format!("{}", p.display()))
+				.unwrap_or_default(),
+			data.task_count,
+			run_metadata,
+		);
+
+		// Only a verification of the whole location counts for its schedule
+		if init.sub_path.is_none() {
+			ctx.library
+				.db
+				.location()
+				.update(
+					location::id::equals(init.location.id),
+					vec![location::date_last_integrity_check::set(Some(
+						Utc::now().into(),
+					))],
+				)
+				.exec()
+				.await?;
+		}
+
+		if run_metadata.newly_corrupted > 0 {
+			ctx.library
+				.emit_notification(
+					NotificationData::new(
+						"Possibly corrupted files found",
+						format!(
+							"{} files in location \"{}\" don't match their checksum anymore although they weren't modified",
+							run_metadata.newly_corrupted,
+							init.location.name.as_deref().unwrap_or_default(),
+						),
+						NotificationKind::Warning,
+					)
+					.with_category(NotificationCategory::Integrity)
+					.with_group(format!("integrity:{}", init.location.id)),
+					None,
+				)
+				.await;
+		}
+
+		invalidate_query!(ctx.library, "integrity.report");
+
+		Ok(Some(json!({ "init": init, "run_metadata": run_metadata })))
+	}
+}
@@ -1,7 +1,8 @@
 use crate::{
 	location::{indexer::IndexerError, LocationError},
 	object::{
-		fs::error::FileSystemJobsError, media::old_media_processor::MediaProcessorError,
+		content::ContentIndexerError, duplicates::DuplicatesError, fs::error::FileSystemJobsError,
+		media::old_media_processor::MediaProcessorError,
 		old_file_identifier::FileIdentifierJobError, validation::ValidatorError,
 	},
 };
@@ -65,6 +66,10 @@ pub enum JobError {
 	#[error(transparent)]
 	Validator(#[from] ValidatorError),
 	#[error(transparent)]
+	ContentIndexer(#[from] ContentIndexerError),
+	#[error(transparent)]
+	Duplicates(#[from] DuplicatesError),
+	#[error(transparent)]
 	FileSystemJobsError(#[from] FileSystemJobsError),
 	// #[error(transparent)]
 	// CryptoError(#[from] CryptoError),
@@ -1,14 +1,19 @@
 use crate::{
-	library::Library,
+	library::{old_sync_compactor_job::OldSyncCompactorJobInit, Library},
 	location::indexer::old_indexer_job::OldIndexerJobInit,
 	object::{
+		content::old_content_indexer_job::OldContentIndexerJobInit,
+		duplicates::old_duplicate_finder_job::OldDuplicateFinderJobInit,
 		fs::{
 			old_copy::OldFileCopierJobInit, old_cut::OldFileCutterJobInit,
 			old_delete::OldFileDeleterJobInit, old_erase::OldFileEraserJobInit,
 		},
 		media::old_media_processor::OldMediaProcessorJobInit,
 		old_file_identifier::old_file_identifier_job::OldFileIdentifierJobInit,
-		validation::old_validator_job::OldObjectValidatorJobInit,
+		validation::{
+			old_integrity_verifier_job::OldIntegrityVerifierJobInit,
+			old_validator_job::OldObjectValidatorJobInit,
+		},
 	},
 	old_job::{worker::Worker, DynJob, Job, JobError},
 	Node,
@@ -400,6 +405,10 @@ fn initialize_resumable_job(
 			OldIndexerJobInit,
 			OldFileIdentifierJobInit,
 			OldObjectValidatorJobInit,
+			OldContentIndexerJobInit,
+			OldDuplicateFinderJobInit,
+			OldIntegrityVerifierJobInit,
+			OldSyncCompactorJobInit,
 			OldFileCutterJobInit,
 			OldFileCopierJobInit,
 			OldFileDeleterJobInit,
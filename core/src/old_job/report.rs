@@ -275,8 +275,10 @@ impl JobStatus {
 		matches!(
 			self,
 			Self::Completed
-				| Self::Canceled | Self::Paused
-				| Self::Failed | Self::CompletedWithErrors
+				| Self::Canceled
+				| Self::Paused
+				| Self::Failed
+				| Self::CompletedWithErrors
 		)
 	}
 }
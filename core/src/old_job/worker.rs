@@ -1,4 +1,14 @@
-use crate::{api::CoreEvent, invalidate_query, library::Library, Node};
+use crate::{
+	api::{
+		notifications::{
+			NotificationAction, NotificationCategory, NotificationData, NotificationKind,
+		},
+		CoreEvent,
+	},
+	invalidate_query,
+	library::Library,
+	Node,
+};
 
 use std::{
 	fmt,
@@ -547,6 +557,7 @@ impl Worker {
 					report.id, report.name
 				);
 				report.status = JobStatus::CompletedWithErrors;
+				notify_job_failure(library, report, job.hash(), errors_summary(&errors)).await;
 				report.errors_text = errors;
 				report.data = None;
 				report.metadata = match (report.metadata.take(), metadata) {
@@ -631,6 +642,7 @@ impl Worker {
 
 				report.status = JobStatus::Failed;
 				report.data = None;
+				notify_job_failure(library, report, job.hash(), format!("Failed: {e}")).await;
 				if let Err(e) = report.update(library).await {
 					error!("failed to update job report: {:#?}", e);
 				}
@@ -652,6 +664,47 @@ struct JobWorkTable {
 	report: JobReport,
 }
 
+/// Errors listed in the notification of a job that completed with errors, the others are counted
+const NOTIFIED_ERRORS: usize = 3;
+
+fn errors_summary(errors: &[String]) -> String {
+	let mut summary = format!(
+		"Completed with {} errors: {}",
+		errors.len(),
+		errors[..errors.len().min(NOTIFIED_ERRORS)].join("; ")
+	);
+
+	if errors.len() > NOTIFIED_ERRORS {
+		summary.push_str(&format!(" and {} more", errors.len() - NOTIFIED_ERRORS));
+	}
+
+	summary
+}
+
+/// Notifies the user of a job that failed or completed with errors. A job that keeps failing with
+/// the same arguments, eg. on the same location, replaces its previous notification, which is
+/// found from the job's hash.
+async fn notify_job_failure(library: &Library, report: &JobReport, hash: u64, content: String) {
+	library
+		.emit_notification(
+			NotificationData::new(
+				format!("Job \"{}\" didn't complete successfully", report.name),
+				content,
+				NotificationKind::Error,
+			)
+			.with_category(NotificationCategory::Job)
+			.with_group(format!("job:{}:{hash:x}", report.name))
+			.with_action(NotificationAction {
+				label: "Clear job".to_string(),
+				procedure: "jobs.clear".to_string(),
+				input: json!(report.id),
+				dismiss: true,
+			}),
+			None,
+		)
+		.await;
+}
+
 fn invalidate_queries(library: &Library) {
 	invalidate_query!(library, "jobs.isActive");
 	invalidate_query!(library, "jobs.reports");
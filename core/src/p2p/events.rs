@@ -61,6 +61,11 @@ pub enum P2PEvent {
 	SpacedropRejected {
 		id: Uuid,
 	},
+	// A peer was paired with this node and is now trusted
+	PeerPaired {
+		identity: RemoteIdentity,
+		name: String,
+	},
 }
 
 /// A P2P hook which listens for events and sends them over a channel which can be connected to the frontend.
@@ -4,8 +4,11 @@ use crate::{
 		get_hardware_model_name, HardwareModel,
 	},
 	p2p::{
-		libraries::libraries_hook, operations, sync::SyncMessage, Header, OperatingSystem,
-		SPACEDRIVE_APP_ID,
+		libraries::libraries_hook,
+		operations,
+		operations::pairing::{trust_instance_peer, PairingSession},
+		sync::SyncMessage,
+		Header, OperatingSystem, PEER_IDENTITY_KEY, SPACEDRIVE_APP_ID,
 	},
 	Node,
 };
@@ -18,6 +21,8 @@ use sd_p2p::{
 	UnicastStream, P2P,
 };
 use sd_p2p_tunnel::Tunnel;
+use sd_prisma::prisma::instance;
+use sd_utils::from_bytes_to_uuid;
 use serde::Serialize;
 use serde_json::json;
 use specta::Type;
@@ -29,7 +34,7 @@ use std::{
 	time::Duration,
 };
 use tower_service::Service;
-use tracing::error;
+use tracing::{error, warn};
 
 use tokio::sync::oneshot;
 use tracing::info;
@@ -46,6 +51,7 @@ pub struct P2PManager {
 	pub(crate) events: P2PEvents,
 	pub(super) spacedrop_pairing_reqs: Arc<Mutex<HashMap<Uuid, oneshot::Sender<Option<String>>>>>,
 	pub(super) spacedrop_cancellations: Arc<Mutex<HashMap<Uuid, Arc<AtomicBool>>>>,
+	pub(super) pairing_session: Mutex<Option<PairingSession>>,
 	pub(crate) node_config: Arc<config::Manager>,
 	pub libraries_hook_id: HookId,
 }
@@ -73,6 +79,7 @@ impl P2PManager {
 			events: P2PEvents::spawn(p2p.clone(), libraries_hook_id),
 			spacedrop_pairing_reqs: Default::default(),
 			spacedrop_cancellations: Default::default(),
+			pairing_session: Default::default(),
 			node_config,
 			libraries_hook_id,
 		});
@@ -125,8 +132,15 @@ impl P2PManager {
 		}))
 	}
 
+	/// The metadata of this node's library instances, along with its identity so the nodes of
+	/// the other instances trust it when it joins a library
 	pub fn peer_metadata(&self) -> HashMap<String, String> {
-		self.p2p.metadata().clone()
+		let mut metadata = self.p2p.metadata().clone();
+		metadata.insert(
+			PEER_IDENTITY_KEY.to_string(),
+			self.p2p.remote_identity().to_string(),
+		);
+		metadata
 	}
 
 	// TODO: Remove this and add a subscription system to `config::Manager`
@@ -290,28 +304,31 @@ async fn start(
 				return;
 			};
 
+			// Unpaired peers can only ping us, pair with us or send us Spacedrops. The nodes of our
+			// library instances are trusted when they join the library or when it loads, and the
+			// others must prove they are an instance of the library before syncing, below
+			let peer = stream.remote_identity();
+			let is_trusted = this.is_trusted(&peer).await;
+			if !matches!(
+				header,
+				Header::Ping | Header::Pair | Header::Spacedrop(_) | Header::Sync(_)
+			) && !is_trusted
+			{
+				warn!("Rejecting {header:?} from untrusted peer '{peer}'");
+				return;
+			}
+
 			match header {
 				Header::Ping => operations::ping::receiver(stream).await,
 				Header::Spacedrop(req) => {
-					let Err(()) = operations::spacedrop::receiver(&this, req, stream).await else {
+					let Err(()) = operations::spacedrop::receiver(&this, &node, req, stream).await
+					else {
 						return;
 					};
 
 					error!("Failed to handle Spacedrop request");
 				}
 				Header::Sync(library_id) => {
-					let Ok(mut tunnel) = Tunnel::responder(stream).await.map_err(|err| {
-						error!("Failed `Tunnel::responder`: {}", err);
-					}) else {
-						return;
-					};
-
-					let Ok(msg) = SyncMessage::from_stream(&mut tunnel).await.map_err(|err| {
-						error!("Failed `SyncMessage::from_stream`: {}", err);
-					}) else {
-						return;
-					};
-
 					let Ok(library) =
 						node.libraries
 							.get_library(&library_id)
@@ -325,9 +342,73 @@ async fn start(
 						return;
 					};
 
+					let Ok(mut tunnel) = Tunnel::responder(stream, &library.identity)
+						.await
+						.map_err(|err| {
+							error!("Failed `Tunnel::responder`: {}", err);
+						})
+					else {
+						return;
+					};
+
+					// The tunnel authenticated the remote, but it must also be an instance of this library
+					let remote_identity = tunnel.remote_identity();
+					let Ok(Some(remote_instance)) = library
+						.db
+						.instance()
+						.find_first(vec![instance::remote_identity::equals(
+							remote_identity.get_bytes().to_vec(),
+						)])
+						.select(instance::select!({ pub_id metadata }))
+						.exec()
+						.await
+						.map_err(|err| {
+							error!("Failed to check instance '{remote_identity}': {err:?}");
+						})
+					else {
+						error!(
+							"Rejecting sync from '{remote_identity}' which isn't an instance of library '{library_id}'"
+						);
+						return;
+					};
+
+					if !is_trusted {
+						let metadata: HashMap<String, String> = remote_instance
+							.metadata
+							.as_deref()
+							.and_then(|metadata| serde_json::from_slice(metadata).ok())
+							.unwrap_or_default();
+
+						let Some(name) = trust_instance_peer(&peer, &metadata) else {
+							warn!(
+								"Rejecting sync from untrusted peer '{peer}' which isn't the node of instance '{remote_identity}'"
+							);
+							return;
+						};
+
+						if let Err(err) = this.trust_library_peer(peer, name).await {
+							error!(
+								"Failed to trust the node of instance '{remote_identity}': {err:?}"
+							);
+							return;
+						}
+					}
+
+					let Ok(msg) = SyncMessage::from_stream(&mut tunnel).await.map_err(|err| {
+						error!("Failed `SyncMessage::from_stream`: {}", err);
+					}) else {
+						return;
+					};
+
 					match msg {
 						SyncMessage::NewOperations => {
-							let Err(()) = super::sync::responder(&mut tunnel, library).await else {
+							let Err(()) = super::sync::responder(
+								&mut tunnel,
+								library,
+								from_bytes_to_uuid(&remote_instance.pub_id),
+							)
+							.await
+							else {
 								return;
 							};
 
@@ -343,6 +424,14 @@ async fn start(
 
 					error!("Failed to handling rspc request with '{remote}': {err:?}");
 				}
+				Header::Pair => {
+					let remote = stream.remote_identity();
+					let Err(err) = operations::pairing::receiver(&this, stream).await else {
+						return;
+					};
+
+					error!("Failed to pair with '{remote}': {err:?}");
+				}
 			};
 		});
 	}
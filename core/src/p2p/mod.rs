@@ -15,3 +15,5 @@ pub use metadata::*;
 pub use protocol::*;
 
 pub(super) const SPACEDRIVE_APP_ID: &str = "sd";
+/// Key of the node's identity in the metadata of its library instances, see [`P2PManager::peer_metadata`]
+pub(crate) const PEER_IDENTITY_KEY: &str = "identity";
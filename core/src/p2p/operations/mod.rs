@@ -1,6 +1,8 @@
+pub mod pairing;
 pub mod ping;
 pub mod rspc;
 pub mod spacedrop;
+mod spacedrop_manifest;
 
 pub use rspc::remote_rspc;
 pub use spacedrop::spacedrop;
@@ -0,0 +1,583 @@
+//! Pairing makes two nodes trust each other, which is required before they can make rspc requests
+//! to or sync with one another. The nodes of library instances are trusted when they join the
+//! library instead. One node shows a short code (or a QR code of it) which is entered on
+//! the other one. Both then run SPAKE2 with the code and confirm they derived the same key, so
+//! what's sent over the connection can't be used to guess the code offline and a peer guessing
+//! codes only gets a few attempts.
+
+use std::{
+	collections::HashMap,
+	io,
+	str::FromStr,
+	sync::{Arc, PoisonError},
+	time::Duration,
+};
+
+use crate::{
+	node::config::{NodeConfigError, TrustedPeer},
+	p2p::{Header, P2PEvent, P2PManager, PEER_IDENTITY_KEY},
+};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sd_p2p::{RemoteIdentity, UnicastStream};
+use sd_p2p_proto::{decode, encode};
+use serde::Serialize;
+use spake2::{Ed25519Group, Password, Spake2};
+use specta::Type;
+use thiserror::Error;
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	time::Instant,
+};
+use tracing::{debug, info, warn};
+
+/// How long a pairing code can be used for after it was shown
+const PAIRING_CODE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// How many wrong codes are accepted before the pairing code is discarded
+const MAX_PAIRING_ATTEMPTS: u8 = 3;
+/// Number of digits in a pairing code
+const PAIRING_CODE_LEN: usize = 6;
+
+/// Context for deriving the key used to confirm both peers know the pairing code
+const PAIRING_KEY_CONTEXT: &str = "Spacedrive 2024-04-01 sd-p2p pairing code";
+/// Length of a SPAKE2 message over the Ed25519 group
+const SPAKE2_MESSAGE_LEN: usize = 33;
+
+// Responses of the node showing the code to a pairing request
+const PAIRING_REJECTED: u8 = 0;
+const PAIRING_ACCEPTED: u8 = 1;
+
+#[derive(Debug, Error)]
+pub enum PairingError {
+	#[error("peer '{0}' not found")]
+	PeerNotFound(RemoteIdentity),
+	#[error("failed to connect to peer: {0}")]
+	Connect(String),
+	#[error("io error while pairing: {0}")]
+	Io(#[from] io::Error),
+	#[error("error decoding pairing message: {0}")]
+	Decode(#[from] decode::Error),
+	#[error("the pairing code is invalid or expired")]
+	Rejected,
+	#[error("the peer couldn't prove it knows the pairing code")]
+	InvalidProof,
+	#[error(transparent)]
+	NodeConfig(#[from] NodeConfigError),
+}
+
+impl From<PairingError> for rspc::Error {
+	fn from(err: PairingError) -> Self {
+		use rspc::ErrorCode;
+
+		match err {
+			PairingError::PeerNotFound(_) => {
+				Self::with_cause(ErrorCode::NotFound, err.to_string(), err)
+			}
+			PairingError::Rejected | PairingError::InvalidProof => {
+				Self::with_cause(ErrorCode::BadRequest, err.to_string(), err)
+			}
+			_ => Self::with_cause(ErrorCode::InternalServerError, err.to_string(), err),
+		}
+	}
+}
+
+/// A pairing code shown to the user, to be entered on the other node
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct PairingCode {
+	pub code: String,
+	/// Contents of the QR code, so the other node can be paired with by scanning it
+	pub qr: String,
+	/// Short fingerprint of this node's identity, for the user to check on the other node
+	pub fingerprint: String,
+	pub expires_at: DateTime<Utc>,
+}
+
+/// The pairing code this node is currently showing
+#[derive(Debug)]
+pub(crate) struct PairingSession {
+	code: String,
+	expires: Instant,
+	attempts: u8,
+}
+
+impl PairingSession {
+	fn new(code: String) -> Self {
+		Self {
+			code,
+			expires: Instant::now() + PAIRING_CODE_TIMEOUT,
+			attempts: 0,
+		}
+	}
+
+	/// The code of `session`, discarding it if it expired
+	fn code(session: &mut Option<Self>) -> Option<String> {
+		if session
+			.as_ref()
+			.is_some_and(|session| session.expires < Instant::now())
+		{
+			session.take();
+		}
+
+		session.as_ref().map(|session| session.code.clone())
+	}
+
+	/// Takes the code of `session` if `check` accepts it, otherwise counts a failed attempt
+	fn check(session: &mut Option<Self>, check: impl FnOnce(&str) -> bool) -> Option<String> {
+		let current = session.as_mut()?;
+		if current.expires < Instant::now() {
+			session.take();
+			return None;
+		}
+
+		if check(&current.code) {
+			return session.take().map(|session| session.code);
+		}
+
+		current.attempts += 1;
+		if current.attempts >= MAX_PAIRING_ATTEMPTS {
+			warn!("Too many invalid pairing attempts, discarding the pairing code");
+			session.take();
+		}
+
+		None
+	}
+}
+
+/// Short human-readable fingerprint of an identity, eg. `8f3a-91c2-07de-b455`
+pub fn fingerprint(identity: &RemoteIdentity) -> String {
+	blake3::hash(&identity.get_bytes()).as_bytes()[..8]
+		.chunks(2)
+		.map(|chunk| format!("{:02x}{:02x}", chunk[0], chunk[1]))
+		.collect::<Vec<_>>()
+		.join("-")
+}
+
+fn spake2_identity(identity: &RemoteIdentity) -> spake2::Identity {
+	spake2::Identity::new(&identity.get_bytes())
+}
+
+/// Proves the SPAKE2 exchange derived the same key on both sides, which only happens if both used
+/// the same pairing code
+fn confirmation(key: &[u8], role: &[u8]) -> blake3::Hash {
+	blake3::keyed_hash(&blake3::derive_key(PAIRING_KEY_CONTEXT, key), role)
+}
+
+/// The key the node showing the code derived, if the initiator proved it derived the same one
+fn responder_key(
+	spake: Spake2<Ed25519Group>,
+	initiator_message: &[u8],
+	initiator_confirmation: blake3::Hash,
+) -> Option<Vec<u8>> {
+	spake
+		.finish(initiator_message)
+		.ok()
+		.filter(|key| initiator_confirmation == confirmation(key, b"initiator"))
+}
+
+async fn read_spake2_message(
+	stream: &mut UnicastStream,
+) -> Result<[u8; SPAKE2_MESSAGE_LEN], io::Error> {
+	let mut message = [0; SPAKE2_MESSAGE_LEN];
+	stream.read_exact(&mut message).await?;
+	Ok(message)
+}
+
+async fn read_confirmation(stream: &mut UnicastStream) -> Result<blake3::Hash, io::Error> {
+	let mut confirmation = [0; blake3::OUT_LEN];
+	stream.read_exact(&mut confirmation).await?;
+	Ok(blake3::Hash::from(confirmation))
+}
+
+impl P2PManager {
+	/// Shows a new pairing code, replacing any previous one
+	pub fn start_pairing(&self) -> PairingCode {
+		let code = (0..PAIRING_CODE_LEN)
+			.map(|_| char::from(b'0' + rand::thread_rng().gen_range(0..10)))
+			.collect::<String>();
+		let identity = self.p2p.remote_identity();
+
+		*self
+			.pairing_session
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = Some(PairingSession::new(code.clone()));
+
+		PairingCode {
+			qr: format!("spacedrive://pair?identity={identity}&code={code}"),
+			fingerprint: fingerprint(&identity),
+			expires_at: Utc::now()
+				+ chrono::Duration::from_std(PAIRING_CODE_TIMEOUT).expect("valid duration"),
+			code,
+		}
+	}
+
+	pub fn stop_pairing(&self) {
+		self.pairing_session
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.take();
+	}
+
+	/// Pairs with a peer showing `code`
+	pub async fn pair(
+		&self,
+		identity: RemoteIdentity,
+		code: String,
+	) -> Result<TrustedPeer, PairingError> {
+		let peer = self
+			.p2p
+			.peers()
+			.get(&identity)
+			.cloned()
+			.ok_or(PairingError::PeerNotFound(identity))?;
+		let mut stream = peer
+			.new_stream()
+			.await
+			.map_err(|err| PairingError::Connect(format!("{err:?}")))?;
+		let self_identity = self.p2p.remote_identity();
+
+		let (spake, message) = Spake2::<Ed25519Group>::start_a(
+			&Password::new(code.as_bytes()),
+			&spake2_identity(&self_identity),
+			&spake2_identity(&identity),
+		);
+
+		let mut buf = Header::Pair.to_bytes();
+		encode::string(&mut buf, &self.node_config.get().await.name);
+		buf.extend_from_slice(&message);
+		stream.write_all(&buf).await?;
+		stream.flush().await?;
+
+		if stream.read_u8().await? != PAIRING_ACCEPTED {
+			return Err(PairingError::Rejected);
+		}
+
+		let key = spake
+			.finish(&read_spake2_message(&mut stream).await?)
+			.map_err(|_| PairingError::InvalidProof)?;
+		stream
+			.write_all(confirmation(&key, b"initiator").as_bytes())
+			.await?;
+		stream.flush().await?;
+
+		if stream.read_u8().await? != PAIRING_ACCEPTED {
+			return Err(PairingError::Rejected);
+		}
+
+		let name = decode::string(&mut stream).await?;
+		if read_confirmation(&mut stream).await? != confirmation(&key, b"responder") {
+			return Err(PairingError::InvalidProof);
+		}
+
+		self.trust_peer(identity, name).await
+	}
+
+	pub async fn trusted_peers(&self) -> Vec<TrustedPeer> {
+		self.node_config.get().await.trusted_peers
+	}
+
+	pub async fn is_trusted(&self, identity: &RemoteIdentity) -> bool {
+		self.node_config
+			.get()
+			.await
+			.trusted_peers
+			.iter()
+			.any(|peer| peer.identity == *identity)
+	}
+
+	pub async fn untrust_peer(&self, identity: RemoteIdentity) -> Result<(), NodeConfigError> {
+		self.node_config
+			.write(|config| {
+				config
+					.trusted_peers
+					.retain(|peer| peer.identity != identity)
+			})
+			.await
+			.map(|_| ())
+	}
+
+	/// Trusts the node of an instance which joined one of our libraries, the ones already trusted
+	/// are kept as they are
+	pub async fn trust_library_peer(
+		&self,
+		identity: RemoteIdentity,
+		name: String,
+	) -> Result<(), NodeConfigError> {
+		if identity == self.p2p.remote_identity() || self.is_trusted(&identity).await {
+			return Ok(());
+		}
+
+		self.node_config
+			.write(|config| {
+				if !config.trusted_peers.iter().any(|p| p.identity == identity) {
+					config.trusted_peers.push(TrustedPeer {
+						identity,
+						name: name.clone(),
+						paired_at: Utc::now(),
+					});
+				}
+			})
+			.await?;
+
+		info!("Trusting peer '{identity}' of a library instance");
+		self.events
+			.send(P2PEvent::PeerPaired { identity, name })
+			.ok();
+
+		Ok(())
+	}
+
+	async fn trust_peer(
+		&self,
+		identity: RemoteIdentity,
+		name: String,
+	) -> Result<TrustedPeer, PairingError> {
+		let peer = TrustedPeer {
+			identity,
+			name,
+			paired_at: Utc::now(),
+		};
+
+		self.node_config
+			.write(|config| {
+				config.trusted_peers.retain(|p| p.identity != identity);
+				config.trusted_peers.push(peer.clone());
+			})
+			.await?;
+
+		info!("Paired with peer '{identity}'");
+		self.events
+			.send(P2PEvent::PeerPaired {
+				identity,
+				name: peer.name.clone(),
+			})
+			.ok();
+
+		Ok(peer)
+	}
+
+	/// The pairing code currently shown, discarding it if it expired
+	fn pairing_code(&self) -> Option<String> {
+		PairingSession::code(
+			&mut self
+				.pairing_session
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner),
+		)
+	}
+
+	/// Takes the pairing code if `check` accepts it, otherwise counts a failed attempt
+	fn check_pairing_code(&self, check: impl FnOnce(&str) -> bool) -> Option<String> {
+		PairingSession::check(
+			&mut self
+				.pairing_session
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner),
+			check,
+		)
+	}
+}
+
+/// The node of a library instance, from the identity it publishes in the instance's metadata
+pub(crate) fn instance_node(
+	metadata: &HashMap<String, String>,
+) -> Option<(RemoteIdentity, String)> {
+	let identity = RemoteIdentity::from_str(metadata.get(PEER_IDENTITY_KEY)?).ok()?;
+
+	Some((identity, metadata.get("name").cloned().unwrap_or_default()))
+}
+
+/// Whether a peer which isn't trusted, but which the sync tunnel authenticated as an instance of one
+/// of our libraries, is trusted from now on with the returned name
+///
+/// Instances which joined a library before nodes published their identity in the instance's
+/// metadata can only be told apart by their instance identity, so their node is trusted the first
+/// time it proves it holds it.
+pub(crate) fn trust_instance_peer(
+	peer: &RemoteIdentity,
+	metadata: &HashMap<String, String>,
+) -> Option<String> {
+	match instance_node(metadata) {
+		Some((identity, name)) => (identity == *peer).then_some(name),
+		None => Some(metadata.get("name").cloned().unwrap_or_default()),
+	}
+}
+
+async fn reject(stream: &mut UnicastStream, identity: RemoteIdentity) -> Result<(), PairingError> {
+	debug!("Rejecting pairing request from '{identity}'");
+	stream.write_u8(PAIRING_REJECTED).await?;
+	stream.flush().await?;
+	Err(PairingError::Rejected)
+}
+
+pub(crate) async fn receiver(
+	this: &Arc<P2PManager>,
+	mut stream: UnicastStream,
+) -> Result<(), PairingError> {
+	let identity = stream.remote_identity();
+	let self_identity = this.p2p.remote_identity();
+
+	let name = decode::string(&mut stream).await?;
+	let initiator_message = read_spake2_message(&mut stream).await?;
+
+	let Some(code) = this.pairing_code() else {
+		return reject(&mut stream, identity).await;
+	};
+
+	let (spake, message) = Spake2::<Ed25519Group>::start_b(
+		&Password::new(code.as_bytes()),
+		&spake2_identity(&identity),
+		&spake2_identity(&self_identity),
+	);
+
+	let mut buf = vec![PAIRING_ACCEPTED];
+	buf.extend_from_slice(&message);
+	stream.write_all(&buf).await?;
+	stream.flush().await?;
+
+	let initiator_confirmation = read_confirmation(&mut stream).await?;
+	let key = responder_key(spake, &initiator_message, initiator_confirmation);
+
+	// Each wrong confirmation only rules out a single code, so it counts as an attempt
+	let Some(key) = this
+		.check_pairing_code(|current| current == code && key.is_some())
+		.and(key)
+	else {
+		return reject(&mut stream, identity).await;
+	};
+
+	let mut buf = vec![PAIRING_ACCEPTED];
+	encode::string(&mut buf, &this.node_config.get().await.name);
+	buf.extend_from_slice(confirmation(&key, b"responder").as_bytes());
+	stream.write_all(&buf).await?;
+	stream.flush().await?;
+
+	this.trust_peer(identity, name).await.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+	use sd_p2p::Identity;
+
+	use super::*;
+
+	/// Runs SPAKE2 between an initiator entering `entered` and a responder showing `shown`
+	fn exchange(entered: &str, shown: &str) -> Option<Vec<u8>> {
+		let initiator = Identity::new().to_remote_identity();
+		let responder = Identity::new().to_remote_identity();
+
+		let (initiator_spake, initiator_message) = Spake2::<Ed25519Group>::start_a(
+			&Password::new(entered.as_bytes()),
+			&spake2_identity(&initiator),
+			&spake2_identity(&responder),
+		);
+		let (responder_spake, responder_message) = Spake2::<Ed25519Group>::start_b(
+			&Password::new(shown.as_bytes()),
+			&spake2_identity(&initiator),
+			&spake2_identity(&responder),
+		);
+
+		let initiator_key = initiator_spake.finish(&responder_message).unwrap();
+
+		responder_key(
+			responder_spake,
+			&initiator_message,
+			confirmation(&initiator_key, b"initiator"),
+		)
+	}
+
+	fn instance_metadata(identity: Option<&RemoteIdentity>) -> HashMap<String, String> {
+		let mut metadata = HashMap::from([("name".to_string(), "Laptop".to_string())]);
+		if let Some(identity) = identity {
+			metadata.insert(PEER_IDENTITY_KEY.to_string(), identity.to_string());
+		}
+		metadata
+	}
+
+	#[test]
+	fn existing_library_peers_can_sync_after_upgrade() {
+		let node = Identity::new().to_remote_identity();
+
+		// Instances which joined before nodes published their identity, while the upgraded node
+		// config doesn't trust any peer yet
+		assert_eq!(
+			trust_instance_peer(&node, &instance_metadata(None)).as_deref(),
+			Some("Laptop")
+		);
+
+		// The nodes of the other instances are trusted when the library loads
+		let metadata = instance_metadata(Some(&node));
+		assert_eq!(instance_node(&metadata), Some((node, "Laptop".to_string())));
+		assert_eq!(
+			trust_instance_peer(&node, &metadata).as_deref(),
+			Some("Laptop")
+		);
+		assert_eq!(instance_node(&instance_metadata(None)), None);
+	}
+
+	#[test]
+	fn instance_of_another_node_is_rejected() {
+		let node = Identity::new().to_remote_identity();
+		let other_node = Identity::new().to_remote_identity();
+
+		assert_eq!(
+			trust_instance_peer(&other_node, &instance_metadata(Some(&node))),
+			None
+		);
+	}
+
+	#[test]
+	fn wrong_code_is_rejected() {
+		assert!(exchange("123456", "123456").is_some());
+		assert!(exchange("123457", "123456").is_none());
+
+		let mut session = Some(PairingSession::new("123456".into()));
+		assert_eq!(PairingSession::check(&mut session, |_| false), None);
+		assert_eq!(
+			PairingSession::code(&mut session).as_deref(),
+			Some("123456")
+		);
+		assert_eq!(
+			PairingSession::check(&mut session, |code| code == "123456").as_deref(),
+			Some("123456")
+		);
+		// A code can only be used once
+		assert!(session.is_none());
+	}
+
+	#[test]
+	fn code_is_discarded_after_too_many_attempts() {
+		let mut session = Some(PairingSession::new("123456".into()));
+
+		for _ in 0..MAX_PAIRING_ATTEMPTS {
+			assert_eq!(PairingSession::check(&mut session, |_| false), None);
+		}
+
+		assert!(session.is_none());
+		assert_eq!(
+			PairingSession::check(&mut session, |code| code == "123456"),
+			None
+		);
+	}
+
+	#[test]
+	fn expired_code_is_rejected() {
+		let mut session = Some(PairingSession {
+			expires: Instant::now() - Duration::from_secs(1),
+			..PairingSession::new("123456".into())
+		});
+
+		assert_eq!(
+			PairingSession::check(&mut session, |code| code == "123456"),
+			None
+		);
+		assert!(session.is_none());
+
+		let mut session = Some(PairingSession {
+			expires: Instant::now() - Duration::from_secs(1),
+			..PairingSession::new("123456".into())
+		});
+
+		assert_eq!(PairingSession::code(&mut session), None);
+		assert!(session.is_none());
+	}
+}
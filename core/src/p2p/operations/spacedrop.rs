@@ -1,29 +1,60 @@
 use std::{
-	borrow::Cow,
-	path::PathBuf,
+	collections::hash_map::Entry,
+	io::{self, SeekFrom},
+	path::{Path, PathBuf},
 	sync::{
 		atomic::{AtomicBool, Ordering},
 		Arc, PoisonError,
 	},
-	time::Duration,
+	time::{Duration, SystemTime},
 };
 
-use crate::p2p::{Header, P2PEvent, P2PManager};
-use futures::future::join_all;
+use crate::{
+	api::notifications::{
+		NotificationAction, NotificationCategory, NotificationData, NotificationKind,
+	},
+	object::validation::hash::file_checksum,
+	p2p::{Header, P2PEvent, P2PManager},
+	Node,
+};
+use chrono::Utc;
 use sd_p2p::{RemoteIdentity, UnicastStream};
-use sd_p2p_block::{BlockSize, Range, SpaceblockRequest, SpaceblockRequests, Transfer};
+use sd_p2p_block::{
+	BlockSize, Range, SpaceblockDirectory, SpaceblockRequest, SpaceblockRequests, Transfer,
+};
+use sd_p2p_proto::{decode, encode};
+use serde_json::json;
 use tokio::{
-	fs::{create_dir_all, File},
-	io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+	fs::{self, create_dir_all, metadata, remove_file, rename, File, OpenOptions},
+	io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter},
 	sync::oneshot,
 	time::{sleep, Instant},
 };
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+use super::spacedrop_manifest::{
+	prune_expired_manifests, RelativePaths, SpacedropManifest, SPACEDROP_MANIFESTS_DIR,
+};
+
 /// The amount of time to wait for a Spacedrop request to be accepted or rejected before it's automatically rejected
 pub(crate) const SPACEDROP_TIMEOUT: Duration = Duration::from_secs(60);
 
+// Responses of the receiver to a Spacedrop request
+const SPACEDROP_REJECTED: u8 = 0;
+const SPACEDROP_ACCEPTED: u8 = 1;
+/// Accepted, and files will be sent with delta transfers so existing copies are reused
+const SPACEDROP_ACCEPTED_DELTA: u8 = 2;
+/// An interrupted Spacedrop is resumed, followed by how much of each file the receiver already has
+const SPACEDROP_RESUMED: u8 = 3;
+
+/// How long to wait between attempts to reconnect to the receiver of an interrupted Spacedrop
+const SPACEDROP_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+/// How long to keep trying to resume an interrupted Spacedrop before giving up
+const SPACEDROP_RESUME_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// How many times files failing verification are sent again before giving up
+const SPACEDROP_MAX_VERIFICATION_FAILURES: u32 = 3;
+
 // TODO: Proper error handling
 pub async fn spacedrop(
 	p2p: Arc<P2PManager>,
@@ -34,38 +65,169 @@ pub async fn spacedrop(
 		return Err(());
 	}
 
-	let (files, requests): (Vec<_>, Vec<_>) = join_all(paths.into_iter().map(|path| async move {
-		let file = File::open(&path).await?;
-		let metadata = file.metadata().await?;
-		let name = path
-			.file_name()
-			.map(|v| v.to_string_lossy())
-			.unwrap_or(Cow::Borrowed(""))
-			.to_string();
-
-		Ok((
-			(path, file),
-			SpaceblockRequest {
-				name,
-				size: metadata.len(),
-				range: Range::Full,
-			},
-		))
-	}))
-	.await
-	.into_iter()
-	.collect::<Result<Vec<_>, std::io::Error>>()
-	.map_err(|err| {
-		warn!("error opening file: '{err:?}'");
+	let (files, directories) = walk(paths).await.map_err(|err| {
+		warn!("error reading files to Spacedrop: '{err:?}'");
 		// TODO: Proper error type
-	})?
-	.into_iter()
-	.unzip();
+	})?;
+	let (files, requests): (Vec<_>, Vec<_>) = files.into_iter().unzip();
 
 	let total_length: u64 = requests.iter().map(|req| req.size).sum();
 
 	let id = Uuid::new_v4();
 	debug!("({id}): starting Spacedrop with peer '{identity}");
+	let stream = connect(&p2p, id, identity).await?;
+
+	tokio::spawn(async move {
+		let requests = SpaceblockRequests {
+			id,
+			block_size: BlockSize::from_size(total_length),
+			delta: true,
+			requests,
+			directories,
+		};
+
+		let cancelled = Arc::new(AtomicBool::new(false));
+		p2p.spacedrop_cancellations
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(id, cancelled.clone());
+
+		let mut stream = Some(stream);
+		let mut resuming = false;
+		let mut interrupted_at = Instant::now();
+		let mut acknowledged = 0;
+		let mut verification_failures = 0;
+		loop {
+			let stream = match stream.take() {
+				Some(stream) => stream,
+				None => match connect(&p2p, id, identity).await {
+					Ok(stream) => stream,
+					Err(()) if interrupted_at.elapsed() < SPACEDROP_RESUME_TIMEOUT => {
+						sleep(SPACEDROP_RETRY_INTERVAL).await;
+						continue;
+					}
+					Err(()) => {
+						warn!("({id}): giving up on resuming Spacedrop with '{identity}'");
+						break;
+					}
+				},
+			};
+
+			let previously_acknowledged = acknowledged;
+			match send_attempt(
+				&p2p,
+				&requests,
+				&files,
+				&cancelled,
+				stream,
+				resuming,
+				&mut acknowledged,
+			)
+			.await
+			{
+				Attempt::Done => break,
+				Attempt::Interrupted => {
+					// Only progress restarts the timeout, so a receiver failing the same way every
+					// time is eventually given up on
+					if !resuming || acknowledged > previously_acknowledged {
+						interrupted_at = Instant::now();
+					} else if interrupted_at.elapsed() >= SPACEDROP_RESUME_TIMEOUT {
+						warn!("({id}): giving up on resuming Spacedrop with '{identity}'");
+						break;
+					}
+
+					debug!("({id}): interrupted, will resume once '{identity}' is reachable");
+					resuming = true;
+					sleep(SPACEDROP_RETRY_INTERVAL).await;
+				}
+				Attempt::VerificationFailed => {
+					verification_failures += 1;
+					if verification_failures >= SPACEDROP_MAX_VERIFICATION_FAILURES {
+						error!("({id}): files keep failing verification, giving up");
+						break;
+					}
+
+					warn!("({id}): files failed verification, resending them");
+					resuming = true;
+					interrupted_at = Instant::now();
+				}
+			}
+		}
+
+		p2p.spacedrop_cancellations
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&id);
+	});
+
+	Ok(id)
+}
+
+/// Lists the files and directories to send, recursing into directories so their relative paths
+/// are kept on the receiver. Symlinks and special files inside directories are skipped.
+async fn walk(
+	paths: Vec<PathBuf>,
+) -> Result<(Vec<(PathBuf, SpaceblockRequest)>, Vec<SpaceblockDirectory>), io::Error> {
+	let mut files = Vec::new();
+	let mut directories = Vec::new();
+
+	let mut to_walk = Vec::new();
+	for path in paths {
+		let metadata = metadata(&path).await?;
+		let name = path
+			.file_name()
+			.map(|name| name.to_string_lossy().to_string())
+			.unwrap_or_default();
+		to_walk.push((path, name, metadata));
+	}
+	// Popped from the back, so reversed to keep the order of the paths
+	to_walk.reverse();
+
+	while let Some((path, name, metadata)) = to_walk.pop() {
+		let modified = metadata.modified().ok();
+
+		if metadata.is_file() {
+			files.push((
+				path,
+				SpaceblockRequest {
+					name,
+					size: metadata.len(),
+					modified,
+					range: Range::Full,
+				},
+			));
+			continue;
+		}
+
+		let mut entries = Vec::new();
+		let mut read_dir = fs::read_dir(&path).await?;
+		while let Some(entry) = read_dir.next_entry().await? {
+			let metadata = entry.metadata().await?;
+			if !metadata.is_file() && !metadata.is_dir() {
+				debug!(
+					"skipping '{:?}' as it's not a file or directory",
+					entry.path()
+				);
+				continue;
+			}
+
+			let entry_name = format!("{name}/{}", entry.file_name().to_string_lossy());
+			entries.push((entry.path(), entry_name, metadata));
+		}
+		entries.sort_by(|(a, _, _), (b, _, _)| b.cmp(a));
+		to_walk.extend(entries);
+
+		directories.push(SpaceblockDirectory { name, modified });
+	}
+
+	Ok((files, directories))
+}
+
+async fn connect(
+	p2p: &P2PManager,
+	id: Uuid,
+	identity: RemoteIdentity,
+) -> Result<UnicastStream, ()> {
 	let peer = p2p
 		.p2p
 		.peers()
@@ -76,84 +238,179 @@ pub async fn spacedrop(
 		})?
 		.clone();
 
-	let mut stream = peer.new_stream().await.map_err(|err| {
+	peer.new_stream().await.map_err(|err| {
 		debug!("({id}): failed to connect to '{identity}': {err:?}");
 		// TODO: Proper error
-	})?;
+	})
+}
 
-	tokio::spawn(async move {
-		debug!("({id}): connected, sending header");
-		let header = Header::Spacedrop(SpaceblockRequests {
-			id,
-			block_size: BlockSize::from_size(total_length),
-			requests,
-		});
-		if let Err(err) = stream.write_all(&header.to_bytes()).await {
-			debug!("({id}): failed to send header: {err}");
-			return;
-		}
-		let Header::Spacedrop(requests) = header else {
-			unreachable!();
-		};
+/// The outcome of sending a Spacedrop over one connection
+enum Attempt {
+	/// The Spacedrop completed, or was rejected, timed out or cancelled
+	Done,
+	/// The connection dropped after the receiver accepted, so the transfer can be resumed
+	Interrupted,
+	/// Some files didn't match their checksum once received, they have to be sent again
+	VerificationFailed,
+}
 
-		debug!("({id}): waiting for response");
-		let result = tokio::select! {
-		  result = stream.read_u8() => result,
-		  // Add 5 seconds incase the user responded on the deadline and slow network
-		   _ = sleep(SPACEDROP_TIMEOUT + Duration::from_secs(5)) => {
-				debug!("({id}): timed out, cancelling");
-				p2p.events.send(P2PEvent::SpacedropTimedOut { id }).ok();
-				return;
-			},
-		};
+async fn send_attempt(
+	p2p: &P2PManager,
+	requests: &SpaceblockRequests,
+	files: &[PathBuf],
+	cancelled: &AtomicBool,
+	mut stream: UnicastStream,
+	resuming: bool,
+	acknowledged: &mut u64,
+) -> Attempt {
+	let id = requests.id;
+	// Before the receiver accepted, a dropped connection means the Spacedrop failed
+	let interrupted = if resuming {
+		Attempt::Interrupted
+	} else {
+		Attempt::Done
+	};
 
-		match result {
-			Ok(0) => {
-				debug!("({id}): Spacedrop was rejected from peer '{identity}'");
-				p2p.events.send(P2PEvent::SpacedropRejected { id }).ok();
-				return;
+	debug!("({id}): connected, sending header");
+	let header = Header::Spacedrop(requests.clone());
+	if let Err(err) = stream.write_all(&header.to_bytes()).await {
+		debug!("({id}): failed to send header: {err}");
+		return interrupted;
+	}
+	let Header::Spacedrop(mut requests) = header else {
+		unreachable!();
+	};
+
+	debug!("({id}): waiting for response");
+	let result = tokio::select! {
+	  result = stream.read_u8() => result,
+	  // Add 5 seconds incase the user responded on the deadline and slow network
+	   _ = sleep(SPACEDROP_TIMEOUT + Duration::from_secs(5)) => {
+			debug!("({id}): timed out, cancelling");
+			p2p.events.send(P2PEvent::SpacedropTimedOut { id }).ok();
+			return Attempt::Done;
+		},
+	};
+
+	let delta = match result {
+		Ok(SPACEDROP_REJECTED) => {
+			debug!(
+				"({id}): Spacedrop was rejected from peer '{}'",
+				stream.remote_identity()
+			);
+			p2p.events.send(P2PEvent::SpacedropRejected { id }).ok();
+			return Attempt::Done;
+		}
+		Ok(SPACEDROP_ACCEPTED) => false,
+		Ok(SPACEDROP_ACCEPTED_DELTA) => true,
+		Ok(SPACEDROP_RESUMED) => {
+			// The receiver tells us how much of each file it already has
+			let mut received = 0u64;
+			for req in &mut requests.requests {
+				match stream.read_u64_le().await {
+					Ok(start) => {
+						received = received.saturating_add(start);
+						req.range = Range::Partial(start..req.size);
+					}
+					Err(err) => {
+						debug!("({id}): failed to read resume offsets: {err}");
+						return Attempt::Interrupted;
+					}
+				}
 			}
-			Ok(1) => {}                     // Okay
-			Ok(_) => todo!(),               // TODO: Proper error
-			Err(err) => todo!("{:?}", err), // TODO: Proper error
+			*acknowledged = received;
+			false
+		}
+		Ok(response) => {
+			debug!("({id}): invalid response '{response}' from receiver");
+			return Attempt::Done;
+		}
+		Err(err) => {
+			debug!("({id}): failed to read response: {err}");
+			return interrupted;
 		}
+	};
 
-		let cancelled = Arc::new(AtomicBool::new(false));
-		p2p.spacedrop_cancellations
-			.lock()
-			.unwrap_or_else(PoisonError::into_inner)
-			.insert(id, cancelled.clone());
+	debug!("({id}): starting transfer (delta: {delta})");
+	let i = Instant::now();
 
-		debug!("({id}): starting transfer");
-		let i = Instant::now();
-
-		let mut transfer = Transfer::new(
-			&requests,
-			|percent| {
-				p2p.events
-					.send(P2PEvent::SpacedropProgress { id, percent })
-					.ok();
-			},
-			&cancelled,
-		);
+	let mut transfer = Transfer::new(
+		&requests,
+		|percent| {
+			p2p.events
+				.send(P2PEvent::SpacedropProgress { id, percent })
+				.ok();
+		},
+		cancelled,
+	);
 
-		for (file_id, (path, file)) in files.into_iter().enumerate() {
-			debug!("({id}): transmitting '{file_id}' from '{path:?}'");
-			let file = BufReader::new(file);
-			if let Err(err) = transfer.send(&mut stream, file).await {
-				debug!("({id}): failed to send file '{file_id}': {err}");
-				// TODO: Error to frontend
-				// p2p.events
-				// 	.send(P2PEvent::SpacedropFailed { id, file_id })
-				// 	.ok();
-				return;
+	for (file_id, (path, req)) in files.iter().zip(&requests.requests).enumerate() {
+		debug!(
+			"({id}): transmitting '{file_id}' from '{path:?}' ({:?})",
+			req.range
+		);
+		// Files are opened one at a time as a directory can contain more files than we can keep open
+		let file = async {
+			let mut file = File::open(path).await?;
+			file.seek(SeekFrom::Start(req.byte_range().start)).await?;
+			Ok::<_, io::Error>(file)
+		}
+		.await;
+		let file = match file {
+			Ok(file) => BufReader::new(file),
+			Err(err) => {
+				error!("({id}): failed to open file '{file_id}': {err}");
+				return Attempt::Done;
 			}
+		};
+
+		let result = if delta {
+			transfer.send_delta(&mut stream, file).await
+		} else {
+			transfer.send(&mut stream, file).await
+		};
+		if let Err(err) = result {
+			debug!("({id}): failed to send file '{file_id}': {err}");
+			// TODO: Error to frontend
+			// p2p.events
+			// 	.send(P2PEvent::SpacedropFailed { id, file_id })
+			// 	.ok();
+			return Attempt::Interrupted;
 		}
 
-		debug!("({id}): finished; took '{:?}", i.elapsed());
-	});
+		if cancelled.load(Ordering::Relaxed) {
+			debug!("({id}): cancelled");
+			return Attempt::Done;
+		}
+	}
 
-	Ok(id)
+	// The receiver compares the checksums of the files it received with ours before keeping them
+	let mut checksums = Vec::new();
+	for path in files {
+		match file_checksum(path).await {
+			Ok(checksum) => encode::string(&mut checksums, &checksum),
+			Err(err) => {
+				error!("({id}): failed to compute checksum of '{path:?}': {err}");
+				return Attempt::Done;
+			}
+		}
+	}
+	if let Err(err) = stream.write_all(&checksums).await {
+		debug!("({id}): failed to send checksums: {err}");
+		return Attempt::Interrupted;
+	}
+
+	match stream.read_u8().await {
+		Ok(1) => {
+			debug!("({id}): finished; took '{:?}", i.elapsed());
+			Attempt::Done
+		}
+		Ok(_) => Attempt::VerificationFailed,
+		Err(err) => {
+			debug!("({id}): failed to read verification result: {err}");
+			Attempt::Interrupted
+		}
+	}
 }
 
 // TODO: Move these off the manager
@@ -202,16 +459,55 @@ impl P2PManager {
 
 pub(crate) async fn receiver(
 	this: &Arc<P2PManager>,
+	node: &Arc<Node>,
 	req: SpaceblockRequests,
 	mut stream: UnicastStream,
 ) -> Result<(), ()> {
 	let id = req.id;
+	let identity = stream.remote_identity();
+	let manifests_dir = this
+		.node_config
+		.data_directory()
+		.join(SPACEDROP_MANIFESTS_DIR);
+
+	if let Some(manifest) = SpacedropManifest::load(&manifests_dir, id).await {
+		if manifest.matches(&identity, &req) {
+			return resume(this, req, stream, manifest).await;
+		}
+
+		warn!("({id}): peer '{identity}' tried to resume a Spacedrop it didn't start, rejecting!");
+		stream
+			.write_all(&[SPACEDROP_REJECTED])
+			.await
+			.map_err(|err| {
+				error!("({id}): error sending rejection: '{err:?}'");
+			})?;
+		return stream.flush().await.map_err(|err| {
+			error!("({id}): error flushing rejection: '{err:?}'");
+		});
+	}
+
+	prune_expired_manifests(&manifests_dir).await;
+
+	let Some(paths) = RelativePaths::new(&req) else {
+		warn!("({id}): peer '{identity}' sent a path outside of the Spacedrop, rejecting!");
+		stream
+			.write_all(&[SPACEDROP_REJECTED])
+			.await
+			.map_err(|err| {
+				error!("({id}): error sending rejection: '{err:?}'");
+			})?;
+		return stream.flush().await.map_err(|err| {
+			error!("({id}): error flushing rejection: '{err:?}'");
+		});
+	};
+
 	let (tx, rx) = oneshot::channel();
 
 	info!(
 		"({id}): received '{}' files from peer '{}' with block size '{:?}'",
 		req.requests.len(),
-		stream.remote_identity(),
+		identity,
 		req.block_size
 	);
 	this.spacedrop_pairing_reqs
@@ -223,7 +519,7 @@ pub(crate) async fn receiver(
 		.events
 		.send(P2PEvent::SpacedropRequest {
 			id,
-			identity: stream.remote_identity(),
+			identity,
 			peer_name: "Unknown".into(),
 			// TODO: A better solution to this
 			// manager
@@ -247,11 +543,36 @@ pub(crate) async fn receiver(
 		error!("TODO: Outright reject Spacedrop");
 	}
 
+	// Accepting needs a destination picked in the Spacedrop dialog, only rejecting can be done
+	// right from the notification
+	node.emit_notification(
+		NotificationData::new(
+			"Spacedrop request",
+			format!(
+				"Peer '{identity}' wants to send you {} files",
+				req.requests.len()
+			),
+			NotificationKind::Info,
+		)
+		.with_category(NotificationCategory::Spacedrop)
+		.with_group(spacedrop_notification_group(id))
+		.with_action(NotificationAction {
+			label: "Reject".to_string(),
+			procedure: "p2p.acceptSpacedrop".to_string(),
+			input: json!([id, null]),
+			dismiss: true,
+		}),
+		chrono::Duration::from_std(SPACEDROP_TIMEOUT)
+			.ok()
+			.map(|timeout| Utc::now() + timeout),
+	)
+	.await;
+
 	tokio::select! {
 		_ = sleep(SPACEDROP_TIMEOUT) => {
 			info!("({id}): timeout, rejecting!");
 
-			stream.write_all(&[0]).await.map_err(|err| {
+			stream.write_all(&[SPACEDROP_REJECTED]).await.map_err(|err| {
 				error!("({id}): error reject bit: '{err:?}'");
 			})?;
 			stream.flush().await.map_err(|err| {
@@ -269,7 +590,22 @@ pub(crate) async fn receiver(
 						.unwrap_or_else(PoisonError::into_inner)
 						.insert(id, cancelled.clone());
 
-					stream.write_all(&[1]).await.map_err(|err| {
+					let manifest = SpacedropManifest::new(
+						&manifests_dir,
+						identity,
+						&req,
+						paths,
+						PathBuf::from(file_path),
+					);
+					if let Err(err) = manifest.save().await {
+						// We can still receive the files, the Spacedrop just can't be resumed
+						warn!("({id}): error saving Spacedrop manifest: '{err:?}'");
+					}
+
+					// We always opt into delta transfers when the sender supports them
+					let delta = req.delta;
+					let response = if delta { SPACEDROP_ACCEPTED_DELTA } else { SPACEDROP_ACCEPTED };
+					stream.write_all(&[response]).await.map_err(|err| {
 						error!("({id}): error sending continuation bit: '{err:?}'");
 
 						// TODO: Send error to the frontend
@@ -277,56 +613,18 @@ pub(crate) async fn receiver(
 						// TODO: make sure the other peer times out or we retry???
 					})?;
 
-					let names = req.requests.iter().map(|req| req.name.clone()).collect::<Vec<_>>();
-					let mut transfer = Transfer::new(&req, |percent| {
-						this.events.send(P2PEvent::SpacedropProgress { id, percent }).ok();
-					}, &cancelled);
-
-					let file_path = PathBuf::from(file_path);
-					let names_len = names.len();
-					for file_name in names {
-						 // When transferring more than 1 file we wanna join the incoming file name to the directory provided by the user
-						 let mut path = file_path.clone();
-						 if names_len != 1 {
-							// We know the `file_path` will be a directory so we can just push the file name to it
-							path.push(&file_name);
-						}
-
-						debug!("({id}): accepting '{file_name}' and saving to '{:?}'", path);
-
-						if let Some(parent) = path.parent() {
-						  create_dir_all(&parent).await.map_err(|err| {
-								error!("({id}): error creating parent directory '{parent:?}': '{err:?}'");
-
-								// TODO: Send error to the frontend
-
-								// TODO: Send error to remote peer
-							})?;
-						}
-
-						let f = File::create(&path).await.map_err(|err| {
-							error!("({id}): error creating file at '{path:?}': '{err:?}'");
-
-							// TODO: Send error to the frontend
-
-							// TODO: Send error to remote peer
-						})?;
-						let f = BufWriter::new(f);
-						if let Err(err) = transfer.receive(&mut stream, f).await {
-							error!("({id}): error receiving file '{file_name}': '{err:?}'");
-
-							// TODO: Send error to frontend
-
-							break;
-						}
-					}
-
-					info!("({id}): complete");
+					let result =
+						receive_files(this, &req, &mut stream, manifest, delta, &cancelled).await;
+					this.spacedrop_cancellations
+						.lock()
+						.unwrap_or_else(PoisonError::into_inner)
+						.remove(&id);
+					result?;
 				}
 				Ok(None) => {
 					info!("({id}): rejected");
 
-					stream.write_all(&[0]).await.map_err(|err| {
+					stream.write_all(&[SPACEDROP_REJECTED]).await.map_err(|err| {
 					   error!("({id}): error sending rejection: '{err:?}'");
 					})?;
 					stream.flush().await.map_err(|err| {
@@ -342,3 +640,265 @@ pub(crate) async fn receiver(
 
 	Ok(())
 }
+
+/// Group of the notification of a Spacedrop request, to dismiss it once the request is answered
+pub(crate) fn spacedrop_notification_group(id: Uuid) -> String {
+	format!("spacedrop:{id}")
+}
+
+/// Continues an interrupted Spacedrop the user already accepted, from the last block we received
+async fn resume(
+	this: &Arc<P2PManager>,
+	mut req: SpaceblockRequests,
+	mut stream: UnicastStream,
+	manifest: SpacedropManifest,
+) -> Result<(), ()> {
+	let id = req.id;
+
+	let cancelled = match this
+		.spacedrop_cancellations
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.entry(id)
+	{
+		Entry::Occupied(_) => {
+			// The old connection hasn't timed out yet, the sender will try again later
+			debug!("({id}): Spacedrop is still being received, ignoring resume");
+			return Ok(());
+		}
+		Entry::Vacant(entry) => entry.insert(Arc::new(AtomicBool::new(false))).clone(),
+	};
+
+	let block_size = u64::from(req.block_size.size());
+	let mut response = vec![SPACEDROP_RESUMED];
+	for (file, req) in manifest.files.iter().zip(&mut req.requests) {
+		let start = file.received(block_size).await;
+		req.range = Range::Partial(start..file.size);
+		response.extend_from_slice(&start.to_le_bytes());
+	}
+
+	info!(
+		"({id}): resuming Spacedrop from peer '{}'",
+		stream.remote_identity()
+	);
+	let result = async {
+		stream.write_all(&response).await.map_err(|err| {
+			error!("({id}): error sending resume offsets: '{err:?}'");
+		})?;
+
+		receive_files(this, &req, &mut stream, manifest, false, &cancelled).await
+	}
+	.await;
+
+	this.spacedrop_cancellations
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.remove(&id);
+
+	result
+}
+
+/// Receives the files into partial files and moves them to their destination once verified.
+///
+/// If the connection drops the partial files and manifest are kept so the sender can resume.
+async fn receive_files(
+	this: &Arc<P2PManager>,
+	req: &SpaceblockRequests,
+	stream: &mut UnicastStream,
+	mut manifest: SpacedropManifest,
+	delta: bool,
+	cancelled: &AtomicBool,
+) -> Result<(), ()> {
+	let id = req.id;
+	let mut transfer = Transfer::new(
+		req,
+		|percent| {
+			this.events
+				.send(P2PEvent::SpacedropProgress { id, percent })
+				.ok();
+		},
+		cancelled,
+	);
+
+	for path in &manifest.directories {
+		create_dir_all(path).await.map_err(|err| {
+			error!("({id}): error creating directory '{path:?}': '{err:?}'");
+
+			// TODO: Send error to the frontend
+		})?;
+	}
+
+	for (file, file_req) in manifest.files.iter().zip(&req.requests) {
+		let path = &file.path;
+		if file.verified {
+			// Received before the connection dropped, so there is nothing to transfer
+			transfer
+				.receive(stream, tokio::io::sink())
+				.await
+				.map_err(|err| {
+					error!("({id}): error skipping file '{}': '{err:?}'", file.name);
+				})?;
+			continue;
+		}
+
+		debug!("({id}): accepting '{}' and saving to '{path:?}'", file.name);
+
+		if let Some(parent) = path.parent() {
+			create_dir_all(&parent).await.map_err(|err| {
+				error!("({id}): error creating parent directory '{parent:?}': '{err:?}'");
+
+				// TODO: Send error to the frontend
+
+				// TODO: Send error to remote peer
+			})?;
+		}
+
+		// An existing file at the destination is used as the basis of a delta transfer. It's only
+		// replaced once the partial file is complete and verified.
+		let basis = if delta && metadata(path).await.map(|m| m.is_file()).unwrap_or(false) {
+			File::open(path).await.ok()
+		} else {
+			None
+		};
+
+		let partial_path = file.partial_path();
+		let start = file_req.byte_range().start;
+		let f = async {
+			let mut f = OpenOptions::new()
+				.create(true)
+				.write(true)
+				.open(&partial_path)
+				.await?;
+			// Anything after the last complete block is received again
+			f.set_len(start).await?;
+			f.seek(SeekFrom::Start(start)).await?;
+			Ok::<_, std::io::Error>(f)
+		}
+		.await
+		.map_err(|err| {
+			error!("({id}): error opening file at '{partial_path:?}': '{err:?}'");
+
+			// TODO: Send error to the frontend
+
+			// TODO: Send error to remote peer
+		})?;
+
+		let f = BufWriter::new(f);
+		let result = if delta {
+			transfer.receive_delta(stream, f, basis).await
+		} else {
+			transfer.receive(stream, f).await
+		};
+		if let Err(err) = result {
+			error!(
+				"({id}): error receiving file '{}', keeping it so it can be resumed: '{err:?}'",
+				file.name
+			);
+
+			// TODO: Send error to frontend
+
+			return Err(());
+		}
+
+		if cancelled.load(Ordering::Relaxed) {
+			break;
+		}
+	}
+
+	if cancelled.load(Ordering::Relaxed) {
+		info!("({id}): cancelled");
+		manifest.discard().await;
+		return Ok(());
+	}
+
+	// The sender sends the checksum of every file so we only keep them if they arrived intact
+	let mut all_verified = true;
+	for (file, file_req) in manifest.files.iter_mut().zip(&req.requests) {
+		let expected = decode::string(&mut *stream).await.map_err(|err| {
+			error!(
+				"({id}): error receiving checksum of '{}': '{err:?}'",
+				file.name
+			);
+		})?;
+		if file.verified {
+			continue;
+		}
+
+		let partial_path = file.partial_path();
+		let verified = match file_checksum(&partial_path).await {
+			Ok(checksum) => checksum == expected,
+			Err(err) => {
+				error!("({id}): error computing checksum of '{partial_path:?}': '{err:?}'");
+				false
+			}
+		};
+		if !verified {
+			// The sender will send it again from the start
+			warn!("({id}): '{}' failed verification, discarding it", file.name);
+			remove_file(&partial_path).await.ok();
+			all_verified = false;
+			continue;
+		}
+
+		if let Err(err) = rename(&partial_path, &file.path).await {
+			error!(
+				"({id}): error moving '{partial_path:?}' to '{:?}': '{err:?}'",
+				file.path
+			);
+
+			// TODO: Send error to frontend
+
+			all_verified = false;
+			continue;
+		}
+
+		if let Some(modified) = file_req.modified {
+			set_modified(&file.path, modified).await;
+		}
+
+		file.verified = true;
+	}
+
+	if all_verified {
+		// Done last as receiving the files into the directories changes their modification time
+		for (path, dir) in manifest.directories.iter().zip(&req.directories) {
+			if let Some(modified) = dir.modified {
+				set_modified(path, modified).await;
+			}
+		}
+
+		manifest.remove().await;
+		info!("({id}): complete");
+	} else if let Err(err) = manifest.save().await {
+		error!("({id}): error saving Spacedrop manifest: '{err:?}'");
+	}
+
+	stream
+		.write_u8(u8::from(all_verified))
+		.await
+		.map_err(|err| {
+			error!("({id}): error sending verification result: '{err:?}'");
+		})?;
+	stream.flush().await.map_err(|err| {
+		error!("({id}): error flushing verification result: '{err:?}'");
+	})
+}
+
+/// Restores the modification time a file or directory had on the sender
+async fn set_modified(path: &Path, modified: SystemTime) {
+	let result = async {
+		let file = if metadata(path).await?.is_dir() {
+			File::open(path).await?
+		} else {
+			OpenOptions::new().write(true).open(path).await?
+		};
+
+		file.into_std().await.set_modified(modified)
+	}
+	.await;
+
+	if let Err(err) = result {
+		// Not worth failing the Spacedrop over, eg. directories can't be opened on Windows
+		debug!("error setting modification time of '{path:?}': '{err:?}'");
+	}
+}
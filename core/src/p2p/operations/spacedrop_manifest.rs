@@ -0,0 +1,307 @@
+//! Spacedrops are received into partial files, with a manifest recording where each of them goes.
+//! If the connection drops, the sender reconnects with the same Spacedrop id and the manifest
+//! tells us how much of each file we already have, so the transfer resumes instead of restarting.
+
+use std::{
+	io,
+	path::{Component, Path, PathBuf},
+	time::{Duration, SystemTime},
+};
+
+use sd_p2p::RemoteIdentity;
+use sd_p2p_block::SpaceblockRequests;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+/// Directory in the node's data directory where the manifests of partial Spacedrops are stored
+pub(super) const SPACEDROP_MANIFESTS_DIR: &str = "spacedrop";
+
+/// Partial Spacedrops which haven't been resumed for this long are removed
+const MANIFEST_EXPIRY: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Extension appended to the name of files while they are being received
+const PARTIAL_FILE_EXTENSION: &str = "sdpart";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct SpacedropManifest {
+	#[serde(skip)]
+	manifest_path: PathBuf,
+	pub id: Uuid,
+	/// Only the peer which started the Spacedrop is allowed to resume it
+	pub identity: RemoteIdentity,
+	pub files: Vec<ManifestFile>,
+	/// Where each directory of the Spacedrop is created, in the order of the request
+	#[serde(default)]
+	pub directories: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct ManifestFile {
+	pub name: String,
+	pub size: u64,
+	pub path: PathBuf,
+	/// The checksum of the file matched the sender's one and it was moved to `path`
+	pub verified: bool,
+}
+
+impl ManifestFile {
+	pub fn partial_path(&self) -> PathBuf {
+		let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+		name.push(".");
+		name.push(PARTIAL_FILE_EXTENSION);
+		self.path.with_file_name(name)
+	}
+
+	/// How much of the file we already have, up to the last complete block.
+	///
+	/// Anything after it may have been cut off mid-write when the connection dropped.
+	pub async fn received(&self, block_size: u64) -> u64 {
+		if self.verified {
+			return self.size;
+		}
+
+		match fs::metadata(self.partial_path()).await {
+			Ok(metadata) if metadata.len() >= self.size => self.size,
+			Ok(metadata) => metadata.len() - metadata.len() % block_size,
+			Err(_) => 0,
+		}
+	}
+}
+
+/// Paths of the files and directories of a Spacedrop relative to where it's saved.
+///
+/// The names come from the sender so they are checked to not escape the destination.
+pub(super) struct RelativePaths {
+	files: Vec<PathBuf>,
+	directories: Vec<PathBuf>,
+}
+
+impl RelativePaths {
+	/// Returns `None` if any of the names isn't a safe relative path
+	pub fn new(req: &SpaceblockRequests) -> Option<Self> {
+		Some(Self {
+			files: req
+				.requests
+				.iter()
+				.map(|req| sanitise_relative_path(&req.name))
+				.collect::<Option<_>>()?,
+			directories: req
+				.directories
+				.iter()
+				.map(|dir| sanitise_relative_path(&dir.name))
+				.collect::<Option<_>>()?,
+		})
+	}
+}
+
+/// Converts a `/` separated relative path received from a peer to a path, rejecting anything
+/// which could point outside of the directory it's joined to
+fn sanitise_relative_path(name: &str) -> Option<PathBuf> {
+	name.split('/')
+		.map(|component| {
+			// Backslashes and colons would be separators or drive prefixes on Windows
+			if component.contains(['\\', ':', '\0']) {
+				return None;
+			}
+
+			let mut components = Path::new(component).components();
+			match (components.next(), components.next()) {
+				(Some(Component::Normal(component)), None) => Some(component),
+				_ => None,
+			}
+		})
+		.collect()
+}
+
+impl SpacedropManifest {
+	pub fn new(
+		manifests_dir: &Path,
+		identity: RemoteIdentity,
+		req: &SpaceblockRequests,
+		paths: RelativePaths,
+		destination: PathBuf,
+	) -> Self {
+		// When transferring more than a single file we wanna join the incoming paths to the
+		// directory provided by the user
+		let single_file = req.requests.len() == 1 && req.directories.is_empty();
+
+		Self {
+			manifest_path: manifest_path(manifests_dir, req.id),
+			id: req.id,
+			identity,
+			files: req
+				.requests
+				.iter()
+				.zip(paths.files)
+				.map(|(req, path)| ManifestFile {
+					name: req.name.clone(),
+					size: req.size,
+					path: if single_file {
+						destination.clone()
+					} else {
+						destination.join(path)
+					},
+					verified: false,
+				})
+				.collect(),
+			directories: paths
+				.directories
+				.into_iter()
+				.map(|path| destination.join(path))
+				.collect(),
+		}
+	}
+
+	pub async fn load(manifests_dir: &Path, id: Uuid) -> Option<Self> {
+		let manifest_path = manifest_path(manifests_dir, id);
+
+		let bytes = match fs::read(&manifest_path).await {
+			Ok(bytes) => bytes,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+			Err(e) => {
+				error!("({id}): error reading Spacedrop manifest at '{manifest_path:?}': {e:?}");
+				return None;
+			}
+		};
+
+		serde_json::from_slice::<Self>(&bytes)
+			.map(|manifest| Self {
+				manifest_path,
+				..manifest
+			})
+			.map_err(|e| error!("({id}): invalid Spacedrop manifest: {e:?}"))
+			.ok()
+	}
+
+	/// Checks the request is for the same files from the peer which started the Spacedrop
+	pub fn matches(&self, identity: &RemoteIdentity, req: &SpaceblockRequests) -> bool {
+		self.identity == *identity
+			&& self.directories.len() == req.directories.len()
+			&& self.files.len() == req.requests.len()
+			&& self
+				.files
+				.iter()
+				.zip(&req.requests)
+				.all(|(file, req)| file.name == req.name && file.size == req.size)
+	}
+
+	pub async fn save(&self) -> Result<(), io::Error> {
+		if let Some(parent) = self.manifest_path.parent() {
+			fs::create_dir_all(parent).await?;
+		}
+
+		fs::write(
+			&self.manifest_path,
+			serde_json::to_vec(self).map_err(io::Error::from)?,
+		)
+		.await
+	}
+
+	/// Removes the manifest once all files have been verified
+	pub async fn remove(self) {
+		if let Err(e) = fs::remove_file(&self.manifest_path).await {
+			if e.kind() != io::ErrorKind::NotFound {
+				warn!("({}): error removing Spacedrop manifest: {e:?}", self.id);
+			}
+		}
+	}
+
+	/// Removes the manifest and the partial files of a Spacedrop which won't be resumed
+	pub async fn discard(self) {
+		for file in self.files.iter().filter(|file| !file.verified) {
+			let partial_path = file.partial_path();
+			if let Err(e) = fs::remove_file(&partial_path).await {
+				if e.kind() != io::ErrorKind::NotFound {
+					warn!(
+						"({}): error removing partial file '{partial_path:?}': {e:?}",
+						self.id
+					);
+				}
+			}
+		}
+
+		self.remove().await;
+	}
+}
+
+fn manifest_path(manifests_dir: &Path, id: Uuid) -> PathBuf {
+	manifests_dir.join(format!("{id}.json"))
+}
+
+/// Discards the partial Spacedrops which weren't resumed in time
+pub(super) async fn prune_expired_manifests(manifests_dir: &Path) {
+	let mut read_dir = match fs::read_dir(manifests_dir).await {
+		Ok(read_dir) => read_dir,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+		Err(e) => {
+			error!("error reading Spacedrop manifests directory: {e:?}");
+			return;
+		}
+	};
+
+	while let Ok(Some(entry)) = read_dir.next_entry().await {
+		let expired = entry
+			.metadata()
+			.await
+			.and_then(|metadata| metadata.modified())
+			.ok()
+			.and_then(|modified| SystemTime::now().duration_since(modified).ok())
+			.is_some_and(|age| age > MANIFEST_EXPIRY);
+		if !expired {
+			continue;
+		}
+
+		let Some(id) = entry
+			.path()
+			.file_stem()
+			.and_then(|stem| Uuid::parse_str(&stem.to_string_lossy()).ok())
+		else {
+			continue;
+		};
+
+		if let Some(manifest) = SpacedropManifest::load(manifests_dir, id).await {
+			debug!("({id}): discarding expired partial Spacedrop");
+			manifest.discard().await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sanitise_relative_path() {
+		assert_eq!(
+			sanitise_relative_path("Folder/Sub folder/file.txt"),
+			Some(PathBuf::from("Folder").join("Sub folder").join("file.txt"))
+		);
+		assert_eq!(
+			sanitise_relative_path("file.txt"),
+			Some(PathBuf::from("file.txt"))
+		);
+
+		for name in [
+			"",
+			".",
+			"..",
+			"../file.txt",
+			"Folder/../../file.txt",
+			"Folder//file.txt",
+			"Folder/",
+			"/etc/passwd",
+			"..\\file.txt",
+			"C:\\Windows",
+			"C:file.txt",
+			"file\0.txt",
+		] {
+			assert_eq!(
+				sanitise_relative_path(name),
+				None,
+				"'{name}' must be rejected"
+			);
+		}
+	}
+}
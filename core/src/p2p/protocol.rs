@@ -13,6 +13,8 @@ pub enum Header {
 	Sync(Uuid),
 	// A HTTP server used for rspc requests and streaming files
 	Http,
+	// Pairing with a code shown on the other node, which makes each node trust the other
+	Pair,
 }
 
 #[derive(Debug, Error)]
@@ -21,6 +23,8 @@ pub enum HeaderError {
 	DiscriminatorIo(std::io::Error),
 	#[error("invalid discriminator '{0}'")]
 	DiscriminatorInvalid(u8),
+	#[error("the peer sent a Spacedrop without a Spaceblock version, it has to be updated")]
+	SpacedropUnversioned,
 	#[error("error reading spacedrop request: {0}")]
 	SpacedropRequest(#[from] SpaceblockRequestsError),
 	#[error("error reading sync request: {0}")]
@@ -35,9 +39,8 @@ impl Header {
 			.map_err(HeaderError::DiscriminatorIo)?;
 
 		match discriminator {
-			0 => Ok(Self::Spacedrop(
-				SpaceblockRequests::from_stream(stream).await?,
-			)),
+			// Spacedrops from before the Spaceblock version was sent, which would be misread
+			0 => Err(HeaderError::SpacedropUnversioned),
 			1 => Ok(Self::Ping),
 			3 => Ok(Self::Sync(
 				decode::uuid(stream)
@@ -45,6 +48,10 @@ impl Header {
 					.map_err(HeaderError::SyncRequest)?,
 			)),
 			5 => Ok(Self::Http),
+			6 => Ok(Self::Pair),
+			7 => Ok(Self::Spacedrop(
+				SpaceblockRequests::from_stream(stream).await?,
+			)),
 			d => Err(HeaderError::DiscriminatorInvalid(d)),
 		}
 	}
@@ -52,7 +59,7 @@ impl Header {
 	pub fn to_bytes(&self) -> Vec<u8> {
 		match self {
 			Self::Spacedrop(transfer_request) => {
-				let mut bytes = vec![0];
+				let mut bytes = vec![7];
 				bytes.extend_from_slice(&transfer_request.to_bytes());
 				bytes
 			}
@@ -63,6 +70,7 @@ impl Header {
 				bytes
 			}
 			Self::Http => vec![5],
+			Self::Pair => vec![6],
 		}
 	}
 }
@@ -2,11 +2,17 @@
 
 use crate::{
 	library::Library,
-	sync::{self, GetOpsArgs},
+	sync::{
+		self,
+		diagnostics::{Direction, Transport},
+		GetOpsArgs,
+	},
 };
 
 use sd_p2p_proto::{decode, encode};
+use sd_prisma::prisma::instance;
 use sd_sync::CRDTOperation;
+use sd_utils::from_bytes_to_uuid;
 
 use std::sync::Arc;
 
@@ -25,8 +31,11 @@ mod originator {
 
 	use super::*;
 	use responder::tx as rx;
+	use sd_p2p::{Identity, RemoteIdentity};
 	use sd_p2p_tunnel::Tunnel;
 
+	use std::str::FromStr;
+
 	pub mod tx {
 		use super::*;
 
@@ -81,12 +90,42 @@ mod originator {
 	}
 
 	/// REMEMBER: This only syncs one direction!
-	pub async fn run(library_id: Uuid, sync: &Arc<sync::Manager>, p2p: &Arc<super::P2PManager>) {
+	pub async fn run(
+		library_id: Uuid,
+		identity: &Arc<Identity>,
+		sync: &Arc<sync::Manager>,
+		p2p: &Arc<super::P2PManager>,
+	) {
 		for (remote_identity, peer) in p2p.get_library_instances(&library_id) {
 			if !peer.is_connected() {
 				continue;
 			};
 
+			// The identity of the remote's instance of this library, which the tunnel must authenticate
+			let Some(instance_identity) = peer
+				.metadata()
+				.get(&library_id.to_string())
+				.and_then(|identity| RemoteIdentity::from_str(identity).ok())
+			else {
+				continue;
+			};
+
+			// Only used to keep track of the exchanges with it
+			let Ok(Some(instance)) = sync
+				.db
+				.instance()
+				.find_first(vec![instance::remote_identity::equals(
+					instance_identity.get_bytes().to_vec(),
+				)])
+				.select(instance::select!({ pub_id }))
+				.exec()
+				.await
+			else {
+				continue;
+			};
+			let peer_instance = from_bytes_to_uuid(&instance.pub_id);
+
+			let identity = identity.clone();
 			let sync = sync.clone();
 
 			tokio::spawn(async move {
@@ -101,7 +140,22 @@ mod originator {
 					.await
 					.unwrap();
 
-				let mut tunnel = Tunnel::initiator(stream).await.unwrap();
+				let mut tunnel = match Tunnel::initiator(stream, &identity).await {
+					Ok(tunnel) => tunnel,
+					Err(e) => {
+						error!(
+							"Failed to establish a tunnel with peer '{remote_identity:?}' for library '{library_id:?}': {e:#?}"
+						);
+						return;
+					}
+				};
+				if tunnel.remote_identity() != instance_identity {
+					error!(
+						"Peer '{remote_identity:?}' authenticated as '{}' instead of its instance of library '{library_id:?}'",
+						tunnel.remote_identity()
+					);
+					return;
+				}
 
 				tunnel
 					.write_all(&SyncMessage::NewOperations.to_bytes())
@@ -112,13 +166,25 @@ mod originator {
 				while let Ok(rx::MainRequest::GetOperations(args)) =
 					rx::MainRequest::from_stream(&mut tunnel).await
 				{
+					if let Err(e) = sync.record_peer_clocks(peer_instance, &args.clocks).await {
+						error!("Failed to record the clocks of peer '{peer_instance}': {e:#?}");
+					}
+
 					let ops = sync.get_ops(args).await.unwrap();
+					let count = ops.len();
 
 					tunnel
 						.write_all(&tx::Operations(ops).to_bytes())
 						.await
 						.unwrap();
 					tunnel.flush().await.unwrap();
+
+					sync.diagnostics.record_exchange(
+						Some(peer_instance),
+						Transport::P2P,
+						Direction::Sent,
+						count,
+					);
 				}
 			});
 		}
@@ -187,6 +253,7 @@ mod responder {
 	pub async fn run(
 		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
 		library: Arc<Library>,
+		peer_instance: Uuid,
 	) -> Result<(), ()> {
 		let ingest = &library.sync.ingest;
 
@@ -236,6 +303,13 @@ mod responder {
 
 			let rx::Operations(ops) = rx::Operations::from_stream(stream).await.unwrap();
 
+			library.sync.diagnostics.record_exchange(
+				Some(peer_instance),
+				Transport::P2P,
+				Direction::Received,
+				ops.len(),
+			);
+
 			ingest
 				.event_tx
 				.send(Event::Messages(MessagesEvent {
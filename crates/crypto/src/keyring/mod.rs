@@ -4,7 +4,7 @@ use std::fmt::Display;
 mod identifier;
 mod session;
 
-use identifier::Identifier;
+pub use identifier::Identifier;
 use session::SessionKeyring;
 
 #[cfg(target_os = "linux")]
@@ -12,10 +12,10 @@ use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 
 use super::{
-	file_path_for_file_identifier, file_path_for_media_processor, file_path_for_object_validator,
-	file_path_to_full_path, file_path_to_handle_custom_uri, file_path_to_handle_p2p_serve_file,
-	file_path_to_isolate, file_path_to_isolate_with_id, file_path_walker, file_path_with_object,
-	FilePathError,
+	file_path_for_file_identifier, file_path_for_integrity_check, file_path_for_media_processor,
+	file_path_for_object_validator, file_path_to_full_path, file_path_to_handle_custom_uri,
+	file_path_to_handle_p2p_serve_file, file_path_to_isolate, file_path_to_isolate_with_id,
+	file_path_walker, file_path_with_object, FilePathError,
 };
 
 static FORBIDDEN_FILE_NAMES: OnceLock<RegexSet> = OnceLock::new();
@@ -467,6 +467,7 @@ impl_from_db!(
 	file_path_to_isolate,
 	file_path_walker,
 	file_path_to_isolate_with_id,
+	file_path_for_integrity_check,
 	file_path_with_object
 );
 
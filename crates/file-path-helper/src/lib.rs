@@ -45,6 +45,18 @@ file_path::select!(file_path_for_object_validator {
 	extension
 	integrity_checksum
 });
+file_path::select!(file_path_for_integrity_check {
+	id
+	pub_id
+	location_id
+	materialized_path
+	is_dir
+	name
+	extension
+	integrity_checksum
+	date_modified
+	integrity_check
+});
 file_path::select!(file_path_for_media_processor {
 	id
 	materialized_path
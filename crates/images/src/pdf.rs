@@ -76,14 +76,41 @@ static LANDSCAPE_CONFIG: Lazy<PdfRenderConfig> = Lazy::new(|| {
 	thumbnail_config(PdfRenderConfig::new().set_target_width(PDF_LANDSCAPE_RENDER_WIDTH))
 });
 
+fn bind_pdfium() -> Result<Pdfium> {
+	Ok(Pdfium::new(
+		Pdfium::bind_to_library(PDFIUM_LIB.as_str()).or_else(|err| {
+			error!("{err:#?}");
+			Pdfium::bind_to_system_library()
+		})?,
+	))
+}
+
+/// Extracts the text of every page of a PDF document, separating pages with a newline.
+///
+/// Extraction stops once `max_len` bytes of text have been collected, so callers can bound
+/// how much of a huge document ends up in memory.
+pub fn extract_pdf_text(path: impl AsRef<Path>, max_len: usize) -> Result<String> {
+	let pdfium = bind_pdfium()?;
+	let pdf = pdfium.load_pdf_from_file(path.as_ref(), None)?;
+
+	let mut text = String::new();
+	for page in pdf.pages().iter() {
+		if text.len() >= max_len {
+			break;
+		}
+
+		text.push_str(&page.text()?.all());
+		text.push('\n');
+	}
+
+	Ok(text)
+}
+
 pub struct PdfHandler {}
 
 impl ImageHandler for PdfHandler {
 	fn handle_image(&self, path: &Path) -> Result<DynamicImage> {
-		let pdfium = Pdfium::new(Pdfium::bind_to_library(PDFIUM_LIB.as_str()).or_else(|err| {
-			error!("{err:#?}");
-			Pdfium::bind_to_system_library()
-		})?);
+		let pdfium = bind_pdfium()?;
 
 		let pdf = pdfium.load_pdf_from_file(path, None)?;
 		let first_page = pdf.pages().first()?;
@@ -0,0 +1,109 @@
+use image::{imageops::FilterType, DynamicImage};
+
+/// The image is reduced to a grid one pixel wider than it is tall, so each row yields 8 comparisons
+const GRID_WIDTH: u32 = 9;
+const GRID_HEIGHT: u32 = 8;
+
+/// Computes a 64 bits difference hash (dHash) of an image.
+///
+/// Each bit tells if a pixel of the downscaled grayscale image is brighter than its right neighbour,
+/// so resized, re-encoded or slightly edited copies of the same picture have hashes that are only
+/// a few bits apart. Use [`hamming_distance`] to compare them.
+#[must_use]
+pub fn perceptual_hash(img: &DynamicImage) -> u64 {
+	let grid = img
+		.resize_exact(GRID_WIDTH, GRID_HEIGHT, FilterType::Triangle)
+		.into_luma8();
+
+	let mut hash = 0;
+	for y in 0..GRID_HEIGHT {
+		for x in 0..GRID_WIDTH - 1 {
+			let left = grid.get_pixel(x, y)[0];
+			let right = grid.get_pixel(x + 1, y)[0];
+			hash = (hash << 1) | u64::from(left > right);
+		}
+	}
+
+	hash
+}
+
+/// The number of differing bits between two perceptual hashes, where 0 means the images look the same
+#[must_use]
+pub const fn hamming_distance(a: u64, b: u64) -> u32 {
+	(a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::{f32::consts::PI, io::Cursor};
+
+	use image::{ImageBuffer, ImageOutputFormat, Rgb};
+
+	/// Largest distance the similar objects search accepts by default
+	const SIMILARITY_THRESHOLD: u32 = 10;
+
+	fn picture(width: u32, height: u32, pattern: fn(f32, f32) -> f32) -> DynamicImage {
+		DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+			let value = pattern(x as f32 / width as f32, y as f32 / height as f32);
+			let luma = (127.5 + 127.5 * value) as u8;
+			Rgb([luma, luma / 2, 255 - luma])
+		}))
+	}
+
+	fn waves(u: f32, v: f32) -> f32 {
+		(2.0 * PI * (1.5 * u + v)).sin()
+	}
+
+	fn rings(u: f32, v: f32) -> f32 {
+		(6.0 * PI * ((u - 0.3).powi(2) + (v - 0.6).powi(2)).sqrt()).cos()
+	}
+
+	fn reencode_as_jpeg(img: &DynamicImage) -> DynamicImage {
+		let mut encoded = Cursor::new(vec![]);
+		img.write_to(&mut encoded, ImageOutputFormat::Jpeg(50))
+			.unwrap();
+		image::load_from_memory(encoded.get_ref()).unwrap()
+	}
+
+	#[test]
+	fn hash_is_stable() {
+		assert_eq!(
+			perceptual_hash(&picture(640, 480, waves)),
+			perceptual_hash(&picture(640, 480, waves))
+		);
+	}
+
+	#[test]
+	fn hamming_distance_counts_differing_bits() {
+		assert_eq!(hamming_distance(0, 0), 0);
+		assert_eq!(hamming_distance(0b1011, 0b0010), 2);
+		assert_eq!(hamming_distance(0, u64::MAX), 64);
+		assert_eq!(hamming_distance(u64::MAX, 1 << 63), 63);
+	}
+
+	#[test]
+	fn copies_are_within_threshold() {
+		let original = picture(640, 480, waves);
+		let hash = perceptual_hash(&original);
+
+		let resized = original.resize_exact(200, 150, FilterType::Lanczos3);
+		let reencoded = reencode_as_jpeg(&original);
+		let both = reencode_as_jpeg(&resized);
+
+		for copy in [resized, reencoded, both] {
+			assert!(hamming_distance(hash, perceptual_hash(&copy)) <= SIMILARITY_THRESHOLD);
+		}
+	}
+
+	#[test]
+	fn unrelated_images_are_beyond_threshold() {
+		assert!(
+			hamming_distance(
+				perceptual_hash(&picture(640, 480, waves)),
+				perceptual_hash(&picture(640, 480, rings))
+			) > SIMILARITY_THRESHOLD
+		);
+	}
+}
@@ -0,0 +1,233 @@
+//! Content-defined chunking used for delta transfers.
+//!
+//! Files are split where a rolling gear hash of the last bytes matches a bitmask, so the boundaries
+//! depend on the content and not on the position in the file. Inserting or removing bytes only
+//! changes the chunks around the edit, which lets the sender skip every chunk the receiver already has.
+
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Chunks are never cut before this size, except for the last one of a file
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024; // 16 KiB
+/// Chunks are always cut at this size, even if no boundary was found
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024; // 256 KiB
+/// A boundary is found when the low bits of the gear hash are zero, which gives ~64 KiB chunks
+const BOUNDARY_MASK: u64 = (1 << 16) - 1;
+
+/// A signature never describes more chunks than this, anything after it is just not reused.
+/// This bounds the memory a peer can make us allocate.
+const MAX_SIGNATURE_CHUNKS: u32 = 1 << 20;
+
+/// Random values for each byte, generated with splitmix64 so both peers always agree on them
+const GEAR: [u64; 256] = {
+	let mut table = [0; 256];
+	let mut state: u64 = 0x5370_6163_6562_6c6b; // "Spaceblk"
+	let mut i = 0;
+	while i < 256 {
+		state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		table[i] = z ^ (z >> 31);
+		i += 1;
+	}
+	table
+};
+
+/// Returns the length of the first chunk of `data`.
+///
+/// `data` must hold at least [`MAX_CHUNK_SIZE`] bytes unless it's the end of the file.
+fn find_boundary(data: &[u8]) -> usize {
+	let len = data.len().min(MAX_CHUNK_SIZE);
+	if len <= MIN_CHUNK_SIZE {
+		return len;
+	}
+
+	let mut hash = 0u64;
+	for (i, byte) in data.iter().enumerate().take(len).skip(MIN_CHUNK_SIZE) {
+		hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+		if hash & BOUNDARY_MASK == 0 {
+			return i + 1;
+		}
+	}
+
+	len
+}
+
+/// Splits a file into content-defined chunks
+pub struct Chunker<R> {
+	reader: R,
+	buf: Vec<u8>,
+	eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> Chunker<R> {
+	pub fn new(reader: R) -> Self {
+		Self {
+			reader,
+			buf: Vec::with_capacity(MAX_CHUNK_SIZE),
+			eof: false,
+		}
+	}
+
+	/// Returns the next chunk of the file, or `None` once it has been fully read
+	pub async fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+		while !self.eof && self.buf.len() < MAX_CHUNK_SIZE {
+			let len = self.buf.len();
+			self.buf.resize(MAX_CHUNK_SIZE, 0);
+			let read = self.reader.read(&mut self.buf[len..]).await?;
+			self.buf.truncate(len + read);
+			self.eof = read == 0;
+		}
+
+		if self.buf.is_empty() {
+			return Ok(None);
+		}
+
+		let rest = self.buf.split_off(find_boundary(&self.buf));
+		Ok(Some(std::mem::replace(&mut self.buf, rest)))
+	}
+}
+
+/// A chunk of the file the receiver already has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSignature {
+	pub offset: u64,
+	pub size: u64,
+	pub hash: [u8; 32],
+}
+
+/// The chunks of the file the receiver already has, sent to the sender before a delta transfer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signature {
+	pub chunks: Vec<ChunkSignature>,
+}
+
+impl Signature {
+	/// Chunks and hashes a file
+	pub async fn from_reader(reader: impl AsyncRead + Unpin) -> io::Result<Self> {
+		let mut chunker = Chunker::new(reader);
+		let mut chunks = Vec::new();
+		let mut offset = 0;
+
+		while let Some(chunk) = chunker.next_chunk().await? {
+			if chunks.len() >= MAX_SIGNATURE_CHUNKS as usize {
+				break;
+			}
+
+			chunks.push(ChunkSignature {
+				offset,
+				size: chunk.len() as u64,
+				hash: *blake3::hash(&chunk).as_bytes(),
+			});
+			offset += chunk.len() as u64;
+		}
+
+		Ok(Self { chunks })
+	}
+
+	pub async fn from_stream(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Self> {
+		let len = stream.read_u32_le().await?;
+		if len > MAX_SIGNATURE_CHUNKS {
+			return Err(io::Error::new(
+				ErrorKind::InvalidData,
+				"signature has too many chunks",
+			));
+		}
+
+		let mut chunks = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			let offset = stream.read_u64_le().await?;
+			let size = stream.read_u64_le().await?;
+			let mut hash = [0; 32];
+			stream.read_exact(&mut hash).await?;
+
+			chunks.push(ChunkSignature { offset, size, hash });
+		}
+
+		Ok(Self { chunks })
+	}
+
+	#[must_use]
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut buf = Vec::with_capacity(4 + self.chunks.len() * 48);
+		buf.extend_from_slice(&(self.chunks.len() as u32).to_le_bytes());
+		for ChunkSignature { offset, size, hash } in &self.chunks {
+			buf.extend_from_slice(&offset.to_le_bytes());
+			buf.extend_from_slice(&size.to_le_bytes());
+			buf.extend_from_slice(hash);
+		}
+		buf
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::*;
+
+	fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+		let mut state = seed;
+		(0..len)
+			.map(|_| {
+				state ^= state << 13;
+				state ^= state >> 7;
+				state ^= state << 17;
+				state as u8
+			})
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn test_chunks_within_bounds() {
+		let data = random_bytes(2 * 1024 * 1024, 42);
+
+		let mut chunker = Chunker::new(Cursor::new(data.clone()));
+		let mut result = Vec::new();
+		while let Some(chunk) = chunker.next_chunk().await.unwrap() {
+			assert!(chunk.len() <= MAX_CHUNK_SIZE);
+			result.extend(chunk);
+		}
+		assert_eq!(result, data);
+	}
+
+	#[tokio::test]
+	async fn test_chunks_resync_after_insertion() {
+		let data = random_bytes(2 * 1024 * 1024, 69);
+		let mut edited = data.clone();
+		edited.splice(1024 * 1024..1024 * 1024, b"Spacedrive".iter().copied());
+
+		let original = Signature::from_reader(Cursor::new(data)).await.unwrap();
+		let edited = Signature::from_reader(Cursor::new(edited)).await.unwrap();
+
+		let reused = edited
+			.chunks
+			.iter()
+			.filter(|chunk| original.chunks.iter().any(|c| c.hash == chunk.hash))
+			.count();
+
+		// Only the chunk with the insertion should differ
+		assert!(reused + 2 >= edited.chunks.len());
+	}
+
+	#[tokio::test]
+	async fn test_signature() {
+		let signature = Signature::from_reader(Cursor::new(random_bytes(100_000, 7)))
+			.await
+			.unwrap();
+		let bytes = signature.to_bytes();
+		let signature2 = Signature::from_stream(&mut Cursor::new(bytes))
+			.await
+			.unwrap();
+		assert_eq!(signature, signature2);
+
+		let signature = Signature::default();
+		let bytes = signature.to_bytes();
+		let signature2 = Signature::from_stream(&mut Cursor::new(bytes))
+			.await
+			.unwrap();
+		assert_eq!(signature, signature2);
+	}
+}
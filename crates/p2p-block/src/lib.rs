@@ -7,7 +7,8 @@
 #![allow(unused)] // TODO: This module is still in heavy development!
 
 use std::{
-	io,
+	collections::{HashMap, HashSet},
+	io::{self, SeekFrom},
 	marker::PhantomData,
 	path::{Path, PathBuf},
 	string::FromUtf8Error,
@@ -20,7 +21,10 @@ use std::{
 use thiserror::Error;
 use tokio::{
 	fs::File,
-	io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+	io::{
+		AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+		BufReader,
+	},
 };
 use tracing::debug;
 
@@ -29,16 +33,24 @@ use sd_p2p_proto::{decode, encode};
 
 mod block;
 mod block_size;
+mod delta;
 mod sb_request;
 
 pub use block::*;
 pub use block_size::*;
+pub use delta::*;
 pub use sb_request::*;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Msg<'a> {
 	Block(Block<'a>),
 	Cancelled,
+	/// Copy a chunk of the receiver's existing file, only sent in delta transfers
+	Copy {
+		offset: u64,
+		basis_offset: u64,
+		size: u64,
+	},
 }
 
 impl<'a> Msg<'a> {
@@ -50,6 +62,11 @@ impl<'a> Msg<'a> {
 		match discriminator {
 			0 => Ok(Msg::Block(Block::from_stream(stream, data_buf).await?)),
 			1 => Ok(Msg::Cancelled),
+			2 => Ok(Msg::Copy {
+				offset: stream.read_u64_le().await?,
+				basis_offset: stream.read_u64_le().await?,
+				size: stream.read_u64_le().await?,
+			}),
 			_ => Err(io::Error::new(
 				io::ErrorKind::Other,
 				"Invalid 'Msg' discriminator!",
@@ -67,6 +84,17 @@ impl<'a> Msg<'a> {
 				bytes
 			}
 			Msg::Cancelled => vec![1],
+			Msg::Copy {
+				offset,
+				basis_offset,
+				size,
+			} => {
+				let mut bytes = vec![2];
+				bytes.extend_from_slice(&offset.to_le_bytes());
+				bytes.extend_from_slice(&basis_offset.to_le_bytes());
+				bytes.extend_from_slice(&size.to_le_bytes());
+				bytes
+			}
 		}
 	}
 }
@@ -86,13 +114,18 @@ impl<'a, F> Transfer<'a, F>
 where
 	F: Fn(u8) + 'a,
 {
-	// TODO: Handle `req.range` correctly in this code
-
+	/// `cancelled` is also set when the remote peer cancels the transfer, so callers can tell a
+	/// cancelled file apart from a completed one.
 	pub fn new(req: &'a SpaceblockRequests, on_progress: F, cancelled: &'a AtomicBool) -> Self {
 		Self {
 			reqs: req,
 			on_progress,
-			total_offset: 0,
+			// Bytes skipped by partial ranges were already transferred, so they count as progress
+			total_offset: req
+				.requests
+				.iter()
+				.map(|req| req.size - (req.byte_range().end - req.byte_range().start))
+				.sum(),
 			total_bytes: req.requests.iter().map(|req| req.size).sum(),
 			i: 0,
 			cancelled,
@@ -100,14 +133,24 @@ where
 	}
 
 	// TODO: Should `new` take in the streams too cause this means we `Stream` `SpaceblockRequest` could get outta sync.
+	/// Sends the current file.
+	///
+	/// If the request has a [`Range::Partial`], `file` must already be positioned at its start.
 	pub async fn send(
 		&mut self,
 		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
-		mut file: (impl AsyncBufRead + Unpin),
+		file: (impl AsyncBufRead + Unpin),
 	) -> Result<(), io::Error> {
+		let range = self.current_request_range()?;
+		self.i += 1;
+		if range.is_empty() {
+			return Ok(());
+		}
+
 		// We manually implement what is basically a `BufReader` so we have more control
 		let mut buf = vec![0u8; self.reqs.block_size.size() as usize];
-		let mut offset: u64 = 0;
+		let mut offset = range.start;
+		let mut file = file.take(range.end - range.start);
 
 		loop {
 			if self.cancelled.load(Ordering::Relaxed) {
@@ -124,10 +167,10 @@ where
 
 			if read == 0 {
 				#[allow(clippy::panic)] // TODO: Remove panic
-						// The file may have been modified during sender on the sender and we don't account for that.
-						// TODO: Error handling + send error to remote
+							// The file may have been modified during sender on the sender and we don't account for that.
+							// TODO: Error handling + send error to remote
 				assert!(
-					(offset + read as u64) == self.reqs.requests[self.i].size,
+					offset == range.end,
 					"File sending has stopped but it doesn't match the expected length!"
 				);
 
@@ -154,31 +197,41 @@ where
 				// Cancelled by user
 				1 => {
 					debug!("Receiver cancelled Spacedrop transfer!");
+					self.cancelled.store(true, Ordering::Relaxed);
 					return Ok(());
 				}
 				// Transfer complete
 				2 => return Ok(()),
-				_ => todo!(),
+				_ => {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						"Invalid acknowledgement from receiver!",
+					))
+				}
 			}
 		}
 	}
 
 	// TODO: Timeout on receiving/sending
+	/// Receives the current file.
+	///
+	/// If the request has a [`Range::Partial`], `file` must already be positioned at its start.
 	pub async fn receive(
 		&mut self,
 		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
 		mut file: (impl AsyncWrite + Unpin),
 		// TODO: Proper error type
 	) -> Result<(), io::Error> {
-		// We manually implement what is basically a `BufReader` so we have more control
-		let mut data_buf = vec![0u8; self.reqs.block_size.size() as usize];
-		let mut offset: u64 = 0;
-
-		if self.reqs.requests[self.i].size == 0 {
+		let range = self.current_request_range()?;
+		if range.is_empty() {
 			self.i += 1;
 			return Ok(());
 		}
 
+		// We manually implement what is basically a `BufReader` so we have more control
+		let mut data_buf = vec![0u8; self.reqs.block_size.size() as usize];
+		let mut offset = range.start;
+
 		// TODO: Prevent loop being a DOS vector
 		loop {
 			if self.cancelled.load(Ordering::Relaxed) {
@@ -200,16 +253,18 @@ where
 						"Received block at offset {} of size {}",
 						block.offset, block.size
 					);
+					if block.offset != offset || offset + block.size > range.end {
+						return Err(io::Error::new(
+							io::ErrorKind::InvalidData,
+							"Received a block outside of the requested range!",
+						));
+					}
 					offset += block.size;
 
 					file.write_all(&data_buf[..block.size as usize]).await?;
 
-					let req = self.reqs.requests.get(self.i).ok_or_else(|| {
-						debug!("Vector read out of bounds!");
-						io::ErrorKind::Other
-					})?;
 					// TODO: Should this be `read == 0`
-					if offset == req.size {
+					if offset == range.end {
 						break;
 					}
 
@@ -220,8 +275,15 @@ where
 				}
 				Msg::Cancelled => {
 					debug!("Sender cancelled Spacedrop transfer!");
+					self.cancelled.store(true, Ordering::Relaxed);
 					return Ok(());
 				}
+				Msg::Copy { .. } => {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						"Received a chunk copy outside of a delta transfer!",
+					));
+				}
 			}
 		}
 
@@ -232,6 +294,304 @@ where
 
 		Ok(())
 	}
+
+	/// Sends a file only transferring the chunks the receiver doesn't already have.
+	///
+	/// Must be paired with [`Transfer::receive_delta`], which starts by sending the [`Signature`]
+	/// of the receiver's existing copy of the file.
+	pub async fn send_delta(
+		&mut self,
+		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+		file: (impl AsyncRead + Unpin),
+	) -> Result<(), io::Error> {
+		let size = self.current_request_size()?;
+		self.i += 1;
+		if size == 0 {
+			return Ok(());
+		}
+
+		let signature = Signature::from_stream(stream).await?;
+		let known_chunks = signature
+			.chunks
+			.iter()
+			.map(|chunk| (chunk.hash, chunk))
+			.collect::<HashMap<_, _>>();
+
+		let block_size = self.reqs.block_size.size() as usize;
+		let mut chunker = Chunker::new(file);
+		let mut offset: u64 = 0;
+
+		while let Some(chunk) = chunker.next_chunk().await? {
+			let chunk_size = chunk.len() as u64;
+
+			let ack = match known_chunks.get(blake3::hash(&chunk).as_bytes()) {
+				Some(known) if known.size == chunk_size => {
+					debug!(
+						"Reusing chunk at offset {} of size {chunk_size} from offset {}",
+						offset, known.offset
+					);
+
+					self.send_msg(
+						stream,
+						&Msg::Copy {
+							offset,
+							basis_offset: known.offset,
+							size: chunk_size,
+						},
+						chunk_size,
+					)
+					.await?
+				}
+				_ => {
+					let mut ack = Ack::Continue;
+					let mut block_offset = offset;
+					for data in chunk.chunks(block_size) {
+						debug!(
+							"Sending block at offset {block_offset} of size {}",
+							data.len()
+						);
+
+						ack = self
+							.send_msg(
+								stream,
+								&Msg::Block(Block {
+									offset: block_offset,
+									size: data.len() as u64,
+									data,
+								}),
+								data.len() as u64,
+							)
+							.await?;
+						block_offset += data.len() as u64;
+
+						if ack != Ack::Continue {
+							break;
+						}
+					}
+					ack
+				}
+			};
+
+			offset += chunk_size;
+
+			match ack {
+				Ack::Continue => {}
+				Ack::Cancelled => {
+					debug!("Spacedrop transfer was cancelled!");
+					return Ok(());
+				}
+				Ack::Complete => return Ok(()),
+			}
+		}
+
+		// The file may have been modified during the transfer
+		Err(io::Error::new(
+			io::ErrorKind::UnexpectedEof,
+			"File sending has stopped but it doesn't match the expected length!",
+		))
+	}
+
+	/// Receives a file sent with [`Transfer::send_delta`], reusing chunks from `basis`,
+	/// the receiver's existing copy of the file, if there is one.
+	///
+	/// `file` and `basis` must not be the same file, as `basis` is read while `file` is written.
+	pub async fn receive_delta(
+		&mut self,
+		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+		mut file: (impl AsyncWrite + Unpin),
+		mut basis: Option<impl AsyncRead + AsyncSeek + Unpin>,
+	) -> Result<(), io::Error> {
+		let size = self.current_request_size()?;
+		if size == 0 {
+			self.i += 1;
+			return Ok(());
+		}
+
+		let signature = match &mut basis {
+			Some(basis) => Signature::from_reader(basis).await?,
+			None => Signature::default(),
+		};
+		stream.write_all(&signature.to_bytes()).await?;
+		stream.flush().await?;
+
+		let known_chunks = signature
+			.chunks
+			.iter()
+			.map(|chunk| (chunk.offset, chunk.size))
+			.collect::<HashSet<_>>();
+
+		let mut data_buf = vec![0u8; self.reqs.block_size.size() as usize];
+		let mut offset: u64 = 0;
+
+		// TODO: Prevent loop being a DOS vector
+		loop {
+			if self.cancelled.load(Ordering::Relaxed) {
+				stream.write_u8(1).await?;
+				stream.flush().await?;
+				return Ok(());
+			}
+
+			let received = match Msg::from_stream(stream, &mut data_buf).await? {
+				Msg::Block(block) => {
+					debug!(
+						"Received block at offset {} of size {}",
+						block.offset, block.size
+					);
+					check_delta_msg_range(block.offset, block.size, offset, size)?;
+
+					file.write_all(&data_buf[..block.size as usize]).await?;
+					block.size
+				}
+				Msg::Copy {
+					offset: chunk_offset,
+					basis_offset,
+					size: chunk_size,
+				} => {
+					debug!(
+						"Copying chunk at offset {chunk_offset} of size {chunk_size} from offset {basis_offset}"
+					);
+					check_delta_msg_range(chunk_offset, chunk_size, offset, size)?;
+
+					// We only copy chunks we told the sender about, so it can't make us read anything else
+					let basis = basis
+						.as_mut()
+						.filter(|_| known_chunks.contains(&(basis_offset, chunk_size)))
+						.ok_or_else(|| {
+							io::Error::new(
+								io::ErrorKind::InvalidData,
+								"Received a copy of an unknown chunk!",
+							)
+						})?;
+
+					basis.seek(SeekFrom::Start(basis_offset)).await?;
+					let mut remaining = chunk_size;
+					while remaining > 0 {
+						let len = remaining.min(data_buf.len() as u64) as usize;
+						basis.read_exact(&mut data_buf[..len]).await?;
+						file.write_all(&data_buf[..len]).await?;
+						remaining -= len as u64;
+					}
+
+					chunk_size
+				}
+				Msg::Cancelled => {
+					debug!("Sender cancelled Spacedrop transfer!");
+					self.cancelled.store(true, Ordering::Relaxed);
+					return Ok(());
+				}
+			};
+
+			offset += received;
+			self.total_offset += received;
+			(self.on_progress)(
+				((self.total_offset as f64 / self.total_bytes as f64) * 100.0) as u8,
+			); // SAFETY: Percent must be between 0 and 100
+
+			if offset == size {
+				break;
+			}
+
+			stream
+				.write_u8(u8::from(self.cancelled.load(Ordering::Relaxed)))
+				.await?;
+			stream.flush().await?;
+		}
+
+		stream.write_u8(2).await?;
+		stream.flush().await?;
+		file.flush().await?;
+		self.i += 1;
+
+		Ok(())
+	}
+
+	fn current_request_size(&self) -> Result<u64, io::Error> {
+		self.reqs
+			.requests
+			.get(self.i)
+			.map(|req| req.size)
+			.ok_or_else(|| {
+				debug!("Vector read out of bounds!");
+				io::ErrorKind::Other.into()
+			})
+	}
+
+	fn current_request_range(&self) -> Result<std::ops::Range<u64>, io::Error> {
+		self.reqs
+			.requests
+			.get(self.i)
+			.map(SpaceblockRequest::byte_range)
+			.ok_or_else(|| {
+				debug!("Vector read out of bounds!");
+				io::ErrorKind::Other.into()
+			})
+	}
+
+	/// Sends a message of a delta transfer and waits for the receiver to acknowledge it
+	async fn send_msg(
+		&mut self,
+		stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+		msg: &Msg<'_>,
+		size: u64,
+	) -> Result<Ack, io::Error> {
+		if self.cancelled.load(Ordering::Relaxed) {
+			stream.write_all(&Msg::Cancelled.to_bytes()).await?;
+			stream.flush().await?;
+			return Ok(Ack::Cancelled);
+		}
+
+		stream.write_all(&msg.to_bytes()).await?;
+		stream.flush().await?;
+
+		self.total_offset += size;
+		(self.on_progress)(((self.total_offset as f64 / self.total_bytes as f64) * 100.0) as u8); // SAFETY: Percent must be between 0 and 100
+
+		match stream.read_u8().await? {
+			0 => Ok(Ack::Continue),
+			1 => {
+				self.cancelled.store(true, Ordering::Relaxed);
+				Ok(Ack::Cancelled)
+			}
+			2 => Ok(Ack::Complete),
+			_ => Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Invalid acknowledgement from receiver!",
+			)),
+		}
+	}
+}
+
+/// The receiver's response to each message of a transfer
+#[derive(Debug, PartialEq, Eq)]
+enum Ack {
+	Continue,
+	Cancelled,
+	Complete,
+}
+
+/// Delta transfers are written sequentially, so a message must start where the previous one
+/// ended and not go past the end of the file
+fn check_delta_msg_range(
+	msg_offset: u64,
+	msg_size: u64,
+	offset: u64,
+	size: u64,
+) -> Result<(), io::Error> {
+	if msg_offset != offset {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"Received data out of order!",
+		));
+	}
+
+	if offset.checked_add(msg_size).map_or(true, |end| end > size) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"Received more data than the expected length!",
+		));
+	}
+
+	Ok(())
 }
 
 #[cfg(test)]
@@ -252,11 +612,14 @@ mod tests {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size: BlockSize::from_size(data.len() as u64),
+			delta: false,
 			requests: vec![SpaceblockRequest {
 				name: "Demo".to_string(),
 				size: data.len() as u64,
+				modified: None,
 				range: Range::Full,
 			}],
+			directories: vec![],
 		};
 
 		let (tx, rx) = oneshot::channel();
@@ -294,11 +657,14 @@ mod tests {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size,
+			delta: false,
 			requests: vec![SpaceblockRequest {
 				name: "Demo".to_string(),
 				size: data.len() as u64,
+				modified: None,
 				range: Range::Full,
 			}],
+			directories: vec![],
 		};
 
 		let (tx, rx) = oneshot::channel();
@@ -323,6 +689,51 @@ mod tests {
 		assert_eq!(result, data);
 	}
 
+	#[tokio::test]
+	async fn test_spaceblock_partial_range() {
+		let (mut client, mut server) = tokio::io::duplex(64);
+
+		// This is sent out of band of Spaceblock
+		let data = b"Spacedrive, a file explorer from the future.".repeat(100);
+		let start = 1000;
+		let req = SpaceblockRequests {
+			id: Uuid::new_v4(),
+			block_size: BlockSize::dangerously_new(256),
+			delta: false,
+			requests: vec![SpaceblockRequest {
+				name: "Demo".to_string(),
+				size: data.len() as u64,
+				modified: None,
+				range: Range::Partial(start..data.len() as u64),
+			}],
+			directories: vec![],
+		};
+
+		let (tx, rx) = oneshot::channel();
+		tokio::spawn({
+			let req = req.clone();
+			let data = data.clone();
+			async move {
+				let mut file = BufReader::new(Cursor::new(data));
+				file.seek(SeekFrom::Start(start)).await.unwrap();
+				tx.send(()).unwrap();
+				Transfer::new(&req, |_| {}, &Default::default())
+					.send(&mut client, file)
+					.await;
+			}
+		});
+
+		rx.await.unwrap();
+
+		// The receiver already has the start of the file from a previous transfer
+		let mut result = data[..start as usize].to_vec();
+		Transfer::new(&req, |_| {}, &Default::default())
+			.receive(&mut server, &mut result)
+			.await
+			.unwrap();
+		assert_eq!(result, data);
+	}
+
 	#[tokio::test]
 	async fn test_transfer_receiver_cancelled() {
 		let (mut client, mut server) = tokio::io::duplex(64);
@@ -335,11 +746,14 @@ mod tests {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size,
+			delta: false,
 			requests: vec![SpaceblockRequest {
 				name: "Demo".to_string(),
 				size: data.len() as u64,
+				modified: None,
 				range: Range::Full,
 			}],
+			directories: vec![],
 		};
 
 		let (tx, rx) = oneshot::channel();
@@ -359,10 +773,12 @@ mod tests {
 		rx.await.unwrap();
 
 		let mut result = Vec::new();
-		Transfer::new(&req, |_| {}, &Default::default())
+		let cancelled = AtomicBool::default();
+		Transfer::new(&req, |_| {}, &cancelled)
 			.receive(&mut server, &mut result)
 			.await;
 		assert_eq!(result, Vec::<u8>::new()); // Cancelled by sender so no data
+		assert!(cancelled.load(Ordering::Relaxed));
 	}
 
 	#[tokio::test]
@@ -377,11 +793,14 @@ mod tests {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size,
+			delta: false,
 			requests: vec![SpaceblockRequest {
 				name: "Demo".to_string(),
 				size: data.len() as u64,
+				modified: None,
 				range: Range::Full,
 			}],
+			directories: vec![],
 		};
 
 		let (tx, rx) = oneshot::channel();
@@ -420,11 +839,14 @@ mod tests {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size,
+			delta: false,
 			requests: vec![SpaceblockRequest {
 				name: "Demo".to_string(),
 				size: data.len() as u64,
+				modified: None,
 				range: Range::Full,
 			}],
+			directories: vec![],
 		};
 
 		let (tx, rx) = oneshot::channel();
@@ -450,6 +872,157 @@ mod tests {
 		assert_eq!(result, Vec::<u8>::new()); // Cancelled by sender so no data
 	}
 
+	fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+		let mut state = seed;
+		(0..len)
+			.map(|_| {
+				state ^= state << 13;
+				state ^= state >> 7;
+				state ^= state << 17;
+				state as u8
+			})
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_delta() {
+		let (mut client, mut server) = tokio::io::duplex(64);
+
+		// The receiver has an older version of the file, with a chunk edited and some data appended
+		let basis = random_bytes(1024 * 1024, 42);
+		let mut data = basis.clone();
+		data[512 * 1024..512 * 1024 + 10].copy_from_slice(b"Spacedrive");
+		data.extend(random_bytes(100_000, 69));
+
+		let req = SpaceblockRequests {
+			id: Uuid::new_v4(),
+			block_size: BlockSize::dangerously_new(65_536),
+			delta: true,
+			requests: vec![SpaceblockRequest {
+				name: "Demo".to_string(),
+				size: data.len() as u64,
+				modified: None,
+				range: Range::Full,
+			}],
+			directories: vec![],
+		};
+
+		let (tx, rx) = oneshot::channel();
+		tokio::spawn({
+			let req = req.clone();
+			let data = data.clone();
+			async move {
+				let file = BufReader::new(Cursor::new(data));
+				tx.send(()).unwrap();
+				Transfer::new(&req, |_| {}, &Default::default())
+					.send_delta(&mut client, file)
+					.await
+					.unwrap();
+			}
+		});
+
+		rx.await.unwrap();
+
+		let mut result = Vec::new();
+		Transfer::new(&req, |_| {}, &Default::default())
+			.receive_delta(&mut server, &mut result, Some(Cursor::new(basis)))
+			.await
+			.unwrap();
+		assert_eq!(result, data);
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_delta_without_basis() {
+		let (mut client, mut server) = tokio::io::duplex(64);
+
+		let data = random_bytes(300_000, 7);
+
+		let req = SpaceblockRequests {
+			id: Uuid::new_v4(),
+			block_size: BlockSize::dangerously_new(4096),
+			delta: true,
+			requests: vec![SpaceblockRequest {
+				name: "Demo".to_string(),
+				size: data.len() as u64,
+				modified: None,
+				range: Range::Full,
+			}],
+			directories: vec![],
+		};
+
+		let (tx, rx) = oneshot::channel();
+		tokio::spawn({
+			let req = req.clone();
+			let data = data.clone();
+			async move {
+				let file = BufReader::new(Cursor::new(data));
+				tx.send(()).unwrap();
+				Transfer::new(&req, |_| {}, &Default::default())
+					.send_delta(&mut client, file)
+					.await
+					.unwrap();
+			}
+		});
+
+		rx.await.unwrap();
+
+		let mut result = Vec::new();
+		Transfer::new(&req, |_| {}, &Default::default())
+			.receive_delta(&mut server, &mut result, None::<Cursor<Vec<u8>>>)
+			.await
+			.unwrap();
+		assert_eq!(result, data);
+	}
+
+	#[tokio::test]
+	async fn test_spaceblock_delta_rejects_out_of_order_data() {
+		let (mut client, mut server) = tokio::io::duplex(64);
+
+		let basis = random_bytes(300_000, 7);
+		let req = SpaceblockRequests {
+			id: Uuid::new_v4(),
+			block_size: BlockSize::dangerously_new(4096),
+			delta: true,
+			requests: vec![SpaceblockRequest {
+				name: "Demo".to_string(),
+				size: basis.len() as u64,
+				modified: None,
+				range: Range::Full,
+			}],
+			directories: vec![],
+		};
+
+		tokio::spawn(async move {
+			let signature = Signature::from_stream(&mut client).await.unwrap();
+			let chunk = signature.chunks.first().unwrap();
+
+			// A chunk the receiver knows about, but not where it's expected
+			client
+				.write_all(
+					&Msg::Copy {
+						offset: chunk.size,
+						basis_offset: chunk.offset,
+						size: chunk.size,
+					}
+					.to_bytes(),
+				)
+				.await
+				.unwrap();
+			client.flush().await.unwrap();
+
+			// Keep the stream open until the receiver gave up
+			client.read_u8().await.ok();
+		});
+
+		let mut result = Vec::new();
+		let err = Transfer::new(&req, |_| {}, &Default::default())
+			.receive_delta(&mut server, &mut result, Some(Cursor::new(basis)))
+			.await
+			.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+		assert!(result.is_empty());
+	}
+
 	#[tokio::test]
 	async fn test_msg() {
 		let block = Block {
@@ -477,5 +1050,16 @@ mod tests {
 			.await
 			.unwrap();
 		assert_eq!(msg, msg2);
+
+		let msg = Msg::Copy {
+			offset: 420,
+			basis_offset: 69,
+			size: 42069,
+		};
+		let bytes = msg.to_bytes();
+		let msg2 = Msg::from_stream(&mut Cursor::new(bytes), &mut [0u8; 64])
+			.await
+			.unwrap();
+		assert_eq!(msg, msg2);
 	}
 }
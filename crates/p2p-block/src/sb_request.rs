@@ -1,4 +1,7 @@
-use std::io;
+use std::{
+	io,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt};
@@ -50,15 +53,73 @@ impl Range {
 	}
 }
 
+/// Modification time of a file or directory, as seconds and nanoseconds since the Unix epoch
+async fn modified_from_stream(
+	stream: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<SystemTime>> {
+	match stream.read_u8().await? {
+		0 => Ok(None),
+		1 => {
+			let secs = stream.read_u64_le().await?;
+			let nanos = stream.read_u32_le().await?;
+			// `Duration::new` panics if the nanoseconds carried into the seconds overflow them
+			if nanos >= 1_000_000_000 {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					"Invalid modification time nanoseconds",
+				));
+			}
+
+			Ok(UNIX_EPOCH.checked_add(Duration::new(secs, nanos)))
+		}
+		_ => Err(io::Error::new(
+			io::ErrorKind::Other,
+			"Invalid modification time discriminator",
+		)),
+	}
+}
+
+fn modified_to_bytes(buf: &mut Vec<u8>, modified: Option<SystemTime>) {
+	// Times before the Unix epoch are sent as unknown
+	match modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()) {
+		Some(duration) => {
+			buf.push(1);
+			buf.extend_from_slice(&duration.as_secs().to_le_bytes());
+			buf.extend_from_slice(&duration.subsec_nanos().to_le_bytes());
+		}
+		None => buf.push(0),
+	}
+}
+
+/// Version of the Spaceblock wire format, sent first so peers using another one fail clearly
+/// instead of misreading the transfer. Optional features of a version are negotiated through the
+/// features byte.
+pub const SPACEBLOCK_VERSION: u8 = 1;
+
+/// Bit of the features byte of [`SpaceblockRequests`] set when the sender supports delta transfers
+const FEATURE_DELTA: u8 = 1 << 0;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SpaceblockRequests {
 	pub id: Uuid,
 	pub block_size: BlockSize,
+	/// The sender supports delta transfers, the receiver decides whether to use them when accepting.
+	/// See [`Transfer::send_delta`](crate::Transfer::send_delta).
+	pub delta: bool,
 	pub requests: Vec<SpaceblockRequest>,
+	/// Directories to create, so empty ones are kept and their modification times restored
+	pub directories: Vec<SpaceblockDirectory>,
 }
 
 #[derive(Debug, Error)]
 pub enum SpaceblockRequestsError {
+	#[error("SpaceblockRequestsError::Version({0})")]
+	Version(std::io::Error),
+	#[error(
+		"the peer uses version {0} of Spaceblock, this one supports version {}",
+		SPACEBLOCK_VERSION
+	)]
+	UnsupportedVersion(u8),
 	#[error("SpaceblockRequestsError::Id({0:?})")]
 	Id(#[from] decode::Error),
 	#[error("SpaceblockRequestsError::InvalidLen({0})")]
@@ -67,12 +128,25 @@ pub enum SpaceblockRequestsError {
 	SpaceblockRequest(#[from] SpaceblockRequestError),
 	#[error("SpaceblockRequestsError::BlockSize({0:?})")]
 	BlockSize(std::io::Error),
+	#[error("SpaceblockRequestsError::Features({0:?})")]
+	Features(std::io::Error),
+	#[error("SpaceblockRequestsError::SpaceblockDirectory({0:?})")]
+	SpaceblockDirectory(#[from] SpaceblockDirectoryError),
 }
 
 impl SpaceblockRequests {
 	pub async fn from_stream(
 		stream: &mut (impl AsyncRead + Unpin),
 	) -> Result<Self, SpaceblockRequestsError> {
+		let version = stream
+			.read_u8()
+			.await
+			.map_err(SpaceblockRequestsError::Version)?;
+
+		if version != SPACEBLOCK_VERSION {
+			return Err(SpaceblockRequestsError::UnsupportedVersion(version));
+		}
+
 		let id = decode::uuid(stream)
 			.await
 			.map_err(SpaceblockRequestsError::Id)?;
@@ -81,21 +155,38 @@ impl SpaceblockRequests {
 			.await
 			.map_err(SpaceblockRequestsError::BlockSize)?;
 
-		let size = stream
-			// Max of 255 files in one request
+		let features = stream
 			.read_u8()
 			.await
+			.map_err(SpaceblockRequestsError::Features)?;
+
+		let size = stream
+			.read_u32_le()
+			.await
 			.map_err(SpaceblockRequestsError::InvalidLen)?;
 
+		// The length comes from the remote peer, so we don't trust it to preallocate
 		let mut requests = Vec::new();
 		for i in 0..size {
 			requests.push(SpaceblockRequest::from_stream(stream).await?);
 		}
 
+		let size = stream
+			.read_u32_le()
+			.await
+			.map_err(SpaceblockRequestsError::InvalidLen)?;
+
+		let mut directories = Vec::new();
+		for i in 0..size {
+			directories.push(SpaceblockDirectory::from_stream(stream).await?);
+		}
+
 		Ok(Self {
 			id,
 			block_size,
+			delta: features & FEATURE_DELTA != 0,
 			requests,
+			directories,
 		})
 	}
 
@@ -104,21 +195,28 @@ impl SpaceblockRequests {
 		let Self {
 			id,
 			block_size,
+			delta,
 			requests,
+			directories,
 		} = self;
 		#[allow(clippy::panic)] // TODO: Remove this panic
 		assert!(
-			requests.len() <= 255,
-			"Can't Spacedrop more than 255 files at once!"
+			requests.len() <= u32::MAX as usize && directories.len() <= u32::MAX as usize,
+			"Can't Spacedrop more than 2^32 files at once!"
 		);
 
-		let mut buf = vec![];
+		let mut buf = vec![SPACEBLOCK_VERSION];
 		encode::uuid(&mut buf, id);
 		buf.append(&mut block_size.to_bytes().to_vec());
-		buf.push(requests.len() as u8);
+		buf.push(if *delta { FEATURE_DELTA } else { 0 });
+		buf.extend_from_slice(&(requests.len() as u32).to_le_bytes());
 		for request in requests {
 			buf.extend_from_slice(&request.to_bytes());
 		}
+		buf.extend_from_slice(&(directories.len() as u32).to_le_bytes());
+		for directory in directories {
+			buf.extend_from_slice(&directory.to_bytes());
+		}
 		buf
 	}
 }
@@ -126,8 +224,12 @@ impl SpaceblockRequests {
 /// TODO
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SpaceblockRequest {
+	/// Path of the file relative to the Spacedrop, with `/` separated components.
+	///
+	/// It comes from the remote peer so it must be sanitised before being joined to a path!
 	pub name: String,
 	pub size: u64,
+	pub modified: Option<SystemTime>,
 	// TODO: Include file permissions
 	pub range: Range,
 }
@@ -138,6 +240,8 @@ pub enum SpaceblockRequestError {
 	Name(decode::Error),
 	#[error("SpaceblockRequestError::Size({0})")]
 	Size(std::io::Error),
+	#[error("SpaceblockRequestError::Modified({0})")]
+	Modified(std::io::Error),
 	// TODO: From outside. Probs remove?
 	#[error("SpaceblockRequestError::RangeError({0:?})")]
 	RangeError(io::Error),
@@ -156,9 +260,14 @@ impl SpaceblockRequest {
 			.await
 			.map_err(SpaceblockRequestError::Size)?;
 
+		let modified = modified_from_stream(stream)
+			.await
+			.map_err(SpaceblockRequestError::Modified)?;
+
 		Ok(Self {
 			name,
 			size,
+			modified,
 			range: Range::from_stream(stream)
 				.await
 				.map_err(SpaceblockRequestError::Size)?,
@@ -167,14 +276,73 @@ impl SpaceblockRequest {
 
 	#[must_use]
 	pub fn to_bytes(&self) -> Vec<u8> {
-		let Self { name, size, range } = self;
+		let Self {
+			name,
+			size,
+			modified,
+			range,
+		} = self;
 		let mut buf = Vec::new();
 
 		encode::string(&mut buf, name);
 		buf.extend_from_slice(&self.size.to_le_bytes());
+		modified_to_bytes(&mut buf, *modified);
 		buf.extend_from_slice(&self.range.to_bytes());
 		buf
 	}
+
+	/// The bytes of the file which will actually be transferred, clamped to the size of the file
+	#[must_use]
+	pub fn byte_range(&self) -> std::ops::Range<u64> {
+		match &self.range {
+			Range::Full => 0..self.size,
+			Range::Partial(range) => {
+				let end = range.end.min(self.size);
+				range.start.min(end)..end
+			}
+		}
+	}
+}
+
+/// A directory of a Spacedrop, which has no data to transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaceblockDirectory {
+	/// Path of the directory relative to the Spacedrop, like [`SpaceblockRequest::name`]
+	pub name: String,
+	pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Error)]
+pub enum SpaceblockDirectoryError {
+	#[error("SpaceblockDirectoryError::Name({0})")]
+	Name(decode::Error),
+	#[error("SpaceblockDirectoryError::Modified({0})")]
+	Modified(std::io::Error),
+}
+
+impl SpaceblockDirectory {
+	pub async fn from_stream(
+		stream: &mut (impl AsyncRead + Unpin),
+	) -> Result<Self, SpaceblockDirectoryError> {
+		let name = decode::string(stream)
+			.await
+			.map_err(SpaceblockDirectoryError::Name)?;
+
+		let modified = modified_from_stream(stream)
+			.await
+			.map_err(SpaceblockDirectoryError::Modified)?;
+
+		Ok(Self { name, modified })
+	}
+
+	#[must_use]
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+
+		encode::string(&mut buf, &self.name);
+		modified_to_bytes(&mut buf, self.modified);
+		buf
+	}
 }
 
 #[cfg(test)]
@@ -196,12 +364,34 @@ mod tests {
 		assert_eq!(req, req2);
 	}
 
+	#[test]
+	fn test_byte_range() {
+		let mut req = SpaceblockRequest {
+			name: "Demo".to_string(),
+			size: 42,
+			modified: None,
+			range: Range::Full,
+		};
+		assert_eq!(req.byte_range(), 0..42);
+
+		req.range = Range::Partial(10..20);
+		assert_eq!(req.byte_range(), 10..20);
+
+		req.range = Range::Partial(40..69);
+		assert_eq!(req.byte_range(), 40..42);
+
+		req.range = Range::Partial(69..420);
+		assert_eq!(req.byte_range(), 42..42);
+	}
+
 	#[tokio::test]
 	async fn test_spaceblock_requests_empty() {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size: BlockSize::from_size(42069),
+			delta: false,
 			requests: vec![],
+			directories: vec![],
 		};
 
 		let bytes = req.to_bytes();
@@ -216,11 +406,14 @@ mod tests {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size: BlockSize::from_size(42069),
+			delta: false,
 			requests: vec![SpaceblockRequest {
 				name: "Demo".to_string(),
 				size: 42069,
+				modified: Some(UNIX_EPOCH + Duration::new(1_712_000_000, 42)),
 				range: Range::Full,
 			}],
+			directories: vec![],
 		};
 
 		let bytes = req.to_bytes();
@@ -232,6 +425,7 @@ mod tests {
 		let req = SpaceblockRequest {
 			name: "Demo".to_string(),
 			size: 42069,
+			modified: None,
 			range: Range::Partial(0..420),
 		};
 
@@ -247,18 +441,31 @@ mod tests {
 		let req = SpaceblockRequests {
 			id: Uuid::new_v4(),
 			block_size: BlockSize::from_size(42069),
+			delta: true,
 			requests: vec![
 				SpaceblockRequest {
 					name: "Demo".to_string(),
 					size: 42069,
+					modified: None,
 					range: Range::Full,
 				},
 				SpaceblockRequest {
-					name: "Demo2".to_string(),
+					name: "Folder/Demo2".to_string(),
 					size: 420,
+					modified: Some(SystemTime::now()),
 					range: Range::Full,
 				},
 			],
+			directories: vec![
+				SpaceblockDirectory {
+					name: "Folder".to_string(),
+					modified: Some(SystemTime::now()),
+				},
+				SpaceblockDirectory {
+					name: "Folder/Empty".to_string(),
+					modified: None,
+				},
+			],
 		};
 
 		let bytes = req.to_bytes();
@@ -267,4 +474,34 @@ mod tests {
 			.unwrap();
 		assert_eq!(req, req2);
 	}
+
+	#[tokio::test]
+	async fn test_unsupported_version() {
+		let mut bytes = SpaceblockRequests {
+			id: Uuid::new_v4(),
+			block_size: BlockSize::from_size(42069),
+			delta: false,
+			requests: vec![],
+			directories: vec![],
+		}
+		.to_bytes();
+		bytes[0] = SPACEBLOCK_VERSION + 1;
+
+		assert!(matches!(
+			SpaceblockRequests::from_stream(&mut Cursor::new(bytes)).await,
+			Err(SpaceblockRequestsError::UnsupportedVersion(version)) if version == SPACEBLOCK_VERSION + 1
+		));
+	}
+
+	#[tokio::test]
+	async fn test_invalid_modified_nanos() {
+		let mut bytes = vec![1];
+		bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+		bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+
+		let err = modified_from_stream(&mut Cursor::new(bytes))
+			.await
+			.unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	}
 }
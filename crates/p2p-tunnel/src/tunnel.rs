@@ -1,77 +1,421 @@
 use std::{
-	io,
+	fmt, io,
 	pin::Pin,
-	task::{Context, Poll},
+	task::{ready, Context, Poll},
 };
 
+use snow::{params::NoiseParams, Builder, HandshakeState, TransportState};
+use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
-use sd_p2p::UnicastStream;
+use sd_p2p::{
+	Identity, IdentityErr, RemoteIdentity, UnicastStream, REMOTE_IDENTITY_LEN, SIGNATURE_LEN,
+};
+
+/// `XX` so both peers authenticate each other, with ephemeral keys for forward secrecy
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// Mixed into the handshake so it can't be confused with another Noise protocol
+const NOISE_PROLOGUE: &[u8] = b"sd-p2p-tunnel/1";
+/// Prefix of the message each peer signs with its [`Identity`] to bind it to the Noise static key
+const STATIC_KEY_SIGNATURE_PREFIX: &[u8] = b"sd-p2p-tunnel-static-key:";
+
+/// The largest message Noise can encrypt or decrypt
+const MAX_MESSAGE_LEN: usize = u16::MAX as usize;
+/// Leaves room for the ChaChaPoly authentication tag
+const MAX_PAYLOAD_LEN: usize = MAX_MESSAGE_LEN - 16;
+/// Each message is prefixed with its length as a `u16`
+const FRAME_HEADER_LEN: usize = 2;
+
+#[derive(Debug, Error)]
+pub enum TunnelError {
+	#[error("error writing discriminator: {0}")]
+	DiscriminatorWrite(io::Error),
+	#[error("error reading discriminator, is this stream actually a tunnel? {0}")]
+	DiscriminatorRead(io::Error),
+	#[error("invalid discriminator '{0}', is this stream actually a tunnel?")]
+	InvalidDiscriminator(u8),
+	#[error("io error during handshake: {0}")]
+	HandshakeIo(#[from] io::Error),
+	#[error("noise error during handshake: {0}")]
+	Noise(#[from] snow::Error),
+	#[error("remote peer sent a malformed identity")]
+	MalformedIdentity,
+	#[error("remote peer sent an invalid identity: {0}")]
+	InvalidIdentity(#[from] IdentityErr),
+	#[error("remote peer's signature doesn't match its identity")]
+	InvalidSignature,
+}
+
+/// An encrypted and mutually authenticated stream between two [`Identity`]s.
+///
+/// The tunnel is established with a Noise `XX` handshake using fresh static keys. Each peer signs
+/// its static key with its [`Identity`], so once established [`Tunnel::remote_identity`] is
+/// guaranteed to be the peer on the other end, even when the connection goes through a relay.
+pub struct Tunnel<S = UnicastStream> {
+	stream: S,
+	noise: Box<TransportState>,
+	remote_identity: RemoteIdentity,
+	/// The encrypted message currently being read, including its length prefix
+	read_frame: Vec<u8>,
+	read_frame_filled: usize,
+	/// Decrypted data which hasn't been read yet
+	plaintext: Vec<u8>,
+	plaintext_pos: usize,
+	/// Encrypted data which hasn't been written to the stream yet
+	write_frame: Vec<u8>,
+	write_frame_pos: usize,
+}
 
-#[derive(Debug)]
-pub struct Tunnel {
-	stream: UnicastStream,
+impl<S> fmt::Debug for Tunnel<S> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Tunnel")
+			.field("remote_identity", &self.remote_identity)
+			.finish_non_exhaustive()
+	}
 }
 
-impl Tunnel {
-	// TODO: Proper errors
-	pub async fn initiator(mut stream: UnicastStream) -> Result<Self, &'static str> {
+impl<S: AsyncRead + AsyncWrite + Unpin> Tunnel<S> {
+	pub async fn initiator(mut stream: S, identity: &Identity) -> Result<Self, TunnelError> {
 		stream
 			.write_all(&[b'T'])
 			.await
-			.map_err(|_| "Error writing discriminator")?;
+			.map_err(TunnelError::DiscriminatorWrite)?;
 
-		// TODO: Do pairing + authentication
+		let (mut handshake, static_key) = build_handshake(true)?;
+		let mut buf = vec![0; MAX_MESSAGE_LEN];
 
-		Ok(Self { stream })
+		// -> e
+		let len = handshake.write_message(&[], &mut buf)?;
+		write_frame(&mut stream, &buf[..len]).await?;
+
+		// <- e, ee, s, es
+		let frame = read_frame(&mut stream).await?;
+		let len = handshake.read_message(&frame, &mut buf)?;
+		let remote_identity = verify_static_key(&handshake, &buf[..len])?;
+
+		// -> s, se
+		let payload = sign_static_key(&static_key, identity);
+		let len = handshake.write_message(&payload, &mut buf)?;
+		write_frame(&mut stream, &buf[..len]).await?;
+
+		Ok(Self::new(stream, handshake, remote_identity)?)
 	}
 
-	// TODO: Proper errors
-	pub async fn responder(mut stream: UnicastStream) -> Result<Self, &'static str> {
+	pub async fn responder(mut stream: S, identity: &Identity) -> Result<Self, TunnelError> {
 		let discriminator = stream
 			.read_u8()
 			.await
-			.map_err(|_| "Error reading discriminator. Is this stream actually a tunnel?")?;
+			.map_err(TunnelError::DiscriminatorRead)?;
 		if discriminator != b'T' {
-			return Err("Invalid discriminator. Is this stream actually a tunnel?");
+			return Err(TunnelError::InvalidDiscriminator(discriminator));
+		}
+
+		let (mut handshake, static_key) = build_handshake(false)?;
+		let mut buf = vec![0; MAX_MESSAGE_LEN];
+
+		// -> e
+		let frame = read_frame(&mut stream).await?;
+		handshake.read_message(&frame, &mut buf)?;
+
+		// <- e, ee, s, es
+		let payload = sign_static_key(&static_key, identity);
+		let len = handshake.write_message(&payload, &mut buf)?;
+		write_frame(&mut stream, &buf[..len]).await?;
+
+		// -> s, se
+		let frame = read_frame(&mut stream).await?;
+		let len = handshake.read_message(&frame, &mut buf)?;
+		let remote_identity = verify_static_key(&handshake, &buf[..len])?;
+
+		Ok(Self::new(stream, handshake, remote_identity)?)
+	}
+
+	fn new(
+		stream: S,
+		handshake: HandshakeState,
+		remote_identity: RemoteIdentity,
+	) -> Result<Self, snow::Error> {
+		Ok(Self {
+			stream,
+			noise: Box::new(handshake.into_transport_mode()?),
+			remote_identity,
+			read_frame: vec![0; FRAME_HEADER_LEN + MAX_MESSAGE_LEN],
+			read_frame_filled: 0,
+			plaintext: Vec::with_capacity(MAX_MESSAGE_LEN),
+			plaintext_pos: 0,
+			write_frame: Vec::with_capacity(FRAME_HEADER_LEN + MAX_MESSAGE_LEN),
+			write_frame_pos: 0,
+		})
+	}
+
+	/// The authenticated identity of the peer on the other end of the tunnel
+	#[must_use]
+	pub fn remote_identity(&self) -> RemoteIdentity {
+		self.remote_identity
+	}
+
+	/// Writes the pending encrypted message to the stream
+	fn poll_write_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		while self.write_frame_pos < self.write_frame.len() {
+			let written = ready!(Pin::new(&mut self.stream)
+				.poll_write(cx, &self.write_frame[self.write_frame_pos..]))?;
+			if written == 0 {
+				return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+			}
+			self.write_frame_pos += written;
 		}
 
-		// TODO: Do pairing + authentication
+		self.write_frame.clear();
+		self.write_frame_pos = 0;
+		Poll::Ready(Ok(()))
+	}
+}
+
+/// Starts a handshake with a static key generated for this tunnel, which is returned with it
+fn build_handshake(initiator: bool) -> Result<(HandshakeState, Vec<u8>), snow::Error> {
+	let params: NoiseParams = NOISE_PARAMS.parse()?;
+	let builder = Builder::new(params);
+	let keypair = builder.generate_keypair()?;
+	let builder = builder
+		.local_private_key(&keypair.private)
+		.prologue(NOISE_PROLOGUE);
+
+	let handshake = if initiator {
+		builder.build_initiator()?
+	} else {
+		builder.build_responder()?
+	};
+	Ok((handshake, keypair.public))
+}
+
+/// The handshake payload proving we own `identity`: our remote identity followed by a signature
+/// of the Noise static key we generated for this tunnel
+fn sign_static_key(static_key: &[u8], identity: &Identity) -> Vec<u8> {
+	let mut msg = STATIC_KEY_SIGNATURE_PREFIX.to_vec();
+	msg.extend_from_slice(static_key);
+
+	let mut payload = identity.to_remote_identity().get_bytes().to_vec();
+	payload.extend_from_slice(&identity.sign(&msg));
+	payload
+}
+
+fn verify_static_key(
+	handshake: &HandshakeState,
+	payload: &[u8],
+) -> Result<RemoteIdentity, TunnelError> {
+	if payload.len() != REMOTE_IDENTITY_LEN + SIGNATURE_LEN {
+		return Err(TunnelError::MalformedIdentity);
+	}
+	let (remote_identity, signature) = payload.split_at(REMOTE_IDENTITY_LEN);
+	let remote_identity = RemoteIdentity::from_bytes(remote_identity)?;
+
+	let mut msg = STATIC_KEY_SIGNATURE_PREFIX.to_vec();
+	msg.extend_from_slice(
+		handshake
+			.get_remote_static()
+			.ok_or(TunnelError::MalformedIdentity)?,
+	);
 
-		Ok(Self { stream })
+	if !remote_identity.verify(&msg, signature) {
+		return Err(TunnelError::InvalidSignature);
 	}
+
+	Ok(remote_identity)
+}
+
+async fn write_frame(stream: &mut (impl AsyncWrite + Unpin), msg: &[u8]) -> io::Result<()> {
+	stream.write_u16_le(msg.len() as u16).await?;
+	stream.write_all(msg).await?;
+	stream.flush().await
+}
+
+async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+	let len = stream.read_u16_le().await?;
+	let mut buf = vec![0; len as usize];
+	stream.read_exact(&mut buf).await?;
+	Ok(buf)
 }
 
-impl AsyncRead for Tunnel {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Tunnel<S> {
 	fn poll_read(
 		self: Pin<&mut Self>,
 		cx: &mut Context<'_>,
 		buf: &mut ReadBuf<'_>,
 	) -> Poll<io::Result<()>> {
-		// TODO: Do decryption
+		let this = self.get_mut();
+
+		loop {
+			if this.plaintext_pos < this.plaintext.len() || buf.remaining() == 0 {
+				let len = buf
+					.remaining()
+					.min(this.plaintext.len() - this.plaintext_pos);
+				buf.put_slice(&this.plaintext[this.plaintext_pos..this.plaintext_pos + len]);
+				this.plaintext_pos += len;
+				return Poll::Ready(Ok(()));
+			}
 
-		Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+			// Read the length of the next message, and then the message itself
+			let frame_len = if this.read_frame_filled < FRAME_HEADER_LEN {
+				FRAME_HEADER_LEN
+			} else {
+				FRAME_HEADER_LEN
+					+ u16::from_le_bytes([this.read_frame[0], this.read_frame[1]]) as usize
+			};
+
+			if this.read_frame_filled < frame_len {
+				let mut read_buf =
+					ReadBuf::new(&mut this.read_frame[this.read_frame_filled..frame_len]);
+				ready!(Pin::new(&mut this.stream).poll_read(cx, &mut read_buf))?;
+
+				let read = read_buf.filled().len();
+				if read == 0 {
+					return Poll::Ready(if this.read_frame_filled == 0 {
+						// The stream was closed between messages
+						Ok(())
+					} else {
+						Err(io::ErrorKind::UnexpectedEof.into())
+					});
+				}
+
+				this.read_frame_filled += read;
+				continue;
+			}
+
+			this.plaintext.resize(MAX_MESSAGE_LEN, 0);
+			let len = this
+				.noise
+				.read_message(
+					&this.read_frame[FRAME_HEADER_LEN..frame_len],
+					&mut this.plaintext,
+				)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+			this.plaintext.truncate(len);
+			this.plaintext_pos = 0;
+			this.read_frame_filled = 0;
+		}
 	}
 }
 
-impl AsyncWrite for Tunnel {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Tunnel<S> {
 	fn poll_write(
 		self: Pin<&mut Self>,
 		cx: &mut Context<'_>,
 		buf: &[u8],
 	) -> Poll<io::Result<usize>> {
-		// TODO: Do encryption
+		let this = self.get_mut();
+
+		// Only one message is buffered at a time so writes are backpressured by the stream
+		ready!(this.poll_write_frame(cx))?;
+		if buf.is_empty() {
+			return Poll::Ready(Ok(0));
+		}
+
+		let len = buf.len().min(MAX_PAYLOAD_LEN);
+		this.write_frame
+			.resize(FRAME_HEADER_LEN + MAX_MESSAGE_LEN, 0);
+		let encrypted_len = this
+			.noise
+			.write_message(&buf[..len], &mut this.write_frame[FRAME_HEADER_LEN..])
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		this.write_frame[..FRAME_HEADER_LEN].copy_from_slice(&(encrypted_len as u16).to_le_bytes());
+		this.write_frame.truncate(FRAME_HEADER_LEN + encrypted_len);
 
-		Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+		Poll::Ready(Ok(len))
 	}
 
 	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-		Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+		let this = self.get_mut();
+		ready!(this.poll_write_frame(cx))?;
+		Pin::new(&mut this.stream).poll_flush(cx)
 	}
 
 	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-		Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+		let this = self.get_mut();
+		ready!(this.poll_write_frame(cx))?;
+		Pin::new(&mut this.stream).poll_shutdown(cx)
 	}
 }
 
-// TODO: Unit tests
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_tunnel() {
+		let (client, server) = tokio::io::duplex(64);
+		let (client_identity, server_identity) = (Identity::new(), Identity::new());
+
+		let (client, server) = tokio::join!(
+			Tunnel::initiator(client, &client_identity),
+			Tunnel::responder(server, &server_identity)
+		);
+		let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+		assert_eq!(
+			client.remote_identity(),
+			server_identity.to_remote_identity()
+		);
+		assert_eq!(
+			server.remote_identity(),
+			client_identity.to_remote_identity()
+		);
+
+		// Larger than a single Noise message
+		let data = (0..200_000).map(|i| i as u8).collect::<Vec<_>>();
+		let (_, result) = tokio::join!(
+			async {
+				client.write_all(&data).await.unwrap();
+				client.flush().await.unwrap();
+			},
+			async {
+				let mut result = vec![0; data.len()];
+				server.read_exact(&mut result).await.unwrap();
+				result
+			}
+		);
+		assert_eq!(result, data);
+
+		server.write_all(b"Spacedrive").await.unwrap();
+		server.flush().await.unwrap();
+		let mut result = [0; 10];
+		client.read_exact(&mut result).await.unwrap();
+		assert_eq!(&result, b"Spacedrive");
+	}
+
+	#[tokio::test]
+	async fn test_tunnel_invalid_discriminator() {
+		let (mut client, server) = tokio::io::duplex(64);
+		client.write_all(b"X").await.unwrap();
+
+		assert!(matches!(
+			Tunnel::responder(server, &Identity::new()).await,
+			Err(TunnelError::InvalidDiscriminator(b'X'))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_tunnel_forged_identity() {
+		let (client, mut server) = tokio::io::duplex(1024);
+		let (client_identity, identity, impersonated) =
+			(Identity::new(), Identity::new(), Identity::new());
+
+		let responder = async {
+			assert_eq!(server.read_u8().await.unwrap(), b'T');
+			let (mut handshake, static_key) = build_handshake(false).unwrap();
+			let mut buf = vec![0; MAX_MESSAGE_LEN];
+
+			let frame = read_frame(&mut server).await.unwrap();
+			handshake.read_message(&frame, &mut buf).unwrap();
+
+			// Claim to be another peer without being able to sign as it
+			let mut payload = sign_static_key(&static_key, &identity);
+			payload[..REMOTE_IDENTITY_LEN]
+				.copy_from_slice(&impersonated.to_remote_identity().get_bytes());
+			let len = handshake.write_message(&payload, &mut buf).unwrap();
+			write_frame(&mut server, &buf[..len]).await.unwrap();
+		};
+
+		let (result, ()) = tokio::join!(Tunnel::initiator(client, &client_identity), responder);
+		assert!(matches!(result, Err(TunnelError::InvalidSignature)));
+	}
+}
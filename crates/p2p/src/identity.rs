@@ -6,7 +6,7 @@ use std::{
 };
 
 use base64::{engine::general_purpose, Engine};
-use ed25519_dalek::{VerifyingKey, SECRET_KEY_LENGTH};
+use ed25519_dalek::{Signer, VerifyingKey, SECRET_KEY_LENGTH};
 use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 use specta::Type;
@@ -14,6 +14,7 @@ use thiserror::Error;
 use zeroize::ZeroizeOnDrop;
 
 pub const REMOTE_IDENTITY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
 
 #[derive(Debug, Error)]
 #[error(transparent)]
@@ -63,6 +64,12 @@ impl Identity {
 	pub fn to_remote_identity(&self) -> RemoteIdentity {
 		RemoteIdentity(self.0.verifying_key())
 	}
+
+	/// Sign a message so the remote peer can verify it with our [`RemoteIdentity`]
+	#[must_use]
+	pub fn sign(&self, msg: &[u8]) -> [u8; SIGNATURE_LEN] {
+		self.0.sign(msg).to_bytes()
+	}
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Type)]
@@ -163,6 +170,14 @@ impl RemoteIdentity {
 	pub fn verifying_key(&self) -> VerifyingKey {
 		self.0
 	}
+
+	/// Check `signature` was made over `msg` by the [`Identity`] of this peer
+	#[must_use]
+	pub fn verify(&self, msg: &[u8], signature: &[u8]) -> bool {
+		ed25519_dalek::Signature::from_slice(signature)
+			.map(|signature| self.0.verify_strict(msg, &signature).is_ok())
+			.unwrap_or(false)
+	}
 }
 
 impl From<ed25519_dalek::SigningKey> for Identity {
@@ -11,7 +11,7 @@ mod smart_guards;
 mod stream;
 
 pub use hooks::{HookEvent, HookId, ListenerId, ShutdownGuard};
-pub use identity::{Identity, IdentityErr, RemoteIdentity};
+pub use identity::{Identity, IdentityErr, RemoteIdentity, REMOTE_IDENTITY_LEN, SIGNATURE_LEN};
 pub use mdns::Mdns;
 pub use p2p::{Listener, P2P};
 pub use peer::{ConnectionRequest, Peer, PeerConnectionCandidate};
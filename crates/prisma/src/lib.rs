@@ -18,6 +18,8 @@ impl_model!(object);
 impl_model!(location);
 impl_model!(indexer_rule);
 impl_model!(file_path);
+impl_model!(album);
+impl_model!(space);
 
 pub async fn test_db() -> std::sync::Arc<prisma::PrismaClient> {
 	std::sync::Arc::new(
@@ -92,6 +92,20 @@ impl CompressedCRDTOperations {
 
 		ops
 	}
+
+	/// The operations of each record along with the instance that created them and the record's
+	/// model, in the order they were compressed
+	pub fn into_records(
+		self,
+	) -> impl Iterator<Item = (Uuid, String, rmpv::Value, Vec<CompressedCRDTOperation>)> {
+		self.0.into_iter().flat_map(|(instance_id, instance)| {
+			instance.into_iter().flat_map(move |(model_str, model)| {
+				model.into_iter().map(move |(record_id, record)| {
+					(instance_id, model_str.clone(), record_id, record)
+				})
+			})
+		})
+	}
 }
 
 #[derive(PartialEq, Serialize, Deserialize, Clone)]
@@ -247,4 +261,38 @@ mod test {
 		assert_eq!(uncompressed[4].model, "Object");
 		assert_eq!(uncompressed[6].model, "FilePath");
 	}
+
+	#[test]
+	fn into_records() {
+		let instance = Uuid::new_v4();
+
+		let op = |model: &str, record_id: i64| CRDTOperation {
+			instance,
+			timestamp: NTP64(0),
+			model: model.to_string(),
+			record_id: record_id.into(),
+			data: CRDTOperationData::Create,
+		};
+
+		let records = CompressedCRDTOperations::new(vec![
+			op("FilePath", 0),
+			op("FilePath", 0),
+			op("FilePath", 1),
+			op("Object", 0),
+			op("FilePath", 0),
+		])
+		.into_records()
+		.map(|(_, model, record_id, ops)| (model, record_id, ops.len()))
+		.collect::<Vec<_>>();
+
+		assert_eq!(
+			records,
+			vec![
+				("FilePath".to_string(), 0.into(), 2),
+				("FilePath".to_string(), 1.into(), 1),
+				("Object".to_string(), 0.into(), 1),
+				("FilePath".to_string(), 0.into(), 1),
+			]
+		);
+	}
 }